@@ -292,6 +292,7 @@ impl Core {
                     info!("Committed {} -> {:?}", block, x);
                 }
 
+                self.mempool_driver.committed(&block).await;
                 if let Err(e) = self.tx_commit.send(block).await {
                     warn!("Failed to send block through the commit channel: {}", e);
                 }
@@ -65,6 +65,14 @@ impl MempoolDriver {
         Ok(false)
     }
 
+    /// Tells the mempool which batches a committed block carries.
+    pub async fn committed(&mut self, block: &Block) {
+        self.tx_mempool
+            .send(ConsensusMempoolMessage::Committed(block.round, block.payload.clone()))
+            .await
+            .expect("Failed to send commit notification");
+    }
+
     pub async fn cleanup(&mut self, round: Round) {
         // Cleanup the mempool.
         self.tx_mempool
@@ -181,7 +181,7 @@ pub fn generate_secp256k_keypair() -> (PublicKey, SecretKey)
 }
 
 /// Represents an ed25519 signature.
-#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq, Eq)]
 pub struct Signature {
     part1: [u8; 32],
     part2: [u8; 32],
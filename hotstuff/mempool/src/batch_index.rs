@@ -0,0 +1,138 @@
+use crate::mempool::Round;
+use crypto::Digest;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+#[cfg(test)]
+#[path = "tests/batch_index_tests.rs"]
+pub mod batch_index_tests;
+
+struct Rounds {
+    /// The latest consensus round we heard of.
+    current: Round,
+    /// The digests of the batches stored during each round.
+    digests: BTreeMap<Round, Vec<Digest>>,
+    /// The digests of the stored batches the consensus committed.
+    committed: HashSet<Digest>,
+    /// The digests of the stored batches the consensus committed during each round.
+    commits: BTreeMap<Round, Vec<Digest>>,
+}
+
+/// How many of the batches stored during a round the consensus committed so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundCommits {
+    pub round: Round,
+    pub committed: usize,
+    pub uncommitted: usize,
+}
+
+/// Remembers during which consensus round each batch was stored, so that the batches can be
+/// garbage collected and their commits accounted for round by round. The round is our own view of the consensus (updated on
+/// cleanup), so it is only approximately the round in which the batch was sequenced.
+#[derive(Clone)]
+pub struct BatchIndex {
+    rounds: Arc<Mutex<Rounds>>,
+    /// Publishes the current round to the tasks that need to follow it.
+    tx_round: Arc<watch::Sender<Round>>,
+}
+
+impl BatchIndex {
+    pub fn new() -> Self {
+        Self {
+            rounds: Arc::new(Mutex::new(Rounds {
+                current: Round::default(),
+                digests: BTreeMap::new(),
+                committed: HashSet::new(),
+                commits: BTreeMap::new(),
+            })),
+            tx_round: Arc::new(watch::channel(Round::default()).0),
+        }
+    }
+
+    /// Returns a receiver notified whenever the index moves to a newer round.
+    pub fn subscribe(&self) -> watch::Receiver<Round> {
+        self.tx_round.subscribe()
+    }
+
+    /// Moves to a new consensus round and forgets the batches stored or committed at or before
+    /// `gc_round`. Returns the digests of the batches the consensus committed at or before
+    /// `gc_round`, except those stored again after it: the consensus can no longer reference them.
+    /// Batches it never committed are not returned, as a late commit may still reference them.
+    pub fn advance(&self, round: Round, gc_round: Option<Round>) -> Vec<Digest> {
+        let mut rounds = self.rounds.lock().unwrap();
+        if round > rounds.current {
+            rounds.current = round;
+            self.tx_round.send_replace(round);
+        }
+        let gc_round = match gc_round {
+            Some(x) => x,
+            None => return Vec::new(),
+        };
+        let rounds = &mut *rounds;
+        rounds.digests = rounds.digests.split_off(&(gc_round + 1));
+        let retained = rounds.commits.split_off(&(gc_round + 1));
+        let pruned = std::mem::replace(&mut rounds.commits, retained);
+        if pruned.is_empty() {
+            return Vec::new();
+        }
+        let kept: HashSet<_> = rounds.digests.values().flatten().cloned().collect();
+        let mut seen = HashSet::new();
+        pruned
+            .into_values()
+            .flatten()
+            .filter(|digest| rounds.committed.remove(digest))
+            .filter(|digest| !kept.contains(digest) && seen.insert(digest.clone()))
+            .collect()
+    }
+
+    /// Records a batch stored during the current round.
+    pub fn insert(&self, digest: Digest) {
+        let mut rounds = self.rounds.lock().unwrap();
+        let current = rounds.current;
+        rounds.digests.entry(current).or_default().push(digest);
+    }
+
+    /// Records that the consensus committed the batches of `digests` in `round`. Returns how many
+    /// of them are stored batches that were not committed yet; the others are ignored.
+    pub fn commit(&self, round: Round, digests: &[Digest]) -> usize {
+        let mut rounds = self.rounds.lock().unwrap();
+        let rounds = &mut *rounds;
+        let stored: HashSet<_> = rounds.digests.values().flatten().cloned().collect();
+        let committed: Vec<_> = digests
+            .iter()
+            .filter(|digest| stored.contains(digest))
+            .filter(|digest| rounds.committed.insert((*digest).clone()))
+            .cloned()
+            .collect();
+        let count = committed.len();
+        if count > 0 {
+            rounds.commits.entry(round).or_default().extend(committed);
+        }
+        count
+    }
+
+    /// Returns, for each round still indexed, how many of the batches stored during it were
+    /// committed by the consensus and how many were not (yet).
+    pub fn commit_breakdown(&self) -> Vec<RoundCommits> {
+        let rounds = self.rounds.lock().unwrap();
+        rounds
+            .digests
+            .iter()
+            .map(|(round, digests)| {
+                let committed = digests.iter().filter(|d| rounds.committed.contains(d)).count();
+                RoundCommits {
+                    round: *round,
+                    committed,
+                    uncommitted: digests.len() - committed,
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for BatchIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,67 @@
+use crate::mempool::MempoolMessage;
+use bincode::Options as _;
+use serde::{Deserialize, Serialize};
+
+#[cfg(test)]
+#[path = "tests/codec_tests.rs"]
+pub mod codec_tests;
+
+/// Prefixes a `MempoolMessage` serialized with postcard. Bincode encodes the message variant as a
+/// little endian u32, so no bincode message starts with this byte (nor with the `COMPRESSED` one).
+/// This lets us tell a peer using another codec apart from a corrupted message.
+pub const POSTCARD: u8 = 0xfe;
+
+/// The format in which mempool messages are serialized. Every mempool of the committee must use
+/// the same one: the digests of the batches are computed over their serialized form.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    #[default]
+    Bincode,
+    Postcard,
+}
+
+/// Serializes a `MempoolMessage` with `codec`.
+pub fn encode_message(codec: Codec, message: &MempoolMessage) -> Result<Vec<u8>, bincode::Error> {
+    match codec {
+        Codec::Bincode => bincode::serialize(message),
+        Codec::Postcard => {
+            let mut serialized = vec![POSTCARD];
+            serialized.extend(postcard::to_allocvec(message).map_err(|e| {
+                Box::new(bincode::ErrorKind::Custom(e.to_string()))
+            })?);
+            Ok(serialized)
+        }
+    }
+}
+
+/// Deserializes a `MempoolMessage` serialized with `codec`, refusing to read past `max_len` bytes
+/// so that a crafted length prefix cannot make us allocate more. A message serialized with the
+/// other codec is rejected with an error saying so.
+pub fn decode_message(
+    codec: Codec,
+    serialized: &[u8],
+    max_len: u64,
+) -> Result<MempoolMessage, bincode::Error> {
+    if serialized.len() as u64 > max_len {
+        return Err(Box::new(bincode::ErrorKind::SizeLimit));
+    }
+    let is_postcard = serialized.first() == Some(&POSTCARD);
+    match codec {
+        Codec::Bincode if is_postcard => Err(codec_mismatch(Codec::Postcard, codec)),
+        Codec::Bincode => bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(max_len)
+            .deserialize(serialized),
+        Codec::Postcard if !is_postcard => Err(codec_mismatch(Codec::Bincode, codec)),
+        Codec::Postcard => postcard::from_bytes(&serialized[1..])
+            .map_err(|e| Box::new(bincode::ErrorKind::Custom(e.to_string()))),
+    }
+}
+
+fn codec_mismatch(found: Codec, expected: Codec) -> bincode::Error {
+    Box::new(bincode::ErrorKind::Custom(format!(
+        "Message is serialized with {:?} but we use {:?}: all mempools must use the same codec",
+        found, expected
+    )))
+}
@@ -0,0 +1,44 @@
+use bytes::Bytes;
+
+#[cfg(test)]
+#[path = "tests/compression_tests.rs"]
+pub mod compression_tests;
+
+/// Prefixes a compressed `MempoolMessage`. Bincode encodes the message variant as a little endian
+/// u32, so no uncompressed message starts with this byte. Peers that do not compress can thus
+/// keep sending plain messages.
+pub const COMPRESSED: u8 = 0xff;
+
+/// Compresses a serialized `MempoolMessage` and prefixes it with the `COMPRESSED` header.
+///
+/// Snappy plays in the same league as lz4 (fast, modest ratio) and is pure Rust, so it needs no C
+/// toolchain, and the node already ships it for the beacon network. Its raw format also records
+/// the decompressed length up front, which `decompress` checks before allocating anything.
+pub fn compress(serialized: &[u8]) -> Vec<u8> {
+    let compressed = snap::raw::Encoder::new()
+        .compress_vec(serialized)
+        .expect("Failed to compress our own message");
+    let mut message = Vec::with_capacity(compressed.len() + 1);
+    message.push(COMPRESSED);
+    message.extend_from_slice(&compressed);
+    message
+}
+
+/// Returns the serialized `MempoolMessage` carried by `message`, decompressing it if it starts
+/// with the `COMPRESSED` header. A message claiming to decompress to more than `max_len` bytes is
+/// rejected before any allocation.
+pub fn decompress(message: Bytes, max_len: u64) -> Result<Bytes, bincode::Error> {
+    if message.first() != Some(&COMPRESSED) {
+        return Ok(message);
+    }
+    let compressed = &message[1..];
+    let len = snap::raw::decompress_len(compressed)
+        .map_err(|e| Box::new(bincode::ErrorKind::Custom(e.to_string())))?;
+    if len as u64 > max_len {
+        return Err(Box::new(bincode::ErrorKind::SizeLimit));
+    }
+    snap::raw::Decoder::new()
+        .decompress_vec(compressed)
+        .map(Bytes::from)
+        .map_err(|e| Box::new(bincode::ErrorKind::Custom(e.to_string())))
+}
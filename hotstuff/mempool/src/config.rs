@@ -1,24 +1,126 @@
+use crate::codec::Codec;
+use crate::mempool::CHANNEL_CAPACITY;
 use crypto::PublicKey;
 use log::info;
+use network::TcpSettings;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+#[cfg(test)]
+#[path = "tests/config_tests.rs"]
+pub mod config_tests;
 
 #[derive(Deserialize, Serialize)]
+#[serde(default)]
 pub struct Parameters {
     /// The depth of the garbage collection (Denominated in number of rounds).
     pub gc_depth: u64,
     /// The delay after which the synchronizer retries to send sync requests. Denominated in ms.
     pub sync_retry_delay: u64,
+    /// The factor by which the sync retry delay grows after every round of retries, until a
+    /// missing batch is recovered. It must be greater than 1; if unset, the delay stays fixed.
+    pub sync_retry_backoff_factor: Option<f64>,
+    /// The maximum delay between two rounds of sync retries. Denominated in ms.
+    pub sync_retry_max_delay: u64,
     /// Determine with how many nodes to sync when re-trying to send sync-request. These nodes
     /// are picked at random from the committee.
     pub sync_retry_nodes: usize,
+    /// The time constant with which the record of how well each node answers our sync requests
+    /// decays. Denominated in ms.
+    pub sync_peer_score_window: u64,
     /// The preferred batch size. The workers seal a batch of transactions when it reaches this size.
     /// Denominated in bytes.
     pub batch_size: usize,
     /// The delay after which the workers seal a batch of transactions, even if `max_batch_size`
     /// is not reached. Denominated in ms.
     pub max_batch_delay: u64,
+    /// The size above which messages from other mempools are rejected. It is never lower than
+    /// twice `batch_size`. Denominated in bytes.
+    pub max_message_bytes: usize,
+    /// The size above which client transactions are dropped before reaching the batch maker.
+    /// Denominated in bytes.
+    pub max_transaction_bytes: usize,
+    /// The number of recently seen transactions remembered to drop duplicates before they are
+    /// added to a batch. Zero disables deduplication.
+    pub dedup_cache_size: usize,
+    /// The maximum number of sealed batches waiting for a quorum of acknowledgements. Once reached,
+    /// no more transactions are pulled until some batches are done. Zero disables the cap.
+    pub max_inflight_batches: usize,
+    /// The delay after which the quorum waiter gives up on a batch that did not gather enough
+    /// acknowledgements. The batch is then dropped. Denominated in ms.
+    pub quorum_timeout: u64,
+    /// Replaces the stake our batches must gather (2f+1 by default) before they are delivered to
+    /// the consensus, e.g. to run a single node on a test network. This weakens the guarantee
+    /// that the other mempools hold our batches, so it is never set by default and every batch
+    /// is delivered with a warning.
+    pub quorum_threshold_override: Option<Stake>,
+    /// The maximum number of batch requests served to a single authority in a burst.
+    pub batch_request_bucket_size: u32,
+    /// The number of batch requests per second an authority may send once its burst is used up.
+    pub batch_request_refill_rate: u32,
+    /// The maximum number of batches sent in reply to a single batch request. The digests left
+    /// over are not answered, the requestor asks for them again. Zero disables the limit.
+    pub max_request_reply_batches: usize,
+    /// The maximum size of the batches sent in reply to a single batch request, so that a request
+    /// for many large batches does not load them all in memory at once. At least one batch is
+    /// always sent. Zero disables the limit. Denominated in bytes.
+    pub max_request_reply_bytes: usize,
+    /// The number of batch writes the processor groups before flushing them to the store. The
+    /// digests are only forwarded to the consensus once their batches are stored.
+    pub store_batch_size: usize,
+    /// The delay after which the processor flushes its pending writes, even if `store_batch_size`
+    /// is not reached. Denominated in ms.
+    pub max_store_delay: u64,
+    /// The number of times the processors retry to forward the digest of a stored batch to the
+    /// consensus, while its channel is full, before dropping it. By default there is no limit:
+    /// the processors warn and keep waiting, since a dropped digest is a batch the consensus never
+    /// orders.
+    pub max_forward_retries: Option<u32>,
+    /// How long the processors wait for room in the consensus channel before retrying to forward
+    /// a digest, doubled after every further attempt. Denominated in ms.
+    pub forward_retry_delay: u64,
+    /// Whether to sign the acknowledgements of the batches we accept, so that their senders can
+    /// prove we received them.
+    pub signed_acks: bool,
+    /// Whether to sign the batches we broadcast, and to reject the batches of the other mempools
+    /// that are not signed by their originator, or whose originator is not a committee member.
+    /// All mempools of the committee must agree on it.
+    pub signed_batches: bool,
+    /// Whether to compress the batches we broadcast. Compressed batches are always accepted, so
+    /// this can be enabled once every mempool of the committee runs a version that understands them.
+    pub compression: bool,
+    /// The number of batches from other mempools waiting to be stored above which new ones are
+    /// turned away, so that we do not acknowledge batches we cannot promptly store. Zero disables
+    /// the limit.
+    pub processor_high_water_mark: usize,
+    /// The number of batches waiting to be stored below which batches from other mempools are
+    /// accepted again once the high water mark was reached.
+    pub processor_low_water_mark: usize,
+    /// Whether to forward the digests of our own batches and of the other mempools' batches to
+    /// the consensus through a single task that logs them in order, so that their order can be
+    /// replayed from the logs.
+    pub ordered_digests: bool,
+    /// The format in which mempool messages are serialized. All mempools of the committee must
+    /// use the same one.
+    pub codec: Codec,
+    /// The capacity of the channel feeding our clients' transactions to the `BatchMaker`. Zero
+    /// falls back to `CHANNEL_CAPACITY`, like the other channel capacities.
+    pub batch_maker_channel_capacity: usize,
+    /// The capacity of the channel feeding our sealed batches to the `QuorumWaiter`.
+    pub quorum_waiter_channel_capacity: usize,
+    /// The capacity of the channel feeding our acknowledged batches to their `Processor`.
+    pub processor_channel_capacity: usize,
+    /// The capacity of the channel feeding the batches of the other mempools to their `Processor`.
+    pub peer_processor_channel_capacity: usize,
+    /// The capacity of the channel feeding the batch requests of the other mempools to the
+    /// `Helper`.
+    pub helper_channel_capacity: usize,
+    /// The socket options of the mempool connections, both the ones we open and the ones we
+    /// accept. By default only Nagle's algorithm is turned off, so that the small acknowledgements
+    /// are not delayed; keepalive and the buffer sizes are left to the system.
+    pub tcp: TcpSettings,
 }
 
 impl Default for Parameters {
@@ -26,28 +128,123 @@ impl Default for Parameters {
         Self {
             gc_depth: 50,
             sync_retry_delay: 5_000,
+            sync_retry_backoff_factor: None,
+            sync_retry_max_delay: 60_000,
             sync_retry_nodes: 3,
+            sync_peer_score_window: 300_000,
             batch_size: 500_000,
             max_batch_delay: 100,
             // max_batch_delay: 300,
+            max_message_bytes: 2_000_000,
+            max_transaction_bytes: 100_000,
+            dedup_cache_size: 0,
+            max_inflight_batches: 0,
+            quorum_timeout: 12_000,
+            quorum_threshold_override: None,
+            batch_request_bucket_size: 50,
+            batch_request_refill_rate: 10,
+            max_request_reply_batches: 1_000,
+            max_request_reply_bytes: 50_000_000,
+            store_batch_size: 1,
+            max_store_delay: 10,
+            max_forward_retries: None,
+            forward_retry_delay: 100,
+            signed_acks: false,
+            signed_batches: false,
+            compression: false,
+            processor_high_water_mark: 800,
+            processor_low_water_mark: 400,
+            ordered_digests: false,
+            codec: Codec::default(),
+            batch_maker_channel_capacity: CHANNEL_CAPACITY,
+            quorum_waiter_channel_capacity: CHANNEL_CAPACITY,
+            processor_channel_capacity: CHANNEL_CAPACITY,
+            peer_processor_channel_capacity: CHANNEL_CAPACITY,
+            helper_channel_capacity: CHANNEL_CAPACITY,
+            tcp: TcpSettings::default(),
         }
     }
 }
 
 impl Parameters {
+    /// The size above which messages from other mempools are rejected. A batch may exceed
+    /// `batch_size` by one transaction, so the limit leaves plenty of room above it.
+    pub fn max_message_bytes(&self) -> usize {
+        self.max_message_bytes.max(2 * self.batch_size)
+    }
+
+    /// The capacity to give a channel configured with `capacity`: a channel cannot be empty, so
+    /// zero falls back to `CHANNEL_CAPACITY`.
+    pub fn channel_capacity(capacity: usize) -> usize {
+        match capacity {
+            0 => CHANNEL_CAPACITY,
+            capacity => capacity,
+        }
+    }
+
+    /// Rejects the settings the mempool cannot run with.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(factor) = self.sync_retry_backoff_factor {
+            // A factor of at most 1 would keep the delay fixed or shrink it; NaN fails this too.
+            if !(factor > 1.0 && factor.is_finite()) {
+                return Err(format!(
+                    "sync_retry_backoff_factor must be a finite number greater than 1, got {}",
+                    factor
+                ));
+            }
+        }
+        Ok(())
+    }
+
     pub fn log(&self) {
         // NOTE: These log entries are used to compute performance.
         info!("Garbage collection depth set to {} rounds", self.gc_depth);
         info!("Sync retry delay set to {} ms", self.sync_retry_delay);
+        info!("Sync retry backoff factor set to {:?}", self.sync_retry_backoff_factor);
+        info!("Sync retry max delay set to {} ms", self.sync_retry_max_delay);
         info!("Sync retry nodes set to {} nodes", self.sync_retry_nodes);
+        info!("Sync peer score window set to {} ms", self.sync_peer_score_window);
         info!("Batch size set to {} B", self.batch_size);
         info!("Max batch delay set to {} ms", self.max_batch_delay);
+        info!("Max message size set to {} B", self.max_message_bytes());
+        info!("Max transaction size set to {} B", self.max_transaction_bytes);
+        info!("Dedup cache size set to {} transactions", self.dedup_cache_size);
+        info!("Max in-flight batches set to {} batches", self.max_inflight_batches);
+        info!("Quorum timeout set to {} ms", self.quorum_timeout);
+        info!("Quorum threshold override set to {:?}", self.quorum_threshold_override);
+        info!("Batch request bucket size set to {} requests", self.batch_request_bucket_size);
+        info!("Batch request refill rate set to {} requests/s", self.batch_request_refill_rate);
+        info!("Max request reply batches set to {} batches", self.max_request_reply_batches);
+        info!("Max request reply size set to {} B", self.max_request_reply_bytes);
+        info!("Store batch size set to {} batches", self.store_batch_size);
+        info!("Max store delay set to {} ms", self.max_store_delay);
+        info!("Max forward retries set to {:?}", self.max_forward_retries);
+        info!("Forward retry delay set to {} ms", self.forward_retry_delay);
+        info!("Signed acks set to {}", self.signed_acks);
+        info!("Signed batches set to {}", self.signed_batches);
+        info!("Compression set to {}", self.compression);
+        info!("Processor high water mark set to {} batches", self.processor_high_water_mark);
+        info!("Processor low water mark set to {} batches", self.processor_low_water_mark);
+        info!("Ordered digests set to {}", self.ordered_digests);
+        info!("Codec set to {:?}", self.codec);
+        info!("Batch maker channel capacity set to {}", Self::channel_capacity(self.batch_maker_channel_capacity));
+        info!("Quorum waiter channel capacity set to {}", Self::channel_capacity(self.quorum_waiter_channel_capacity));
+        info!("Processor channel capacity set to {}", Self::channel_capacity(self.processor_channel_capacity));
+        info!("Peer processor channel capacity set to {}", Self::channel_capacity(self.peer_processor_channel_capacity));
+        info!("Helper channel capacity set to {}", Self::channel_capacity(self.helper_channel_capacity));
+        info!("TCP nodelay set to {}", self.tcp.nodelay);
+        info!("TCP keepalive set to {} s", self.tcp.keepalive_secs);
+        info!("TCP send buffer size set to {} B", self.tcp.send_buffer_bytes);
+        info!("TCP receive buffer size set to {} B", self.tcp.recv_buffer_bytes);
     }
 }
 
 pub type EpochNumber = u128;
 pub type Stake = u32;
 
+/// The committee shared by the mempool tasks, so that it can be reloaded without restarting them.
+pub type SharedCommittee = Arc<RwLock<Committee>>;
+
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Authority {
     /// The voting power of this authority.
@@ -90,12 +287,16 @@ impl Committee {
         self.authorities.get(name).map_or_else(|| 0, |x| x.stake)
     }
 
+    /// Returns the sum of the stakes of all authorities.
+    pub fn total_stake(&self) -> Stake {
+        self.authorities.values().map(|x| x.stake).sum()
+    }
+
     /// Returns the stake required to reach a quorum (2f+1).
     pub fn quorum_threshold(&self) -> Stake {
         // If N = 3f + 1 + k (0 <= k < 3)
         // then (2 N + 3) / 3 = 2f + 1 + (2k + 2)/3 = 2f + 1 + k = N - f
-        let total_votes: Stake = self.authorities.values().map(|x| x.stake).sum();
-        2 * total_votes / 3 + 1
+        2 * self.total_stake() / 3 + 1
     }
 
     /// Returns the address to receive client transactions.
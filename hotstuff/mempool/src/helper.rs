@@ -1,35 +1,93 @@
-use crate::config::Committee;
+use crate::config::SharedCommittee;
+use crate::metrics;
 use bytes::Bytes;
 use crypto::{Digest, PublicKey};
 use log::{info, error, warn, debug};
-use network::{SimpleSender, DvfMessage, VERSION};
+use network::{SimpleSender, DvfMessage, TcpSettings, VERSION};
+use std::collections::HashMap;
 use store::Store;
 use tokio::sync::mpsc::Receiver;
+use tokio::time::Instant;
 
 #[cfg(test)]
 #[path = "tests/helper_tests.rs"]
 pub mod helper_tests;
 
+/// A token bucket limiting how many batch requests we serve to a single authority.
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(size: u32) -> Self {
+        Self {
+            tokens: size as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Refill the bucket for the elapsed time and take a token if one is available.
+    fn try_take(&mut self, size: u32, refill_rate: u32) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * refill_rate as f64).min(size as f64);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The settings of a `Helper`.
+#[derive(Clone)]
+pub struct HelperParams {
+    /// The maximum number of batch requests served to a single authority in a burst.
+    pub batch_request_bucket_size: u32,
+    /// The number of batch requests per second an authority regains.
+    pub batch_request_refill_rate: u32,
+    /// The maximum number of batches served in reply to a single request (0 for no limit).
+    pub max_reply_batches: usize,
+    /// The maximum number of batch bytes served in reply to a single request (0 for no limit).
+    pub max_reply_bytes: usize,
+    /// The TCP settings of the connections to the other mempools.
+    pub tcp_settings: TcpSettings,
+    pub validator_id: u64,
+}
+
 /// A task dedicated to help other authorities by replying to their batch requests.
 pub struct Helper {
-    /// The committee information.
-    committee: Committee,
+    /// The committee information, which may be reloaded at runtime.
+    committee: SharedCommittee,
     /// The persistent storage.
     store: Store,
     /// Input channel to receive batch requests.
     rx_request: Receiver<(Vec<Digest>, PublicKey)>,
     /// A network sender to send the batches to the other mempools.
     network: SimpleSender,
+    /// The maximum number of batch requests served to a single authority in a burst.
+    batch_request_bucket_size: u32,
+    /// The number of batch requests per second an authority regains.
+    batch_request_refill_rate: u32,
+    /// The maximum number of batches served in reply to a single request (0 for no limit).
+    max_reply_batches: usize,
+    /// The maximum number of batch bytes served in reply to a single request (0 for no limit).
+    max_reply_bytes: usize,
+    /// The rate limiting state of each authority that sent us batch requests.
+    buckets: HashMap<PublicKey, TokenBucket>,
     validator_id: u64,
     exit: exit_future::Exit
 }
 
 impl Helper {
     pub fn spawn(
-        committee: Committee,
+        committee: SharedCommittee,
         store: Store,
         rx_request: Receiver<(Vec<Digest>, PublicKey)>,
-        validator_id: u64,
+        params: HelperParams,
         exit: exit_future::Exit
     ) {
         tokio::spawn(async move {
@@ -37,9 +95,14 @@ impl Helper {
                 committee,
                 store,
                 rx_request,
-                network: SimpleSender::new(),
-                validator_id: validator_id,
-                exit: exit
+                network: SimpleSender::with_tcp_settings(params.tcp_settings),
+                batch_request_bucket_size: params.batch_request_bucket_size,
+                batch_request_refill_rate: params.batch_request_refill_rate,
+                max_reply_batches: params.max_reply_batches,
+                max_reply_bytes: params.max_reply_bytes,
+                buckets: HashMap::new(),
+                validator_id: params.validator_id,
+                exit,
             }
             .run()
             .await;
@@ -51,10 +114,10 @@ impl Helper {
             let exit = self.exit.clone();
             tokio::select! {
                 Some((digests, origin)) = self.rx_request.recv() => {
-                    // TODO [issue #7]: Do some accounting to prevent bad nodes from monopolizing our resources.
-
-                    // get the requestors address.
-                    let address = match self.committee.mempool_address(&origin) {
+                    // Only serve members of the committee, and get the requestors address. This is
+                    // checked first so that unknown keys do not get a rate limiting bucket. Requests
+                    // are checked against the current committee, so removed members stop being served.
+                    let address = match self.committee.read().unwrap().mempool_address(&origin) {
                         Some(x) => x,
                         None => {
                             warn!("Received batch request from unknown authority: {}", origin);
@@ -62,19 +125,66 @@ impl Helper {
                         }
                     };
 
-                    // Reply to the request (the best we can).
+                    // Prevent bad nodes from monopolizing our resources.
+                    let size = self.batch_request_bucket_size;
+                    let refill_rate = self.batch_request_refill_rate;
+                    let allowed = self.buckets
+                        .entry(origin)
+                        .or_insert_with(|| TokenBucket::new(size))
+                        .try_take(size, refill_rate);
+                    if !allowed {
+                        warn!("Dropping batch request from {}: rate limit exceeded", origin);
+                        metrics::inc_counter_vec(&metrics::BATCH_REQUESTS_DROPPED, &[&self.validator_id.to_string()]);
+                        continue;
+                    }
+
+                    // Reply to the request (the best we can). The reply is cut short once it
+                    // reaches the limits, the requestor asks again for the batches it still misses.
+                    let requested = digests.len();
+                    let mut looked_up = 0;
+                    let mut found = 0;
+                    let mut reply_bytes = 0;
                     for digest in digests {
+                        if self.max_reply_batches > 0 && found >= self.max_reply_batches {
+                            break;
+                        }
+                        looked_up += 1;
                         match self.store.read(digest.to_vec()).await {
                             Ok(Some(data)) => {
+                                // A single batch is always served, however large.
+                                reply_bytes += data.len();
+                                if self.max_reply_bytes > 0 && found > 0 && reply_bytes > self.max_reply_bytes {
+                                    looked_up -= 1;
+                                    break;
+                                }
                                 let dvf_message = DvfMessage { version: VERSION, validator_id: self.validator_id, message: data};
                                 let serialized_msg = bincode::serialize(&dvf_message).unwrap();
                                 debug!("[MemHELPER] Sending to {:?}", address);
-                                self.network.feed(address, Bytes::from(serialized_msg)).await
+                                self.network.feed(address, Bytes::from(serialized_msg)).await;
+                                metrics::inc_counter_vec(&metrics::BATCH_REQUEST_REPLIES, &[&self.validator_id.to_string()]);
+                                found += 1;
                             },
-                            Ok(None) => (),
+                            Ok(None) => debug!("Requested batch {} is not in the store", digest),
                             Err(e) => error!("{:?}", e),
                         }
                     }
+                    debug!("Serving batch request from {}: found {}/{} batches", origin, found, requested);
+                    if found < looked_up {
+                        metrics::inc_counter_vec_by(
+                            &metrics::BATCH_REQUEST_MISSES,
+                            &[&self.validator_id.to_string()],
+                            (looked_up - found) as u64,
+                        );
+                    }
+                    if looked_up < requested {
+                        warn!(
+                            "Truncated batch request from {}: served {} batches, {} digests left unanswered",
+                            origin,
+                            found,
+                            requested - looked_up
+                        );
+                        metrics::inc_counter_vec(&metrics::BATCH_REQUESTS_TRUNCATED, &[&self.validator_id.to_string()]);
+                    }
                     self.network.flush(address).await;
                 },
                 () = exit => {
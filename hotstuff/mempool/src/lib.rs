@@ -1,15 +1,26 @@
+mod batch_index;
 mod batch_maker;
+mod codec;
+mod compression;
 mod config;
 mod helper;
 mod mempool;
+pub mod metrics;
+mod nonce_tracker;
 mod processor;
 mod quorum_waiter;
+mod sequencer;
 mod synchronizer;
 
 #[cfg(test)]
 #[path = "tests/common.rs"]
 mod common;
 
-pub use crate::config::{Committee, Parameters};
-pub use crate::mempool::{ConsensusMempoolMessage, Mempool, MempoolMessage, TxReceiverHandler, MempoolReceiverHandler};
-pub use crate::batch_maker::{Batch, Transaction};
+pub use crate::batch_index::RoundCommits;
+pub use crate::config::{Committee, Parameters, SharedCommittee};
+pub use crate::mempool::{ConsensusMempoolMessage, Mempool, MempoolAck, MempoolStats, MempoolMessage, TxReceiverHandler, MempoolReceiverHandler};
+pub use crate::batch_maker::{
+    Batch, BatchDecision, BatchPolicy, IngressTransaction, Transaction, TransactionValidator,
+};
+pub use crate::codec::{decode_message, encode_message, Codec};
+pub use crate::nonce_tracker::NonceExtractor;
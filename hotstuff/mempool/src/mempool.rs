@@ -1,22 +1,30 @@
-use crate::batch_maker::{Batch, BatchMaker, Transaction};
-use crate::config::{Committee, Parameters};
-use crate::helper::Helper;
-use crate::processor::{Processor, SerializedBatchMessage};
-use crate::quorum_waiter::QuorumWaiter;
-use crate::synchronizer::Synchronizer;
+use crate::batch_index::{BatchIndex, RoundCommits};
+use crate::batch_maker::{
+    Batch, BatchMaker, BatchMakerParams, BatchPolicy, IngressTransaction, TransactionValidator,
+};
+use crate::codec::{decode_message, Codec};
+use crate::compression;
+use crate::config::{Committee, Parameters, SharedCommittee};
+use crate::helper::{Helper, HelperParams};
+use crate::metrics;
+use crate::nonce_tracker::{NonceExtractor, NonceTracker};
+use crate::processor::{digest, Processor, ProcessorLoad, ProcessorParams, SerializedBatchMessage};
+use crate::quorum_waiter::{QuorumWaiter, QuorumWaiterMessage, QuorumWaiterParams};
+use crate::sequencer::DigestSequencer;
+use crate::synchronizer::{MissingBatches, Synchronizer};
 use async_trait::async_trait;
 use bytes::Bytes;
-use crypto::{Digest, PublicKey};
+use crypto::{Digest, PublicKey, Signature, SignatureService};
 use futures::sink::SinkExt as _;
-use log::{info, warn};
+use log::{debug, info, warn};
 use network::{MessageHandler, Writer};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
-use store::Store;
+use store::{Store, StoreError};
 use std::sync::Arc;
 use tokio::sync::mpsc::{Receiver};
 use tokio::sync::RwLock;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use utils::monitored_channel::{MonitoredChannel, MonitoredSender};
 #[cfg(test)]
 #[path = "tests/mempool_tests.rs"]
@@ -33,6 +41,93 @@ pub type Round = u64;
 pub enum MempoolMessage {
     Batch(Batch),
     BatchRequest(Vec<Digest>, /* origin */ PublicKey),
+    /// A batch along with the signature of the mempool that produced it, so that its receivers can
+    /// attribute it. Sent instead of `Batch` if `signed_batches` is enabled.
+    SignedBatch(Batch, /* originator */ PublicKey, Signature),
+}
+
+impl MempoolMessage {
+    /// Wraps `batch` along with our signature as its originator. The signature covers the digest
+    /// of the batch serialized with bincode, whatever the codec of the message.
+    pub async fn signed_batch(
+        batch: Batch,
+        name: PublicKey,
+        signature_service: &mut SignatureService,
+    ) -> Self {
+        let digest = originator_digest(&batch);
+        let signature = signature_service.request_signature(digest).await;
+        MempoolMessage::SignedBatch(batch, name, signature)
+    }
+
+    /// Checks that this is a batch signed by the originator it claims, and that the originator is
+    /// a member of `committee`.
+    pub fn verify_originator(&self, committee: &Committee) -> bool {
+        match self {
+            MempoolMessage::SignedBatch(batch, originator, signature) => {
+                committee.authorities.contains_key(originator)
+                    && signature.verify(&originator_digest(batch), originator).is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The digest an originator signs for its batch.
+fn originator_digest(batch: &Batch) -> Digest {
+    digest(&bincode::serialize(batch).expect("Failed to serialize batch"))
+}
+
+/// The reply of the mempools that predate `MempoolAck`. It is too short to be an encoded
+/// `MempoolAck`, so it cannot be mistaken for one.
+pub const LEGACY_ACK: &[u8] = b"Ack";
+
+/// The reply sent back by a mempool for every message it receives from another mempool.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MempoolAck {
+    /// The batch was accepted and handed over for storage.
+    BatchAccepted,
+    /// The batch request was queued for the helper.
+    RequestQueued,
+    /// The message could not be handled.
+    Rejected(String),
+    /// The batch was accepted and handed over for storage. Carries the digest of the batch,
+    /// signed by the mempool that accepted it.
+    SignedBatchAccepted(Digest, Signature),
+    /// The batch was turned away because the mempool is too far behind storing batches.
+    Busy,
+}
+
+impl MempoolAck {
+    pub fn encode(&self) -> Bytes {
+        Bytes::from(bincode::serialize(self).expect("Failed to serialize mempool ack"))
+    }
+
+    /// Decodes the reply of a mempool, as received through a `CancelHandler`. Mempools that
+    /// predate `MempoolAck` reply with the raw bytes `LEGACY_ACK` to every batch they accept.
+    pub fn decode(bytes: &[u8]) -> Result<Self, bincode::Error> {
+        if bytes == LEGACY_ACK {
+            return Ok(MempoolAck::BatchAccepted);
+        }
+        bincode::deserialize(bytes)
+    }
+
+    /// Acknowledges the serialized `batch`, signing its digest with our key.
+    pub async fn signed(batch: &[u8], signature_service: &mut SignatureService) -> Self {
+        let digest = digest(batch);
+        let signature = signature_service.request_signature(digest.clone()).await;
+        MempoolAck::SignedBatchAccepted(digest, signature)
+    }
+
+    /// Checks that this is a signed acknowledgement of the serialized `batch` by `author`.
+    pub fn verify(&self, batch: &[u8], author: &PublicKey) -> bool {
+        match self {
+            MempoolAck::SignedBatchAccepted(ack_digest, signature) => {
+                *ack_digest == digest(batch)
+                    && Signature::verify_batch(ack_digest, &[(*author, signature.clone())]).is_ok()
+            }
+            _ => false,
+        }
+    }
 }
 
 /// The messages sent by the consensus and the mempool.
@@ -42,19 +137,110 @@ pub enum ConsensusMempoolMessage {
     Synchronize(Vec<Digest>, /* target */ PublicKey),
     /// The consensus notifies the mempool of a round update.
     Cleanup(Round),
+    /// The consensus notifies the mempool that it committed a block of this round carrying these
+    /// batches.
+    Committed(Round, Vec<Digest>),
+}
+
+/// A runtime view of the backlog of the mempool pipeline handling our clients' transactions. It
+/// also lets the committee be reloaded while the mempool runs.
+pub struct MempoolStats {
+    /// The transactions waiting for the `BatchMaker`.
+    tx_batch_maker: MonitoredSender<IngressTransaction>,
+    /// The sealed batches waiting for the `QuorumWaiter`.
+    tx_quorum_waiter: MonitoredSender<QuorumWaiterMessage>,
+    /// The acknowledged batches waiting for the `Processor`.
+    tx_processor: MonitoredSender<SerializedBatchMessage>,
+    /// The batches the `Synchronizer` is still trying to get.
+    missing_batches: MissingBatches,
+    /// The stored batches, and which of them the consensus committed.
+    batch_index: BatchIndex,
+    /// The committee read by the mempool tasks.
+    committee: SharedCommittee,
+    /// The persistent storage of the batches.
+    store: Store,
+}
+
+impl MempoolStats {
+    /// The number of transactions queued for the `BatchMaker`.
+    pub fn batch_maker_depth(&self) -> usize {
+        self.tx_batch_maker.queue_depth()
+    }
+
+    /// The number of batches queued for the `QuorumWaiter`.
+    pub fn quorum_waiter_depth(&self) -> usize {
+        self.tx_quorum_waiter.queue_depth()
+    }
+
+    /// The number of batches queued for the `Processor`.
+    pub fn processor_depth(&self) -> usize {
+        self.tx_processor.queue_depth()
+    }
+
+    /// The digests of the batches we are still syncing from the other mempools.
+    pub fn missing_batches(&self) -> HashSet<Digest> {
+        self.missing_batches.snapshot()
+    }
+
+    /// How many of the batches stored during each round the consensus committed so far. Batches
+    /// that stay uncommitted long after their round point at a liveness issue.
+    pub fn commit_breakdown(&self) -> Vec<RoundCommits> {
+        self.batch_index.commit_breakdown()
+    }
+
+    /// Waits until the batches handed over to the store so far are durable, e.g. before a backup
+    /// or a controlled shutdown. Does not depend on the exit signal. The batches still queued for
+    /// the processors (see `processor_depth`) are not written yet, so they are not covered.
+    pub async fn flush(&self) -> Result<(), StoreError> {
+        self.store.flush().await
+    }
+
+    /// Replaces the committee, e.g. when operators join or leave the validator set. The new
+    /// committee applies to:
+    /// - the batches reaching the `QuorumWaiter` afterwards, whose acknowledgements are weighed
+    ///   (and whose quorum threshold is computed) with the new stakes;
+    /// - the batch requests the `Helper` receives afterwards, which are only served to members;
+    /// - the sync requests and retries the `Synchronizer` sends afterwards;
+    /// - the batches the `BatchMaker` seals afterwards, which are broadcast to the new members.
+    ///
+    /// The batches already waiting for a quorum keep the old committee. Our own stake is fixed
+    /// when the mempool is spawned, so a change of our own membership still needs a restart.
+    pub fn reload_committee(&self, new: Committee) {
+        info!(
+            "Reloading the mempool committee: {} authorities (epoch {})",
+            new.authorities.len(),
+            new.epoch
+        );
+        *self.committee.write().unwrap() = new;
+    }
 }
 
 pub struct Mempool {
     /// The public key of this authority.
     name: PublicKey,
-    /// The committee information.
-    committee: Committee,
+    /// The committee information, shared with the tasks reading it at runtime.
+    committee: SharedCommittee,
     /// The configuration parameters.
     parameters: Parameters,
+    /// Signs the acknowledgements of the batches we accept, if `signed_acks` is enabled, and our
+    /// own batches, if `signed_batches` is.
+    signature_service: SignatureService,
     /// The persistent storage.
     store: Store,
-    /// Send messages to consensus.
-    tx_consensus: MonitoredSender<Digest>,
+    /// Send the digests of our own batches to consensus.
+    tx_own_digests: MonitoredSender<Digest>,
+    /// Send the digests of the other mempools' batches to consensus.
+    tx_peer_digests: MonitoredSender<Digest>,
+    /// The rounds in which our batches were stored, shared by the tasks that follow the consensus.
+    batch_index: BatchIndex,
+    /// The batches the `Synchronizer` is still trying to get, shared with the stats.
+    missing_batches: MissingBatches,
+    /// Drops replayed client transactions, if the transactions carry a nonce.
+    nonce_tracker: Option<NonceTracker>,
+    /// Inspects our batches before they are broadcast, if set.
+    batch_policy: Option<BatchPolicy>,
+    /// Checks our clients' transactions before they are added to a batch, if set.
+    transaction_validator: Option<TransactionValidator>,
     /// Validator id.
     validator_id: u64,
     /// Exit 
@@ -66,24 +252,48 @@ impl Mempool {
         name: PublicKey,
         committee: Committee,
         parameters: Parameters,
+        signature_service: SignatureService,
         store: Store,
         rx_consensus: Receiver<ConsensusMempoolMessage>,
         tx_consensus: MonitoredSender<Digest>,
+        nonce_extractor: Option<NonceExtractor>,
+        batch_policy: Option<BatchPolicy>,
+        transaction_validator: Option<TransactionValidator>,
         validator_id: u64,
         tx_handler_map : Arc<RwLock<HashMap<u64, TxReceiverHandler>>>,
         mempool_handler_map: Arc<RwLock<HashMap<u64, MempoolReceiverHandler>>>,
         exit: exit_future::Exit
-    ) {
+    ) -> Arc<MempoolStats> {
+        parameters.validate().expect("Invalid mempool parameters");
         // NOTE: This log entry is used to compute performance.
         parameters.log();
 
+        // Both processors forward their digests straight to the consensus, unless they have to go
+        // through the `DigestSequencer` first.
+        let (tx_own_digests, tx_peer_digests) = match parameters.ordered_digests {
+            true => {
+                let (tx_own, rx_own) = MonitoredChannel::new(CHANNEL_CAPACITY, format!("{}-mempool-own-digests", validator_id), "info");
+                let (tx_peers, rx_peers) = MonitoredChannel::new(CHANNEL_CAPACITY, format!("{}-mempool-peer-digests", validator_id), "info");
+                DigestSequencer::spawn(rx_own, rx_peers, tx_consensus, validator_id, exit.clone());
+                (tx_own, tx_peers)
+            }
+            false => (tx_consensus.clone(), tx_consensus),
+        };
+
         // Define a mempool instance.
         let mempool = Self {
             name,
-            committee,
+            committee: Arc::new(std::sync::RwLock::new(committee)),
             parameters,
+            signature_service,
             store,
-            tx_consensus,
+            tx_own_digests,
+            tx_peer_digests,
+            batch_index: BatchIndex::new(),
+            missing_batches: MissingBatches::default(),
+            nonce_tracker: nonce_extractor.map(NonceTracker::new),
+            batch_policy,
+            transaction_validator,
             validator_id, 
             exit
         };
@@ -91,17 +301,20 @@ impl Mempool {
         // Spawn all mempool tasks.
         mempool.handle_consensus_messages(rx_consensus);
         
-        mempool.handle_clients_transactions(Arc::clone(&tx_handler_map)).await;
+        let stats = mempool.handle_clients_transactions(Arc::clone(&tx_handler_map)).await;
         mempool.handle_mempool_messages(Arc::clone(&mempool_handler_map)).await;
 
         info!(
             "Mempool successfully booted on {}",
             mempool
                 .committee
+                .read()
+                .unwrap()
                 .mempool_address(&mempool.name)
                 .expect("Our public key is not in the committee")
                 .ip()
         );
+        Arc::new(stats)
     }
 
     /// Spawn all tasks responsible to handle messages from the consensus.
@@ -114,7 +327,14 @@ impl Mempool {
             self.store.clone(),
             self.parameters.gc_depth,
             self.parameters.sync_retry_delay,
+            self.parameters.sync_retry_backoff_factor.unwrap_or(1.0),
+            self.parameters.sync_retry_max_delay,
             self.parameters.sync_retry_nodes,
+            self.parameters.sync_peer_score_window,
+            self.batch_index.clone(),
+            self.missing_batches.clone(),
+            self.parameters.codec,
+            self.parameters.tcp,
             /* rx_message */ rx_consensus,
             self.validator_id,
             self.exit.clone()
@@ -122,64 +342,135 @@ impl Mempool {
     }
 
     /// Spawn all tasks responsible to handle clients transactions.
-    async fn handle_clients_transactions(&self, tx_handler_map: Arc<RwLock<HashMap<u64, TxReceiverHandler>>>) {
+    async fn handle_clients_transactions(&self, tx_handler_map: Arc<RwLock<HashMap<u64, TxReceiverHandler>>>) -> MempoolStats {
 
-        let (tx_batch_maker, rx_batch_maker) = MonitoredChannel::new(CHANNEL_CAPACITY, format!("{}-mempool-tx-batch-maker", self.validator_id), "info");
-        let (tx_quorum_waiter, rx_quorum_waiter) = MonitoredChannel::new(CHANNEL_CAPACITY, format!("{}-mempool-tx-quorum-waiter", self.validator_id), "info");
-        let (tx_processor, rx_processor) = MonitoredChannel::new(CHANNEL_CAPACITY, format!("{}-mempool-tx-processor", self.validator_id), "info");
+        let (tx_batch_maker, rx_batch_maker) = MonitoredChannel::new(Parameters::channel_capacity(self.parameters.batch_maker_channel_capacity), format!("{}-mempool-tx-batch-maker", self.validator_id), "info");
+        let (tx_quorum_waiter, rx_quorum_waiter) = MonitoredChannel::new(Parameters::channel_capacity(self.parameters.quorum_waiter_channel_capacity), format!("{}-mempool-tx-quorum-waiter", self.validator_id), "info");
+        let (tx_processor, rx_processor) = MonitoredChannel::new(Parameters::channel_capacity(self.parameters.processor_channel_capacity), format!("{}-mempool-tx-processor", self.validator_id), "info");
 
         {
             tx_handler_map
                 .write()
                 .await
-                .insert(self.validator_id.clone(), TxReceiverHandler{tx_batch_maker});
+                .insert(self.validator_id, TxReceiverHandler{
+                    tx_batch_maker: tx_batch_maker.clone(),
+                    max_transaction_bytes: self.parameters.max_transaction_bytes,
+                    nonce_tracker: self.nonce_tracker.clone(),
+                    validator_id: self.validator_id,
+                });
             info!("Insert transaction handler for validator: {}", self.validator_id);
         }
+        let stats = MempoolStats {
+            tx_batch_maker,
+            tx_quorum_waiter: tx_quorum_waiter.clone(),
+            tx_processor: tx_processor.clone(),
+            missing_batches: self.missing_batches.clone(),
+            batch_index: self.batch_index.clone(),
+            committee: self.committee.clone(),
+            store: self.store.clone(),
+        };
+
+        // On exit, the pipeline drains in order: the `BatchMaker` seals its partial batch, then the
+        // `QuorumWaiter` processes the batches queued behind it, and only then does the `Processor`
+        // store the remaining batches and terminate. Each stage signals the next once drained. The
+        // `BatchMaker` keeps its network sender alive until the `QuorumWaiter` is drained.
+        let (batch_maker_drained, quorum_waiter_exit) = exit_future::signal();
+        let (quorum_waiter_drained, processor_exit) = exit_future::signal();
 
         // The transactions are sent to the `BatchMaker` that assembles them into batches. It then broadcasts
         // (in a reliable manner) the batches to all other mempools that share the same `id` as us. Finally,
         // it gathers the 'cancel handlers' of the messages and send them to the `QuorumWaiter`.
         BatchMaker::spawn(
-            self.parameters.batch_size,
-            self.parameters.max_batch_delay,
+            BatchMakerParams {
+                batch_size: self.parameters.batch_size,
+                max_batch_delay: self.parameters.max_batch_delay,
+                dedup_cache_size: self.parameters.dedup_cache_size,
+                max_inflight_batches: self.parameters.max_inflight_batches,
+                compression: self.parameters.compression,
+                codec: self.parameters.codec,
+                policy: self.batch_policy.clone(),
+                validator: self.transaction_validator.clone(),
+                signer: self
+                    .parameters
+                    .signed_batches
+                    .then(|| (self.name, self.signature_service.clone())),
+                tcp_settings: self.parameters.tcp,
+                name: self.name,
+                committee: self.committee.clone(),
+                validator_id: self.validator_id,
+            },
             /* rx_transaction */ rx_batch_maker,
             /* tx_message */ tx_quorum_waiter,
-            /* mempool_addresses */
-            self.committee.broadcast_addresses(&self.name),
-            self.validator_id,
-            self.exit.clone()
+            self.exit.clone(),
+            batch_maker_drained,
+            processor_exit.clone(),
         );
 
         // The `QuorumWaiter` waits for 2f authorities to acknowledge reception of the batch. It then forwards
         // the batch to the `Processor`.
         QuorumWaiter::spawn(
             self.committee.clone(),
-            /* stake */ self.committee.stake(&self.name),
+            /* stake */ self.committee.read().unwrap().stake(&self.name),
+            QuorumWaiterParams {
+                quorum_timeout: self.parameters.quorum_timeout,
+                quorum_threshold_override: self.parameters.quorum_threshold_override,
+                gc_depth: self.parameters.gc_depth,
+                batch_index: self.batch_index.clone(),
+            },
             /* rx_message */ rx_quorum_waiter,
             /* tx_batch */ tx_processor,
-            self.exit.clone()
+            quorum_waiter_exit,
+            quorum_waiter_drained,
         );
 
         // The `Processor` hashes and stores the batch. It then forwards the batch's digest to the consensus.
         Processor::spawn(
             self.store.clone(),
             /* rx_batch */ rx_processor,
-            /* tx_digest */ self.tx_consensus.clone(),
-            self.exit.clone()
+            /* tx_digest */ self.tx_own_digests.clone(),
+            ProcessorParams {
+                validator_id: self.validator_id,
+                from_peers: false,
+                codec: self.parameters.codec,
+                signed_batches: false,
+                committee: self.committee.clone(),
+                store_batch_size: self.parameters.store_batch_size,
+                max_store_delay: self.parameters.max_store_delay,
+                max_forward_retries: self.parameters.max_forward_retries,
+                forward_retry_delay: self.parameters.forward_retry_delay,
+                batch_index: self.batch_index.clone(),
+                load: None,
+            },
+            processor_exit
         );
+        stats
     }
 
     /// Spawn all tasks responsible to handle messages from other mempools.
     async fn handle_mempool_messages(&self, mempool_handler_map: Arc<RwLock<HashMap<u64, MempoolReceiverHandler>>>) {
 
-        let (tx_helper, rx_helper) = MonitoredChannel::new(CHANNEL_CAPACITY, format!("{}-mempool-helper", self.validator_id), "info");
-        let (tx_processor, rx_processor) = MonitoredChannel::new(CHANNEL_CAPACITY, format!("{}-mempool-processor", self.validator_id), "info");
+        let (tx_helper, rx_helper) = MonitoredChannel::new(Parameters::channel_capacity(self.parameters.helper_channel_capacity), format!("{}-mempool-helper", self.validator_id), "info");
+        let (tx_processor, rx_processor) = MonitoredChannel::new(Parameters::channel_capacity(self.parameters.peer_processor_channel_capacity), format!("{}-mempool-processor", self.validator_id), "info");
+        let processor_load = ProcessorLoad::new(
+            self.parameters.processor_high_water_mark,
+            self.parameters.processor_low_water_mark,
+        );
 
         {
             mempool_handler_map
                 .write()
                 .await
-                .insert(self.validator_id.clone(), MempoolReceiverHandler{tx_helper, tx_processor});
+                .insert(self.validator_id, MempoolReceiverHandler{
+                    tx_helper,
+                    tx_processor,
+                    max_message_bytes: self.parameters.max_message_bytes() as u64,
+                    signature_service: self
+                        .parameters
+                        .signed_acks
+                        .then(|| self.signature_service.clone()),
+                    processor_load: processor_load.clone(),
+                    codec: self.parameters.codec,
+                });
             info!("Insert mempool handler for validator: {}", self.validator_id);
         }
 
@@ -188,7 +479,14 @@ impl Mempool {
             self.committee.clone(),
             self.store.clone(),
             /* rx_request */ rx_helper,
-            self.validator_id,
+            HelperParams {
+                batch_request_bucket_size: self.parameters.batch_request_bucket_size,
+                batch_request_refill_rate: self.parameters.batch_request_refill_rate,
+                max_reply_batches: self.parameters.max_request_reply_batches,
+                max_reply_bytes: self.parameters.max_request_reply_bytes,
+                tcp_settings: self.parameters.tcp,
+                validator_id: self.validator_id,
+            },
             self.exit.clone()
         );
 
@@ -197,7 +495,20 @@ impl Mempool {
         Processor::spawn(
             self.store.clone(),
             /* rx_batch */ rx_processor,
-            /* tx_digest */ self.tx_consensus.clone(),
+            /* tx_digest */ self.tx_peer_digests.clone(),
+            ProcessorParams {
+                validator_id: self.validator_id,
+                from_peers: true,
+                codec: self.parameters.codec,
+                signed_batches: self.parameters.signed_batches,
+                committee: self.committee.clone(),
+                store_batch_size: self.parameters.store_batch_size,
+                max_store_delay: self.parameters.max_store_delay,
+                max_forward_retries: self.parameters.max_forward_retries,
+                forward_retry_delay: self.parameters.forward_retry_delay,
+                batch_index: self.batch_index.clone(),
+                load: Some(processor_load),
+            },
             self.exit.clone()
         );
     }
@@ -206,17 +517,45 @@ impl Mempool {
 /// Defines how the network receiver handles incoming transactions.
 #[derive(Clone)]
 pub struct TxReceiverHandler {
-    tx_batch_maker: MonitoredSender<Transaction>,
+    tx_batch_maker: MonitoredSender<IngressTransaction>,
+    max_transaction_bytes: usize,
+    nonce_tracker: Option<NonceTracker>,
+    validator_id: u64,
 }
 
 #[async_trait]
 impl MessageHandler for TxReceiverHandler {
-    async fn dispatch(&self, _writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
-        // Send the transaction to the batch maker.
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+        // Drop oversized transactions, so that a single one cannot bloat a batch.
+        if message.len() > self.max_transaction_bytes {
+            warn!(
+                "Dropping transaction of {} B (max {} B)",
+                message.len(),
+                self.max_transaction_bytes
+            );
+            metrics::inc_counter_vec(&metrics::TRANSACTIONS_DROPPED, &[&self.validator_id.to_string()]);
+            let reason = format!("transaction exceeds {} B", self.max_transaction_bytes);
+            let _ = writer.send(MempoolAck::Rejected(reason).encode()).await;
+            return Ok(());
+        }
+
+        // Drop transactions whose nonce we already saw from the same client.
+        let transaction = message.to_vec();
+        if let Some(nonce_tracker) = &self.nonce_tracker {
+            if !nonce_tracker.check(&transaction) {
+                warn!("Dropping replayed or out-of-order transaction");
+                metrics::inc_counter_vec(&metrics::TRANSACTIONS_REPLAYED, &[&self.validator_id.to_string()]);
+                let _ = writer.send(MempoolAck::Rejected("stale nonce".to_string()).encode()).await;
+                return Ok(());
+            }
+        }
+
+        // Send the transaction to the batch maker. Its channel is only closed on shutdown, so the
+        // error is returned for the receiver to log rather than taking the network task down.
         self.tx_batch_maker
-            .send(message.to_vec())
+            .send(transaction.into())
             .await
-            .expect("Failed to send transaction");
+            .map_err(|e| format!("Failed to send transaction: {}", e))?;
 
         // Give the change to schedule other tasks.
         // tokio::task::yield_now().await;
@@ -227,29 +566,63 @@ impl MessageHandler for TxReceiverHandler {
 /// Defines how the network receiver handles incoming mempool messages.
 #[derive(Clone)]
 pub struct MempoolReceiverHandler {
-    tx_helper: MonitoredSender<(Vec<Digest>, PublicKey)>,
-    tx_processor: MonitoredSender<SerializedBatchMessage>,
+    pub(crate) tx_helper: MonitoredSender<(Vec<Digest>, PublicKey)>,
+    pub(crate) tx_processor: MonitoredSender<SerializedBatchMessage>,
+    /// The size above which messages are rejected without being deserialized (in bytes).
+    pub(crate) max_message_bytes: u64,
+    /// Signs the acknowledgements of accepted batches. Plain acknowledgements are sent if `None`.
+    pub(crate) signature_service: Option<SignatureService>,
+    /// The number of batches the `Processor` has yet to store.
+    pub(crate) processor_load: ProcessorLoad,
+    /// The format in which the messages of the other mempools are serialized.
+    pub(crate) codec: Codec,
 }
 
 #[async_trait]
 impl MessageHandler for MempoolReceiverHandler {
     async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
-        // Reply with an ACK.
-        let _ = writer.send(Bytes::from("Ack")).await;
-
-        // Deserialize and parse the message.
-        match bincode::deserialize(&serialized) {
-            Ok(MempoolMessage::Batch(..)) => self
-                .tx_processor
-                .send(serialized.to_vec())
-                .await
-                .expect("Failed to send batch"),
-            Ok(MempoolMessage::BatchRequest(missing, requestor)) => self
-                .tx_helper
-                .send((missing, requestor))
-                .await
-                .expect("Failed to send batch request"),
-            Err(e) => warn!("Serialization error: {}", e),
+        // Decompress, deserialize and parse the message, then reply with a typed ACK.
+        let decoded = compression::decompress(serialized, self.max_message_bytes)
+            .and_then(|serialized| {
+                let message = decode_message(self.codec, &serialized, self.max_message_bytes)?;
+                Ok((message, serialized))
+            });
+        match decoded {
+            Ok((MempoolMessage::Batch(..) | MempoolMessage::SignedBatch(..), _))
+                if self.processor_load.is_overloaded() =>
+            {
+                // Let the sender know we cannot promptly store the batch rather than acknowledge it.
+                debug!("Turning away batch: the processor is overloaded");
+                metrics::inc_counter(&metrics::BATCHES_REJECTED_BUSY);
+                let _ = writer.send(MempoolAck::Busy.encode()).await;
+            }
+            Ok((MempoolMessage::Batch(..) | MempoolMessage::SignedBatch(..), serialized)) => {
+                let ack = match &self.signature_service {
+                    Some(signature_service) => {
+                        MempoolAck::signed(&serialized, &mut signature_service.clone()).await
+                    }
+                    None => MempoolAck::BatchAccepted,
+                };
+                let _ = writer.send(ack.encode()).await;
+                self.processor_load.add();
+                self.tx_processor
+                    .send(serialized.to_vec())
+                    .await
+                    .map_err(|e| format!("Failed to send batch: {}", e))?
+            }
+            Ok((MempoolMessage::BatchRequest(missing, requestor), _)) => {
+                let _ = writer.send(MempoolAck::RequestQueued.encode()).await;
+                self.tx_helper
+                    .send((missing, requestor))
+                    .await
+                    .map_err(|e| format!("Failed to send batch request: {}", e))?
+            }
+            Err(e) => {
+                // Rejecting the message is enough: failing the dispatch would drop the connection.
+                warn!("Serialization error: {}", e);
+                metrics::inc_counter(&metrics::MESSAGES_REJECTED);
+                let _ = writer.send(MempoolAck::Rejected(e.to_string()).encode()).await;
+            }
         }
         Ok(())
     }
@@ -0,0 +1,193 @@
+//! The mempool metrics, registered with the default prometheus registry so that they are exported
+//! along with the metrics of the node embedding the mempool. The helpers mirror the ones of
+//! lighthouse's `lighthouse_metrics`, which hotstuff cannot depend on as a standalone workspace.
+pub use prometheus::{Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Result};
+
+/// Attempts to create an `IntCounter`, returning `Err` if the registry does not accept the counter
+/// (potentially due to naming conflict).
+pub fn try_create_int_counter(name: &str, help: &str) -> Result<IntCounter> {
+    let counter = IntCounter::with_opts(Opts::new(name, help))?;
+    prometheus::register(Box::new(counter.clone()))?;
+    Ok(counter)
+}
+
+/// Attempts to create an `IntCounterVec`, returning `Err` if the registry does not accept the
+/// counter (potentially due to naming conflict).
+pub fn try_create_int_counter_vec(name: &str, help: &str, label_names: &[&str]) -> Result<IntCounterVec> {
+    let counter_vec = IntCounterVec::new(Opts::new(name, help), label_names)?;
+    prometheus::register(Box::new(counter_vec.clone()))?;
+    Ok(counter_vec)
+}
+
+/// Attempts to create a `HistogramVec`, returning `Err` if the registry does not accept the
+/// histogram (potentially due to naming conflict).
+pub fn try_create_histogram_vec(name: &str, help: &str, label_names: &[&str]) -> Result<HistogramVec> {
+    let histogram_vec = HistogramVec::new(HistogramOpts::new(name, help), label_names)?;
+    prometheus::register(Box::new(histogram_vec.clone()))?;
+    Ok(histogram_vec)
+}
+
+/// Returns the `IntCounter` of `int_counter_vec` with the given `label_values`, if any.
+pub fn get_int_counter(int_counter_vec: &Result<IntCounterVec>, label_values: &[&str]) -> Option<IntCounter> {
+    int_counter_vec
+        .as_ref()
+        .ok()?
+        .get_metric_with_label_values(label_values)
+        .ok()
+}
+
+/// Returns the `Histogram` of `histogram_vec` with the given `label_values`, if any.
+pub fn get_histogram(histogram_vec: &Result<HistogramVec>, label_values: &[&str]) -> Option<Histogram> {
+    histogram_vec
+        .as_ref()
+        .ok()?
+        .get_metric_with_label_values(label_values)
+        .ok()
+}
+
+pub fn inc_counter(counter: &Result<IntCounter>) {
+    if let Ok(counter) = counter {
+        counter.inc();
+    }
+}
+
+pub fn inc_counter_vec(int_counter_vec: &Result<IntCounterVec>, label_values: &[&str]) {
+    if let Some(counter) = get_int_counter(int_counter_vec, label_values) {
+        counter.inc();
+    }
+}
+
+pub fn inc_counter_vec_by(int_counter_vec: &Result<IntCounterVec>, label_values: &[&str], amount: u64) {
+    if let Some(counter) = get_int_counter(int_counter_vec, label_values) {
+        counter.inc_by(amount);
+    }
+}
+
+pub fn observe_vec(histogram_vec: &Result<HistogramVec>, label_values: &[&str], value: f64) {
+    if let Some(histogram) = get_histogram(histogram_vec, label_values) {
+        histogram.observe(value);
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref BATCHES_CREATED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_batches_created_total",
+        "Total count of batches sealed by the batch maker",
+        &["validator_id"]
+    );
+    pub static ref BATCHES_REJECTED_BY_POLICY: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_batches_rejected_by_policy_total",
+        "Total count of sealed batches discarded by the batch policy instead of being broadcast",
+        &["validator_id"]
+    );
+    pub static ref BATCHES_REJECTED_INVALID: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_batches_rejected_invalid_total",
+        "Total count of batches from other mempools rejected because they are not in canonical form",
+        &["validator_id"]
+    );
+    pub static ref BATCHES_REJECTED_UNSIGNED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_batches_rejected_unsigned_total",
+        "Total count of batches from other mempools rejected because they lack a valid signature of their originator",
+        &["validator_id"]
+    );
+    pub static ref TRANSACTIONS_DROPPED_BY_POLICY: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_transactions_dropped_by_policy_total",
+        "Total count of transactions removed from sealed batches by the batch policy",
+        &["validator_id"]
+    );
+    pub static ref BATCHES_RECEIVED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_batches_received_total",
+        "Total count of batches received from other mempools",
+        &["validator_id"]
+    );
+    pub static ref BATCH_REQUEST_REPLIES: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_batch_request_replies_total",
+        "Total count of batches sent by the helper in reply to batch requests",
+        &["validator_id"]
+    );
+    pub static ref BATCH_REQUEST_MISSES: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_batch_request_misses",
+        "Total count of requested batches the helper could not find in the store",
+        &["validator_id"]
+    );
+    pub static ref BATCH_REQUESTS_DROPPED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_batch_requests_dropped_total",
+        "Total count of batch requests dropped by the helper because of rate limiting",
+        &["validator_id"]
+    );
+    pub static ref BATCH_REQUESTS_TRUNCATED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_batch_requests_truncated_total",
+        "Total count of batch requests only partially served because the reply reached its size limits",
+        &["validator_id"]
+    );
+    pub static ref TRANSACTIONS_DROPPED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_transactions_dropped_total",
+        "Total count of client transactions dropped because they exceed the maximum transaction size",
+        &["validator_id"]
+    );
+    pub static ref TRANSACTIONS_INVALID: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_transactions_invalid_total",
+        "Total count of client transactions dropped because they failed the transaction validator",
+        &["validator_id"]
+    );
+    pub static ref TRANSACTIONS_REPLAYED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_transactions_replayed_total",
+        "Total count of client transactions dropped because their nonce was not higher than the last one seen",
+        &["validator_id"]
+    );
+    pub static ref STORED_BYTES: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_stored_bytes_total",
+        "Total count of batch bytes written to the store by the processor",
+        &["validator_id"]
+    );
+    pub static ref DIGESTS_DEAD_LETTERED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_digests_dead_lettered_total",
+        "Total count of digests of stored batches dropped because they could not be forwarded to the consensus",
+        &["validator_id"]
+    );
+    pub static ref BATCHES_COMMITTED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_batches_committed_total",
+        "Total count of stored batches the consensus committed",
+        &["validator_id"]
+    );
+    pub static ref BATCHES_RECLAIMED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_batches_reclaimed_total",
+        "Total count of committed batches deleted from the store once older than the garbage collection depth",
+        &["validator_id"]
+    );
+    pub static ref SYNC_DIGESTS_LOCAL: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_sync_digests_local_total",
+        "Total count of digests the consensus asked to sync that were already in the store",
+        &["validator_id"]
+    );
+    pub static ref SYNC_DIGESTS_FETCHED: Result<IntCounterVec> = try_create_int_counter_vec(
+        "mempool_sync_digests_fetched_total",
+        "Total count of digests the consensus asked to sync that were recovered from the other mempools",
+        &["validator_id"]
+    );
+    pub static ref MESSAGES_REJECTED: Result<IntCounter> = try_create_int_counter(
+        "mempool_messages_rejected_total",
+        "Total count of messages from other mempools that could not be decoded"
+    );
+    pub static ref BATCHES_REJECTED_BUSY: Result<IntCounter> = try_create_int_counter(
+        "mempool_batches_rejected_busy_total",
+        "Total count of batches from other mempools turned away because the processor was overloaded"
+    );
+    pub static ref QUORUM_WAITER_TIMEOUTS: Result<IntCounter> = try_create_int_counter(
+        "mempool_quorum_waiter_timeouts_total",
+        "Total count of batches dropped because a quorum of acknowledgements was not reached in time"
+    );
+    pub static ref QUORUM_WAITER_STALE_BATCHES: Result<IntCounter> = try_create_int_counter(
+        "mempool_quorum_waiter_stale_batches_total",
+        "Total count of batches dropped because the consensus moved past their round by more than the gc depth before a quorum was reached"
+    );
+    pub static ref QUORUM_UNREACHABLE: Result<IntCounter> = try_create_int_counter(
+        "mempool_quorum_unreachable_total",
+        "Total count of batches dropped because the committee stake is below the quorum threshold"
+    );
+    pub static ref TRANSACTION_INGRESS_TO_SEAL_SECONDS: Result<HistogramVec> = try_create_histogram_vec(
+        "mempool_transaction_ingress_to_seal_seconds",
+        "Time between a transaction being received from a client and its batch being sealed",
+        &["validator_id"]
+    );
+}
@@ -0,0 +1,46 @@
+use crate::batch_maker::Transaction;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[cfg(test)]
+#[path = "tests/nonce_tracker_tests.rs"]
+pub mod nonce_tracker_tests;
+
+/// Extracts the client identifier and the nonce from a transaction. The mempool does not know the
+/// format of the transactions, so this is provided by whoever spawns it. Transactions for which it
+/// returns `None` are not subject to replay protection.
+pub type NonceExtractor = Arc<dyn Fn(&Transaction) -> Option<(Vec<u8>, u64)> + Send + Sync>;
+
+/// Remembers the highest nonce seen from each client, so that replayed or out-of-order transactions
+/// can be dropped before they are batched.
+#[derive(Clone)]
+pub struct NonceTracker {
+    extractor: NonceExtractor,
+    highest: Arc<Mutex<HashMap<Vec<u8>, u64>>>,
+}
+
+impl NonceTracker {
+    pub fn new(extractor: NonceExtractor) -> Self {
+        Self {
+            extractor,
+            highest: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Records the nonce of the transaction and returns `true` if it is higher than any nonce seen
+    /// from the same client. Nonces may skip values, but never go back.
+    pub fn check(&self, transaction: &Transaction) -> bool {
+        let (client, nonce) = match (self.extractor)(transaction) {
+            Some(x) => x,
+            None => return true,
+        };
+        let mut highest = self.highest.lock().unwrap();
+        match highest.get(&client) {
+            Some(last) if nonce <= *last => false,
+            _ => {
+                highest.insert(client, nonce);
+                true
+            }
+        }
+    }
+}
@@ -1,11 +1,21 @@
+use crate::batch_index::BatchIndex;
+use crate::codec::{decode_message, encode_message, Codec};
+use crate::config::SharedCommittee;
+use crate::mempool::MempoolMessage;
+use crate::metrics;
 use crypto::Digest;
 use ed25519_dalek::Digest as _;
 use ed25519_dalek::Sha512;
+use futures::FutureExt as _;
 use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 use store::Store;
 use tokio::sync::mpsc::{Receiver};
+use tokio::sync::mpsc::error::SendTimeoutError;
+use tokio::time::{sleep, Duration, Instant};
 use utils::monitored_channel::MonitoredSender;
-use log::{info};
+use log::{info, error, warn};
 
 #[cfg(test)]
 #[path = "tests/processor_tests.rs"]
@@ -14,9 +24,125 @@ pub mod processor_tests;
 /// Indicates a serialized `MempoolMessage::Batch` message.
 pub type SerializedBatchMessage = Vec<u8>;
 
+/// Computes the digest under which a batch (or any other blob of mempool data) is identified.
+pub fn digest(data: &[u8]) -> Digest {
+    Digest(Sha512::digest(data).as_slice()[..32].try_into().unwrap())
+}
+
+/// Checks that `batch` is a (possibly signed) `MempoolMessage::Batch` serialized exactly as its
+/// author would have, i.e. that re-encoding it yields the same `digest`, and returns the decoded
+/// message. This rejects, among others, batches padded with trailing bytes, which decode fine but
+/// would be stored under a digest nobody else knows.
+pub fn verify_batch(batch: &[u8], digest: &Digest, codec: Codec) -> Result<MempoolMessage, String> {
+    let message = decode_message(codec, batch, batch.len() as u64)
+        .map_err(|e| format!("Failed to decode batch: {}", e))?;
+    if !matches!(message, MempoolMessage::Batch(_) | MempoolMessage::SignedBatch(..)) {
+        return Err("Message is not a batch".to_string());
+    }
+    let canonical = encode_message(codec, &message)
+        .map_err(|e| format!("Failed to re-encode batch: {}", e))?;
+    let expected = self::digest(&canonical);
+    if &expected != digest {
+        return Err(format!("Digest mismatch: the batch's canonical digest is {}", expected));
+    }
+    Ok(message)
+}
+
+/// Tells the network handler whether the `Processor` is falling behind, so that it stops accepting
+/// batches it cannot promptly store. The load is the number of batches handed over to the
+/// processor whose writes are not confirmed yet. The indicator is raised once the load reaches the
+/// high water mark and only lowered once it is back to the low water mark, so that it does not flap.
+#[derive(Clone)]
+pub struct ProcessorLoad {
+    /// Zero disables the indicator.
+    high_water_mark: usize,
+    low_water_mark: usize,
+    queued: Arc<AtomicUsize>,
+    overloaded: Arc<AtomicBool>,
+}
+
+impl ProcessorLoad {
+    pub fn new(high_water_mark: usize, low_water_mark: usize) -> Self {
+        Self {
+            high_water_mark,
+            low_water_mark,
+            queued: Arc::new(AtomicUsize::new(0)),
+            overloaded: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Records a batch handed over to the processor.
+    pub fn add(&self) {
+        let queued = self.queued.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.high_water_mark > 0 && queued >= self.high_water_mark {
+            self.overloaded.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Records `count` batches the processor is done with, whether stored or dropped.
+    pub fn remove(&self, count: usize) {
+        let previous = self
+            .queued
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |x| Some(x.saturating_sub(count)))
+            .unwrap_or_default();
+        if previous.saturating_sub(count) <= self.low_water_mark {
+            self.overloaded.store(false, Ordering::SeqCst);
+        }
+    }
+
+    pub fn is_overloaded(&self) -> bool {
+        self.overloaded.load(Ordering::SeqCst)
+    }
+}
+
 /// Hashes and stores batches, it then outputs the batch's digest.
+///
+/// Store writes are grouped: the batches received since the last flush are held back until
+/// `store_batch_size` of them are pending or `max_store_delay` ms have elapsed. The processor then
+/// writes them to the store at once and only forwards their digests once the write is applied, so
+/// the consensus never references an unstored batch.
+///
+/// Batches from other mempools are only stored if they are in the canonical form of a
+/// `MempoolMessage::Batch`: otherwise their digest would not match the one their author computed,
+/// which is the one the consensus and the `BatchRequest`s of the other mempools refer to. If
+/// `signed_batches` is set, they must also carry a valid signature of the originator they claim.
+///
+/// If the consensus does not take a digest within `forward_retry_delay` ms, the processor warns and
+/// keeps waiting, doubling the wait after every attempt. Digests that the consensus can no longer
+/// receive at all, or that still cannot be forwarded after `max_forward_retries` attempts if set,
+/// are dead-lettered: they are logged and counted, and the processor moves on.
+///
+/// On exit, the batches already queued on `rx_batch` are still stored before the processor
+/// terminates, and it only returns once the store has applied all of its writes.
 pub struct Processor;
 
+/// The settings of a `Processor`.
+#[derive(Clone)]
+pub struct ProcessorParams {
+    pub validator_id: u64,
+    /// Whether the batches come from other mempools rather than from our own batch maker.
+    pub from_peers: bool,
+    /// The format in which the batches are serialized, used to check the batches from peers.
+    pub codec: Codec,
+    /// Whether the batches from peers must be signed by their originator.
+    pub signed_batches: bool,
+    /// The committee the originators of the batches from peers must belong to.
+    pub committee: SharedCommittee,
+    /// The number of pending writes after which the processor flushes.
+    pub store_batch_size: usize,
+    /// The maximum delay (in ms) a write stays pending before the processor flushes.
+    pub max_store_delay: u64,
+    /// The number of times the processor retries to forward a digest before dead-lettering it, if
+    /// set. Otherwise it waits for the consensus for as long as it takes.
+    pub max_forward_retries: Option<u32>,
+    /// The delay (in ms) before the first retry, doubled after every further attempt.
+    pub forward_retry_delay: u64,
+    /// Records the round in which each batch was stored.
+    pub batch_index: BatchIndex,
+    /// Told about the batches whose writes are confirmed, if the sender tracks the load.
+    pub load: Option<ProcessorLoad>,
+}
+
 impl Processor {
     pub fn spawn(
         // The persistent storage.
@@ -25,26 +151,166 @@ impl Processor {
         mut rx_batch: Receiver<SerializedBatchMessage>,
         // Output channel to send out batches' digests.
         tx_digest: MonitoredSender<Digest>,
+        params: ProcessorParams,
         exit: exit_future::Exit
     ) {
         tokio::spawn(async move {
+            let mut pending: Vec<(Digest, SerializedBatchMessage)> = Vec::with_capacity(params.store_batch_size);
+            let timer = sleep(Duration::from_millis(params.max_store_delay));
+            tokio::pin!(timer);
+
             loop {
-                let exit = exit.clone();
                 tokio::select! {
                     Some(batch) = rx_batch.recv() => {
-                        let digest = Digest(Sha512::digest(&batch).as_slice()[..32].try_into().unwrap());
-
-                        // Store the batch.
-                        store.write(digest.to_vec(), batch).await;
-
-                        tx_digest.send(digest).await.expect("Failed to send digest");
+                        match Self::check_batch(batch, &params) {
+                            Some(write) => pending.push(write),
+                            None => {
+                                if let Some(load) = &params.load {
+                                    load.remove(1);
+                                }
+                                continue;
+                            }
+                        }
+                        if pending.len() >= params.store_batch_size {
+                            Self::flush(&store, &mut pending, &params, &tx_digest, &exit).await;
+                            timer.as_mut().reset(Instant::now() + Duration::from_millis(params.max_store_delay));
+                        }
+                    },
+                    () = &mut timer => {
+                        if !pending.is_empty() {
+                            Self::flush(&store, &mut pending, &params, &tx_digest, &exit).await;
+                        }
+                        timer.as_mut().reset(Instant::now() + Duration::from_millis(params.max_store_delay));
                     },
-                    () = exit => {
+                    () = exit.clone() => {
                         break;
                     }
                 }
             }
+
+            // Drain the batches that are already queued and flush them all at once. The consensus
+            // may be shutting down as well, so failing to forward the digests is not an error and
+            // is not retried.
+            while let Ok(batch) = rx_batch.try_recv() {
+                match Self::check_batch(batch, &params) {
+                    Some(write) => pending.push(write),
+                    None => {
+                        if let Some(load) = &params.load {
+                            load.remove(1);
+                        }
+                    }
+                }
+            }
+            Self::flush(&store, &mut pending, &params, &tx_digest, &exit).await;
             info!("Shutting down mempool processor");
         });
     }
+
+    /// Hashes a batch and returns it along with its digest, ready to be written to the store.
+    /// Returns `None` if the batch comes from a peer and fails the integrity check, or the
+    /// originator check if `signed_batches` is set.
+    fn check_batch(
+        batch: SerializedBatchMessage,
+        params: &ProcessorParams,
+    ) -> Option<(Digest, SerializedBatchMessage)> {
+        let digest = digest(&batch);
+        if params.from_peers {
+            let validator_id = params.validator_id.to_string();
+            metrics::inc_counter_vec(&metrics::BATCHES_RECEIVED, &[&validator_id]);
+            let message = match verify_batch(&batch, &digest, params.codec) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Rejecting batch {} from a peer: {}", digest, e);
+                    metrics::inc_counter_vec(&metrics::BATCHES_REJECTED_INVALID, &[&validator_id]);
+                    return None;
+                }
+            };
+            if params.signed_batches && !message.verify_originator(&params.committee.read().unwrap()) {
+                warn!("Rejecting batch {} from a peer: missing or invalid originator signature", digest);
+                metrics::inc_counter_vec(&metrics::BATCHES_REJECTED_UNSIGNED, &[&validator_id]);
+                return None;
+            }
+        }
+        Some((digest, batch))
+    }
+
+    /// Writes the pending batches to the store at once, then forwards their digests in order.
+    /// If the write fails, the digests are dropped rather than forwarded.
+    async fn flush(
+        store: &Store,
+        pending: &mut Vec<(Digest, SerializedBatchMessage)>,
+        params: &ProcessorParams,
+        tx_digest: &MonitoredSender<Digest>,
+        exit: &exit_future::Exit,
+    ) {
+        if pending.is_empty() {
+            return;
+        }
+        let validator_id = params.validator_id.to_string();
+
+        let (digests, writes): (Vec<_>, Vec<_>) = pending
+            .drain(..)
+            .map(|(digest, batch)| (digest.clone(), (digest.to_vec(), batch)))
+            .unzip();
+        let bytes: usize = writes.iter().map(|(_, batch)| batch.len()).sum();
+        let result = store.write_batch(writes).await;
+        if let Some(load) = &params.load {
+            load.remove(digests.len());
+        }
+        if let Err(e) = result {
+            error!("Failed to flush {} batches: {}", digests.len(), e);
+            return;
+        }
+        metrics::inc_counter_vec_by(&metrics::STORED_BYTES, &[&validator_id], bytes as u64);
+
+        let mut digests = digests.into_iter();
+        for digest in digests.by_ref() {
+            params.batch_index.insert(digest.clone());
+            if let Err(digest) = Self::forward(tx_digest, digest, params, exit).await {
+                // The consensus is gone, stuck for longer than we are willing to wait, or we are
+                // shutting down: the following digests would not make it either, so they are dead-lettered without retrying.
+                let dropped: Vec<_> = std::iter::once(digest).chain(digests).collect();
+                if exit.clone().now_or_never().is_none() {
+                    error!("Failed to forward {} digests to the consensus, dropping them: {:?}", dropped.len(), dropped);
+                    metrics::inc_counter_vec_by(&metrics::DIGESTS_DEAD_LETTERED, &[&validator_id], dropped.len() as u64);
+                }
+                return;
+            }
+        }
+    }
+
+    /// Forwards a digest to the consensus. If its channel is full, the digest is offered again,
+    /// waiting `forward_retry_delay` ms for room and doubling the wait after every attempt. Gives
+    /// the digest back right away if the consensus is gone or we are shutting down, or once
+    /// `max_forward_retries` retries are exhausted if set.
+    async fn forward(
+        tx_digest: &MonitoredSender<Digest>,
+        digest: Digest,
+        params: &ProcessorParams,
+        exit: &exit_future::Exit,
+    ) -> Result<(), Digest> {
+        let mut digest = digest;
+        let mut delay = params.forward_retry_delay;
+        let mut attempt = 0;
+        loop {
+            // Offer the digest first, so that it still goes through on exit if there is room.
+            tokio::select! {
+                biased;
+                result = tx_digest.send_timeout(digest.clone(), Duration::from_millis(delay)) => match result {
+                    Ok(()) => return Ok(()),
+                    Err(SendTimeoutError::Closed(x)) => return Err(x),
+                    Err(SendTimeoutError::Timeout(x)) => digest = x,
+                },
+                () = exit.clone() => break,
+            }
+            if params.max_forward_retries.is_some_and(|max| attempt >= max) {
+                break;
+            }
+            warn!("The consensus did not take digest {} within {} ms, retrying", digest, delay);
+            attempt += 1;
+            // Never retry without waiting, so that a zero delay does not spin.
+            delay = delay.saturating_mul(2).max(1);
+        }
+        Err(digest)
+    }
 }
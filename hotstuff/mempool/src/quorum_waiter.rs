@@ -1,13 +1,18 @@
-use crate::config::{Committee, Stake};
+use crate::batch_index::BatchIndex;
+use crate::config::{SharedCommittee, Stake};
+use crate::mempool::{MempoolAck, Round};
+use crate::metrics;
 use crate::processor::SerializedBatchMessage;
 use crypto::PublicKey;
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use network::CancelHandler;
 use tokio::sync::mpsc::{Receiver};
+use tokio::sync::watch;
+use tokio::sync::OwnedSemaphorePermit;
 use utils::monitored_channel::MonitoredSender;
 use tokio::time::{Duration, timeout};
-use log::{info, warn};
+use log::{debug, error, info, warn};
 
 #[cfg(test)]
 #[path = "tests/quorum_waiter_tests.rs"]
@@ -19,57 +24,237 @@ pub struct QuorumWaiterMessage {
     pub batch: SerializedBatchMessage,
     /// The cancel handlers to receive the acknowledgements of our broadcast.
     pub handlers: Vec<(PublicKey, CancelHandler)>,
+    /// Released once we are done with the batch, to bound the number of batches in flight.
+    pub inflight_permit: Option<OwnedSemaphorePermit>,
+}
+
+/// The settings of a `QuorumWaiter`.
+#[derive(Clone)]
+pub struct QuorumWaiterParams {
+    /// The delay after which a batch without enough acknowledgements is dropped (in ms).
+    pub quorum_timeout: u64,
+    /// Replaces the committee's quorum threshold if set. Only meant for test networks.
+    pub quorum_threshold_override: Option<Stake>,
+    /// The depth of the garbage collection (in rounds).
+    pub gc_depth: Round,
+    /// Tells in which consensus round we are.
+    pub batch_index: BatchIndex,
 }
 
 /// The QuorumWaiter waits for 2f authorities to acknowledge reception of a batch.
+///
+/// A batch is dropped without waiting any longer once the consensus moves more than `gc_depth`
+/// rounds past the round in which the batch reached the QuorumWaiter: the other mempools have
+/// cleaned up that round by then, so their acknowledgements no longer matter.
+///
+/// The stakes and the quorum threshold are read from the current committee when a batch arrives,
+/// so a reloaded committee applies to the batches that arrive afterwards. A batch already waiting
+/// for acknowledgements keeps the committee it started with.
+///
+/// On exit, the batches already queued on `rx_message` are still processed before `tx_drained`
+/// fires, so that the processor downstream only shuts down after them.
 pub struct QuorumWaiter {
-    /// The committee information.
-    committee: Committee,
+    /// The committee information, which may be reloaded at runtime.
+    committee: SharedCommittee,
     /// The stake of this authority.
     stake: Stake,
+    /// The delay after which a batch without enough acknowledgements is dropped (in ms).
+    quorum_timeout: u64,
+    /// Replaces the committee's quorum threshold if set, in which case every batch comes with a
+    /// warning.
+    quorum_threshold_override: Option<Stake>,
+    /// The depth of the garbage collection (in rounds), after which a waiting batch is stale.
+    gc_depth: Round,
+    /// Follows the consensus round.
+    rx_round: watch::Receiver<Round>,
     /// Input Channel to receive commands.
     rx_message: Receiver<QuorumWaiterMessage>,
     /// Channel to deliver batches for which we have enough acknowledgements.
     tx_batch: MonitoredSender<SerializedBatchMessage>,
-    exit: exit_future::Exit
+    exit: exit_future::Exit,
+    /// Fires once we are done draining our input channel after `exit`.
+    tx_drained: Option<exit_future::Signal>,
 }
 
 impl QuorumWaiter {
     /// Spawn a new QuorumWaiter.
     pub fn spawn(
-        committee: Committee,
+        committee: SharedCommittee,
         stake: Stake,
+        params: QuorumWaiterParams,
         rx_message: Receiver<QuorumWaiterMessage>,
         tx_batch: MonitoredSender<Vec<u8>>,
-        exit: exit_future::Exit
+        exit: exit_future::Exit,
+        tx_drained: exit_future::Signal,
     ) {
+        {
+            let committee = committee.read().unwrap();
+            let threshold = match params.quorum_threshold_override {
+                Some(threshold) => {
+                    warn!(
+                        "Quorum threshold overridden to {} (committee threshold {}): batches may be delivered without a quorum of the committee holding them, safety guarantees are reduced",
+                        threshold,
+                        committee.quorum_threshold()
+                    );
+                    threshold
+                }
+                None => committee.quorum_threshold(),
+            };
+            if committee.total_stake() < threshold {
+                error!(
+                    "Misconfigured committee: total stake {} is below the quorum threshold {}, no batch will be delivered",
+                    committee.total_stake(),
+                    threshold
+                );
+            }
+        }
         tokio::spawn(async move {
             Self {
                 committee,
                 stake,
+                quorum_timeout: params.quorum_timeout,
+                quorum_threshold_override: params.quorum_threshold_override,
+                gc_depth: params.gc_depth,
+                rx_round: params.batch_index.subscribe(),
                 rx_message,
                 tx_batch,
-                exit
+                exit,
+                tx_drained: Some(tx_drained),
             }
             .run()
             .await;
         });
     }
 
-    /// Helper function. It waits for a future to complete and then delivers a value.
-    async fn waiter(wait_for: CancelHandler, deliver: Stake) -> Stake {
+    /// Helper function. It waits for a future to complete and then delivers a value. Signed
+    /// acknowledgements only count if they are signed by `name` over our batch.
+    async fn waiter(wait_for: CancelHandler, name: PublicKey, batch: &[u8], deliver: Stake) -> Stake {
         let result = wait_for.await;
-        if let Ok(ack) = result {
-            if ack == "Ack" {
-                deliver
+        match result.map(|ack| MempoolAck::decode(&ack)) {
+            Ok(Ok(MempoolAck::BatchAccepted)) => deliver,
+            Ok(Ok(ack @ MempoolAck::SignedBatchAccepted(..))) => {
+                if ack.verify(batch, &name) {
+                    deliver
+                } else {
+                    warn!("Invalid signed ack from {}", name);
+                    0
+                }
+            }
+            Ok(Ok(MempoolAck::Busy)) => {
+                debug!("{} is too busy to take our batch", name);
+                0
             }
-            else {
+            Ok(Ok(ack)) => {
                 // Not a normal ack. Something is wrong.
+                warn!("Unexpected reply to our batch: {:?}", ack);
                 0
             }
+            _ => 0,
         }
-        else {
-            0
+    }
+
+    /// Waits for a quorum of acknowledgements and forwards the batch to the processor. The batch
+    /// is dropped if the quorum is not reached in time.
+    async fn process(&mut self, batch: SerializedBatchMessage, handlers: Vec<(PublicKey, CancelHandler)>) {
+        // Snapshot the committee: a reload while we wait only applies to the next batches.
+        let committee = self.committee.read().unwrap().clone();
+        let threshold = self
+            .quorum_threshold_override
+            .unwrap_or_else(|| committee.quorum_threshold());
+
+        // If the committee does not hold enough stake to ever reach a quorum, drop the batch right
+        // away instead of waiting for acknowledgements that cannot add up.
+        if committee.total_stake() < threshold {
+            error!(
+                "Dropping batch: committee stake {} can never reach the quorum threshold {}",
+                committee.total_stake(),
+                threshold
+            );
+            metrics::inc_counter(&metrics::QUORUM_UNREACHABLE);
+            return;
+        }
+        if self.quorum_threshold_override.is_some() {
+            warn!(
+                "Waiting for an overridden quorum threshold of {} instead of {}: safety guarantees are reduced",
+                threshold,
+                committee.quorum_threshold()
+            );
+        }
+
+        let mut wait_for_quorum: FuturesUnordered<_> = handlers
+            .into_iter()
+            .map(|(name, handler)| {
+                let stake = committee.stake(&name);
+                Self::waiter(handler, name, &batch, stake)
+            })
+            .collect();
+
+        // Wait for the first 2f nodes to send back an Ack. Then we consider the batch
+        // delivered and we send its digest to the consensus (that will include it into
+        // the dag). This should reduce the amount of synching.
+        let mut total_stake = self.stake;
+        let wait_fut = async {
+            while let Some(stake) = wait_for_quorum.next().await {
+                total_stake += stake;
+                if total_stake >= threshold {
+                    return true;
+                }
+            }
+            false
+        };
+
+        // Stop waiting once the consensus moved past the batch's round by more than the gc depth.
+        let round = *self.rx_round.borrow();
+        let stale_round = round.saturating_add(self.gc_depth);
+        let mut rx_round = self.rx_round.clone();
+        let stale_fut = async move {
+            while rx_round.changed().await.is_ok() {
+                if *rx_round.borrow() > stale_round {
+                    return;
+                }
+            }
+            // The index is gone, so the round will not move anymore.
+            futures::future::pending::<()>().await
+        };
+
+        // Drop the batch if the quorum is not reached in time, so that a partitioned
+        // committee does not back-pressure the whole mempool.
+        let result = timeout(Duration::from_millis(self.quorum_timeout), async {
+            tokio::select! {
+                reached = wait_fut => Some(reached),
+                () = stale_fut => None,
+            }
+        })
+        .await;
+        // The pending waiters borrow the batch.
+        drop(wait_for_quorum);
+        match result {
+            Ok(Some(true)) => {
+                self.tx_batch
+                    .send(batch)
+                    .await
+                    .expect("Failed to deliver batch");
+            }
+            Ok(Some(false)) => {
+                warn!(
+                    "Failed to broadcast batch: Not enough acknowledgements (stake {}/{})",
+                    total_stake, threshold
+                );
+            }
+            Ok(None) => {
+                warn!(
+                    "Failed to broadcast batch: Stale after the consensus moved past round {} (stake {}/{})",
+                    stale_round, total_stake, threshold
+                );
+                metrics::inc_counter(&metrics::QUORUM_WAITER_STALE_BATCHES);
+            }
+            Err(_) => {
+                warn!(
+                    "Failed to broadcast batch: Timeout after {} ms (stake {}/{})",
+                    self.quorum_timeout, total_stake, threshold
+                );
+                metrics::inc_counter(&metrics::QUORUM_WAITER_TIMEOUTS);
+            }
         }
     }
 
@@ -78,58 +263,24 @@ impl QuorumWaiter {
         loop {
             let exit = self.exit.clone();
             tokio::select! {
-                Some(QuorumWaiterMessage { batch, handlers }) = self.rx_message.recv() => {
-                    let mut wait_for_quorum: FuturesUnordered<_> = handlers
-                        .into_iter()
-                        .map(|(name, handler)| {
-                            let stake = self.committee.stake(&name);
-                            Self::waiter(handler, stake)
-                        })
-                        .collect();
-        
-                    // Wait for the first 2f nodes to send back an Ack. Then we consider the batch
-                    // delivered and we send its digest to the consensus (that will include it into
-                    // the dag). This should reduce the amount of synching.
-                    let mut total_stake = self.stake;
-
-                    let tx_batch = self.tx_batch.clone();
-                    let committee = self.committee.clone();
-
-                    let wait_fut = tokio::spawn(async move {
-                        'wait: loop {
-                            match wait_for_quorum.next().await {
-                                Some(stake) => {
-                                    total_stake += stake;
-                                    if total_stake >= committee.quorum_threshold() {
-                                        tx_batch
-                                            .send(batch)
-                                            .await
-                                            .expect("Failed to deliver batch");
-                                        break 'wait;
-                                    }
-                                }
-                                None => {
-                                    break 'wait;
-                                }
-                            }
-                        }
-                    });
-
-                    // Drop the batch after 12 seconds. This is adapted to our scenario.
-                    match timeout(Duration::from_secs(12), wait_fut).await {
-                        Ok(_) => {
-                        }
-                        Err(_) => {
-                            warn!("Failed to broadcast batch: Timeout");
-                        }
-                    }
-                    
+                Some(QuorumWaiterMessage { batch, handlers, inflight_permit }) = self.rx_message.recv() => {
+                    self.process(batch, handlers).await;
+                    drop(inflight_permit);
                 },
                 () = exit => {
                     break;
                 }
             }
         }
+
+        // Drain the batches that are already queued. Each of them may wait up to `quorum_timeout`.
+        while let Ok(QuorumWaiterMessage { batch, handlers, inflight_permit }) = self.rx_message.try_recv() {
+            self.process(batch, handlers).await;
+            drop(inflight_permit);
+        }
+        if let Some(tx_drained) = self.tx_drained.take() {
+            let _ = tx_drained.fire();
+        }
         info!("Shutting down mempool quorum waiter");
     }
 }
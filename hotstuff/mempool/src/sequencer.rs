@@ -0,0 +1,134 @@
+use crypto::Digest;
+use futures::future::poll_fn;
+use futures::FutureExt as _;
+use log::info;
+use std::fmt;
+use std::task::Poll;
+use tokio::sync::mpsc::Receiver;
+use utils::monitored_channel::MonitoredSender;
+
+#[cfg(test)]
+#[path = "tests/sequencer_tests.rs"]
+pub mod sequencer_tests;
+
+/// The `Processor` a digest comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestSource {
+    /// The batches of our own batch maker.
+    Own,
+    /// The batches received from the other mempools.
+    Peers,
+}
+
+impl fmt::Display for DigestSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DigestSource::Own => write!(f, "own"),
+            DigestSource::Peers => write!(f, "peers"),
+        }
+    }
+}
+
+impl DigestSource {
+    fn other(self) -> Self {
+        match self {
+            DigestSource::Own => DigestSource::Peers,
+            DigestSource::Peers => DigestSource::Own,
+        }
+    }
+}
+
+/// Merges the digests of both `Processor`s into a single stream to the consensus, logging each
+/// digest with its sequence number and source as it is forwarded, so that the order in which the
+/// consensus saw the batches can be replayed from the logs.
+///
+/// When digests from both processors are ready, the sequencer takes them in turns, so that a busy
+/// processor cannot starve the other. On exit, the digests already queued are still forwarded;
+/// those the processors flush afterwards are dropped, as the consensus is shutting down as well.
+pub struct DigestSequencer;
+
+impl DigestSequencer {
+    pub fn spawn(
+        // Input channel to receive the digests of our own batches.
+        rx_own: Receiver<Digest>,
+        // Input channel to receive the digests of the batches of the other mempools.
+        rx_peers: Receiver<Digest>,
+        // Output channel to send out the digests to the consensus.
+        tx_consensus: MonitoredSender<Digest>,
+        validator_id: u64,
+        exit: exit_future::Exit,
+    ) {
+        tokio::spawn(async move {
+            Self::run(rx_own, rx_peers, tx_consensus, validator_id, exit).await;
+        });
+    }
+
+    async fn run(
+        mut rx_own: Receiver<Digest>,
+        mut rx_peers: Receiver<Digest>,
+        tx_consensus: MonitoredSender<Digest>,
+        validator_id: u64,
+        exit: exit_future::Exit,
+    ) {
+        let mut turn = DigestSource::Own;
+        // The position of the next digest in the stream to the consensus.
+        let mut sequence: u64 = 0;
+        loop {
+            let exit = exit.clone();
+            let (source, digest) = tokio::select! {
+                next = Self::next(&mut rx_own, &mut rx_peers, turn) => next,
+                () = exit => break,
+            };
+            turn = source.other();
+            if !Self::forward(&tx_consensus, sequence, source, digest, validator_id).await {
+                break;
+            }
+            sequence += 1;
+        }
+
+        // Forward the digests that are already queued, still taking turns.
+        while let Some((source, digest)) = Self::next(&mut rx_own, &mut rx_peers, turn).now_or_never() {
+            turn = source.other();
+            if !Self::forward(&tx_consensus, sequence, source, digest, validator_id).await {
+                break;
+            }
+            sequence += 1;
+        }
+        info!("[VA {}] Shutting down mempool digest sequencer", validator_id);
+    }
+
+    /// Waits for the next digest of either processor, trying the one whose `turn` it is first.
+    /// A processor that is gone is skipped.
+    async fn next(
+        rx_own: &mut Receiver<Digest>,
+        rx_peers: &mut Receiver<Digest>,
+        turn: DigestSource,
+    ) -> (DigestSource, Digest) {
+        poll_fn(|cx| {
+            for source in [turn, turn.other()] {
+                let rx = match source {
+                    DigestSource::Own => &mut *rx_own,
+                    DigestSource::Peers => &mut *rx_peers,
+                };
+                if let Poll::Ready(Some(digest)) = rx.poll_recv(cx) {
+                    return Poll::Ready((source, digest));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Forwards the digest at position `sequence` to the consensus. Returns `false` if the
+    /// consensus is gone.
+    async fn forward(
+        tx_consensus: &MonitoredSender<Digest>,
+        sequence: u64,
+        source: DigestSource,
+        digest: Digest,
+        validator_id: u64,
+    ) -> bool {
+        info!("[VA {}] Digest #{} from {}: {}", validator_id, sequence, source, digest);
+        tx_consensus.send(digest).await.is_ok()
+    }
+}
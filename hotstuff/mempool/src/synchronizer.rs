@@ -1,12 +1,17 @@
-use crate::config::Committee;
+use crate::batch_index::BatchIndex;
+use crate::codec::{encode_message, Codec};
+use crate::config::SharedCommittee;
 use crate::mempool::{ConsensusMempoolMessage, MempoolMessage, Round};
+use crate::metrics;
 use bytes::Bytes;
 use crypto::{Digest, PublicKey};
 use futures::stream::futures_unordered::FuturesUnordered;
 use futures::stream::StreamExt as _;
 use log::{debug, error, info, warn};
-use network::{SimpleSender, DvfMessage, VERSION};
-use std::collections::HashMap;
+use network::{SimpleSender, DvfMessage, TcpSettings, VERSION};
+use rand::seq::SliceRandom as _;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 use store::{Store, StoreError};
 use tokio::sync::mpsc::{channel, Receiver, Sender};
@@ -21,21 +26,176 @@ pub mod synchronizer_tests;
 /// Resolution of the timer managing retrials of sync requests (in ms).
 const TIMER_RESOLUTION: u64 = 2_000;
 
+/// Tracks the delay before re-trying to send sync requests. The delay grows by `factor` after
+/// every round of retries, up to `max_delay`, and falls back to `base_delay` once a missing batch
+/// is recovered. A factor of 1.0 keeps the delay fixed.
+pub struct RetryBackoff {
+    base_delay: u64,
+    factor: f64,
+    max_delay: u64,
+    current_delay: u64,
+}
+
+impl RetryBackoff {
+    pub fn new(base_delay: u64, factor: f64, max_delay: u64) -> Self {
+        Self {
+            base_delay,
+            factor,
+            max_delay,
+            current_delay: base_delay,
+        }
+    }
+
+    /// The delay to wait before re-trying to send sync requests (in ms).
+    pub fn delay(&self) -> u64 {
+        self.current_delay
+    }
+
+    /// Grow the delay after a round of retries. The cap never goes below the base delay.
+    pub fn on_failure(&mut self) {
+        let next = (self.current_delay as f64 * self.factor) as u64;
+        self.current_delay = next.min(self.max_delay.max(self.base_delay));
+    }
+
+    /// Go back to the base delay.
+    pub fn reset(&mut self) {
+        self.current_delay = self.base_delay;
+    }
+}
+
+/// The decayed record of a peer's answers to our sync requests.
+struct PeerRecord {
+    successes: f64,
+    failures: f64,
+    updated: Instant,
+}
+
+/// Scores the peers by how often they answer our sync requests, so that retries go to the peers
+/// most likely to have the missing batches. A peer is credited with a success when a batch we
+/// requested from it shows up in the store, and with a failure when we have to retry. Records
+/// decay exponentially with a time constant of `window`, so old behaviour is eventually forgotten.
+pub struct PeerScores {
+    window: Duration,
+    records: HashMap<PublicKey, PeerRecord>,
+}
+
+impl PeerScores {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            records: HashMap::new(),
+        }
+    }
+
+    pub fn record_success(&mut self, peer: &PublicKey) {
+        self.record_at(peer, true, Instant::now());
+    }
+
+    pub fn record_failure(&mut self, peer: &PublicKey) {
+        self.record_at(peer, false, Instant::now());
+    }
+
+    /// The estimated response rate of the peer, between 0 and 1. Peers we know nothing about
+    /// score 0.5.
+    pub fn score(&self, peer: &PublicKey) -> f64 {
+        self.score_at(peer, Instant::now())
+    }
+
+    /// Picks `nodes` peers, best scores first. Peers with equal scores (e.g. the ones we know
+    /// nothing about) are picked at random.
+    pub fn select(&self, mut peers: Vec<(PublicKey, SocketAddr)>, nodes: usize) -> Vec<(PublicKey, SocketAddr)> {
+        let now = Instant::now();
+        peers.shuffle(&mut rand::thread_rng());
+        let mut scored: Vec<_> = peers
+            .into_iter()
+            .map(|peer| (self.score_at(&peer.0, now), peer))
+            .collect();
+        scored.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        scored.into_iter().take(nodes).map(|(_, peer)| peer).collect()
+    }
+
+    fn decay(&self, record: &PeerRecord, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(record.updated).as_secs_f64();
+        match self.window.as_secs_f64() {
+            window if window > 0.0 => (-elapsed / window).exp(),
+            _ => 0.0,
+        }
+    }
+
+    fn record_at(&mut self, peer: &PublicKey, success: bool, now: Instant) {
+        let (successes, failures) = match self.records.get(peer) {
+            Some(record) => {
+                let decay = self.decay(record, now);
+                (record.successes * decay, record.failures * decay)
+            }
+            None => (0.0, 0.0),
+        };
+        let record = PeerRecord {
+            successes: successes + if success { 1.0 } else { 0.0 },
+            failures: failures + if success { 0.0 } else { 1.0 },
+            updated: now,
+        };
+        self.records.insert(*peer, record);
+    }
+
+    fn score_at(&self, peer: &PublicKey, now: Instant) -> f64 {
+        match self.records.get(peer) {
+            Some(record) => {
+                let decay = self.decay(record, now);
+                // Start from an even prior, so that a single answer does not dominate.
+                (record.successes * decay + 1.0) / ((record.successes + record.failures) * decay + 2.0)
+            }
+            None => 0.5,
+        }
+    }
+}
+
+/// The digests of the batches the `Synchronizer` is still trying to get from the other mempools,
+/// shared so that the sync progress can be observed from outside.
+#[derive(Clone, Default)]
+pub struct MissingBatches {
+    digests: Arc<Mutex<HashSet<Digest>>>,
+}
+
+impl MissingBatches {
+    /// Returns the digests that are currently missing.
+    pub fn snapshot(&self) -> HashSet<Digest> {
+        self.digests.lock().unwrap().clone()
+    }
+
+    fn insert(&self, digest: Digest) {
+        self.digests.lock().unwrap().insert(digest);
+    }
+
+    fn remove(&self, digest: &Digest) {
+        self.digests.lock().unwrap().remove(digest);
+    }
+
+    fn retain<F: FnMut(&Digest) -> bool>(&self, f: F) {
+        self.digests.lock().unwrap().retain(f);
+    }
+}
+
 // The `Synchronizer` is responsible to keep the mempool in sync with the others.
 pub struct Synchronizer {
     /// The public key of this authority.
     name: PublicKey,
-    /// The committee information.
-    committee: Committee,
+    /// The committee information, which may be reloaded at runtime. Each sync request is sent to
+    /// the members of the committee at the time it is sent.
+    committee: SharedCommittee,
     // The persistent storage.
     store: Store,
     /// The depth of the garbage collection.
     gc_depth: Round,
     /// The delay to wait before re-trying to send sync requests.
-    sync_retry_delay: u64,
+    sync_retry_backoff: RetryBackoff,
     /// Determine with how many nodes to sync when re-trying to send sync-requests. These nodes
-    /// are picked at random from the committee.
+    /// are picked from the committee by `peer_scores`.
     sync_retry_nodes: usize,
+    /// The response rates of the other mempools to our sync requests.
+    peer_scores: PeerScores,
+    /// The rounds in which our batches were stored. Advanced and pruned on cleanup.
+    batch_index: BatchIndex,
     /// Input channel to receive the commands from the consensus.
     rx_message: Receiver<ConsensusMempoolMessage>,
     /// A network sender to send requests to the other mempools.
@@ -44,8 +204,12 @@ pub struct Synchronizer {
     round: Round,
     /// Keeps the digests (of batches) that are waiting to be processed by the consensus. Their
     /// processing will resume when we get the missing batches in the store or we no longer need them.
-    /// It also keeps the round number and a timestamp (`u128`) of each request we sent.
-    pending: HashMap<Digest, (Round, Sender<()>, u128)>,
+    /// It also keeps the round number, a timestamp (`u128`) and the peers of the last request we sent.
+    pending: HashMap<Digest, (Round, Sender<()>, u128, Vec<PublicKey>)>,
+    /// The digests of `pending`, readable from other tasks.
+    missing: MissingBatches,
+    /// The format in which our batch requests are serialized.
+    codec: Codec,
     /// validator id.
     validator_id: u64,
     /// Exit
@@ -56,11 +220,18 @@ impl Synchronizer {
     #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         name: PublicKey,
-        committee: Committee,
+        committee: SharedCommittee,
         store: Store,
         gc_depth: Round,
         sync_retry_delay: u64,
+        sync_retry_backoff_factor: f64,
+        sync_retry_max_delay: u64,
         sync_retry_nodes: usize,
+        sync_peer_score_window: u64,
+        batch_index: BatchIndex,
+        missing: MissingBatches,
+        codec: Codec,
+        tcp_settings: TcpSettings,
         rx_message: Receiver<ConsensusMempoolMessage>,
         validator_id: u64,
         exit: exit_future::Exit
@@ -71,12 +242,20 @@ impl Synchronizer {
                 committee,
                 store,
                 gc_depth,
-                sync_retry_delay,
+                sync_retry_backoff: RetryBackoff::new(
+                    sync_retry_delay,
+                    sync_retry_backoff_factor,
+                    sync_retry_max_delay,
+                ),
                 sync_retry_nodes,
+                peer_scores: PeerScores::new(Duration::from_millis(sync_peer_score_window)),
+                batch_index,
                 rx_message,
-                network: SimpleSender::new(),
+                network: SimpleSender::with_tcp_settings(tcp_settings),
                 round: Round::default(),
                 pending: HashMap::new(),
+                missing,
+                codec,
                 validator_id: validator_id,
                 exit: exit
             }
@@ -120,12 +299,25 @@ impl Synchronizer {
                             .as_millis();
 
                         let mut missing = Vec::new();
+                        let mut local = 0;
                         for digest in digests {
                             // Ensure we do not send twice the same sync request.
                             if self.pending.contains_key(&digest) {
                                 continue;
                             }
 
+                            // The batch may have reached our store since the consensus looked for it
+                            // (e.g. out of order), in which case there is nothing to request.
+                            match self.store.read(digest.to_vec()).await {
+                                Ok(Some(_)) => {
+                                    debug!("Batch {} is already stored, not requesting it", digest);
+                                    local += 1;
+                                    continue;
+                                }
+                                Ok(None) => (),
+                                Err(e) => error!("{}", e),
+                            }
+
                             // Register the digest as missing.
                             missing.push(digest.clone());
                             debug!("Requesting sync for batch {}", digest);
@@ -135,12 +327,23 @@ impl Synchronizer {
                             let (tx_cancel, rx_cancel) = channel(1);
                             let fut = Self::waiter(digest.clone(), self.store.clone(), deliver, rx_cancel);
                             waiting.push(fut);
-                            self.pending.insert(digest, (self.round, tx_cancel, now));
+                            self.missing.insert(digest.clone());
+                            self.pending.insert(digest, (self.round, tx_cancel, now, vec![target]));
+                        }
+                        if local > 0 {
+                            metrics::inc_counter_vec_by(
+                                &metrics::SYNC_DIGESTS_LOCAL,
+                                &[&self.validator_id.to_string()],
+                                local,
+                            );
+                        }
+                        if missing.is_empty() {
+                            continue;
                         }
 
                         // Send sync request to a single node. If this fails, we will send it
                         // to other nodes when a timer times out.
-                        let address = match self.committee.mempool_address(&target) {
+                        let address = match self.committee.read().unwrap().mempool_address(&target) {
                             Some(address) => address,
                             None => {
                                 error!("Consensus asked us to sync with an unknown node: {}", target);
@@ -148,7 +351,7 @@ impl Synchronizer {
                             }
                         };
                         let message = MempoolMessage::BatchRequest(missing, self.name);
-                        let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
+                        let serialized = encode_message(self.codec, &message).expect("Failed to serialize our own message");
                         
                         let dvf_message = DvfMessage { version: VERSION, validator_id: self.validator_id, message: serialized};
                         let serialized_msg = bincode::serialize(&dvf_message).unwrap();
@@ -158,26 +361,61 @@ impl Synchronizer {
                     ConsensusMempoolMessage::Cleanup(round) => {
                         // Keep track of the consensus' round number.
                         self.round = round;
+                        // Delete the committed batches the consensus can no longer reference.
+                        let reclaimed = self.batch_index.advance(round, self.round.checked_sub(self.gc_depth));
+                        if !reclaimed.is_empty() {
+                            debug!("Deleting {} batches committed at or before round {}", reclaimed.len(), round - self.gc_depth);
+                            for digest in &reclaimed {
+                                self.store.delete(digest.to_vec()).await;
+                            }
+                            metrics::inc_counter_vec_by(
+                                &metrics::BATCHES_RECLAIMED,
+                                &[&self.validator_id.to_string()],
+                                reclaimed.len() as u64,
+                            );
+                        }
 
                         // Cleanup internal state.
                         if self.round < self.gc_depth {
                             continue;
                         }
                         let mut gc_round = self.round - self.gc_depth;
-                        for (r, handler, _) in self.pending.values() {
+                        for (r, handler, _, _) in self.pending.values() {
                             if r <= &gc_round {
                                 let _ = handler.send(()).await;
                             }
                         }
-                        self.pending.retain(|_, (r, _, _)| r > &mut gc_round);
+                        self.pending.retain(|_, (r, _, _, _)| r > &mut gc_round);
+                        let pending = &self.pending;
+                        self.missing.retain(|digest| pending.contains_key(digest));
+                    },
+                    ConsensusMempoolMessage::Committed(round, digests) => {
+                        let committed = self.batch_index.commit(round, &digests);
+                        debug!("Consensus committed {} batches in round {} ({} newly)", digests.len(), round, committed);
+                        metrics::inc_counter_vec_by(
+                            &metrics::BATCHES_COMMITTED,
+                            &[&self.validator_id.to_string()],
+                            committed as u64,
+                        );
                     }
                 },
 
                 // Stream out the futures of the `FuturesUnordered` that completed.
                 Some(result) = waiting.next() => match result {
                     Ok(Some(digest)) => {
-                        // We got the batch, remove it from the pending list.
-                        self.pending.remove(&digest);
+                        // We got the batch, remove it from the pending list and credit the peers
+                        // we last asked for it.
+                        self.missing.remove(&digest);
+                        if let Some((_, _, _, peers)) = self.pending.remove(&digest) {
+                            for peer in &peers {
+                                self.peer_scores.record_success(peer);
+                            }
+                        }
+                        metrics::inc_counter_vec(
+                            &metrics::SYNC_DIGESTS_FETCHED,
+                            &[&self.validator_id.to_string()],
+                        );
+                        self.sync_retry_backoff.reset();
                     },
                     Ok(None) => {
                         // The sync request for this batch has been canceled.
@@ -189,37 +427,51 @@ impl Synchronizer {
                 () = &mut timer => {
                     // We optimistically sent sync requests to a single node. If this timer triggers,
                     // it means we were wrong to trust it. We are done waiting for a reply and we now
-                    // broadcast the request to a bunch of other nodes (selected at random).
+                    // broadcast the request to a bunch of other nodes (the ones that answered best so far).
                     let now = SystemTime::now()
                         .duration_since(UNIX_EPOCH)
                         .expect("Failed to measure time")
                         .as_millis();
                     
-                    let addresses: Vec<SocketAddr> = self.committee
-                        .broadcast_addresses(&self.name)
+                    let peers = self.committee.read().unwrap().broadcast_addresses(&self.name);
+                    let addresses: Vec<SocketAddr> = peers
                         .iter()
                         .map(|(_, address)| *address)
                         .collect();
 
                     match timeout(Duration::from_millis(TIMER_RESOLUTION), self.network.broadcast_flush(addresses.clone())).await {
                         Ok(_) => {
+                            let selected = self.peer_scores.select(peers, self.sync_retry_nodes);
+                            let selected_names: Vec<PublicKey> = selected.iter().map(|(name, _)| *name).collect();
+
                             let mut retry = Vec::new();
-                            for (digest, (_, _, timestamp)) in self.pending.iter_mut() {
-                                if *timestamp + (self.sync_retry_delay as u128) < now {
+                            let mut failed_peers = Vec::new();
+                            let retry_delay = self.sync_retry_backoff.delay();
+                            for (digest, (_, _, timestamp, peers)) in self.pending.iter_mut() {
+                                if *timestamp + (retry_delay as u128) < now {
                                     debug!("Requesting sync for batch {} (retry)", digest);
                                     retry.push(digest.clone());
                                     *timestamp = now;
+                                    // The peers we asked last time did not deliver in time.
+                                    failed_peers.append(peers);
+                                    *peers = selected_names.clone();
                                 }
                             }
+                            for peer in &failed_peers {
+                                self.peer_scores.record_failure(peer);
+                            }
                             if !retry.is_empty() {
                                 let message = MempoolMessage::BatchRequest(retry, self.name);
-                                let serialized = bincode::serialize(&message).expect("Failed to serialize our own message");
+                                let serialized = encode_message(self.codec, &message).expect("Failed to serialize our own message");
                                 let dvf_message = DvfMessage { version: VERSION, validator_id: self.validator_id, message: serialized};
                                 let serialized_msg = bincode::serialize(&dvf_message).unwrap();
-                                info!("[MemSYNC] Lucky broacasting to {:?}", addresses);
+                                let selected_addresses: Vec<SocketAddr> = selected.iter().map(|(_, address)| *address).collect();
+                                let selected_scores: Vec<f64> = selected_names.iter().map(|name| self.peer_scores.score(name)).collect();
+                                info!("[MemSYNC] Broadcasting sync request to {:?} (scores {:?})", selected_addresses, selected_scores);
                                 self.network
-                                    .lucky_broadcast_feed(addresses, Bytes::from(serialized_msg), self.sync_retry_nodes)
+                                    .broadcast_feed(selected_addresses, Bytes::from(serialized_msg))
                                     .await;
+                                self.sync_retry_backoff.on_failure();
                             }
                         },
                         Err(_) => {
@@ -0,0 +1,117 @@
+use super::*;
+
+/// The number of batches stored during each round still indexed.
+fn stored(index: &BatchIndex) -> Vec<(Round, usize)> {
+    index
+        .commit_breakdown()
+        .into_iter()
+        .map(|r| (r.round, r.committed + r.uncommitted))
+        .collect()
+}
+
+#[test]
+fn advance_and_prune() {
+    let index = BatchIndex::new();
+    let digests: Vec<_> = (0..4u8).map(|i| Digest([i; 32])).collect();
+
+    // Store a batch in each of rounds 0, 1, 2 and two in round 5.
+    index.insert(digests[0].clone());
+    index.advance(1, None);
+    index.insert(digests[1].clone());
+    index.advance(2, None);
+    index.insert(digests[2].clone());
+    index.advance(5, None);
+    index.insert(digests[3].clone());
+    index.insert(digests[0].clone());
+    assert_eq!(stored(&index), vec![(0, 1), (1, 1), (2, 1), (5, 2)]);
+
+    // An older round does not move the index back.
+    index.advance(3, None);
+    index.insert(digests[1].clone());
+    assert_eq!(stored(&index), vec![(0, 1), (1, 1), (2, 1), (5, 3)]);
+
+    // Rounds up to the gc round are forgotten.
+    index.advance(6, Some(1));
+    assert_eq!(stored(&index), vec![(2, 1), (5, 3)]);
+}
+
+#[test]
+fn return_committed_digests() {
+    let index = BatchIndex::new();
+    let digests: Vec<_> = (0..4u8).map(|i| Digest([i; 32])).collect();
+
+    // Store two batches in round 0 and a third one in round 1, and commit them in round 2.
+    index.insert(digests[0].clone());
+    index.insert(digests[1].clone());
+    index.advance(1, None);
+    index.insert(digests[2].clone());
+    index.advance(2, None);
+    assert_eq!(index.commit(2, &digests[..3]), 3);
+
+    // A batch stored in round 0 but never committed is not returned.
+    index.advance(3, None);
+    index.insert(digests[3].clone());
+
+    // Batches are returned once the round in which they were committed is collected, not the one
+    // in which they were stored.
+    assert!(index.advance(3, Some(1)).is_empty());
+    assert_eq!(index.advance(4, Some(2)), digests[..3].to_vec());
+    assert!(index.advance(4, Some(2)).is_empty());
+    assert!(index.advance(6, Some(4)).is_empty());
+    assert!(index.commit_breakdown().is_empty());
+}
+
+#[test]
+fn keep_digests_stored_again() {
+    let index = BatchIndex::new();
+    let digest = Digest([0; 32]);
+
+    // A batch stored in round 0, committed in round 1 and stored again in round 3.
+    index.insert(digest.clone());
+    index.advance(1, None);
+    index.commit(1, &[digest.clone()]);
+    index.advance(3, None);
+    index.insert(digest.clone());
+
+    // It is not returned while it is still indexed, and counts as uncommitted again.
+    assert!(index.advance(3, Some(1)).is_empty());
+    assert_eq!(
+        index.commit_breakdown(),
+        vec![RoundCommits { round: 3, committed: 0, uncommitted: 1 }]
+    );
+    assert_eq!(index.commit(3, &[digest.clone()]), 1);
+    assert_eq!(index.advance(5, Some(3)), vec![digest]);
+}
+
+#[test]
+fn commit_accounting() {
+    let index = BatchIndex::new();
+    let digests: Vec<_> = (0..5u8).map(|i| Digest([i; 32])).collect();
+
+    // Store two batches in round 0 and three in round 1.
+    index.insert(digests[0].clone());
+    index.insert(digests[1].clone());
+    index.advance(1, None);
+    for digest in &digests[2..] {
+        index.insert(digest.clone());
+    }
+
+    // Committing a batch twice only counts once, and batches we do not store are ignored.
+    assert_eq!(index.commit(1, &[digests[0].clone(), digests[2].clone()]), 2);
+    assert_eq!(index.commit(1, &[digests[2].clone(), Digest([9; 32])]), 0);
+    assert_eq!(
+        index.commit_breakdown(),
+        vec![
+            RoundCommits { round: 0, committed: 1, uncommitted: 1 },
+            RoundCommits { round: 1, committed: 1, uncommitted: 2 },
+        ]
+    );
+
+    // Pruned rounds drop out of the breakdown.
+    index.commit(1, &digests[3..]);
+    index.advance(2, Some(0));
+    assert_eq!(
+        index.commit_breakdown(),
+        vec![RoundCommits { round: 1, committed: 3, uncommitted: 0 }]
+    );
+}
@@ -1,6 +1,10 @@
 use super::*;
 use crate::common::transaction;
-use tokio::sync::mpsc::channel;
+use crate::config::Committee;
+use tokio::sync::mpsc::{channel, Sender};
+use tokio::task::yield_now;
+use tokio::time::{advance, pause};
+use utils::monitored_channel::MonitoredChannel;
 
 #[tokio::test]
 async fn make_batch() {
@@ -18,12 +22,12 @@ async fn make_batch() {
     );
 
     // Send enough transactions to seal a batch.
-    tx_transaction.send(transaction()).await.unwrap();
-    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(transaction().into()).await.unwrap();
+    tx_transaction.send(transaction().into()).await.unwrap();
 
     // Ensure the batch is as expected.
     let expected_batch = vec![transaction(), transaction()];
-    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
     match bincode::deserialize(&batch).unwrap() {
         MempoolMessage::Batch(batch) => assert_eq!(batch, expected_batch),
         _ => panic!("Unexpected message"),
@@ -46,13 +50,368 @@ async fn batch_timeout() {
     );
 
     // Do not send enough transactions to seal a batch..
-    tx_transaction.send(transaction()).await.unwrap();
+    tx_transaction.send(transaction().into()).await.unwrap();
 
     // Ensure the batch is as expected.
     let expected_batch = vec![transaction()];
-    let QuorumWaiterMessage { batch, handlers: _ } = rx_message.recv().await.unwrap();
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
     match bincode::deserialize(&batch).unwrap() {
         MempoolMessage::Batch(batch) => assert_eq!(batch, expected_batch),
         _ => panic!("Unexpected message"),
     }
 }
+
+#[tokio::test]
+async fn drop_duplicate_transactions() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_message, mut rx_message) =
+        MonitoredChannel::new(1, "test-batch-maker".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    let (tx_drained, _rx_drained) = exit_future::signal();
+    let (_quorum_waiter_signal, quorum_waiter_drained) = exit_future::signal();
+
+    // Spawn a `BatchMaker` instance.
+    BatchMaker::spawn(
+        BatchMakerParams {
+            dedup_cache_size: 10,
+            ..params(200, 1_000_000) // Ensure the timer is not triggered.
+        },
+        rx_transaction,
+        tx_message,
+        exit,
+        tx_drained,
+        quorum_waiter_drained,
+    );
+
+    // Send the same transaction twice, then a different one to seal the batch.
+    tx_transaction.send(transaction().into()).await.unwrap();
+    tx_transaction.send(transaction().into()).await.unwrap();
+    tx_transaction.send(vec![1; 100].into()).await.unwrap();
+
+    // Ensure the duplicate was dropped.
+    let expected_batch = vec![transaction(), vec![1; 100]];
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    match bincode::deserialize(&batch).unwrap() {
+        MempoolMessage::Batch(batch) => assert_eq!(batch, expected_batch),
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[tokio::test]
+async fn cap_inflight_batches() {
+    let (tx_transaction, rx_transaction) = channel(100);
+    let (tx_message, mut rx_message) =
+        MonitoredChannel::new(100, "test-batch-maker".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    let (tx_drained, _rx_drained) = exit_future::signal();
+    let (_quorum_waiter_signal, quorum_waiter_drained) = exit_future::signal();
+    let max_inflight_batches = 3;
+
+    // Spawn a `BatchMaker` instance sealing a batch for every transaction.
+    BatchMaker::spawn(
+        BatchMakerParams {
+            max_inflight_batches,
+            ..params(1, 1_000_000) // Ensure the timer is not triggered.
+        },
+        rx_transaction,
+        tx_message,
+        exit,
+        tx_drained,
+        quorum_waiter_drained,
+    );
+
+    // Send a burst of transactions larger than the cap.
+    for i in 0..10u8 {
+        tx_transaction.send(vec![i; 10].into()).await.unwrap();
+    }
+
+    // Play the `QuorumWaiter`, holding on to the batches: no more than the cap are ever sealed.
+    let mut inflight = Vec::new();
+    for _ in 0..max_inflight_batches {
+        inflight.push(rx_message.recv().await.unwrap());
+    }
+    sleep(Duration::from_millis(100)).await;
+    assert!(rx_message.try_recv().is_err());
+
+    // Completing a batch lets exactly one more through.
+    inflight.remove(0);
+    inflight.push(rx_message.recv().await.unwrap());
+    sleep(Duration::from_millis(100)).await;
+    assert!(rx_message.try_recv().is_err());
+    assert_eq!(inflight.len(), max_inflight_batches);
+}
+
+#[tokio::test]
+async fn batch_delay_starts_at_first_transaction() {
+    let (tx_transaction, rx_transaction) = channel(10);
+    let (tx_message, mut rx_message) =
+        MonitoredChannel::new(10, "test-batch-maker".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    let (tx_drained, _rx_drained) = exit_future::signal();
+    let (_quorum_waiter_signal, quorum_waiter_drained) = exit_future::signal();
+    // Drive the clock by hand, so that the test does not depend on the load of the machine.
+    pause();
+
+    // Spawn a `BatchMaker` instance that only seals on its timer.
+    BatchMaker::spawn(
+        params(1_000_000, 400),
+        rx_transaction,
+        tx_message,
+        exit,
+        tx_drained,
+        quorum_waiter_drained,
+    );
+
+    // Inject transactions at sub-delay spacing, straddling the point where a fixed 400ms clock
+    // started at spawn time would have ticked. The batch maker takes each transaction in before
+    // the clock moves on.
+    advance(Duration::from_millis(300)).await;
+    tx_transaction.send(vec![1; 10].into()).await.unwrap();
+    let first = Instant::now();
+    yield_now().await;
+    advance(Duration::from_millis(200)).await;
+    assert!(rx_message.try_recv().is_err());
+    tx_transaction.send(vec![2; 10].into()).await.unwrap();
+
+    // Ensure both transactions end up in the same batch, sealed 400ms after the first one.
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert_sealed_after(first, Duration::from_millis(400));
+    match bincode::deserialize(&batch).unwrap() {
+        MempoolMessage::Batch(batch) => assert_eq!(batch, vec![vec![1; 10], vec![2; 10]]),
+        _ => panic!("Unexpected message"),
+    }
+
+    // The next batch starts its own delay with its first transaction.
+    advance(Duration::from_millis(300)).await;
+    tx_transaction.send(vec![3; 10].into()).await.unwrap();
+    let first = Instant::now();
+    yield_now().await;
+    advance(Duration::from_millis(200)).await;
+    assert!(rx_message.try_recv().is_err());
+    let QuorumWaiterMessage { batch, .. } = rx_message.recv().await.unwrap();
+    assert_sealed_after(first, Duration::from_millis(400));
+    match bincode::deserialize(&batch).unwrap() {
+        MempoolMessage::Batch(batch) => assert_eq!(batch, vec![vec![3; 10]]),
+        _ => panic!("Unexpected message"),
+    }
+}
+
+/// The settings of a `BatchMaker` sealing batches of `batch_size` bytes or after `max_batch_delay`
+/// ms, with every optional check disabled and no other mempool to broadcast to.
+fn params(batch_size: usize, max_batch_delay: u64) -> BatchMakerParams {
+    BatchMakerParams {
+        batch_size,
+        max_batch_delay,
+        dedup_cache_size: 0,
+        max_inflight_batches: 0,
+        compression: false,
+        codec: Codec::Bincode,
+        policy: None,
+        validator: None,
+        signer: None,
+        tcp_settings: TcpSettings::default(),
+        name: PublicKey::default(),
+        committee: Arc::new(std::sync::RwLock::new(Committee::new(Vec::new(), 0))),
+        validator_id: 0,
+    }
+}
+
+/// Asserts that `delay` elapsed since `start`. The timer wheel rounds deadlines up to the next
+/// millisecond.
+fn assert_sealed_after(start: Instant, delay: Duration) {
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed >= delay && elapsed <= delay + Duration::from_millis(1),
+        "sealed after {:?}",
+        elapsed
+    );
+}
+
+/// Spawns a `BatchMaker` sealing a batch every 20 bytes and applying `policy`. The returned signals
+/// keep it running.
+fn spawn_with_policy(
+    policy: BatchPolicy,
+    validator_id: u64,
+) -> (
+    Sender<IngressTransaction>,
+    Receiver<QuorumWaiterMessage>,
+    Vec<exit_future::Signal>,
+) {
+    spawn_with_checks(Some(policy), None, validator_id)
+}
+
+/// Spawns a `BatchMaker` sealing a batch every 20 bytes, applying `policy` and `validator` if set.
+/// The returned signals keep it running.
+fn spawn_with_checks(
+    policy: Option<BatchPolicy>,
+    validator: Option<TransactionValidator>,
+    validator_id: u64,
+) -> (
+    Sender<IngressTransaction>,
+    Receiver<QuorumWaiterMessage>,
+    Vec<exit_future::Signal>,
+) {
+    let (tx_transaction, rx_transaction) = channel(10);
+    let (tx_message, rx_message) =
+        MonitoredChannel::new(10, "test-batch-maker".to_string(), "debug");
+    let (signal, exit) = exit_future::signal();
+    let (tx_drained, _rx_drained) = exit_future::signal();
+    let (quorum_waiter_signal, quorum_waiter_drained) = exit_future::signal();
+    BatchMaker::spawn(
+        BatchMakerParams {
+            policy,
+            validator,
+            validator_id,
+            ..params(20, 1_000_000) // Ensure the timer is not triggered.
+        },
+        rx_transaction,
+        tx_message,
+        exit,
+        tx_drained,
+        quorum_waiter_drained,
+    );
+    (tx_transaction, rx_message, vec![signal, quorum_waiter_signal])
+}
+
+fn sealed_batch(message: QuorumWaiterMessage) -> Batch {
+    match bincode::deserialize(&message.batch).unwrap() {
+        MempoolMessage::Batch(batch) => batch,
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[tokio::test]
+async fn policy_accepts_batch() {
+    let policy: BatchPolicy = Arc::new(|_: &Batch| BatchDecision::Accept);
+    let (tx_transaction, mut rx_message, _signals) = spawn_with_policy(policy, 0);
+
+    tx_transaction.send(vec![1; 10].into()).await.unwrap();
+    tx_transaction.send(vec![2; 10].into()).await.unwrap();
+    let batch = sealed_batch(rx_message.recv().await.unwrap());
+    assert_eq!(batch, vec![vec![1; 10], vec![2; 10]]);
+}
+
+#[tokio::test]
+async fn policy_drops_transactions() {
+    // Drop the transactions starting with a 9, and any index past the end of the batch.
+    let policy: BatchPolicy = Arc::new(|batch: &Batch| {
+        let mut indices: Vec<_> = (0..batch.len()).filter(|i| batch[*i][0] == 9).collect();
+        indices.push(batch.len());
+        BatchDecision::DropTransactions(indices)
+    });
+    let validator_id = 9_101;
+    let (tx_transaction, mut rx_message, _signals) = spawn_with_policy(policy, validator_id);
+
+    tx_transaction.send(vec![9; 10].into()).await.unwrap();
+    tx_transaction.send(vec![2; 10].into()).await.unwrap();
+    let batch = sealed_batch(rx_message.recv().await.unwrap());
+    assert_eq!(batch, vec![vec![2; 10]]);
+
+    let label = validator_id.to_string();
+    let dropped =
+        metrics::get_int_counter(&metrics::TRANSACTIONS_DROPPED_BY_POLICY, &[&label]).unwrap();
+    assert_eq!(dropped.get(), 1);
+}
+
+#[tokio::test]
+async fn policy_rejects_batch() {
+    let policy: BatchPolicy = Arc::new(|batch: &Batch| match batch.iter().any(|tx| tx[0] == 9) {
+        true => BatchDecision::RejectBatch,
+        false => BatchDecision::Accept,
+    });
+    let validator_id = 9_102;
+    let (tx_transaction, mut rx_message, _signals) = spawn_with_policy(policy, validator_id);
+
+    // The first batch is discarded, the second one goes through.
+    tx_transaction.send(vec![9; 10].into()).await.unwrap();
+    tx_transaction.send(vec![1; 10].into()).await.unwrap();
+    tx_transaction.send(vec![2; 10].into()).await.unwrap();
+    tx_transaction.send(vec![3; 10].into()).await.unwrap();
+    let batch = sealed_batch(rx_message.recv().await.unwrap());
+    assert_eq!(batch, vec![vec![2; 10], vec![3; 10]]);
+
+    let label = validator_id.to_string();
+    let rejected =
+        metrics::get_int_counter(&metrics::BATCHES_REJECTED_BY_POLICY, &[&label]).unwrap();
+    assert_eq!(rejected.get(), 1);
+}
+
+#[tokio::test]
+async fn record_ingress_to_seal_latency() {
+    let policy: BatchPolicy = Arc::new(|_: &Batch| BatchDecision::Accept);
+    // A validator id that no other test uses, so the histogram starts empty.
+    let validator_id = 9_103;
+    let (tx_transaction, mut rx_message, _signals) = spawn_with_policy(policy, validator_id);
+
+    // One transaction has been waiting for a while already, the other just arrived.
+    let early = IngressTransaction {
+        transaction: vec![1; 10],
+        received_at: Instant::now() - Duration::from_millis(50),
+    };
+    tx_transaction.send(early).await.unwrap();
+    tx_transaction.send(vec![2; 10].into()).await.unwrap();
+    rx_message.recv().await.unwrap();
+
+    // Both are recorded once the batch is sealed.
+    let label = validator_id.to_string();
+    let latency =
+        metrics::get_histogram(&metrics::TRANSACTION_INGRESS_TO_SEAL_SECONDS, &[&label]).unwrap();
+    assert_eq!(latency.get_sample_count(), 2);
+    assert!(latency.get_sample_sum() >= 0.05);
+}
+
+#[tokio::test]
+async fn validator_accepts_transactions() {
+    let validator: TransactionValidator =
+        Arc::new(|transaction: &Transaction| transaction.len() >= 10);
+    let (tx_transaction, mut rx_message, _signals) =
+        spawn_with_checks(None, Some(validator), 9_104);
+
+    tx_transaction.send(vec![1; 10].into()).await.unwrap();
+    tx_transaction.send(vec![2; 10].into()).await.unwrap();
+    let batch = sealed_batch(rx_message.recv().await.unwrap());
+    assert_eq!(batch, vec![vec![1; 10], vec![2; 10]]);
+
+    let label = 9_104.to_string();
+    let invalid = metrics::get_int_counter(&metrics::TRANSACTIONS_INVALID, &[&label]).unwrap();
+    assert_eq!(invalid.get(), 0);
+}
+
+#[tokio::test]
+async fn validator_drops_invalid_transactions() {
+    // Only accept the transactions starting with a magic byte.
+    let validator: TransactionValidator =
+        Arc::new(|transaction: &Transaction| transaction.first() == Some(&0xAA));
+    let (tx_transaction, mut rx_message, _signals) =
+        spawn_with_checks(None, Some(validator), 9_105);
+
+    // The invalid transactions do not count towards the batch size.
+    tx_transaction.send(vec![0xAA; 10].into()).await.unwrap();
+    tx_transaction.send(vec![0xBB; 10].into()).await.unwrap();
+    tx_transaction.send(Vec::new().into()).await.unwrap();
+    tx_transaction.send(vec![0xAA; 10].into()).await.unwrap();
+    let batch = sealed_batch(rx_message.recv().await.unwrap());
+    assert_eq!(batch, vec![vec![0xAA; 10], vec![0xAA; 10]]);
+
+    let label = 9_105.to_string();
+    let invalid = metrics::get_int_counter(&metrics::TRANSACTIONS_INVALID, &[&label]).unwrap();
+    assert_eq!(invalid.get(), 2);
+}
+
+#[test]
+fn evict_least_recently_seen_transaction() {
+    let mut seen = SeenTransactions::new(2);
+    assert!(seen.insert(&vec![0; 10]));
+    assert!(seen.insert(&vec![1; 10]));
+
+    // Seeing the first transaction again makes the second one the least recently seen.
+    assert!(!seen.insert(&vec![0; 10]));
+    assert!(seen.insert(&vec![2; 10]));
+    assert!(!seen.insert(&vec![0; 10]));
+    assert!(seen.insert(&vec![1; 10]));
+
+    // Deduplication is disabled with a zero capacity.
+    let mut disabled = SeenTransactions::new(0);
+    assert!(disabled.insert(&vec![0; 10]));
+    assert!(disabled.insert(&vec![0; 10]));
+}
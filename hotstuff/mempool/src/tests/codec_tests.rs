@@ -0,0 +1,52 @@
+use super::*;
+use crate::common::{batch, keys};
+use crypto::Digest;
+
+fn messages() -> Vec<MempoolMessage> {
+    let (name, _) = keys().pop().unwrap();
+    vec![
+        MempoolMessage::Batch(batch()),
+        MempoolMessage::BatchRequest(vec![Digest([1; 32]), Digest([2; 32])], name),
+    ]
+}
+
+fn round_trip(codec: Codec) {
+    for message in messages() {
+        let serialized = encode_message(codec, &message).unwrap();
+        let decoded = decode_message(codec, &serialized, 1_000_000).unwrap();
+        // `MempoolMessage` is not `PartialEq`: compare the serialized forms instead.
+        assert_eq!(encode_message(codec, &decoded).unwrap(), serialized);
+    }
+}
+
+#[test]
+fn round_trip_bincode() {
+    round_trip(Codec::Bincode);
+}
+
+#[test]
+fn round_trip_postcard() {
+    round_trip(Codec::Postcard);
+}
+
+#[test]
+fn reject_other_codec() {
+    for message in messages() {
+        let bincode = encode_message(Codec::Bincode, &message).unwrap();
+        let postcard = encode_message(Codec::Postcard, &message).unwrap();
+
+        let error = decode_message(Codec::Postcard, &bincode, 1_000_000).unwrap_err();
+        assert!(error.to_string().contains("same codec"), "{}", error);
+        let error = decode_message(Codec::Bincode, &postcard, 1_000_000).unwrap_err();
+        assert!(error.to_string().contains("same codec"), "{}", error);
+    }
+}
+
+#[test]
+fn reject_oversized_message() {
+    for codec in [Codec::Bincode, Codec::Postcard] {
+        let serialized = encode_message(codec, &MempoolMessage::Batch(batch())).unwrap();
+        let result = decode_message(codec, &serialized, serialized.len() as u64 - 1);
+        assert!(result.is_err());
+    }
+}
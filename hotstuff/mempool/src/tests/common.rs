@@ -1,6 +1,8 @@
 use crate::batch_maker::{Batch, Transaction};
 use crate::config::Committee;
-use crate::mempool::MempoolMessage;
+use crate::codec::Codec;
+use crate::mempool::{MempoolAck, MempoolMessage, MempoolReceiverHandler};
+use crate::processor::{ProcessorLoad, SerializedBatchMessage};
 use bytes::Bytes;
 use crypto::{generate_keypair, Digest, PublicKey, SecretKey};
 use ed25519_dalek::Digest as _;
@@ -11,9 +13,12 @@ use rand::rngs::StdRng;
 use rand::SeedableRng as _;
 use std::convert::TryInto as _;
 use std::net::SocketAddr;
-use tokio::net::TcpListener;
+use network::Writer;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::Receiver;
 use tokio::task::JoinHandle;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use utils::monitored_channel::MonitoredChannel;
 
 // Fixture
 pub fn keys() -> Vec<(PublicKey, SecretKey)> {
@@ -85,7 +90,7 @@ pub fn listener(address: SocketAddr, expected: Option<Bytes>) -> JoinHandle<()>
         let (mut writer, mut reader) = transport.split();
         match reader.next().await {
             Some(Ok(received)) => {
-                writer.send(Bytes::from("Ack")).await.unwrap();
+                writer.send(MempoolAck::BatchAccepted.encode()).await.unwrap();
                 if let Some(expected) = expected {
                     assert_eq!(received.freeze(), expected);
                 }
@@ -94,3 +99,39 @@ pub fn listener(address: SocketAddr, expected: Option<Bytes>) -> JoinHandle<()>
         }
     })
 }
+
+// Fixture
+/// Opens a connection to get a writer for the replies of a message handler, along with the other
+/// end of the connection to read them from.
+pub async fn reply_connection() -> (Writer, Framed<TcpStream, LengthDelimitedCodec>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let client = TcpStream::connect(address).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+    let (writer, _) = Framed::new(server, LengthDelimitedCodec::new()).split();
+    (writer, Framed::new(client, LengthDelimitedCodec::new()))
+}
+
+/// The digests of a batch request along with the mempool that asked for them.
+type BatchRequest = (Vec<Digest>, PublicKey);
+
+// Fixture
+/// A handler for the messages of the other mempools, along with the receivers of the batch
+/// requests it queues for the helper and of the batches it queues for the processor.
+pub fn handler(
+    max_message_bytes: u64,
+    load: ProcessorLoad,
+) -> (MempoolReceiverHandler, Receiver<BatchRequest>, Receiver<SerializedBatchMessage>) {
+    let (tx_helper, rx_helper) = MonitoredChannel::new(1, "test-helper".to_string(), "debug");
+    let (tx_processor, rx_processor) =
+        MonitoredChannel::new(10, "test-processor".to_string(), "debug");
+    let handler = MempoolReceiverHandler {
+        tx_helper,
+        tx_processor,
+        max_message_bytes,
+        signature_service: None,
+        processor_load: load,
+        codec: Codec::Bincode,
+    };
+    (handler, rx_helper, rx_processor)
+}
@@ -0,0 +1,29 @@
+use super::*;
+use crate::mempool::MempoolMessage;
+
+#[test]
+fn round_trip_large_batch() {
+    let batch: Vec<_> = (0..500u32).map(|i| vec![i as u8; 1_000]).collect();
+    let serialized = bincode::serialize(&MempoolMessage::Batch(batch)).unwrap();
+
+    let compressed = compress(&serialized);
+    assert_eq!(compressed[0], COMPRESSED);
+    assert!(compressed.len() < serialized.len());
+
+    let decompressed = decompress(Bytes::from(compressed), serialized.len() as u64).unwrap();
+    assert_eq!(decompressed, serialized);
+}
+
+#[test]
+fn pass_through_uncompressed() {
+    let serialized = bincode::serialize(&MempoolMessage::Batch(vec![vec![0; 10]])).unwrap();
+    let decompressed = decompress(Bytes::from(serialized.clone()), 1_000).unwrap();
+    assert_eq!(decompressed, serialized);
+}
+
+#[test]
+fn reject_oversized_decompression() {
+    let serialized = bincode::serialize(&MempoolMessage::Batch(vec![vec![0; 2_000]])).unwrap();
+    let compressed = compress(&serialized);
+    assert!(decompress(Bytes::from(compressed), 1_000).is_err());
+}
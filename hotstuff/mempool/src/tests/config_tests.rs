@@ -0,0 +1,16 @@
+use super::*;
+
+#[test]
+fn validate_backoff_factor() {
+    // The default keeps the retry delay fixed.
+    assert!(Parameters::default().validate().is_ok());
+
+    for factor in [2.0, 1.5] {
+        let parameters = Parameters { sync_retry_backoff_factor: Some(factor), ..Parameters::default() };
+        assert!(parameters.validate().is_ok());
+    }
+    for factor in [1.0, 0.5, 0.0, -2.0, f64::NAN, f64::INFINITY] {
+        let parameters = Parameters { sync_retry_backoff_factor: Some(factor), ..Parameters::default() };
+        assert!(parameters.validate().is_err(), "factor {} was accepted", factor);
+    }
+}
@@ -1,7 +1,18 @@
 use super::*;
 use crate::common::{batch_digest, committee_with_base_port, keys, listener, serialized_batch};
+use crate::config::Committee;
+use crate::mempool::MempoolMessage;
+use crypto::generate_secp256k_keypair;
+use ed25519_dalek::Digest as _;
+use ed25519_dalek::Sha512;
+use std::convert::TryInto;
+use futures::stream::StreamExt as _;
 use std::fs;
+use std::sync::{Arc, RwLock};
+use tokio::net::TcpListener;
 use tokio::sync::mpsc::channel;
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 #[tokio::test]
 async fn batch_reply() {
@@ -34,3 +45,220 @@ async fn batch_reply() {
     // Ensure the requestor received the batch (ie. it did not panic).
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn rate_limit_batch_requests() {
+    let (tx_request, rx_request) = channel(10);
+    let (_signal, exit) = exit_future::signal();
+    let (requestor, _) = generate_secp256k_keypair();
+
+    // Bind the requestor's mempool address before building the committee.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let committee = Committee::new(vec![(requestor, 1, address, address, address)], 1);
+
+    // Create a new test store.
+    let path = ".db_test_rate_limit_batch_requests";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Add a batch to the store.
+    store
+        .write(batch_digest().to_vec(), serialized_batch())
+        .await;
+
+    // Spawn an `Helper` instance that serves 2 requests per origin and never refills.
+    Helper::spawn(
+        Arc::new(RwLock::new(committee)),
+        store,
+        rx_request,
+        HelperParams {
+            batch_request_bucket_size: 2,
+            batch_request_refill_rate: 0,
+            max_reply_batches: 0,
+            max_reply_bytes: 0,
+            tcp_settings: TcpSettings::default(),
+            validator_id: 0,
+        },
+        exit,
+    );
+
+    // Flood the helper with batch requests from the same origin.
+    for _ in 0..5 {
+        tx_request.send((vec![batch_digest()], requestor)).await.unwrap();
+    }
+
+    // Ensure only the first requests of the burst are served.
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut reader = Framed::new(socket, LengthDelimitedCodec::new());
+    let mut replies = 0;
+    while let Ok(Some(Ok(_))) = timeout(Duration::from_millis(500), reader.next()).await {
+        replies += 1;
+    }
+    assert_eq!(replies, 2);
+}
+
+#[tokio::test]
+async fn ignore_requests_from_non_members() {
+    let (tx_request, rx_request) = channel(1);
+    let (_signal, exit) = exit_future::signal();
+    let (member, _) = generate_secp256k_keypair();
+    let (outsider, _) = generate_secp256k_keypair();
+
+    // Only `member` is part of the committee.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let committee = Committee::new(vec![(member, 1, address, address, address)], 1);
+
+    // Create a new test store.
+    let path = ".db_test_ignore_requests_from_non_members";
+    let _ = fs::remove_dir_all(path);
+    let mut store = Store::new(path).unwrap();
+
+    // Add a batch to the store.
+    store
+        .write(batch_digest().to_vec(), serialized_batch())
+        .await;
+
+    // Spawn an `Helper` instance.
+    Helper::spawn(
+        Arc::new(RwLock::new(committee)),
+        store,
+        rx_request,
+        HelperParams {
+            batch_request_bucket_size: 10,
+            batch_request_refill_rate: 10,
+            max_reply_batches: 0,
+            max_reply_bytes: 0,
+            tcp_settings: TcpSettings::default(),
+            validator_id: 0,
+        },
+        exit,
+    );
+
+    // Send a batch request from a key that is not in the committee.
+    tx_request.send((vec![batch_digest()], outsider)).await.unwrap();
+
+    // Ensure no reply is produced.
+    assert!(timeout(Duration::from_millis(500), listener.accept()).await.is_err());
+}
+
+#[tokio::test]
+async fn count_batch_request_misses() {
+    let (tx_request, rx_request) = channel(1);
+    let (_signal, exit) = exit_future::signal();
+    let (requestor, _) = generate_secp256k_keypair();
+    // A validator id that no other test uses, so the counter starts from zero.
+    let validator_id = 9_002;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let committee = Committee::new(vec![(requestor, 1, address, address, address)], 1);
+
+    // Create a new test store holding a single batch.
+    let path = ".db_test_count_batch_request_misses";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    store
+        .write(batch_digest().to_vec(), serialized_batch())
+        .await;
+
+    // Spawn an `Helper` instance.
+    Helper::spawn(
+        Arc::new(RwLock::new(committee)),
+        store,
+        rx_request,
+        HelperParams {
+            batch_request_bucket_size: 10,
+            batch_request_refill_rate: 10,
+            max_reply_batches: 0,
+            max_reply_bytes: 0,
+            tcp_settings: TcpSettings::default(),
+            validator_id,
+        },
+        exit,
+    );
+
+    // Request the stored batch along with one we never received.
+    let missing = Digest([1; 32]);
+    tx_request
+        .send((vec![batch_digest(), missing], requestor))
+        .await
+        .unwrap();
+
+    // Ensure the stored batch is served and the missing one is counted.
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut reader = Framed::new(socket, LengthDelimitedCodec::new());
+    assert!(reader.next().await.is_some());
+    let label = validator_id.to_string();
+    let misses = metrics::get_int_counter(&metrics::BATCH_REQUEST_MISSES, &[&label]).unwrap();
+    assert_eq!(misses.get(), 1);
+}
+
+#[tokio::test]
+async fn truncate_large_replies() {
+    let (tx_request, rx_request) = channel(10);
+    let (_signal, exit) = exit_future::signal();
+    let (requestor, _) = generate_secp256k_keypair();
+    // A validator id that no other test uses, so the counter starts from zero.
+    let validator_id = 9_003;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let committee = Committee::new(vec![(requestor, 1, address, address, address)], 1);
+
+    // Create a new test store holding a few batches of the same size.
+    let path = ".db_test_truncate_large_replies";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let batches: Vec<_> = (0..4u8)
+        .map(|i| bincode::serialize(&MempoolMessage::Batch(vec![vec![i; 100]])).unwrap())
+        .collect();
+    let mut digests = Vec::new();
+    for serialized in &batches {
+        let digest = Digest(Sha512::digest(serialized).as_slice()[..32].try_into().unwrap());
+        store.write(digest.to_vec(), serialized.clone()).await;
+        digests.push(digest);
+    }
+
+    // Spawn an `Helper` instance serving up to 3 batches, and fewer than 3 batches' worth of bytes.
+    Helper::spawn(
+        Arc::new(RwLock::new(committee)),
+        store,
+        rx_request,
+        HelperParams {
+            batch_request_bucket_size: 10,
+            batch_request_refill_rate: 10,
+            max_reply_batches: 3,
+            max_reply_bytes: 2 * batches[0].len() + 1,
+            tcp_settings: TcpSettings::default(),
+            validator_id,
+        },
+        exit,
+    );
+
+    // Request all the batches: only the first two fit in the reply.
+    tx_request.send((digests.clone(), requestor)).await.unwrap();
+    let (socket, _) = listener.accept().await.unwrap();
+    let mut reader = Framed::new(socket, LengthDelimitedCodec::new());
+    let mut replies = 0;
+    while let Ok(Some(Ok(_))) = timeout(Duration::from_millis(500), reader.next()).await {
+        replies += 1;
+    }
+    assert_eq!(replies, 2);
+
+    // The requestor asks again for the batches it still misses.
+    tx_request.send((digests[2..].to_vec(), requestor)).await.unwrap();
+    let mut replies = 0;
+    while let Ok(Some(Ok(_))) = timeout(Duration::from_millis(500), reader.next()).await {
+        replies += 1;
+    }
+    assert_eq!(replies, 2);
+
+    // Only the first request was truncated, and nothing was missing from the store.
+    let label = validator_id.to_string();
+    let truncated = metrics::get_int_counter(&metrics::BATCH_REQUESTS_TRUNCATED, &[&label]).unwrap();
+    assert_eq!(truncated.get(), 1);
+    let misses = metrics::get_int_counter(&metrics::BATCH_REQUEST_MISSES, &[&label]).unwrap();
+    assert_eq!(misses.get(), 0);
+}
@@ -1,7 +1,14 @@
 use super::*;
-use crate::common::{batch_digest, committee_with_base_port, keys, listener, transaction};
-use network::SimpleSender;
+use crate::common::{
+    batch, batch_digest, committee_with_base_port, handler, keys, listener, reply_connection,
+    transaction,
+};
+use crypto::generate_secp256k_keypair;
+use futures::stream::StreamExt as _;
+use network::{SimpleSender, TcpSettings};
 use std::fs;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::channel;
 
 #[tokio::test]
 async fn handle_clients_transactions() {
@@ -44,3 +51,370 @@ async fn handle_clients_transactions() {
     let received = rx_mempool_to_consensus.recv().await.unwrap();
     assert_eq!(batch_digest(), received);
 }
+
+#[tokio::test]
+async fn reject_malformed_message() {
+    let (handler, _rx_helper, _rx_processor) = handler(1_000, ProcessorLoad::new(0, 0));
+
+    // Open a connection to get a writer for the handler's replies.
+    let (mut writer, mut reader) = reply_connection().await;
+
+    // Dispatch bytes that are not a valid `MempoolMessage`.
+    handler
+        .dispatch(&mut writer, Bytes::from(vec![0xff; 4]))
+        .await
+        .unwrap();
+
+    // Ensure the handler replied with a rejection.
+    let reply = reader.next().await.unwrap().unwrap();
+    match MempoolAck::decode(&reply).unwrap() {
+        MempoolAck::Rejected(_) => (),
+        ack => panic!("Unexpected ack {:?}", ack),
+    }
+}
+
+#[tokio::test]
+async fn reject_oversized_message() {
+    let (handler, _rx_helper, mut rx_processor) = handler(1_000, ProcessorLoad::new(0, 0));
+
+    // Open a connection to get a writer for the handler's replies.
+    let (mut writer, mut reader) = reply_connection().await;
+
+    // A small `Batch` whose length prefix claims billions of transactions.
+    let mut crafted = 0u32.to_le_bytes().to_vec();
+    crafted.extend_from_slice(&(u32::MAX as u64).to_le_bytes());
+    crafted.extend_from_slice(&[0; 64]);
+
+    // A well-formed batch that is larger than the limit.
+    let oversized = bincode::serialize(&MempoolMessage::Batch(vec![vec![0; 2_000]])).unwrap();
+
+    for message in vec![crafted, oversized] {
+        handler
+            .dispatch(&mut writer, Bytes::from(message))
+            .await
+            .unwrap();
+
+        // Ensure the handler replied with a rejection and did not forward anything.
+        let reply = reader.next().await.unwrap().unwrap();
+        match MempoolAck::decode(&reply).unwrap() {
+            MempoolAck::Rejected(_) => (),
+            ack => panic!("Unexpected ack {:?}", ack),
+        }
+        assert!(rx_processor.try_recv().is_err());
+    }
+}
+
+#[tokio::test]
+async fn accept_compressed_batch() {
+    let (handler, _rx_helper, mut rx_processor) = handler(1_000_000, ProcessorLoad::new(0, 0));
+
+    // Open a connection to get a writer for the handler's replies.
+    let (mut writer, mut reader) = reply_connection().await;
+
+    // Send a large compressed batch.
+    let serialized = bincode::serialize(&MempoolMessage::Batch(vec![vec![0; 1_000]; 100])).unwrap();
+    handler
+        .dispatch(&mut writer, Bytes::from(compression::compress(&serialized)))
+        .await
+        .unwrap();
+
+    // Ensure the batch is acknowledged and handed over decompressed, so that its digest matches.
+    let reply = reader.next().await.unwrap().unwrap();
+    assert_eq!(MempoolAck::decode(&reply).unwrap(), MempoolAck::BatchAccepted);
+    assert_eq!(rx_processor.recv().await.unwrap(), serialized);
+}
+
+#[tokio::test]
+async fn busy_when_processor_overloaded() {
+    let processor_load = ProcessorLoad::new(/* high_water_mark */ 2, /* low_water_mark */ 1);
+    let (handler, _rx_helper, mut rx_processor) = handler(1_000, processor_load.clone());
+
+    // Open a connection to get a writer for the handler's replies.
+    let (mut writer, mut reader) = reply_connection().await;
+
+    // Simulate a processor that does not store anything: the batches pile up until the high
+    // water mark is reached, after which they are turned away.
+    let serialized = bincode::serialize(&MempoolMessage::Batch(batch())).unwrap();
+    let mut acks = Vec::new();
+    for _ in 0..3 {
+        handler.dispatch(&mut writer, Bytes::from(serialized.clone())).await.unwrap();
+        acks.push(MempoolAck::decode(&reader.next().await.unwrap().unwrap()).unwrap());
+    }
+    assert_eq!(acks, vec![MempoolAck::BatchAccepted, MempoolAck::BatchAccepted, MempoolAck::Busy]);
+    rx_processor.recv().await.unwrap();
+    rx_processor.recv().await.unwrap();
+    assert!(rx_processor.try_recv().is_err());
+
+    // Once the processor catches up to the low water mark, batches are accepted again.
+    processor_load.remove(1);
+    handler.dispatch(&mut writer, Bytes::from(serialized)).await.unwrap();
+    let ack = MempoolAck::decode(&reader.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(ack, MempoolAck::BatchAccepted);
+}
+
+#[tokio::test]
+async fn reject_oversized_transaction() {
+    let (tx_batch_maker, mut rx_batch_maker) =
+        MonitoredChannel::new(1, "test-batch-maker".to_string(), "debug");
+    let handler = TxReceiverHandler { tx_batch_maker, max_transaction_bytes: 100, nonce_tracker: None, validator_id: 0 };
+
+    // Open a connection to get a writer for the handler's replies.
+    let (mut writer, mut reader) = reply_connection().await;
+
+    // Submit a transaction larger than the limit.
+    handler
+        .dispatch(&mut writer, Bytes::from(vec![0; 101]))
+        .await
+        .unwrap();
+
+    // Ensure the handler replied with a rejection and the transaction never reached the batch maker.
+    let reply = reader.next().await.unwrap().unwrap();
+    match MempoolAck::decode(&reply).unwrap() {
+        MempoolAck::Rejected(_) => (),
+        ack => panic!("Unexpected ack {:?}", ack),
+    }
+    assert!(rx_batch_maker.try_recv().is_err());
+
+    // A transaction within the limit is forwarded as before.
+    handler
+        .dispatch(&mut writer, Bytes::from(vec![0; 100]))
+        .await
+        .unwrap();
+    assert_eq!(rx_batch_maker.recv().await.unwrap().transaction, vec![0; 100]);
+}
+
+#[tokio::test]
+async fn dispatch_after_shutdown() {
+    let (tx_batch_maker, rx_batch_maker) =
+        MonitoredChannel::new(1, "test-batch-maker".to_string(), "debug");
+    let handler = TxReceiverHandler { tx_batch_maker, max_transaction_bytes: 100, nonce_tracker: None, validator_id: 0 };
+
+    // Open a connection to get a writer for the handler's replies.
+    let (mut writer, _reader) = reply_connection().await;
+
+    // The batch maker is gone: the transaction cannot be delivered, which is an error but not a
+    // reason to panic.
+    drop(rx_batch_maker);
+    let result = handler.dispatch(&mut writer, Bytes::from(vec![0; 10])).await;
+    assert!(result.unwrap_err().to_string().contains("Failed to send transaction"));
+}
+
+#[tokio::test]
+async fn drain_pipeline_on_exit() {
+    let (tx_transaction, rx_transaction) = channel(1);
+    let (tx_quorum_waiter, rx_quorum_waiter) =
+        MonitoredChannel::new(1, "test-quorum-waiter".to_string(), "debug");
+    let (tx_processor, rx_processor) =
+        MonitoredChannel::new(1, "test-processor".to_string(), "debug");
+    let (tx_digest, mut rx_digest) = MonitoredChannel::new(1, "test-digest".to_string(), "debug");
+    let (signal, exit) = exit_future::signal();
+    let (batch_maker_drained, quorum_waiter_exit) = exit_future::signal();
+    let (quorum_waiter_drained, processor_exit) = exit_future::signal();
+
+    // A committee of two, where the peer acknowledges our batch.
+    let (name, _) = generate_secp256k_keypair();
+    let (peer, _) = generate_secp256k_keypair();
+    let peer_address = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let _handle = listener(peer_address, None);
+    let committee = Committee::new(
+        vec![
+            (name, 1, peer_address, peer_address, peer_address),
+            (peer, 1, peer_address, peer_address, peer_address),
+        ],
+        1,
+    );
+
+    // Create a new test store.
+    let path = ".db_test_drain_pipeline_on_exit";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn the pipeline with a batch maker that never seals on its own.
+    BatchMaker::spawn(
+        BatchMakerParams {
+            batch_size: 1_000_000,
+            max_batch_delay: 1_000_000,
+            dedup_cache_size: 0,
+            max_inflight_batches: 0,
+            compression: false,
+            codec: Codec::Bincode,
+            policy: None,
+            validator: None,
+            signer: None,
+            tcp_settings: TcpSettings::default(),
+            name,
+            committee: Arc::new(std::sync::RwLock::new(committee.clone())),
+            validator_id: 0,
+        },
+        rx_transaction,
+        tx_quorum_waiter,
+        exit,
+        batch_maker_drained,
+        processor_exit.clone(),
+    );
+    QuorumWaiter::spawn(
+        Arc::new(std::sync::RwLock::new(committee.clone())),
+        /* stake */ 1,
+        QuorumWaiterParams {
+            quorum_timeout: 5_000,
+            quorum_threshold_override: None,
+            gc_depth: 50,
+            batch_index: BatchIndex::new(),
+        },
+        rx_quorum_waiter,
+        tx_processor,
+        quorum_waiter_exit,
+        quorum_waiter_drained,
+    );
+    Processor::spawn(
+        store.clone(),
+        rx_processor,
+        tx_digest,
+        ProcessorParams {
+            validator_id: 0,
+            from_peers: false,
+            codec: Codec::Bincode,
+            signed_batches: false,
+            committee: Arc::new(std::sync::RwLock::new(committee.clone())),
+            store_batch_size: 1,
+            max_store_delay: 10,
+            max_forward_retries: Some(0),
+            forward_retry_delay: 0,
+            batch_index: BatchIndex::new(),
+            load: None,
+        },
+        processor_exit,
+    );
+
+    // Submit a transaction and immediately trigger exit.
+    tx_transaction.send(transaction().into()).await.unwrap();
+    signal.fire().unwrap();
+
+    // Ensure the partial batch went through the pipeline and was persisted.
+    let digest = rx_digest.recv().await.unwrap();
+    let stored = store.read(digest.to_vec()).await.unwrap().unwrap();
+    match bincode::deserialize(&stored).unwrap() {
+        MempoolMessage::Batch(batch) => assert_eq!(batch, vec![transaction()]),
+        _ => panic!("Unexpected message"),
+    }
+}
+
+#[tokio::test]
+async fn report_queue_depths() {
+    let (tx_batch_maker, mut rx_batch_maker) =
+        MonitoredChannel::new(10, "test-batch-maker".to_string(), "debug");
+    let (tx_quorum_waiter, _rx_quorum_waiter) =
+        MonitoredChannel::new(10, "test-quorum-waiter".to_string(), "debug");
+    let (tx_processor, _rx_processor) =
+        MonitoredChannel::new(10, "test-processor".to_string(), "debug");
+    let path = ".db_test_report_queue_depths";
+    let _ = fs::remove_dir_all(path);
+    let stats = MempoolStats {
+        tx_batch_maker: tx_batch_maker.clone(),
+        tx_quorum_waiter,
+        tx_processor: tx_processor.clone(),
+        missing_batches: MissingBatches::default(),
+        batch_index: BatchIndex::new(),
+        committee: Arc::new(std::sync::RwLock::new(committee_with_base_port(0))),
+        store: Store::new(path).unwrap(),
+    };
+    assert_eq!(stats.batch_maker_depth(), 0);
+
+    // Queue a few messages.
+    tx_batch_maker.send(transaction().into()).await.unwrap();
+    tx_batch_maker.send(transaction().into()).await.unwrap();
+    tx_processor.send(vec![0u8; 10]).await.unwrap();
+    assert_eq!(stats.batch_maker_depth(), 2);
+    assert_eq!(stats.quorum_waiter_depth(), 0);
+    assert_eq!(stats.processor_depth(), 1);
+
+    // Consuming a message reduces the depth.
+    rx_batch_maker.recv().await.unwrap();
+    assert_eq!(stats.batch_maker_depth(), 1);
+}
+
+#[tokio::test]
+async fn signed_ack_round_trip() {
+    let (name, secret) = keys().pop().unwrap();
+    let (other, _) = generate_secp256k_keypair();
+    let (mut handler, _rx_helper, mut rx_processor) = handler(1_000, ProcessorLoad::new(0, 0));
+    handler.signature_service = Some(SignatureService::new(secret));
+
+    // Open a connection to get a writer for the handler's replies.
+    let (mut writer, mut reader) = reply_connection().await;
+
+    // Dispatch a batch.
+    let serialized = bincode::serialize(&MempoolMessage::Batch(vec![transaction()])).unwrap();
+    handler
+        .dispatch(&mut writer, Bytes::from(serialized.clone()))
+        .await
+        .unwrap();
+    assert_eq!(rx_processor.recv().await.unwrap(), serialized);
+
+    // Ensure the ack is signed by us over the digest of that batch only.
+    let reply = reader.next().await.unwrap().unwrap();
+    let ack = MempoolAck::decode(&reply).unwrap();
+    match &ack {
+        MempoolAck::SignedBatchAccepted(ack_digest, _) => assert_eq!(*ack_digest, digest(&serialized)),
+        ack => panic!("Unexpected ack {:?}", ack),
+    }
+    assert!(ack.verify(&serialized, &name));
+    assert!(!ack.verify(&serialized, &other));
+    assert!(!ack.verify(&serialized[1..], &name));
+    assert!(!MempoolAck::BatchAccepted.verify(&serialized, &name));
+}
+
+#[test]
+fn decode_legacy_ack() {
+    // Mempools that predate `MempoolAck` still accept our batches.
+    assert_eq!(MempoolAck::decode(LEGACY_ACK).unwrap(), MempoolAck::BatchAccepted);
+    assert!(MempoolAck::decode(b"Nack").is_err());
+    let ack = MempoolAck::Busy;
+    assert_eq!(MempoolAck::decode(&ack.encode()).unwrap(), ack);
+}
+
+#[test]
+fn channel_capacities() {
+    let parameters = Parameters::default();
+    assert_eq!(parameters.batch_maker_channel_capacity, CHANNEL_CAPACITY);
+    assert_eq!(parameters.peer_processor_channel_capacity, CHANNEL_CAPACITY);
+    assert_eq!(parameters.helper_channel_capacity, CHANNEL_CAPACITY);
+
+    // A channel cannot be empty, so zero falls back to the default.
+    assert_eq!(Parameters::channel_capacity(0), CHANNEL_CAPACITY);
+    assert_eq!(Parameters::channel_capacity(10_000), 10_000);
+}
+
+#[tokio::test]
+async fn flush_store() {
+    let (tx_batch_maker, _rx_batch_maker) =
+        MonitoredChannel::new(10, "test-batch-maker".to_string(), "debug");
+    let (tx_quorum_waiter, _rx_quorum_waiter) =
+        MonitoredChannel::new(10, "test-quorum-waiter".to_string(), "debug");
+    let (tx_processor, _rx_processor) =
+        MonitoredChannel::new(10, "test-processor".to_string(), "debug");
+
+    // Create a new test store.
+    let path = ".db_test_flush_store";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let stats = MempoolStats {
+        tx_batch_maker,
+        tx_quorum_waiter,
+        tx_processor,
+        missing_batches: MissingBatches::default(),
+        batch_index: BatchIndex::new(),
+        committee: Arc::new(std::sync::RwLock::new(committee_with_base_port(0))),
+        store: store.clone(),
+    };
+
+    // Write a batch the way the processor does, then flush: the batch is there once it returns.
+    let serialized = bincode::serialize(&MempoolMessage::Batch(batch())).unwrap();
+    store.write(batch_digest().to_vec(), serialized.clone()).await;
+    stats.flush().await.unwrap();
+    assert_eq!(store.read(batch_digest().to_vec()).await.unwrap(), Some(serialized));
+}
@@ -0,0 +1,54 @@
+use super::*;
+use std::convert::TryInto as _;
+
+/// Reads the client identifier from the first byte and the nonce from the next 8 bytes.
+fn tracker() -> NonceTracker {
+    NonceTracker::new(Arc::new(|tx: &Transaction| {
+        if tx.len() < 9 {
+            return None;
+        }
+        let nonce = u64::from_be_bytes(tx[1..9].try_into().unwrap());
+        Some((tx[..1].to_vec(), nonce))
+    }))
+}
+
+fn transaction(client: u8, nonce: u64) -> Transaction {
+    let mut tx = vec![client];
+    tx.extend_from_slice(&nonce.to_be_bytes());
+    tx
+}
+
+#[test]
+fn accept_in_order() {
+    let tracker = tracker();
+    assert!(tracker.check(&transaction(0, 1)));
+    assert!(tracker.check(&transaction(0, 2)));
+    // Nonces may skip values.
+    assert!(tracker.check(&transaction(0, 5)));
+    // Every client has its own sequence.
+    assert!(tracker.check(&transaction(1, 1)));
+}
+
+#[test]
+fn reject_duplicate() {
+    let tracker = tracker();
+    assert!(tracker.check(&transaction(0, 1)));
+    assert!(!tracker.check(&transaction(0, 1)));
+}
+
+#[test]
+fn reject_old_nonce() {
+    let tracker = tracker();
+    assert!(tracker.check(&transaction(0, 3)));
+    assert!(!tracker.check(&transaction(0, 2)));
+    // The rejected transaction does not move the sequence back.
+    assert!(!tracker.check(&transaction(0, 3)));
+    assert!(tracker.check(&transaction(0, 4)));
+}
+
+#[test]
+fn accept_without_nonce() {
+    let tracker = tracker();
+    assert!(tracker.check(&vec![0]));
+    assert!(tracker.check(&vec![0]));
+}
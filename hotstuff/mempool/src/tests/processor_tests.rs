@@ -1,8 +1,11 @@
 use super::*;
-use crate::common::batch;
+use crate::common::{batch, committee};
 use crate::mempool::MempoolMessage;
+use crypto::{generate_secp256k_keypair, PublicKey, SignatureService};
 use std::fs;
+use std::sync::RwLock;
 use tokio::sync::mpsc::channel;
+use utils::monitored_channel::MonitoredChannel;
 
 #[tokio::test]
 async fn hash_and_store() {
@@ -36,3 +39,428 @@ async fn hash_and_store() {
     assert!(stored_batch.is_some(), "The batch is not in the store");
     assert_eq!(stored_batch.unwrap(), serialized);
 }
+
+#[tokio::test]
+async fn record_peer_batch_metrics() {
+    let (tx_batch, rx_batch) = channel(1);
+    let (tx_digest, mut rx_digest) =
+        MonitoredChannel::new(1, "test-processor".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    // A validator id that no other test uses, so the counters start from zero.
+    let validator_id = 9_001;
+
+    // Create a new test store.
+    let path = ".db_test_record_peer_batch_metrics";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a new `Processor` instance handling batches from other mempools.
+    Processor::spawn(
+        store,
+        rx_batch,
+        tx_digest,
+        ProcessorParams {
+            validator_id,
+            from_peers: true,
+            codec: Codec::Bincode,
+            signed_batches: false,
+            committee: Arc::new(RwLock::new(committee())),
+            store_batch_size: 1,
+            max_store_delay: 10,
+            max_forward_retries: Some(0),
+            forward_retry_delay: 0,
+            batch_index: BatchIndex::new(),
+            load: None,
+        },
+        exit,
+    );
+
+    // Send a batch to the `Processor` and wait for it to be processed.
+    let message = MempoolMessage::Batch(batch());
+    let serialized = bincode::serialize(&message).unwrap();
+    tx_batch.send(serialized.clone()).await.unwrap();
+    rx_digest.recv().await.unwrap();
+
+    // Ensure the counters are labelled with our validator id.
+    let label = validator_id.to_string();
+    let received = metrics::get_int_counter(&metrics::BATCHES_RECEIVED, &[&label]).unwrap();
+    assert_eq!(received.get(), 1);
+    let stored = metrics::get_int_counter(&metrics::STORED_BYTES, &[&label]).unwrap();
+    assert_eq!(stored.get(), serialized.len() as u64);
+}
+
+#[tokio::test]
+async fn forward_digests_after_flush() {
+    let (tx_batch, rx_batch) = channel(10);
+    let (tx_digest, mut rx_digest) =
+        MonitoredChannel::new(10, "test-processor".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+
+    // Create a new test store.
+    let path = ".db_test_forward_digests_after_flush";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Processor` flushing every 3 batches, with a long enough delay that only the count
+    // triggers a flush.
+    Processor::spawn(
+        store.clone(),
+        rx_batch,
+        tx_digest,
+        ProcessorParams {
+            validator_id: 0,
+            from_peers: false,
+            codec: Codec::Bincode,
+            signed_batches: false,
+            committee: Arc::new(RwLock::new(committee())),
+            store_batch_size: 3,
+            max_store_delay: 60_000,
+            max_forward_retries: Some(0),
+            forward_retry_delay: 0,
+            batch_index: BatchIndex::new(),
+            load: None,
+        },
+        exit,
+    );
+
+    // Send fewer batches than the flush size: no digest should be forwarded yet.
+    let batches: Vec<_> = (0..3u8)
+        .map(|i| bincode::serialize(&MempoolMessage::Batch(vec![vec![i; 10]])).unwrap())
+        .collect();
+    for serialized in &batches[..2] {
+        tx_batch.send(serialized.clone()).await.unwrap();
+    }
+    sleep(Duration::from_millis(100)).await;
+    assert!(rx_digest.try_recv().is_err());
+
+    // Complete the group: every forwarded digest must already be retrievable from the store.
+    tx_batch.send(batches[2].clone()).await.unwrap();
+    for serialized in &batches {
+        let received = rx_digest.recv().await.unwrap();
+        assert_eq!(received, digest(serialized));
+        let stored = store.read(received.to_vec()).await.unwrap();
+        assert_eq!(stored, Some(serialized.clone()));
+    }
+}
+
+#[tokio::test]
+async fn reject_tampered_peer_batch() {
+    let (tx_batch, rx_batch) = channel(10);
+    let (tx_digest, mut rx_digest) =
+        MonitoredChannel::new(10, "test-processor".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    let load = ProcessorLoad::new(0, 0);
+    // A validator id that no other test uses, so the counters start from zero.
+    let validator_id = 9_002;
+
+    // Create a new test store.
+    let path = ".db_test_reject_tampered_peer_batch";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a new `Processor` instance handling batches from other mempools.
+    Processor::spawn(
+        store.clone(),
+        rx_batch,
+        tx_digest,
+        ProcessorParams {
+            validator_id,
+            from_peers: true,
+            codec: Codec::Bincode,
+            signed_batches: false,
+            committee: Arc::new(RwLock::new(committee())),
+            store_batch_size: 1,
+            max_store_delay: 10,
+            max_forward_retries: Some(0),
+            forward_retry_delay: 0,
+            batch_index: BatchIndex::new(),
+            load: Some(load.clone()),
+        },
+        exit,
+    );
+
+    // A batch padded with a trailing byte still decodes, but its digest is not the one its
+    // author computed.
+    let serialized = bincode::serialize(&MempoolMessage::Batch(batch())).unwrap();
+    let mut tampered = serialized.clone();
+    tampered.push(0);
+    assert!(verify_batch(&tampered, &digest(&tampered), Codec::Bincode).is_err());
+    // Neither does anything other than a batch pass the check.
+    let request = bincode::serialize(&MempoolMessage::BatchRequest(Vec::new(), PublicKey::default())).unwrap();
+    assert!(verify_batch(&request, &digest(&request), Codec::Bincode).is_err());
+
+    load.add();
+    tx_batch.send(tampered.clone()).await.unwrap();
+    load.add();
+    tx_batch.send(serialized.clone()).await.unwrap();
+
+    // Only the well-formed batch is forwarded and stored.
+    let received = rx_digest.recv().await.unwrap();
+    assert_eq!(received, digest(&serialized));
+    sleep(Duration::from_millis(50)).await;
+    assert!(rx_digest.try_recv().is_err());
+    assert_eq!(store.read(digest(&tampered).to_vec()).await.unwrap(), None);
+
+    let label = validator_id.to_string();
+    let rejected = metrics::get_int_counter(&metrics::BATCHES_REJECTED_INVALID, &[&label]).unwrap();
+    assert_eq!(rejected.get(), 1);
+}
+
+#[tokio::test]
+async fn dead_letter_digests_without_consensus() {
+    let (tx_batch, rx_batch) = channel(10);
+    let (tx_digest, rx_digest) = MonitoredChannel::new(10, "test-processor".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    // A validator id that no other test uses, so the counters start from zero.
+    let validator_id = 9_003;
+
+    // Create a new test store.
+    let path = ".db_test_dead_letter_digests_without_consensus";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a new `Processor` instance whose consensus is already gone.
+    drop(rx_digest);
+    Processor::spawn(
+        store.clone(),
+        rx_batch,
+        tx_digest,
+        ProcessorParams {
+            validator_id,
+            from_peers: false,
+            codec: Codec::Bincode,
+            signed_batches: false,
+            committee: Arc::new(RwLock::new(committee())),
+            store_batch_size: 1,
+            max_store_delay: 10,
+            max_forward_retries: Some(2),
+            forward_retry_delay: 10,
+            batch_index: BatchIndex::new(),
+            load: None,
+        },
+        exit,
+    );
+
+    // The processor gives up on the digests instead of panicking, so it keeps storing batches.
+    let batches: Vec<_> = (0..2u8)
+        .map(|i| bincode::serialize(&MempoolMessage::Batch(vec![vec![i; 10]])).unwrap())
+        .collect();
+    for serialized in &batches {
+        tx_batch.send(serialized.clone()).await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+        let stored = store.read(digest(serialized).to_vec()).await.unwrap();
+        assert_eq!(stored, Some(serialized.clone()));
+    }
+
+    let label = validator_id.to_string();
+    let dead_lettered = metrics::get_int_counter(&metrics::DIGESTS_DEAD_LETTERED, &[&label]).unwrap();
+    assert_eq!(dead_lettered.get(), 2);
+}
+
+#[tokio::test]
+async fn dead_letter_digests_when_consensus_is_stuck() {
+    let (tx_batch, rx_batch) = channel(10);
+    let (tx_digest, mut rx_digest) = MonitoredChannel::new(1, "test-processor".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    // A validator id that no other test uses, so the counters start from zero.
+    let validator_id = 9_005;
+
+    // Create a new test store.
+    let path = ".db_test_dead_letter_digests_when_consensus_is_stuck";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a new `Processor` instance whose consensus never reads its digests.
+    Processor::spawn(
+        store.clone(),
+        rx_batch,
+        tx_digest,
+        ProcessorParams {
+            validator_id,
+            from_peers: false,
+            codec: Codec::Bincode,
+            signed_batches: false,
+            committee: Arc::new(RwLock::new(committee())),
+            store_batch_size: 1,
+            max_store_delay: 10,
+            max_forward_retries: Some(1),
+            forward_retry_delay: 10,
+            batch_index: BatchIndex::new(),
+            load: None,
+        },
+        exit,
+    );
+
+    // The first digest fills the channel: the second one is dead-lettered once the retries are
+    // exhausted, instead of blocking the processor.
+    let batches: Vec<_> = (0..3u8)
+        .map(|i| bincode::serialize(&MempoolMessage::Batch(vec![vec![i; 10]])).unwrap())
+        .collect();
+    for serialized in &batches {
+        tx_batch.send(serialized.clone()).await.unwrap();
+        sleep(Duration::from_millis(200)).await;
+        let stored = store.read(digest(serialized).to_vec()).await.unwrap();
+        assert_eq!(stored, Some(serialized.clone()));
+    }
+    assert_eq!(rx_digest.recv().await.unwrap(), digest(&batches[0]));
+
+    let label = validator_id.to_string();
+    let dead_lettered = metrics::get_int_counter(&metrics::DIGESTS_DEAD_LETTERED, &[&label]).unwrap();
+    assert_eq!(dead_lettered.get(), 2);
+}
+
+#[tokio::test]
+async fn wait_for_stuck_consensus_without_retry_limit() {
+    let (tx_batch, rx_batch) = channel(10);
+    let (tx_digest, mut rx_digest) = MonitoredChannel::new(1, "test-processor".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    // A validator id that no other test uses, so the counters start from zero.
+    let validator_id = 9_006;
+
+    // Create a new test store.
+    let path = ".db_test_wait_for_stuck_consensus_without_retry_limit";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a new `Processor` instance whose consensus does not read its digests for a while.
+    Processor::spawn(
+        store.clone(),
+        rx_batch,
+        tx_digest,
+        ProcessorParams {
+            validator_id,
+            from_peers: false,
+            codec: Codec::Bincode,
+            signed_batches: false,
+            committee: Arc::new(RwLock::new(committee())),
+            store_batch_size: 1,
+            max_store_delay: 10,
+            max_forward_retries: None,
+            forward_retry_delay: 10,
+            batch_index: BatchIndex::new(),
+            load: None,
+        },
+        exit,
+    );
+
+    // The first digest fills the channel: the following ones wait for room instead of being
+    // dead-lettered, however long the consensus is stuck.
+    let batches: Vec<_> = (0..3u8)
+        .map(|i| bincode::serialize(&MempoolMessage::Batch(vec![vec![i; 10]])).unwrap())
+        .collect();
+    for serialized in &batches {
+        tx_batch.send(serialized.clone()).await.unwrap();
+    }
+    sleep(Duration::from_millis(500)).await;
+    for serialized in &batches {
+        assert_eq!(rx_digest.recv().await.unwrap(), digest(serialized));
+    }
+
+    let label = validator_id.to_string();
+    let dead_lettered = metrics::get_int_counter(&metrics::DIGESTS_DEAD_LETTERED, &[&label]).unwrap();
+    assert_eq!(dead_lettered.get(), 0);
+}
+
+#[test]
+fn processor_load_hysteresis() {
+    let load = ProcessorLoad::new(/* high_water_mark */ 3, /* low_water_mark */ 1);
+    load.add();
+    load.add();
+    assert!(!load.is_overloaded());
+    load.add();
+    assert!(load.is_overloaded());
+
+    // The indicator stays up until the load is back to the low water mark.
+    load.remove(1);
+    assert!(load.is_overloaded());
+    load.remove(1);
+    assert!(!load.is_overloaded());
+
+    // Zero disables the indicator.
+    let disabled = ProcessorLoad::new(0, 0);
+    for _ in 0..10 {
+        disabled.add();
+    }
+    assert!(!disabled.is_overloaded());
+}
+
+#[tokio::test]
+async fn reject_unsigned_peer_batch() {
+    let (tx_batch, rx_batch) = channel(10);
+    let (tx_digest, mut rx_digest) =
+        MonitoredChannel::new(10, "test-processor".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    // A validator id that no other test uses, so the counters start from zero.
+    let validator_id = 9_004;
+
+    // Create a new test store.
+    let path = ".db_test_reject_unsigned_peer_batch";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // A committee counting the originator of our signed batch among its members.
+    let (name, secret) = generate_secp256k_keypair();
+    let mut committee = committee();
+    let authority = committee.authorities.values().next().unwrap().clone();
+    committee.authorities.insert(name, authority);
+
+    // Spawn a new `Processor` instance requiring the batches from other mempools to be signed.
+    Processor::spawn(
+        store.clone(),
+        rx_batch,
+        tx_digest,
+        ProcessorParams {
+            validator_id,
+            from_peers: true,
+            codec: Codec::Bincode,
+            signed_batches: true,
+            committee: Arc::new(RwLock::new(committee.clone())),
+            store_batch_size: 1,
+            max_store_delay: 10,
+            max_forward_retries: Some(0),
+            forward_retry_delay: 0,
+            batch_index: BatchIndex::new(),
+            load: None,
+        },
+        exit,
+    );
+
+    // A signed batch verifies against its originator only, which must be a committee member.
+    let mut signature_service = SignatureService::new(secret);
+    let signed = MempoolMessage::signed_batch(batch(), name, &mut signature_service).await;
+    assert!(signed.verify_originator(&committee));
+    let (stranger, secret) = generate_secp256k_keypair();
+    let mut signature_service = SignatureService::new(secret);
+    let self_signed = MempoolMessage::signed_batch(batch(), stranger, &mut signature_service).await;
+    assert!(!self_signed.verify_originator(&committee));
+    let forged = match &signed {
+        MempoolMessage::SignedBatch(batch, _, signature) => {
+            let (other, _) = generate_secp256k_keypair();
+            MempoolMessage::SignedBatch(batch.clone(), other, signature.clone())
+        }
+        _ => panic!("Unexpected message"),
+    };
+    assert!(!forged.verify_originator(&committee));
+    assert!(!MempoolMessage::Batch(batch()).verify_originator(&committee));
+
+    // Only the batch signed by the member it claims is stored and forwarded.
+    let unsigned = bincode::serialize(&MempoolMessage::Batch(batch())).unwrap();
+    let forged = bincode::serialize(&forged).unwrap();
+    let self_signed = bincode::serialize(&self_signed).unwrap();
+    let signed = bincode::serialize(&signed).unwrap();
+    tx_batch.send(unsigned.clone()).await.unwrap();
+    tx_batch.send(forged.clone()).await.unwrap();
+    tx_batch.send(self_signed.clone()).await.unwrap();
+    tx_batch.send(signed.clone()).await.unwrap();
+    let received = rx_digest.recv().await.unwrap();
+    assert_eq!(received, digest(&signed));
+    sleep(Duration::from_millis(50)).await;
+    assert!(rx_digest.try_recv().is_err());
+    assert_eq!(store.read(digest(&unsigned).to_vec()).await.unwrap(), None);
+    assert_eq!(store.read(digest(&forged).to_vec()).await.unwrap(), None);
+    assert_eq!(store.read(digest(&self_signed).to_vec()).await.unwrap(), None);
+
+    let label = validator_id.to_string();
+    let rejected = metrics::get_int_counter(&metrics::BATCHES_REJECTED_UNSIGNED, &[&label]).unwrap();
+    assert_eq!(rejected.get(), 3);
+}
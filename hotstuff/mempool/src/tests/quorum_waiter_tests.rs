@@ -1,10 +1,14 @@
 use super::*;
 use crate::common::{batch, committee_with_base_port, keys, listener};
+use crate::config::Committee;
 use crate::mempool::MempoolMessage;
 use bytes::Bytes;
+use crypto::generate_secp256k_keypair;
 use futures::future::try_join_all;
 use network::ReliableSender;
+use std::sync::{Arc, RwLock};
 use tokio::sync::mpsc::channel;
+use utils::monitored_channel::MonitoredChannel;
 
 #[tokio::test]
 async fn wait_for_quorum() {
@@ -40,6 +44,7 @@ async fn wait_for_quorum() {
     let message = QuorumWaiterMessage {
         batch: serialized.clone(),
         handlers: names.into_iter().zip(handlers.into_iter()).collect(),
+        inflight_permit: None,
     };
     tx_message.send(message).await.unwrap();
 
@@ -50,3 +55,320 @@ async fn wait_for_quorum() {
     // Ensure the other listeners correctly received the batch.
     assert!(try_join_all(listener_handles).await.is_ok());
 }
+
+#[tokio::test]
+async fn drop_batch_on_quorum_timeout() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) =
+        MonitoredChannel::new(1, "test-quorum-waiter".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    let (tx_drained, _rx_drained) = exit_future::signal();
+    let address: std::net::SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let names: Vec<_> = (0..4).map(|_| generate_secp256k_keypair().0).collect();
+    let committee = Committee::new(
+        names
+            .iter()
+            .map(|name| (*name, /* stake */ 1, address, address, address))
+            .collect(),
+        /* epoch */ 1,
+    );
+
+    // Spawn a `QuorumWaiter` instance with a short quorum timeout.
+    QuorumWaiter::spawn(
+        Arc::new(RwLock::new(committee)),
+        /* stake */ 1,
+        QuorumWaiterParams {
+            quorum_timeout: 50,
+            quorum_threshold_override: None,
+            gc_depth: 50,
+            batch_index: BatchIndex::new(),
+        },
+        rx_message,
+        tx_batch,
+        exit,
+        tx_drained,
+    );
+
+    // Forward a first batch whose acknowledgements never arrive.
+    let mut silent_senders = Vec::new();
+    let mut handlers = Vec::new();
+    for name in &names[1..] {
+        let (sender, handler) = tokio::sync::oneshot::channel();
+        silent_senders.push(sender);
+        handlers.push((*name, handler));
+    }
+    let stuck = bincode::serialize(&MempoolMessage::Batch(vec![vec![0; 10]])).unwrap();
+    tx_message
+        .send(QuorumWaiterMessage { batch: stuck, handlers, inflight_permit: None })
+        .await
+        .unwrap();
+
+    // Forward a second batch that every peer acknowledges.
+    let mut handlers = Vec::new();
+    for name in &names[1..] {
+        let (sender, handler) = tokio::sync::oneshot::channel();
+        sender.send(MempoolAck::BatchAccepted.encode()).unwrap();
+        handlers.push((*name, handler));
+    }
+    let acked = bincode::serialize(&MempoolMessage::Batch(vec![vec![1; 10]])).unwrap();
+    tx_message
+        .send(QuorumWaiterMessage { batch: acked.clone(), handlers, inflight_permit: None })
+        .await
+        .unwrap();
+
+    // The first batch is dropped after the timeout, so the second one is the first to be output.
+    let output = rx_batch.recv().await.unwrap();
+    assert_eq!(output, acked);
+    drop(silent_senders);
+}
+
+#[tokio::test]
+async fn drop_batch_with_unreachable_quorum() {
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) =
+        MonitoredChannel::new(1, "test-quorum-waiter".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    let (tx_drained, _rx_drained) = exit_future::signal();
+
+    // An empty committee can never reach a quorum.
+    let committee = Committee::new(Vec::new(), /* epoch */ 1);
+
+    // Spawn a `QuorumWaiter` instance whose timeout would outlast the test.
+    QuorumWaiter::spawn(
+        Arc::new(RwLock::new(committee)),
+        /* stake */ 0,
+        QuorumWaiterParams {
+            quorum_timeout: 60_000,
+            quorum_threshold_override: None,
+            gc_depth: 50,
+            batch_index: BatchIndex::new(),
+        },
+        rx_message,
+        tx_batch,
+        exit,
+        tx_drained,
+    );
+
+    // Forward a batch and ensure it is dropped right away rather than after the timeout.
+    let batch = bincode::serialize(&MempoolMessage::Batch(vec![vec![0; 10]])).unwrap();
+    tx_message
+        .send(QuorumWaiterMessage { batch, handlers: Vec::new(), inflight_permit: None })
+        .await
+        .unwrap();
+    let dropped = async {
+        while metrics::QUORUM_UNREACHABLE.as_ref().map_or(0, |c| c.get()) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    assert!(timeout(Duration::from_secs(1), dropped).await.is_ok());
+    assert!(rx_batch.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn quorum_threshold_override() {
+    let names: Vec<_> = (0..4).map(|_| generate_secp256k_keypair().0).collect();
+    let address: std::net::SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let committee = Committee::new(
+        names
+            .iter()
+            .map(|name| (*name, /* stake */ 1, address, address, address))
+            .collect(),
+        /* epoch */ 1,
+    );
+
+    // Forwards a batch only one peer acknowledges to a `QuorumWaiter` with the given override,
+    // and returns whether it is delivered before the (long) quorum timeout.
+    let deliver = |quorum_threshold_override: Option<Stake>| {
+        let committee = committee.clone();
+        let names = names.clone();
+        async move {
+            let (tx_message, rx_message) = channel(1);
+            let (tx_batch, mut rx_batch) =
+                MonitoredChannel::new(1, "test-quorum-waiter".to_string(), "debug");
+            let (_signal, exit) = exit_future::signal();
+            let (tx_drained, _rx_drained) = exit_future::signal();
+            QuorumWaiter::spawn(
+                Arc::new(RwLock::new(committee)),
+                /* stake */ 1,
+                QuorumWaiterParams {
+                    quorum_timeout: 60_000,
+                    quorum_threshold_override,
+                    gc_depth: 50,
+                    batch_index: BatchIndex::new(),
+                },
+                rx_message,
+                tx_batch,
+                exit,
+                tx_drained,
+            );
+
+            let mut silent_senders = Vec::new();
+            let mut handlers = Vec::new();
+            for (i, name) in names[1..].iter().enumerate() {
+                let (sender, handler) = tokio::sync::oneshot::channel();
+                if i == 0 {
+                    sender.send(MempoolAck::BatchAccepted.encode()).unwrap();
+                } else {
+                    silent_senders.push(sender);
+                }
+                handlers.push((*name, handler));
+            }
+            let batch = bincode::serialize(&MempoolMessage::Batch(vec![vec![0; 10]])).unwrap();
+            tx_message
+                .send(QuorumWaiterMessage { batch, handlers, inflight_permit: None })
+                .await
+                .unwrap();
+            timeout(Duration::from_millis(500), rx_batch.recv()).await.is_ok()
+        }
+    };
+
+    // Two acknowledgements (ours and a peer's) fall short of the committee's threshold of 3, but
+    // are enough once it is overridden.
+    assert!(!deliver(None).await);
+    assert!(deliver(Some(2)).await);
+}
+
+#[tokio::test]
+async fn drop_stale_batch() {
+    let names: Vec<_> = (0..4).map(|_| generate_secp256k_keypair().0).collect();
+    let address: std::net::SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let committee = Committee::new(
+        names
+            .iter()
+            .map(|name| (*name, /* stake */ 1, address, address, address))
+            .collect(),
+        /* epoch */ 1,
+    );
+
+    // Spawn a `QuorumWaiter` following the rounds of `batch_index`, with a timeout that never
+    // fires during the test.
+    let batch_index = BatchIndex::new();
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) =
+        MonitoredChannel::new(1, "test-quorum-waiter".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    let (tx_drained, _rx_drained) = exit_future::signal();
+    QuorumWaiter::spawn(
+        Arc::new(RwLock::new(committee)),
+        /* stake */ 1,
+        QuorumWaiterParams {
+            quorum_timeout: 60_000,
+            quorum_threshold_override: None,
+            gc_depth: 2,
+            batch_index: batch_index.clone(),
+        },
+        rx_message,
+        tx_batch,
+        exit,
+        tx_drained,
+    );
+
+    // Forward a batch no peer acknowledges: it waits for a quorum.
+    let mut silent_senders = Vec::new();
+    let handlers = names[1..]
+        .iter()
+        .map(|name| {
+            let (sender, handler) = tokio::sync::oneshot::channel();
+            silent_senders.push(sender);
+            (*name, handler)
+        })
+        .collect();
+    let batch = bincode::serialize(&MempoolMessage::Batch(vec![vec![0; 10]])).unwrap();
+    tx_message
+        .send(QuorumWaiterMessage { batch, handlers, inflight_permit: None })
+        .await
+        .unwrap();
+
+    assert!(timeout(Duration::from_millis(100), rx_batch.recv()).await.is_err());
+
+    // The batch is not stale as long as the consensus stays within the gc depth.
+    batch_index.advance(2, None);
+    assert!(timeout(Duration::from_millis(100), rx_batch.recv()).await.is_err());
+
+    // Moving one more round drops it, and the QuorumWaiter moves on to the next batch.
+    let stale_before = metrics::QUORUM_WAITER_STALE_BATCHES.as_ref().map_or(0, |c| c.get());
+    batch_index.advance(3, None);
+    let handlers = names[1..]
+        .iter()
+        .map(|name| {
+            let (sender, handler) = tokio::sync::oneshot::channel();
+            sender.send(MempoolAck::BatchAccepted.encode()).unwrap();
+            (*name, handler)
+        })
+        .collect();
+    let batch = bincode::serialize(&MempoolMessage::Batch(vec![vec![1; 10]])).unwrap();
+    tx_message
+        .send(QuorumWaiterMessage { batch: batch.clone(), handlers, inflight_permit: None })
+        .await
+        .unwrap();
+    let output = timeout(Duration::from_secs(1), rx_batch.recv()).await.unwrap().unwrap();
+    assert_eq!(output, batch);
+    assert!(metrics::QUORUM_WAITER_STALE_BATCHES.as_ref().map_or(0, |c| c.get()) > stale_before);
+}
+
+#[tokio::test]
+async fn reload_committee() {
+    let names: Vec<_> = (0..4).map(|_| generate_secp256k_keypair().0).collect();
+    let address: std::net::SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let make_committee = |names: &[PublicKey]| {
+        Committee::new(
+            names
+                .iter()
+                .map(|name| (*name, /* stake */ 1, address, address, address))
+                .collect(),
+            /* epoch */ 1,
+        )
+    };
+
+    // Spawn a `QuorumWaiter` for a committee of four, whose quorum threshold is 3.
+    let committee = Arc::new(RwLock::new(make_committee(&names)));
+    let (tx_message, rx_message) = channel(1);
+    let (tx_batch, mut rx_batch) =
+        MonitoredChannel::new(1, "test-quorum-waiter".to_string(), "debug");
+    let (_signal, exit) = exit_future::signal();
+    let (tx_drained, _rx_drained) = exit_future::signal();
+    QuorumWaiter::spawn(
+        committee.clone(),
+        /* stake */ 1,
+        QuorumWaiterParams {
+            quorum_timeout: 200,
+            quorum_threshold_override: None,
+            gc_depth: 50,
+            batch_index: BatchIndex::new(),
+        },
+        rx_message,
+        tx_batch,
+        exit,
+        tx_drained,
+    );
+
+    // Forwards a batch only the second authority acknowledges.
+    let forward = |i: u8| {
+        let tx_message = tx_message.clone();
+        let names = names.clone();
+        async move {
+            let (sender, handler) = tokio::sync::oneshot::channel();
+            sender.send(MempoolAck::BatchAccepted.encode()).unwrap();
+            let batch = bincode::serialize(&MempoolMessage::Batch(vec![vec![i; 10]])).unwrap();
+            tx_message
+                .send(QuorumWaiterMessage {
+                    batch: batch.clone(),
+                    handlers: vec![(names[1], handler)],
+                    inflight_permit: None,
+                })
+                .await
+                .unwrap();
+            batch
+        }
+    };
+
+    // Two acknowledgements (ours and a peer's) fall short of the quorum: the batch times out.
+    forward(0).await;
+    assert!(timeout(Duration::from_millis(500), rx_batch.recv()).await.is_err());
+
+    // Once the committee shrinks to two authorities, the same acknowledgements make a quorum.
+    *committee.write().unwrap() = make_committee(&names[..2]);
+    let batch = forward(1).await;
+    let output = timeout(Duration::from_secs(1), rx_batch.recv()).await.unwrap().unwrap();
+    assert_eq!(output, batch);
+}
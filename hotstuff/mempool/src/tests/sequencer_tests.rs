@@ -0,0 +1,49 @@
+use super::*;
+use crypto::Digest;
+use tokio::sync::mpsc::channel;
+use tokio::time::{sleep, Duration};
+use utils::monitored_channel::MonitoredChannel;
+
+#[tokio::test]
+async fn merge_digests_in_order() {
+    let (tx_own, rx_own) = channel(10);
+    let (tx_peers, rx_peers) = channel(10);
+    let (tx_consensus, mut rx_consensus) =
+        MonitoredChannel::new(10, "test-consensus".to_string(), "debug");
+    let (signal, exit) = exit_future::signal();
+
+    // Queue digests on both channels before the sequencer starts: they must come out in turns,
+    // ours first, each stream in its own order.
+    let own: Vec<_> = (0..2u8).map(|i| Digest([i; 32])).collect();
+    let peers: Vec<_> = (10..13u8).map(|i| Digest([i; 32])).collect();
+    for digest in &own {
+        tx_own.send(digest.clone()).await.unwrap();
+    }
+    for digest in &peers {
+        tx_peers.send(digest.clone()).await.unwrap();
+    }
+    DigestSequencer::spawn(rx_own, rx_peers, tx_consensus, /* validator_id */ 0, exit);
+
+    let mut received = Vec::new();
+    for _ in 0..5 {
+        received.push(rx_consensus.recv().await.unwrap());
+    }
+    let expected = vec![
+        own[0].clone(),
+        peers[0].clone(),
+        own[1].clone(),
+        peers[1].clone(),
+        peers[2].clone(),
+    ];
+    assert_eq!(received, expected);
+
+    // Digests queued when the mempool exits are still forwarded, then the sequencer terminates.
+    // The peers went last, so ours go first.
+    tx_peers.send(Digest([20; 32])).await.unwrap();
+    tx_own.send(Digest([21; 32])).await.unwrap();
+    let _ = signal.fire();
+    assert_eq!(rx_consensus.recv().await.unwrap(), Digest([21; 32]));
+    assert_eq!(rx_consensus.recv().await.unwrap(), Digest([20; 32]));
+    sleep(Duration::from_millis(50)).await;
+    assert!(tx_own.send(Digest([22; 32])).await.is_err());
+}
@@ -1,6 +1,7 @@
 use super::*;
-use crate::common::{batch_digest, committee_with_base_port, keys, listener};
+use crate::common::{batch_digest, committee_with_base_port, keys, listener, serialized_batch};
 use std::fs;
+use std::sync::RwLock;
 use tokio::sync::mpsc::channel;
 
 #[tokio::test]
@@ -19,7 +20,7 @@ async fn synchronize() {
     // Spawn a `Synchronizer` instance.
     Synchronizer::spawn(
         name,
-        committee.clone(),
+        Arc::new(RwLock::new(committee.clone())),
         store.clone(),
         /* gc_depth */ 50, // Not used in this test.
         /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
@@ -42,3 +43,246 @@ async fn synchronize() {
     // Ensure the target receives the sync request.
     assert!(handle.await.is_ok());
 }
+
+#[test]
+fn retry_backoff() {
+    let mut backoff = RetryBackoff::new(
+        /* base_delay */ 1_000,
+        /* factor */ 2.0,
+        /* max_delay */ 10_000,
+    );
+
+    // Each failed round doubles the delay until it reaches the cap.
+    let mut delays = vec![backoff.delay()];
+    for _ in 0..5 {
+        backoff.on_failure();
+        delays.push(backoff.delay());
+    }
+    assert_eq!(delays, vec![1_000, 2_000, 4_000, 8_000, 10_000, 10_000]);
+
+    // Recovering a batch goes back to the base delay.
+    backoff.reset();
+    assert_eq!(backoff.delay(), 1_000);
+
+    // A factor of 1.0 keeps the delay fixed.
+    let mut fixed = RetryBackoff::new(5_000, 1.0, 60_000);
+    for _ in 0..5 {
+        fixed.on_failure();
+        assert_eq!(fixed.delay(), 5_000);
+    }
+}
+
+#[test]
+fn deprioritize_failing_peers() {
+    let (failing, _) = crypto::generate_secp256k_keypair();
+    let (unknown, _) = crypto::generate_secp256k_keypair();
+    let (reliable, _) = crypto::generate_secp256k_keypair();
+    let peers = vec![
+        (failing, "127.0.0.1:1".parse().unwrap()),
+        (unknown, "127.0.0.1:2".parse().unwrap()),
+        (reliable, "127.0.0.1:3".parse().unwrap()),
+    ];
+
+    let mut scores = PeerScores::new(Duration::from_secs(60));
+    for _ in 0..5 {
+        scores.record_failure(&failing);
+        scores.record_success(&reliable);
+    }
+    assert!(scores.score(&failing) < scores.score(&unknown));
+    assert!(scores.score(&unknown) < scores.score(&reliable));
+
+    // The failing peer is never picked while better peers are available.
+    for _ in 0..20 {
+        let selected: Vec<_> = scores
+            .select(peers.clone(), 2)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(selected, vec![reliable, unknown]);
+    }
+
+    // Scores decay back towards neutral over time.
+    let later = Instant::now() + Duration::from_secs(600);
+    assert!((scores.score_at(&failing, later) - 0.5).abs() < 0.01);
+}
+
+#[tokio::test]
+async fn track_missing_batches() {
+    let (tx_message, rx_message) = channel(1);
+    let (_signal, exit) = exit_future::signal();
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let (target, _) = keys.pop().unwrap();
+    let committee = committee_with_base_port(9_100);
+
+    // Create a new test store.
+    let path = ".db_test_track_missing_batches";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Spawn a `Synchronizer` instance.
+    let missing = MissingBatches::default();
+    Synchronizer::spawn(
+        name,
+        Arc::new(RwLock::new(committee)),
+        store.clone(),
+        /* gc_depth */ 50,
+        /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
+        /* sync_retry_backoff_factor */ 1.0,
+        /* sync_retry_max_delay */ 1_000_000,
+        /* sync_retry_nodes */ 3,
+        /* sync_peer_score_window */ 300_000,
+        BatchIndex::new(),
+        missing.clone(),
+        Codec::Bincode,
+        TcpSettings::default(),
+        rx_message,
+        /* validator_id */ 0,
+        exit,
+    );
+
+    // Ask for a batch we do not have: it shows up as missing.
+    let message = ConsensusMempoolMessage::Synchronize(vec![batch_digest()], target);
+    tx_message.send(message).await.unwrap();
+    let registered = async {
+        while !missing.snapshot().contains(&batch_digest()) {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    assert!(timeout(Duration::from_secs(1), registered).await.is_ok());
+
+    // Once the batch is recovered, it leaves the set.
+    store.write(batch_digest().to_vec(), serialized_batch()).await;
+    let recovered = async {
+        while !missing.snapshot().is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    assert!(timeout(Duration::from_secs(1), recovered).await.is_ok());
+}
+
+#[tokio::test]
+async fn delete_garbage_collected_batches() {
+    let (tx_message, rx_message) = channel(1);
+    let (_signal, exit) = exit_future::signal();
+    let (name, _) = keys().pop().unwrap();
+    let committee = committee_with_base_port(9_200);
+
+    // Create a new test store.
+    let path = ".db_test_delete_garbage_collected_batches";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Store two batches in round 0 and another one in round 3.
+    let old = Digest([0; 32]);
+    let recent = Digest([1; 32]);
+    let uncommitted = Digest([2; 32]);
+    let batch_index = BatchIndex::new();
+    for digest in [&old, &uncommitted] {
+        batch_index.insert(digest.clone());
+        store.write(digest.to_vec(), serialized_batch()).await;
+    }
+    batch_index.advance(3, None);
+    batch_index.insert(recent.clone());
+    store.write(recent.to_vec(), serialized_batch()).await;
+
+    // Spawn a `Synchronizer` instance.
+    Synchronizer::spawn(
+        name,
+        Arc::new(RwLock::new(committee)),
+        store.clone(),
+        /* gc_depth */ 2,
+        /* sync_retry_delay */ 1_000_000,
+        /* sync_retry_backoff_factor */ 1.0,
+        /* sync_retry_max_delay */ 1_000_000,
+        /* sync_retry_nodes */ 3,
+        /* sync_peer_score_window */ 300_000,
+        batch_index,
+        MissingBatches::default(),
+        Codec::Bincode,
+        TcpSettings::default(),
+        rx_message,
+        /* validator_id */ 0,
+        exit,
+    );
+
+    // The consensus commits the old batch in round 1 and the recent one in round 3.
+    let committed = ConsensusMempoolMessage::Committed(1, vec![old.clone()]);
+    tx_message.send(committed).await.unwrap();
+    let committed = ConsensusMempoolMessage::Committed(3, vec![recent.clone()]);
+    tx_message.send(committed).await.unwrap();
+
+    // Moving to round 3 collects round 1 and below: the old batch is deleted.
+    tx_message.send(ConsensusMempoolMessage::Cleanup(3)).await.unwrap();
+    let deleted = async {
+        while store.read(old.to_vec()).await.unwrap().is_some() {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    assert!(timeout(Duration::from_secs(1), deleted).await.is_ok());
+
+    // The batch committed within the gc depth is kept, and so is the batch never committed.
+    assert!(store.read(recent.to_vec()).await.unwrap().is_some());
+    assert!(store.read(uncommitted.to_vec()).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn skip_stored_digests() {
+    let (tx_message, rx_message) = channel(1);
+    let (_signal, exit) = exit_future::signal();
+    let mut keys = keys();
+    let (name, _) = keys.pop().unwrap();
+    let (target, _) = keys.pop().unwrap();
+    let committee = committee_with_base_port(9_300);
+
+    // Create a new test store, already holding one of the batches we will be asked about.
+    let path = ".db_test_skip_stored_digests";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+    let stored = Digest([2; 32]);
+    store.write(stored.to_vec(), serialized_batch()).await;
+
+    // Spawn a `Synchronizer` instance.
+    let missing = MissingBatches::default();
+    Synchronizer::spawn(
+        name,
+        Arc::new(RwLock::new(committee)),
+        store.clone(),
+        /* gc_depth */ 50,
+        /* sync_retry_delay */ 1_000_000, // Ensure it is not triggered.
+        /* sync_retry_backoff_factor */ 1.0,
+        /* sync_retry_max_delay */ 1_000_000,
+        /* sync_retry_nodes */ 3,
+        /* sync_peer_score_window */ 300_000,
+        BatchIndex::new(),
+        missing.clone(),
+        Codec::Bincode,
+        TcpSettings::default(),
+        rx_message,
+        /* validator_id */ 9_300,
+        exit,
+    );
+
+    // Only the batch we do not have is requested.
+    let message = ConsensusMempoolMessage::Synchronize(vec![stored.clone(), batch_digest()], target);
+    tx_message.send(message).await.unwrap();
+    let registered = async {
+        while !missing.snapshot().contains(&batch_digest()) {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    assert!(timeout(Duration::from_secs(1), registered).await.is_ok());
+    assert!(!missing.snapshot().contains(&stored));
+    let label = 9_300.to_string();
+    let local = metrics::get_int_counter(&metrics::SYNC_DIGESTS_LOCAL, &[&label]).unwrap();
+    assert_eq!(local.get(), 1);
+
+    // The other one is counted as fetched once it shows up.
+    store.write(batch_digest().to_vec(), serialized_batch()).await;
+    let recovered = async {
+        while metrics::get_int_counter(&metrics::SYNC_DIGESTS_FETCHED, &[&label]).map_or(0, |c| c.get()) == 0 {
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    assert!(timeout(Duration::from_secs(1), recovered).await.is_ok());
+}
@@ -3,6 +3,7 @@ mod error;
 mod receiver;
 mod reliable_sender;
 mod simple_sender;
+mod tcp_settings;
 mod dvf_message;
 #[cfg(test)]
 #[path = "tests/common.rs"]
@@ -13,5 +14,6 @@ pub const CHANNEL_CAPACITY: usize = 1_000;
 pub use crate::receiver::{MessageHandler, Receiver, Writer};
 pub use crate::reliable_sender::{CancelHandler, ReliableSender};
 pub use crate::simple_sender::SimpleSender;
+pub use crate::tcp_settings::TcpSettings;
 pub use crate::dvf_message::DvfMessage;
 pub use crate::dvf_message::VERSION;
\ No newline at end of file
@@ -13,6 +13,7 @@ use std::collections::HashMap;
 use std::sync::{Arc};
 use tokio::sync::{RwLock};
 use crate::dvf_message::{DvfMessage, VERSION};
+use crate::tcp_settings::TcpSettings;
 use futures::SinkExt;
 use tokio::time::{sleep, Duration};
 
@@ -41,13 +42,27 @@ pub struct Receiver<Handler: MessageHandler> {
     /// Struct responsible to define how to handle received messages.
     handler_map: Arc<RwLock<HashMap<u64, Handler>>>,
     name: &'static str,
+    /// The socket options of the connections we accept. `None` leaves the system defaults.
+    tcp_settings: Option<TcpSettings>,
 }
 
 impl<Handler: MessageHandler> Receiver<Handler> {
     /// Spawn a new network receiver handling connections from any incoming peer.
     pub fn spawn(address: SocketAddr, handler_map: Arc<RwLock<HashMap<u64, Handler>>>, name: &'static str) {
         tokio::spawn(async move {
-            Self { address, handler_map, name}.run().await;
+            Self { address, handler_map, name, tcp_settings: None }.run().await;
+        });
+    }
+
+    /// Like `spawn`, but applies `tcp_settings` to every accepted connection.
+    pub fn spawn_with_tcp_settings(
+        address: SocketAddr,
+        handler_map: Arc<RwLock<HashMap<u64, Handler>>>,
+        name: &'static str,
+        tcp_settings: TcpSettings,
+    ) {
+        tokio::spawn(async move {
+            Self { address, handler_map, name, tcp_settings: Some(tcp_settings) }.run().await;
         });
     }
 
@@ -67,6 +82,9 @@ impl<Handler: MessageHandler> Receiver<Handler> {
                 }
             };
             debug!("Incoming connection established with {}. Local: {}. [{:?}]", peer, self.address, self.name);
+            if let Some(tcp_settings) = &self.tcp_settings {
+                tcp_settings.apply_or_warn(&socket, peer);
+            }
             self.spawn_runner(socket, peer).await;
         }
     }
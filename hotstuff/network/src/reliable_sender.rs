@@ -17,6 +17,7 @@ use tokio::sync::{oneshot, RwLock};
 use tokio::time::{sleep, Duration};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use std::sync::Arc;
+use crate::tcp_settings::TcpSettings;
 use crate::CHANNEL_CAPACITY;
 use utils::monitored_channel::{MonitoredChannel, MonitoredSender};
 
@@ -39,6 +40,8 @@ pub struct ReliableSender {
     connections: Arc<RwLock<HashMap<SocketAddr, MonitoredSender<InnerMessage>>>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// The socket options of our connections. `None` leaves the system defaults.
+    tcp_settings: Option<TcpSettings>,
 
     signal: Option<exit_future::Signal>,
     exit: exit_future::Exit,
@@ -64,16 +67,24 @@ impl ReliableSender {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
             rng: SmallRng::from_entropy(),
+            tcp_settings: None,
             signal: Some(signal),
             exit,
         }
     }
 
+    /// Makes a sender whose connections use `tcp_settings`.
+    pub fn with_tcp_settings(tcp_settings: TcpSettings) -> Self {
+        let mut sender = Self::new();
+        sender.tcp_settings = Some(tcp_settings);
+        sender
+    }
+
     /// Helper function to spawn a new connection.
     fn spawn_connection(&self, address: SocketAddr) -> MonitoredSender<InnerMessage> {
         debug!("[Reliable] Openning a new connection to {}", address);
         let (tx, rx) = MonitoredChannel::new(CHANNEL_CAPACITY, format!("reliable-{}", address), "info");
-        Connection::spawn(address, rx, self.exit.clone());
+        Connection::spawn(address, rx, self.tcp_settings, self.exit.clone());
         tx
     }
 
@@ -143,18 +154,25 @@ struct Connection {
     retry_delay: u64,
     /// Buffer keeping all messages that need to be re-transmitted.
     buffer: VecDeque<(Bytes, oneshot::Sender<Bytes>)>,
+    tcp_settings: Option<TcpSettings>,
 
     exit: exit_future::Exit,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>, exit: exit_future::Exit) {
+    fn spawn(
+        address: SocketAddr,
+        receiver: Receiver<InnerMessage>,
+        tcp_settings: Option<TcpSettings>,
+        exit: exit_future::Exit,
+    ) {
         tokio::spawn(async move {
             Self {
                 address,
                 receiver,
                 retry_delay: 200,
                 buffer: VecDeque::new(),
+                tcp_settings,
                 exit,
             }
             .run()
@@ -170,6 +188,9 @@ impl Connection {
             match TcpStream::connect(self.address).await {
                 Ok(stream) => {
                     debug!("Outgoing connection established with {}", self.address);
+                    if let Some(tcp_settings) = &self.tcp_settings {
+                        tcp_settings.apply_or_warn(&stream, self.address);
+                    }
 
                     // Reset the delay.
                     delay = self.retry_delay;
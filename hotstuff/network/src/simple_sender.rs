@@ -15,6 +15,7 @@ use tokio::sync::RwLock;
 use tokio::time::{sleep, Duration};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use std::sync::Arc;
+use crate::tcp_settings::TcpSettings;
 use crate::CHANNEL_CAPACITY;
 use utils::monitored_channel::{MonitoredChannel, MonitoredSender};
 
@@ -36,6 +37,8 @@ pub struct SimpleSender {
     connections: Arc<RwLock<HashMap<SocketAddr, MonitoredSender<Command>>>>,
     /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
     rng: SmallRng,
+    /// The socket options of our connections. `None` leaves the system defaults.
+    tcp_settings: Option<TcpSettings>,
 }
 
 impl std::default::Default for SimpleSender {
@@ -49,13 +52,22 @@ impl SimpleSender {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
             rng: SmallRng::from_entropy(),
+            tcp_settings: None,
+        }
+    }
+
+    /// Makes a sender whose connections use `tcp_settings`.
+    pub fn with_tcp_settings(tcp_settings: TcpSettings) -> Self {
+        Self {
+            tcp_settings: Some(tcp_settings),
+            ..Self::new()
         }
     }
 
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> MonitoredSender<Command> {
+    fn spawn_connection(&self, address: SocketAddr) -> MonitoredSender<Command> {
         let (tx, rx) = MonitoredChannel::new(CHANNEL_CAPACITY, format!("simple-{}", address), "debug");
-        Connection::spawn(address, rx);
+        Connection::spawn(address, rx, self.tcp_settings);
         tx
     }
 
@@ -69,7 +81,7 @@ impl SimpleSender {
 
         debug!("[Simple] Openning a new connection to {}", address);
         // Otherwise make a new connection.
-        let tx = Self::spawn_connection(address);
+        let tx = self.spawn_connection(address);
         if tx.send(cmd).await.is_ok() {
             self.connections.write().await.insert(address, tx);
         }
@@ -87,7 +99,7 @@ impl SimpleSender {
 
         // info!("[Simple] Openning a new connection to {}", address);
         // // Otherwise make a new connection.
-        // let tx = Self::spawn_connection(address);
+        // let tx = self.spawn_connection(address);
         // if tx.send(Command::Send(data)).await.is_ok() {
         //     self.connections.write().await.insert(address, tx);
         // }
@@ -156,12 +168,13 @@ struct Connection {
     address: SocketAddr,
     /// Channel from which the connection receives its commands.
     receiver: Receiver<Command>,
+    tcp_settings: Option<TcpSettings>,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<Command>) {
+    fn spawn(address: SocketAddr, receiver: Receiver<Command>, tcp_settings: Option<TcpSettings>) {
         tokio::spawn(async move {
-            Self { address, receiver }.run().await;
+            Self { address, receiver, tcp_settings }.run().await;
         });
     }
 
@@ -169,7 +182,12 @@ impl Connection {
     async fn run(&mut self) {
         // Try to connect to the peer.
         let (mut writer, mut reader) = match TcpStream::connect(self.address).await {
-            Ok(stream) => Framed::new(stream, LengthDelimitedCodec::new()).split(),
+            Ok(stream) => {
+                if let Some(tcp_settings) = &self.tcp_settings {
+                    tcp_settings.apply_or_warn(&stream, self.address);
+                }
+                Framed::new(stream, LengthDelimitedCodec::new()).split()
+            }
             Err(e) => {
                 warn!(
                     "{}",
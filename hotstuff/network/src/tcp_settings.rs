@@ -0,0 +1,67 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use socket2::{SockRef, TcpKeepalive};
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+#[cfg(test)]
+#[path = "tests/tcp_settings_tests.rs"]
+pub mod tcp_settings_tests;
+
+/// Socket options applied to the TCP connections we open or accept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TcpSettings {
+    /// Whether to disable Nagle's algorithm, so that small messages such as acknowledgements are
+    /// sent right away instead of being held back to be coalesced. Enabled by default.
+    pub nodelay: bool,
+    /// The idle time after which TCP keepalive probes are sent, so that dead peers are detected on
+    /// otherwise silent connections. Denominated in seconds. Zero (the default) disables keepalive.
+    pub keepalive_secs: u64,
+    /// The size of the socket send buffer. Denominated in bytes. Zero (the default) leaves the
+    /// system default.
+    pub send_buffer_bytes: usize,
+    /// The size of the socket receive buffer. Denominated in bytes. Zero (the default) leaves the
+    /// system default.
+    pub recv_buffer_bytes: usize,
+}
+
+impl Default for TcpSettings {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive_secs: 0,
+            send_buffer_bytes: 0,
+            recv_buffer_bytes: 0,
+        }
+    }
+}
+
+impl TcpSettings {
+    /// Applies the settings to `stream`.
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        let socket = SockRef::from(stream);
+        if self.keepalive_secs > 0 {
+            let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(self.keepalive_secs));
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        if self.send_buffer_bytes > 0 {
+            socket.set_send_buffer_size(self.send_buffer_bytes)?;
+        }
+        if self.recv_buffer_bytes > 0 {
+            socket.set_recv_buffer_size(self.recv_buffer_bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the settings to a connection with `peer`. Failing to do so is not a reason to drop
+    /// the connection, so errors are only logged.
+    pub(crate) fn apply_or_warn(&self, stream: &TcpStream, peer: SocketAddr) {
+        if let Err(e) = self.apply(stream) {
+            warn!("Failed to apply TCP settings to the connection with {}: {}", peer, e);
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use super::*;
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn apply_settings() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let stream = TcpStream::connect(address).await.unwrap();
+
+    let settings = TcpSettings {
+        nodelay: true,
+        keepalive_secs: 30,
+        send_buffer_bytes: 64 * 1024,
+        recv_buffer_bytes: 64 * 1024,
+    };
+    settings.apply(&stream).unwrap();
+    let socket = SockRef::from(&stream);
+    assert!(socket.nodelay().unwrap());
+    assert!(socket.keepalive().unwrap());
+    // The kernel may round the buffer sizes up, but never below what we asked for.
+    assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+    assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+
+    // The defaults only turn Nagle's algorithm off.
+    let stream = TcpStream::connect(address).await.unwrap();
+    TcpSettings::default().apply(&stream).unwrap();
+    let socket = SockRef::from(&stream);
+    assert!(socket.nodelay().unwrap());
+    assert!(!socket.keepalive().unwrap());
+}
@@ -1,7 +1,7 @@
 use std::collections::{HashMap, VecDeque};
 use tokio::sync::mpsc::{channel, Sender};
 use tokio::sync::oneshot;
-use rocksdb::{DB, Options, LogLevel};
+use rocksdb::{DB, Options, LogLevel, WriteBatch};
 use log::{info, warn, error};
 use std::fmt::Display;
 use std::error::Error;
@@ -34,10 +34,12 @@ type Value = Vec<u8>;
 
 pub enum StoreCommand {
     Write(Key, Value),
+    WriteBatch(Vec<(Key, Value)>, oneshot::Sender<StoreResult<()>>),
     Read(Key, oneshot::Sender<StoreResult<Option<Value>>>),
     NotifyRead(Key, oneshot::Sender<StoreResult<Value>>),
     NotifyDestroy(oneshot::Sender<bool>),
     Delete(Key),
+    Flush(oneshot::Sender<StoreResult<()>>),
     Exit(oneshot::Sender<()>),
 }
 
@@ -76,6 +78,23 @@ impl Store {
                             }
                         }
                     }
+                    StoreCommand::WriteBatch(writes, sender) => {
+                        let mut batch = WriteBatch::default();
+                        for (key, value) in &writes {
+                            batch.put(key, value);
+                        }
+                        let response = db.write(batch).map_err(StoreError::RocksdbError);
+                        if response.is_ok() {
+                            for (key, value) in writes {
+                                if let Some(mut senders) = obligations.remove(&key) {
+                                    while let Some(s) = senders.pop_front() {
+                                        let _ = s.send(Ok(value.clone()));
+                                    }
+                                }
+                            }
+                        }
+                        let _ = sender.send(response);
+                    }
                     StoreCommand::Read(key, sender) => {
                         let response = db.get(&key).map_err(|e| StoreError::RocksdbError(e));
                         let _ = sender.send(response);
@@ -111,6 +130,14 @@ impl Store {
                     StoreCommand::Delete(key) => {
                         let _ = db.delete(key);
                     }
+                    StoreCommand::Flush(sender) => {
+                        // Sync the write-ahead log, then persist the memtables.
+                        let response = db
+                            .flush_wal(true)
+                            .and_then(|()| db.flush())
+                            .map_err(StoreError::RocksdbError);
+                        let _ = sender.send(response);
+                    }
                     StoreCommand::Exit(sender) => {
                         info!("Store receives exit signal");
                         let _ = sender.send(());
@@ -131,6 +158,18 @@ impl Store {
         }
     }
 
+    /// Writes all the given entries at once, and returns once they have been applied. Either all
+    /// of them are written or none is.
+    pub async fn write_batch(&self, writes: Vec<(Key, Value)>) -> StoreResult<()> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self.channel.send(StoreCommand::WriteBatch(writes, sender)).await {
+            error!("Failed to send WriteBatch command to store: {}", e);
+        }
+        receiver
+            .await
+            .unwrap_or(Err(StoreError::OtherError("Failed to receive reply to WriteBatch command from store".to_string())))
+    }
+
     pub async fn read(&self, key: Key) -> StoreResult<Option<Value>> {
         let (sender, receiver) = oneshot::channel();
         if let Err(e) = self.channel.send(StoreCommand::Read(key, sender)).await {
@@ -163,6 +202,18 @@ impl Store {
             .unwrap_or(Err(StoreError::OtherError("Failed to receive reply to NotifyRead command from store".to_string())))
     }
 
+    /// Makes the writes sent so far durable. The commands are applied in order, so every write
+    /// sent before the flush is covered.
+    pub async fn flush(&self) -> StoreResult<()> {
+        let (sender, receiver) = oneshot::channel();
+        if let Err(e) = self.channel.send(StoreCommand::Flush(sender)).await {
+            error!("Failed to send Flush command to store: {}", e);
+        }
+        receiver
+            .await
+            .unwrap_or(Err(StoreError::OtherError("Failed to receive reply to Flush command from store".to_string())))
+    }
+
     pub async fn notify_destroy(&self) {
         let (sender, _receiver) = oneshot::channel();
         if let Err(e) = self
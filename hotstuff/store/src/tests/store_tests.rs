@@ -71,3 +71,30 @@ async fn read_notify() {
     store.write(key, value).await;
     assert!(handle.await.is_ok());
 }
+
+#[tokio::test]
+async fn write_batch_values() {
+    // Create new store.
+    let path = ".db_test_write_batch_values";
+    let _ = fs::remove_dir_all(path);
+    let store = Store::new(path).unwrap();
+
+    // Wait for a value that is part of the batch.
+    let key = vec![0u8, 1u8, 2u8, 3u8];
+    let value = vec![4u8, 5u8, 6u8, 7u8];
+    let store_copy = store.clone();
+    let key_copy = key.clone();
+    let value_copy = value.clone();
+    let handle = tokio::spawn(async move {
+        assert_eq!(store_copy.notify_read(key_copy).await.unwrap(), value_copy);
+    });
+
+    // Write both values at once, then read them back.
+    let other_key = vec![8u8, 9u8];
+    let other_value = vec![10u8, 11u8];
+    let writes = vec![(key.clone(), value.clone()), (other_key.clone(), other_value.clone())];
+    assert!(store.write_batch(writes).await.is_ok());
+    assert_eq!(store.read(key).await.unwrap(), Some(value));
+    assert_eq!(store.read(other_key).await.unwrap(), Some(other_value));
+    assert!(handle.await.is_ok());
+}
@@ -1,15 +1,25 @@
 use tokio::sync::mpsc::{channel, Sender, Receiver};
-use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::error::{SendError, SendTimeoutError};
 use tokio::time::{sleep, Duration};
 use log::{warn};
 
-#[derive(Clone)]
 pub struct MonitoredSender<T> {
     pub inner: Sender<T>,
     _tag: String,
     _level: String,
 }
 
+// Implemented by hand so that senders of messages that are not `Clone` can be cloned too.
+impl<T> Clone for MonitoredSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            _tag: self._tag.clone(),
+            _level: self._level.clone(),
+        }
+    }
+}
+
 impl <T> MonitoredSender<T> 
 where T: Send + 'static {
     pub fn new(
@@ -35,6 +45,16 @@ where T: Send + 'static {
         self.inner.send(msg).await
     }
 
+    /// Like `send`, but gives the message back if the channel is still full after `timeout`.
+    pub async fn send_timeout(&self, msg: T, timeout: Duration) -> Result<(), SendTimeoutError<T>> {
+        self.inner.send_timeout(msg, timeout).await
+    }
+
+    /// The number of messages currently queued in the channel (including reserved slots).
+    pub fn queue_depth(&self) -> usize {
+        self.inner.max_capacity() - self.inner.capacity()
+    }
+
     async fn log(sender: Sender<T>, tag: String, _level: String) {
         loop {
             sleep(Duration::from_millis(60_000)).await;
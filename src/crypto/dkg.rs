@@ -20,6 +20,12 @@ use serde_derive::{Serialize, Deserialize};
 use serde::{Serialize as SerializeTrait, Deserialize as DeserializeTrait, Serializer, Deserializer};
 use async_trait::async_trait;
 use log::{info, error};
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// How long to wait for a message from another party before giving up on the DKG.
+const DKG_PARTICIPANT_TIMEOUT: Duration = Duration::from_secs(300);
+
 pub struct PlainVssShare {
     pub share: blst_scalar,
     pub committed_poly: CommittedPoly,
@@ -249,7 +255,9 @@ where
     async fn run(&self) -> Result<(WrapKeypair, WrapPublicKey, HashMap<u64, WrapPublicKey>), DvfError> {
         let mut threshold_sig = ThresholdSignature::new(self.threshold);
         let ids = self.io.ids();
-        let (kp, kps, _poly) = threshold_sig.key_gen_with_poly(ids)?;
+        let (kp, kps, _poly) = threshold_sig
+            .key_gen_with_poly(ids)
+            .map_err(|e| DvfError::DkgRound1Failed(format!("{:?}", e)))?;
 
         // 0. Broadcast shares and commitments
         let kps_ref = &kps; // Take reference so that it can be used in async move
@@ -365,7 +373,9 @@ where
     pub fn share_generation(&self) -> Result<(WrapKeypair, HashMap<u64, WrapKeypair>, Polynomial<BigInt>, CommittedPoly), DvfError> {
         let mut threshold_sig = ThresholdSignature::new(self.threshold);
         let ids = self.io.ids();
-        let (kp, kps, poly) = threshold_sig.key_gen_with_poly(ids)?;
+        let (kp, kps, poly) = threshold_sig
+            .key_gen_with_poly(ids)
+            .map_err(|e| DvfError::DkgRound1Failed(format!("{:?}", e)))?;
         let committed_poly = poly.commit(&self.h);
 
         Ok((kp, kps, poly, committed_poly))
@@ -401,17 +411,18 @@ where
             };
             let fut = async move {
                 send_channel.send(payload.to_bytes()).await;
-                let partner_palyload = VssSharePayload::from_bytes(&recv_channel.recv().await);
-                (*id, partner_palyload)
+                let msg = timeout(DKG_PARTICIPANT_TIMEOUT, recv_channel.recv())
+                    .await
+                    .map_err(|_| DvfError::DkgParticipantTimeout { id: *id })?;
+                let partner_palyload = VssSharePayload::from_bytes(&msg);
+                Ok((*id, partner_palyload))
             };
             futs.push(fut);
         }
-        let results = join_all(futs)
+        join_all(futs)
             .await
             .into_iter()
-            .collect::<HashMap<u64, VssSharePayload>>();
-
-        Ok(results)
+            .collect::<Result<HashMap<u64, VssSharePayload>, DvfError>>()
     }
 
     pub fn reveal_shares(&self, shares: &HashMap<u64, VssSharePayload>) -> HashMap<u64, blst_scalar> {
@@ -449,7 +460,7 @@ where
         }
     }
 
-    pub async fn exchange_verification_results(&self, r: &VerificationResult) -> HashMap<u64, VerificationResult> {
+    pub async fn exchange_verification_results(&self, r: &VerificationResult) -> Result<HashMap<u64, VerificationResult>, DvfError> {
         self.io.broadcast(r.to_bytes()).await;
         let mut others = HashMap::<u64, VerificationResult>::default();
         // TODO: parallelize
@@ -458,11 +469,13 @@ where
                 continue;
             }
             let recv_channel = self.io.channel(*id, self.party);
-            let msg = recv_channel.recv().await;
+            let msg = timeout(DKG_PARTICIPANT_TIMEOUT, recv_channel.recv())
+                .await
+                .map_err(|_| DvfError::DkgParticipantTimeout { id: *id })?;
             let result = VerificationResult::from_bytes(&msg);
             others.insert(*id, result);
         }
-        others
+        Ok(others)
     }
 
     pub async fn issude_dispute_claim(&self, corrupted_party_id: u64, share: &VssSharePayload) -> Result<(), DvfError> {
@@ -711,16 +724,16 @@ where
         info!("[DKG] Party {}: Transmit encrypted local key shares and committment to other parties --> Done", self.party);
         let vrfy_result = self.share_verification(&payloads);
         info!("[DKG] Party {}: Verify received shares and committment --> Success", self.party);
-        let other_vrfy_results = self.exchange_verification_results(&vrfy_result).await;
+        let other_vrfy_results = self.exchange_verification_results(&vrfy_result).await?;
         info!("[DKG] Party {}: Exchange verification results --> Done", self.party);
 
         // Issue dispute claim if any verification fails
-        let mut self_abort = false;
+        let mut invalid_share_from: Option<u64> = None;
         for (id, valid) in vrfy_result.results.iter() {
             if *valid {
                 continue;
             }
-            self_abort = true;
+            invalid_share_from = Some(invalid_share_from.map_or(*id, |from| from.min(*id)));
             self.issude_dispute_claim(*id, payloads.get(&id).unwrap()).await?;
             info!("[DKG] Party {}: Issue dispute claim against {} --> Done", self.party, id);
         }
@@ -749,9 +762,9 @@ where
             error!("[DKG] Party {}: Aborting due to a valid received dispute claim", self.party);
             return Err(DvfError::InvalidDkgShare(valid_claims));
         }
-        if self_abort {
+        if let Some(from) = invalid_share_from {
             error!("[DKG] Party {}: Aborting due to a valid local dispute claim", self.party);
-            return Err(DvfError::VssShareVerificationFailed);
+            return Err(DvfError::DkgShareVerificationFailed { from });
         }
         info!("[DKG] Party {}: No valid dispute claims found --> Continue", self.party);
 
@@ -137,13 +137,15 @@ where
             return Err(DvfError::DifferentLength{x: sigs.len(), y: ids.len()}); 
         }
         if sigs.len() < self.threshold() {
-            return Err(DvfError::InsufficientSignatures{got: sigs.len(), expected: self.threshold()}); 
+            // The full operator set is not known here, so absent operators are left to the caller.
+            return Err(DvfError::InsufficientSignatures{got: sigs.len(), expected: self.threshold(), failed_operators: Vec::new()}); 
         }
 
         let mut pks_valid: Vec<&PublicKey> = Vec::new();
         let mut sigs_valid: Vec<&Signature> = Vec::new();
         let mut ids_valid: Vec<u64> = Vec::new();
         let mut valid_set: HashSet<u64> = HashSet::new();
+        let mut ids_invalid: Vec<u64> = Vec::new();
 
         let total = sigs.len();
         for i in 0..total {
@@ -165,10 +167,11 @@ where
             }
             else {
                 error!("Invalid signature from operator {}", ids[i]);
+                ids_invalid.push(ids[i]);
             }
         }
         if pks_valid.len() < self.threshold() {
-            return Err(DvfError::InsufficientValidSignatures{got: pks_valid.len(), expected: self.threshold()}); 
+            return Err(DvfError::InsufficientValidSignatures{got: pks_valid.len(), expected: self.threshold(), failed_operators: ids_invalid}); 
         }
 
         Ok(self.unsafe_aggregate(&sigs_valid, &ids_valid[..]))
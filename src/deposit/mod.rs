@@ -36,7 +36,7 @@ pub async fn get_valid_beacon_node_http_client(beacon_nodes_urls: &Vec<Sensitive
             }
         }  
     }
-    Err(DvfError::BeaconNodeClientError)
+    Err(DvfError::BeaconNodeUnavailable {tried: beacon_nodes_urls.len()})
 }
 
 /// Refer to `/lighthouse/common/deposit_contract/src/lib.rs`
@@ -1,6 +1,7 @@
 use std::fs::create_dir_all;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
+use std::time::Duration;
 
 use directory::{DEFAULT_ROOT_DIR, DEFAULT_SECRET_DIR, DEFAULT_VALIDATOR_DIR};
 use serde_derive::{Deserialize, Serialize};
@@ -84,6 +85,41 @@ pub fn base_to_signature_addr(base_addr: SocketAddr) -> SocketAddr {
     }
 }
 
+/// How long the aggregator waits for the operators' signature shares once the committee agreed on
+/// a duty. When the timeout fires, the shares received so far are aggregated if there are enough
+/// of them, otherwise signing fails with `InsufficientSignatures`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SigningTimeouts {
+    pub block_ms: u64,
+    pub randao_ms: u64,
+    pub attestation_ms: u64,
+}
+
+impl Default for SigningTimeouts {
+    fn default() -> Self {
+        // Enough for a remote operator to answer within its retries.
+        Self {
+            block_ms: 2_000,
+            randao_ms: 2_000,
+            attestation_ms: 2_000,
+        }
+    }
+}
+
+impl SigningTimeouts {
+    pub fn block(&self) -> Duration {
+        Duration::from_millis(self.block_ms)
+    }
+
+    pub fn randao(&self) -> Duration {
+        Duration::from_millis(self.randao_ms)
+    }
+
+    pub fn attestation(&self) -> Duration {
+        Duration::from_millis(self.attestation_ms)
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct NodeConfig {
     pub base_address: SocketAddr,
@@ -92,7 +128,9 @@ pub struct NodeConfig {
     pub validator_dir: PathBuf,
     pub secrets_dir: PathBuf,
     pub boot_enrs: Vec<Enr<CombinedKey>>,
-    pub beacon_nodes: Vec<SensitiveUrl>
+    pub beacon_nodes: Vec<SensitiveUrl>,
+    #[serde(default)]
+    pub signing_timeouts: SigningTimeouts,
 }
 
 impl Default for NodeConfig {
@@ -136,7 +174,8 @@ impl NodeConfig {
             validator_dir,
             secrets_dir,
             boot_enrs,
-            beacon_nodes: Vec::new()
+            beacon_nodes: Vec::new(),
+            signing_timeouts: SigningTimeouts::default(),
         }
     }
 
@@ -182,4 +221,9 @@ impl NodeConfig {
         self.beacon_nodes = beacon_nodes;
         self
     }
+
+    pub fn set_signing_timeouts(mut self, signing_timeouts: SigningTimeouts) -> Self {
+        self.signing_timeouts = signing_timeouts;
+        self
+    }
 }
@@ -1,6 +1,7 @@
 use std::error::Error;
 use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
 use bls::{Hash256, Signature};
@@ -11,7 +12,7 @@ use futures::SinkExt;
 use hsconfig::{Committee as HotstuffCommittee, Parameters};
 use hscrypto::SignatureService;
 use hsutils::monitored_channel::{MonitoredChannel, MonitoredSender};
-use mempool::{Mempool, MempoolMessage};
+use mempool::{decode_message, Codec, Mempool, MempoolMessage, MempoolStats};
 use mempool::Committee as MempoolCommittee;
 use network::{MessageHandler, Writer};
 use serde::{Deserialize, Serialize};
@@ -23,7 +24,8 @@ use types::{EthSpec, Keypair};
 
 use crate::DEFAULT_CHANNEL_CAPACITY;
 use crate::node::config::{invalid_addr, base_to_transaction_addr, base_to_mempool_addr,
-    base_to_consensus_addr, base_to_signature_addr};
+    base_to_consensus_addr, base_to_signature_addr, SigningTimeouts};
+use crate::node::leader_history::{LeaderAssignment, LeaderHistory};
 use crate::node::node::Node;
 use crate::utils::error::DvfError;
 use crate::validation::OperatorCommittee;
@@ -56,7 +58,8 @@ pub struct DvfReceiverHandler {
 impl MessageHandler for DvfReceiverHandler {
     async fn dispatch(&self, _writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
         let dvfinfo = serde_json::from_slice(&message.to_vec())?;
-        self.tx_dvfinfo.send(dvfinfo).await.unwrap();
+        // The receiver is only dropped on shutdown: report it instead of panicking.
+        self.tx_dvfinfo.send(dvfinfo).await.map_err(DvfError::from)?;
         // Give the change to schedule other tasks.
         tokio::task::yield_now().await;
         Ok(())
@@ -128,7 +131,12 @@ pub struct DvfSigner {
     pub operator_committee: OperatorCommittee,
     pub local_keypair: Keypair,
     pub store: Store,
-    pub node_secret: hscrypto::SecretKey
+    pub node_secret: hscrypto::SecretKey,
+    pub signing_timeouts: SigningTimeouts,
+    /// The latest leader decisions of `is_aggregator`.
+    pub leader_history: LeaderHistory,
+    /// The backlog of the mempool, which also reloads its committee and flushes its store.
+    pub mempool_stats: Arc<MempoolStats>,
 }
 
 impl Drop for DvfSigner {
@@ -136,10 +144,42 @@ impl Drop for DvfSigner {
         if let Some(signal) = self.signal.take() {
             info!("Shutting down dvf signer");
             let _ = signal.fire();
+            // The store does not depend on the exit signal, so the batches handed over to it so far
+            // can still be made durable.
+            if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+                let mempool_stats = self.mempool_stats.clone();
+                runtime.spawn(async move {
+                    if let Err(e) = mempool_stats.flush().await {
+                        error!("Failed to flush the mempool store on shutdown: {}", e);
+                    }
+                });
+            }
         }
     }
 }
 
+/// Builds the mempool committee of the operators of `committee_def`.
+pub fn mempool_committee(committee_def: &OperatorCommitteeDefinition) -> MempoolCommittee {
+    let epoch = 1;
+    let stake = 1;
+    MempoolCommittee::new(
+        committee_def.node_public_keys
+            .iter()
+            .enumerate()
+            .map(|(i, pk)| {
+                let addr = committee_def.base_socket_addresses[i].unwrap_or(invalid_addr());
+                (pk.clone(),
+                 stake,
+                 base_to_transaction_addr(addr),
+                 base_to_mempool_addr(addr),
+                 base_to_signature_addr(addr),
+                )
+            })
+            .collect(),
+        epoch,
+    )
+}
+
 impl DvfSigner {
     pub async fn spawn<T: EthSpec>(
         node_para: Arc<RwLock<Node<T>>>,
@@ -149,6 +189,7 @@ impl DvfSigner {
         let node_tmp = Arc::clone(&node_para);
         let node = node_tmp.read().await;
         let node_secret = node.secret.secret.clone();
+        let signing_timeouts = node.config.signing_timeouts;
         let validator_id = committee_def.validator_id;
         // find operator id from operatorCommitteeDefinition
         let operator_index: Vec<usize> = committee_def.node_public_keys.iter().enumerate().filter(|&(_i, x)| {
@@ -166,22 +207,7 @@ impl DvfSigner {
         // Construct the committee for hotstuff protocol
         let epoch = 1;
         let stake = 1;
-        let mempool_committee = MempoolCommittee::new(
-            committee_def.node_public_keys
-                .iter()
-                .enumerate()
-                .map(|(i, pk)| {
-                    let addr = committee_def.base_socket_addresses[i].unwrap_or(invalid_addr());
-                    (pk.clone(),
-                     stake,
-                     base_to_transaction_addr(addr),
-                     base_to_mempool_addr(addr),
-                     base_to_signature_addr(addr),
-                    )
-                })
-                .collect(),
-            epoch,
-        );
+        let mempool_committee = mempool_committee(&committee_def);
         let consensus_committee = ConsensusCommittee::new(
             committee_def.node_public_keys
                 .iter()
@@ -207,7 +233,7 @@ impl DvfSigner {
 
         let (signal, exit) = exit_future::signal();
 
-        DvfCore::spawn(
+        let mempool_stats = DvfCore::spawn(
             operator_id,
             node_para.clone(),
             committee_def.validator_id,
@@ -221,6 +247,7 @@ impl DvfSigner {
         Node::spawn_committee_ip_monitor(
             node_para,
             committee_def,
+            mempool_stats.clone(),
             exit,
         );
 
@@ -230,12 +257,21 @@ impl DvfSigner {
             operator_committee,
             local_keypair: keypair,
             store,
-            node_secret
+            node_secret,
+            signing_timeouts,
+            leader_history: LeaderHistory::default(),
+            mempool_stats,
         })
     }
 
-    pub async fn threshold_sign(&self, message: Hash256) -> Result<(Signature, Vec<u64>), DvfError> {
-        self.operator_committee.sign(message).await
+    /// Waits until the batches the mempool handed over to its store so far are durable, e.g.
+    /// before a backup.
+    pub async fn flush(&self) -> Result<(), DvfError> {
+        self.mempool_stats.flush().await.map_err(DvfError::from)
+    }
+
+    pub async fn threshold_sign(&self, message: Hash256, share_timeout: Option<Duration>) -> Result<(Signature, Vec<u64>), DvfError> {
+        self.operator_committee.sign(message, share_timeout).await
     }
 
     pub fn local_sign(&self, message: Hash256) -> Signature {
@@ -250,9 +286,15 @@ impl DvfSigner {
         self.store.write(key, serialized_signature).await;
     }
 
-    pub async fn is_aggregator(&self, nonce: u64) -> bool {
-        self.operator_committee.get_leader(nonce).await == self.operator_id
-            || self.operator_committee.get_leader(nonce + 1).await == self.operator_id
+    pub async fn is_aggregator(&self, slot: u64, nonce: u64) -> bool {
+        let leaders = vec![
+            self.operator_committee.get_leader(nonce).await,
+            self.operator_committee.get_leader(nonce + 1).await,
+        ];
+        let assignment = LeaderAssignment::new(slot, nonce, leaders, self.operator_id);
+        let is_aggregator = assignment.is_aggregator;
+        self.leader_history.record(assignment);
+        is_aggregator
     }
 
     pub fn validator_public_key(&self) -> String {
@@ -271,6 +313,8 @@ pub struct DvfCore {
     pub operator_id: u64,
     pub bls_keypair: Keypair,
     pub tx_consensus: MonitoredSender<Hash256>,
+    /// The format in which the mempool stores its batches.
+    pub codec: Codec,
     pub exit: exit_future::Exit,
 }
 
@@ -288,7 +332,7 @@ impl DvfCore {
         tx_consensus: MonitoredSender<Hash256>,
         store: Store,
         exit: exit_future::Exit,
-    ) {
+    ) -> Arc<MempoolStats> {
         let node = node.read().await;
 
         let (tx_commit, rx_commit) = MonitoredChannel::new(DEFAULT_CHANNEL_CAPACITY, "dvf-commit".to_string(), "info");
@@ -296,6 +340,7 @@ impl DvfCore {
         let (tx_mempool_to_consensus, rx_mempool_to_consensus) = MonitoredChannel::new(DEFAULT_CHANNEL_CAPACITY, "dvf-mp2cs".to_string(), "info");
 
         let parameters = Parameters::default();
+        let codec = parameters.mempool.codec;
 
         // Run the signature service.
         let signature_service = SignatureService::new(node.secret.secret.clone());
@@ -306,13 +351,17 @@ impl DvfCore {
             .insert(validator_id, DvfSignatureReceiverHandler { store: store.clone() });
         info!("Insert signature handler for validator: {}", validator_id);
 
-        Mempool::spawn(
+        let mempool_stats = Mempool::spawn(
             node.secret.name,
             committee.mempool,
             parameters.mempool,
+            signature_service.clone(),
             store.clone(),
             rx_consensus_to_mempool,
             tx_mempool_to_consensus,
+            None,
+            None,
+            None,
             validator_id,
             Arc::clone(&node.tx_handler_map),
             Arc::clone(&node.mempool_handler_map),
@@ -343,11 +392,14 @@ impl DvfCore {
                 operator_id: operator_id,
                 bls_keypair: keypair,
                 tx_consensus,
+                codec,
                 exit,
             }
                 .run()
                 .await
         });
+
+        mempool_stats
     }
 
     pub async fn run(&mut self) {
@@ -366,9 +418,15 @@ impl DvfCore {
                             Ok(value) => {
                                 match value {
                                     Some(data) => {
-                                        let message: MempoolMessage = bincode::deserialize(&data[..]).unwrap();
+                                        let message: MempoolMessage = match decode_message(self.codec, &data[..], data.len() as u64).map_err(DvfError::from) {
+                                            Ok(message) => message,
+                                            Err(e) => {
+                                                error!("[Dvf {}/{}] Failed to decode stored batch: {:?}", self.operator_id, self.validator_id, e);
+                                                continue;
+                                            }
+                                        };
                                         match message {
-                                            MempoolMessage::Batch(batches) => {
+                                            MempoolMessage::Batch(batches) | MempoolMessage::SignedBatch(batches, _, _) => {
                                                 for batch in batches {
                                                     // construct hash256
                                                     let msg = Hash256::from_slice(&batch[..]);
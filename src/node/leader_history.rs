@@ -0,0 +1,133 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// The number of leader decisions kept per validator.
+pub const LEADER_HISTORY_CAPACITY: usize = 64;
+
+/// A decision of the signing layer on which operators may aggregate the signatures of the duties
+/// only the leader signs (e.g. blocks).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LeaderAssignment {
+    /// The slot of the duty the decision was taken for.
+    pub slot: u64,
+    /// The nonce the leaders were chosen for, i.e. the signing epoch.
+    pub nonce: u64,
+    /// The operators allowed to aggregate for that nonce.
+    pub leaders: Vec<u64>,
+    /// Whether we are one of them.
+    pub is_aggregator: bool,
+}
+
+impl LeaderAssignment {
+    pub fn new(slot: u64, nonce: u64, leaders: Vec<u64>, operator_id: u64) -> Self {
+        let is_aggregator = leaders.contains(&operator_id);
+        Self {
+            slot,
+            nonce,
+            leaders,
+            is_aggregator,
+        }
+    }
+}
+
+/// The leader decisions of a validator's committee for its latest slots, oldest first. This is read-only
+/// telemetry, used to check that the leadership rotates fairly and to explain `NotLeader` results.
+pub struct LeaderHistory {
+    capacity: usize,
+    assignments: Mutex<VecDeque<LeaderAssignment>>,
+}
+
+impl Default for LeaderHistory {
+    fn default() -> Self {
+        Self::new(LEADER_HISTORY_CAPACITY)
+    }
+}
+
+impl LeaderHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            assignments: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Records a decision. The decision is taken once per duty, and a slot may have several
+    /// duties, so a slot that is still recorded only has its entry refreshed.
+    pub fn record(&self, assignment: LeaderAssignment) {
+        let mut assignments = self.assignments.lock();
+        if let Some(existing) = assignments.iter_mut().find(|x| x.slot == assignment.slot) {
+            *existing = assignment;
+            return;
+        }
+        if assignments.len() == self.capacity {
+            assignments.pop_front();
+        }
+        assignments.push_back(assignment);
+    }
+
+    /// Returns the decision for `slot`, if it is still recorded.
+    pub fn get(&self, slot: u64) -> Option<LeaderAssignment> {
+        self.assignments
+            .lock()
+            .iter()
+            .find(|assignment| assignment.slot == slot)
+            .cloned()
+    }
+
+    pub fn snapshot(&self) -> Vec<LeaderAssignment> {
+        self.assignments.lock().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::generic_operator_committee::select_leader;
+
+    #[test]
+    fn records_signing_decisions() {
+        let operator_ids = [4, 1, 3, 2];
+        let operator_id = 2;
+        let slots_per_epoch = 2;
+        let history = LeaderHistory::new(3);
+
+        // Take the decisions the way `DvfSigner::is_aggregator` does.
+        let mut decisions = Vec::new();
+        for slot in 0..10u64 {
+            let nonce = slot / slots_per_epoch;
+            let assignment = LeaderAssignment::new(
+                slot,
+                nonce,
+                vec![
+                    select_leader(&operator_ids, nonce),
+                    select_leader(&operator_ids, nonce + 1),
+                ],
+                operator_id,
+            );
+            decisions.push(assignment.is_aggregator);
+            history.record(assignment);
+        }
+        // Operator 2 has the second smallest id, so it leads the nonces equal to 1 modulo 4, and
+        // aggregates for those and the ones just before.
+        assert_eq!(
+            decisions,
+            vec![true, true, true, true, false, false, false, false, true, true]
+        );
+
+        // Only the latest slots are kept, and their decisions match the ones taken.
+        let snapshot = history.snapshot();
+        assert_eq!(snapshot.iter().map(|x| x.slot).collect::<Vec<_>>(), vec![7, 8, 9]);
+        assert_eq!(snapshot.iter().map(|x| x.nonce).collect::<Vec<_>>(), vec![3, 4, 4]);
+        assert_eq!(
+            snapshot.iter().map(|x| x.is_aggregator).collect::<Vec<_>>(),
+            decisions[7..].to_vec()
+        );
+        assert_eq!(history.get(9).unwrap().leaders, vec![1, 2]);
+        assert!(history.get(6).is_none());
+
+        // Deciding again for a recorded slot, e.g. for another duty, does not evict anything.
+        history.record(LeaderAssignment::new(8, 4, vec![1, 2], operator_id));
+        assert_eq!(history.snapshot().len(), 3);
+    }
+}
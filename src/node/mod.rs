@@ -1,5 +1,6 @@
 pub mod node;
 pub mod dvfcore;
+pub mod leader_history;
 pub mod config;
 pub mod discovery;
 pub mod contract;
@@ -9,7 +9,7 @@ use consensus::ConsensusReceiverHandler;
 use eth2_keystore::KeystoreBuilder;
 use hsconfig::{ConfigError, Secret};
 use hsconfig::Export as _;
-use mempool::{MempoolReceiverHandler, TxReceiverHandler};
+use mempool::{MempoolReceiverHandler, MempoolStats, Parameters as MempoolParameters, TxReceiverHandler};
 use network::Receiver as NetworkReceiver;
 use slot_clock::SystemTimeSlotClock;
 use tokio::sync::RwLock;
@@ -31,7 +31,7 @@ use crate::node::config::{
 };
 use crate::node::discovery::Discovery;
 /// The default channel capacity for this module.
-use crate::node::dvfcore::DvfSignatureReceiverHandler;
+use crate::node::dvfcore::{mempool_committee, DvfSignatureReceiverHandler};
 use crate::node::contract::{
     Contract, ContractCommand, EncryptedSecretKeys, Initiator, OperatorPublicKeys,
     SharedPublicKeys, Validator, SELF_OPERATOR_ID, OperatorIds, CONTRACT_DATABASE_FILE, InitiatorStoreRecord
@@ -82,11 +82,15 @@ impl<T: EthSpec> Node<T> {
         let consensus_handler_map = Arc::new(RwLock::new(HashMap::new()));
         let signature_handler_map = Arc::new(RwLock::new(HashMap::new()));
 
+        // The mempool receivers are shared by all validators, so they use the default mempool
+        // parameters, as each validator's mempool does.
+        let mempool_tcp = MempoolParameters::default().tcp;
         let transaction_address = with_wildcard_ip(base_to_transaction_addr(config.base_address));
-        NetworkReceiver::spawn(
+        NetworkReceiver::spawn_with_tcp_settings(
             transaction_address,
             Arc::clone(&tx_handler_map),
             "transaction",
+            mempool_tcp,
         );
         info!(
             "Node {} listening to client transactions on {}",
@@ -94,7 +98,12 @@ impl<T: EthSpec> Node<T> {
         );
 
         let mempool_address = with_wildcard_ip(base_to_mempool_addr(config.base_address));
-        NetworkReceiver::spawn(mempool_address, Arc::clone(&mempool_handler_map), "mempool");
+        NetworkReceiver::spawn_with_tcp_settings(
+            mempool_address,
+            Arc::clone(&mempool_handler_map),
+            "mempool",
+            mempool_tcp,
+        );
         info!(
             "Node {} listening to mempool messages on {}",
             secret.name, mempool_address
@@ -362,6 +371,7 @@ impl<T: EthSpec> Node<T> {
     pub fn spawn_committee_ip_monitor(
         node: Arc<RwLock<Node<T>>>, 
         mut committee_def: OperatorCommitteeDefinition, 
+        mempool_stats: Arc<MempoolStats>,
         exit: exit_future::Exit
     ) {
         tokio::spawn(async move {
@@ -396,6 +406,9 @@ impl<T: EthSpec> Node<T> {
                         }
                         drop(node_lock);
                         if restart {
+                            // Point the mempool at the new addresses right away, as the restart
+                            // below may have to wait for the validator store.
+                            mempool_stats.reload_committee(mempool_committee(&committee_def));
                             // Save the committee definition to file, as the file will be used to restart the VA.
                             let committee_def_path =
                                 default_operator_committee_definition_path(&validator_pk, validator_dir.clone());
@@ -1,9 +1,20 @@
 use bls::Error as BlsError;
 use blst::BLST_ERROR as BlstError;
+use std::fmt;
+use tokio::sync::mpsc::error::SendError;
 
+/// Panics with `msg` if `status` is false. Prefer `ensure` in async code, where a panic can
+/// silently kill a spawned task.
 pub fn require(status: bool, msg: &'static str) {
-    if !status {
-        panic!("{}", msg);
+    ensure(status, msg).expect(msg);
+}
+
+/// Returns `DvfError::Precondition` with `msg` if `status` is false.
+pub fn ensure(status: bool, msg: &'static str) -> Result<(), DvfError> {
+    if status {
+        Ok(())
+    } else {
+        Err(DvfError::Precondition(msg.to_string()))
     }
 }
 
@@ -16,10 +27,18 @@ pub enum DvfError {
     ConsensusFailure(String),
     /// Key generation failed.
     KeyGenError(String),
+    /// The DKG failed to generate or commit to our local shares.
+    DkgRound1Failed(String),
+    /// The DKG share received from operator `from` does not match its commitment.
+    DkgShareVerificationFailed {from: u64},
+    /// Operator `id` did not send its DKG message in time.
+    DkgParticipantTimeout {id: u64},
+    /// Threshold signature aggregation failed due to insufficient signatures.
+    /// `failed_operators` lists the operators whose shares were absent, when known.
+    InsufficientSignatures {got: usize, expected: usize, failed_operators: Vec<u64>},
     /// Threshold signature aggregation failed due to insufficient valid signatures.
-    InsufficientSignatures {got: usize, expected: usize},
-    /// Threshold signature aggregation failed due to insufficient valid signatures.
-    InsufficientValidSignatures {got: usize, expected: usize},
+    /// `failed_operators` lists the operators whose shares were absent or failed verification.
+    InsufficientValidSignatures {got: usize, expected: usize, failed_operators: Vec<u64>},
     /// Invalid operator signature
     InvalidSignatureShare {id: u64},
     /// Invalid operator id 
@@ -30,10 +49,10 @@ pub enum DvfError {
     InvalidLength,
     /// Should not call the function specified by the string
     UnexpectedCall(String),
+    /// A precondition checked by `ensure` does not hold
+    Precondition(String),
     /// Error propogated from Store
     StoreError(String),
-    /// Vss share verification
-    VssShareVerificationFailed,
     /// Dispute claim
     InvalidDkgShare(Vec<(u64, u64)>),
     /// Commitment
@@ -49,16 +68,31 @@ pub enum DvfError {
     ValidatorStoreNotReady,
     /// Unknown error
     Unknown,
-    /// BeaconNode client error
-    BeaconNodeClientError,
     /// Get beacon genesis error
     BeaconNodeGenesisError,
     /// Get beacon validator data error
     BeaconNodeValidatorError(String),
     /// Get beacon state fork error
-    BeaconNodeStateForkError(String)
+    BeaconNodeStateForkError(String),
+    /// An operation did not complete in time
+    Timeout {context: String, elapsed_ms: u64},
+    /// None of the beacon nodes tried could serve the request
+    BeaconNodeUnavailable {tried: usize},
+    /// A message could not be (de)serialized
+    Serialization(String),
+    /// A message could not be handed over to the network or to the task consuming it, e.g.
+    /// because its channel was closed during shutdown
+    Network(String),
 }
 
+impl fmt::Display for DvfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for DvfError {}
+
 impl From<BlsError> for DvfError {
     fn from(e: BlsError) -> DvfError {
         DvfError::BlsError(e)
@@ -69,4 +103,56 @@ impl From<BlstError> for DvfError {
     fn from(e: BlstError) -> DvfError {
         DvfError::BlstError(e)
     }
-}
\ No newline at end of file
+}
+
+impl From<bincode::Error> for DvfError {
+    fn from(e: bincode::Error) -> DvfError {
+        DvfError::Serialization(e.to_string())
+    }
+}
+
+impl From<store::StoreError> for DvfError {
+    fn from(e: store::StoreError) -> DvfError {
+        DvfError::StoreError(e.to_string())
+    }
+}
+
+impl<T> From<SendError<T>> for DvfError {
+    fn from(e: SendError<T>) -> DvfError {
+        DvfError::Network(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ensure() {
+        assert_eq!(ensure(true, "unused"), Ok(()));
+        assert_eq!(
+            ensure(false, "Different length"),
+            Err(DvfError::Precondition("Different length".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_from_bincode_error() {
+        let e = bincode::deserialize::<u64>(&[0u8; 4]).unwrap_err();
+        assert!(matches!(DvfError::from(e), DvfError::Serialization(_)));
+    }
+
+    #[test]
+    fn test_from_send_error() {
+        assert_eq!(
+            DvfError::from(SendError(1u64)),
+            DvfError::Network("channel closed".to_string())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Different length")]
+    fn test_require_panics() {
+        require(false, "Different length");
+    }
+}
@@ -332,6 +332,14 @@ impl<T: SlotClock, E: EthSpec> BeaconNodeFallback<T, E> {
         self.candidates.len()
     }
 
+    /// The names of the candidates, regardless of their state, in the order they are tried.
+    pub fn candidate_names(&self) -> Vec<String> {
+        self.candidates
+            .iter()
+            .map(|candidate| candidate.beacon_node.to_string())
+            .collect()
+    }
+
     /// The count of synced and ready candidates.
     pub async fn num_synced(&self) -> usize {
         let mut n = 0;
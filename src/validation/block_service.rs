@@ -5,451 +5,3816 @@ use crate::validation::{
     beacon_node_fallback::{BeaconNodeFallback, RequireSynced, OfflineOnFailure},
     graffiti_file::GraffitiFile,
 };
-use crate::validation::{http_metrics::metrics, validator_store::ValidatorStore, validator_store::Error as VSError};
+use crate::validation::{http_metrics::metrics, validator_store::ValidatorStore, validator_store::DoppelgangerStatus, validator_store::Error as VSError};
 use crate::validation::signing_method::Error as SigningError;
+use async_trait::async_trait;
 use environment::RuntimeContext;
 use eth2::types::Graffiti;
-use slog::{crit, debug, error, info, trace, warn};
+use eth2::StatusCode;
+use parking_lot::{Mutex, RwLock};
+use rand::Rng;
+use serde::Serialize;
+use slog::{crit, debug, error, info, trace, warn, Logger};
 use slot_clock::SlotClock;
+use ssz::Encode;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
 use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::time::{sleep, timeout};
+use tokio_util::sync::CancellationToken;
+use types::graffiti::{GraffitiString, GRAFFITI_BYTES_LEN};
 use types::{
-    AbstractExecPayload, BlindedPayload, BlockType, Epoch, EthSpec, FullPayload, PublicKeyBytes, Slot,
+    AbstractExecPayload, BeaconBlock, BlindedPayload, BlockType, ChainSpec, Epoch, EthSpec,
+    ExecPayload, ExecutionBlockHash, ForkName, FullPayload, Keypair, PublicKeyBytes, Signature,
+    SignatureBytes, SignedBeaconBlock, Slot,
 };
 
+/// The base delay between re-requests of a block whose proposer index does not match ours. The
+/// delay grows linearly with each attempt.
+const PROPOSER_MISMATCH_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// The upper bound of the random delay before retrying a block proposal that a beacon node
+/// throttled.
+const THROTTLED_RETRY_MAX_DELAY: Duration = Duration::from_millis(500);
+
+/// Prefix of the graffiti synthesized from the validator index when none is configured.
+const DEFAULT_GRAFFITI_PREFIX: &str = "SafeStake-";
+
+/// Number of epochs (including the latest one) for which randao reveals are kept in the cache.
+const RANDAO_CACHE_EPOCHS: u64 = 2;
+
+/// Default cap on the number of proposers a single slot notification produces blocks for. A slot
+/// has a single proposer, so this is only ever reached with a broken configuration.
+pub const DEFAULT_MAX_PROPOSERS_PER_SLOT: usize = 64;
+
+/// Number of characters of the hex-encoded validator pubkey (including `0x`) used as a metric label.
+const PUBKEY_LABEL_LEN: usize = 10;
+
+/// Number of epochs after which blinded blocks are tried again once no beacon node served them,
+/// e.g. because the nodes were upgraded or the merge activated in the meantime.
+const BLINDED_UNSUPPORTED_REPROBE_EPOCHS: u64 = 8;
+
 #[derive(Debug)]
 pub enum BlockError {
     Recoverable(String),
     Irrecoverable(String),
     RandaoNotLeader,
     SignBlockNotLeader,
+    /// A beacon node rejected the request with HTTP 429. Recoverable, but should be retried after
+    /// a delay rather than immediately.
+    Throttled(String),
+    /// Slashing protection refused to sign the block, e.g. a double proposal. This is a safety
+    /// event and must not be retried on another beacon node.
+    SlashingProtection(String),
+    /// The beacon node does not serve blinded blocks, e.g. it lacks the endpoint or the merge is
+    /// not active on its network. Blinded blocks are skipped for `BLINDED_UNSUPPORTED_REPROBE_EPOCHS`.
+    BlindedUnsupported(String),
+    /// The beacon node is still syncing and cannot produce a block. The next beacon node is tried
+    /// instead; this is only returned once none of them is synced.
+    BeaconNodeNotSynced(String),
 }
 
 impl From<Errors<BlockError>> for BlockError {
     fn from(e: Errors<BlockError>) -> Self {
+        let (recoverable, irrecoverable) = count_node_errors(&e);
+        let not_synced = e
+            .0
+            .iter()
+            .filter(|(_, error)| {
+                matches!(error, FallbackError::RequestFailed(BlockError::BeaconNodeNotSynced(_)))
+            })
+            .count();
+        metrics::inc_counter_by(
+            &metrics::BLOCK_SERVICE_BEACON_NODE_NOT_SYNCED,
+            not_synced as u64,
+        );
+        metrics::inc_counter_vec_by(
+            &metrics::BLOCK_SERVICE_NODE_ERRORS,
+            &[metrics::NODE_ERROR_RECOVERABLE],
+            recoverable as u64,
+        );
+        metrics::inc_counter_vec_by(
+            &metrics::BLOCK_SERVICE_NODE_ERRORS,
+            &[metrics::NODE_ERROR_IRRECOVERABLE],
+            irrecoverable as u64,
+        );
+        let message = describe_node_errors(&e);
+
         if e.0.iter().any(|(_, error)| {
-            matches!(
-                error,
-                FallbackError::RequestFailed(BlockError::Irrecoverable(_))
-            )
+            matches!(error, FallbackError::RequestFailed(BlockError::SlashingProtection(_)))
         }) {
-            BlockError::Irrecoverable(e.to_string())
+            BlockError::SlashingProtection(message)
+        } else if let Some(first) = first_irrecoverable_error(&e) {
+            BlockError::Irrecoverable(first.to_string())
+        } else if e.0.iter().any(|(_, error)| {
+            matches!(error, FallbackError::RequestFailed(BlockError::Throttled(_)))
+        }) {
+            BlockError::Throttled(message)
+        } else if !e.0.is_empty() && e.0.iter().all(|(_, error)| {
+            matches!(error, FallbackError::RequestFailed(BlockError::BlindedUnsupported(_)))
+        }) {
+            BlockError::BlindedUnsupported(message)
+        } else if !e.0.is_empty() && not_synced == e.0.len() {
+            BlockError::BeaconNodeNotSynced(message)
         } else {
-            BlockError::Recoverable(e.to_string())
+            BlockError::Recoverable(
+                first_recoverable_error(&e).map_or(message, ToString::to_string),
+            )
         }
     }
 }
 
-/// Builds a `BlockService`.
-pub struct BlockServiceBuilder<T, E: EthSpec> {
-    validator_store: Option<Arc<ValidatorStore<T, E>>>,
-    slot_clock: Option<Arc<T>>,
-    beacon_nodes: Option<Arc<BeaconNodeFallback<T, E>>>,
-    context: Option<RuntimeContext<E>>,
-    graffiti: Option<Graffiti>,
-    graffiti_file: Option<GraffitiFile>,
-    private_tx_proposals: bool,
+/// Returns the message of the first beacon node that failed with an irrecoverable error, which is
+/// the root cause worth reporting rather than the errors of every node tried.
+fn first_irrecoverable_error(e: &Errors<BlockError>) -> Option<&str> {
+    e.0.iter().find_map(|(_, error)| match error {
+        FallbackError::RequestFailed(BlockError::Irrecoverable(message)) => Some(message.as_str()),
+        _ => None,
+    })
 }
 
-impl<T: SlotClock + 'static, E: EthSpec> BlockServiceBuilder<T, E> {
-    pub fn new() -> Self {
-        Self {
-            validator_store: None,
-            slot_clock: None,
-            beacon_nodes: None,
-            context: None,
-            graffiti: None,
-            graffiti_file: None,
-            private_tx_proposals: false,
+/// Returns the message of the first beacon node that failed with a recoverable error. Nodes that
+/// were not tried at all have no message of their own.
+fn first_recoverable_error(e: &Errors<BlockError>) -> Option<&str> {
+    e.0.iter().find_map(|(_, error)| match error {
+        FallbackError::RequestFailed(BlockError::Recoverable(message)) => Some(message.as_str()),
+        _ => None,
+    })
+}
+
+/// Returns how many beacon nodes failed with a recoverable error (including those that were not
+/// tried at all) and how many with an irrecoverable one.
+fn count_node_errors(e: &Errors<BlockError>) -> (usize, usize) {
+    let irrecoverable = e
+        .0
+        .iter()
+        .filter(|(_, error)| {
+            matches!(
+                error,
+                FallbackError::RequestFailed(BlockError::Irrecoverable(_))
+                    | FallbackError::RequestFailed(BlockError::SlashingProtection(_))
+            )
+        })
+        .count();
+    (e.0.len() - irrecoverable, irrecoverable)
+}
+
+/// Describes the error of each beacon node, one `[index] node => error` entry per node, separated
+/// by `"; "` and in the order the nodes were tried.
+fn describe_node_errors(e: &Errors<BlockError>) -> String {
+    let nodes = e
+        .0
+        .iter()
+        .enumerate()
+        .map(|(i, (node, error))| format!("[{}] {} => {:?}", i, node, error))
+        .collect::<Vec<_>>()
+        .join("; ");
+    format!("{} beacon node(s) failed: {}", e.0.len(), nodes)
+}
+
+impl BlockError {
+    /// Maps an error from the beacon node while producing a block, singling out HTTP 429 and
+    /// beacon nodes that are not synced.
+    fn from_block_production(e: eth2::Error) -> Self {
+        let message = format!("Error from beacon node when producing block: {:?}", e);
+        if e.status() == Some(StatusCode::TOO_MANY_REQUESTS) {
+            BlockError::Throttled(message)
+        } else if is_not_synced(&e) {
+            BlockError::BeaconNodeNotSynced(message)
+        } else {
+            BlockError::Recoverable(message)
         }
     }
 
-    pub fn validator_store(mut self, store: Arc<ValidatorStore<T, E>>) -> Self {
-        self.validator_store = Some(store);
-        self
+    /// Maps an error from the beacon node while producing a blinded block, singling out beacon
+    /// nodes that do not support the blinded block endpoint.
+    fn from_blinded_block_production(e: eth2::Error) -> Self {
+        match e.status() {
+            Some(StatusCode::NOT_FOUND)
+            | Some(StatusCode::METHOD_NOT_ALLOWED)
+            | Some(StatusCode::NOT_IMPLEMENTED) => BlockError::BlindedUnsupported(format!(
+                "Beacon node does not support blinded blocks: {:?}",
+                e
+            )),
+            _ => Self::from_block_production(e),
+        }
     }
 
-    pub fn slot_clock(mut self, slot_clock: T) -> Self {
-        self.slot_clock = Some(Arc::new(slot_clock));
-        self
+    /// Maps an error from the validator store while signing a block, singling out slashing
+    /// protection rejections and operators that are not the leader.
+    fn from_sign_block(e: VSError) -> Self {
+        match e {
+            VSError::UnableToSign(SigningError::NotLeader) => BlockError::SignBlockNotLeader,
+            VSError::Slashable(e) => BlockError::SlashingProtection(format!(
+                "Slashing protection refused to sign block: {:?}",
+                e
+            )),
+            e => BlockError::Recoverable(format!("Unable to sign block: {:?}", e)),
+        }
     }
+}
 
-    pub fn beacon_nodes(mut self, beacon_nodes: Arc<BeaconNodeFallback<T, E>>) -> Self {
-        self.beacon_nodes = Some(beacon_nodes);
-        self
+/// Returns whether the beacon node refused a request because it is syncing. The beacon API answers
+/// with HTTP 503 in that case, which is also what a proxy in front of a node that is down answers,
+/// so the message has to mention the sync too (e.g. "beacon node is syncing" or "not synced").
+fn is_not_synced(e: &eth2::Error) -> bool {
+    match e {
+        eth2::Error::ServerMessage(message) => {
+            message.code == StatusCode::SERVICE_UNAVAILABLE.as_u16()
+                && message.message.to_lowercase().contains("sync")
+        }
+        _ => false,
     }
+}
 
-    pub fn runtime_context(mut self, context: RuntimeContext<E>) -> Self {
-        self.context = Some(context);
-        self
+/// Splits `proposers` into the ones in `allow_list` and the ones that are not. Every proposer is
+/// allowed when there is no allow-list.
+pub fn filter_proposers(
+    proposers: Vec<PublicKeyBytes>,
+    allow_list: Option<&HashSet<PublicKeyBytes>>,
+) -> (Vec<PublicKeyBytes>, Vec<PublicKeyBytes>) {
+    match allow_list {
+        Some(allow_list) => proposers
+            .into_iter()
+            .partition(|pubkey| allow_list.contains(pubkey)),
+        None => (proposers, vec![]),
     }
+}
 
-    pub fn graffiti(mut self, graffiti: Option<Graffiti>) -> Self {
-        self.graffiti = graffiti;
-        self
+/// Splits `proposers` into the first `max_proposers` and the ones beyond them.
+pub fn cap_proposers(
+    mut proposers: Vec<PublicKeyBytes>,
+    max_proposers: usize,
+) -> (Vec<PublicKeyBytes>, Vec<PublicKeyBytes>) {
+    let beyond = proposers.split_off(max_proposers.min(proposers.len()));
+    (proposers, beyond)
+}
+
+/// Returns whether the fork active at `slot` allows producing a block with a blinded payload.
+/// Blinded payloads exist from the merge (bellatrix) on. The match is exhaustive so that every new
+/// fork has to be given a decision here.
+pub fn should_attempt_blinded<E: EthSpec>(slot: Slot, spec: &ChainSpec) -> bool {
+    match spec.fork_name_at_slot::<E>(slot) {
+        ForkName::Base | ForkName::Altair => false,
+        ForkName::Merge | ForkName::Capella => true,
     }
+}
 
-    pub fn graffiti_file(mut self, graffiti_file: Option<GraffitiFile>) -> Self {
-        self.graffiti_file = graffiti_file;
-        self
+/// Returns the `BLOCK_SERVICE_PAYLOAD_OUTCOME` label of a proposal attempt, given whether a
+/// blinded block was tried first and whether we then fell back to a full block. The operators
+/// that are not the leader stop at signing on every proposal, which is not a failure.
+pub fn payload_outcome(
+    result: &Result<(), BlockError>,
+    tried_blinded: bool,
+    fell_back: bool,
+) -> &'static str {
+    match (result, tried_blinded, fell_back) {
+        (Err(BlockError::RandaoNotLeader | BlockError::SignBlockNotLeader), _, _) => {
+            metrics::PAYLOAD_NOT_LEADER
+        }
+        (Err(_), _, _) => metrics::PAYLOAD_FAILED,
+        (Ok(()), true, false) => metrics::PAYLOAD_BLINDED_SUCCESS,
+        (Ok(()), true, true) => metrics::PAYLOAD_FULL_FALLBACK_SUCCESS,
+        (Ok(()), false, _) => metrics::PAYLOAD_FULL_DIRECT,
     }
+}
 
-    pub fn private_tx_proposals(mut self, private_tx_proposals: bool) -> Self {
-        self.private_tx_proposals = private_tx_proposals;
-        self
+/// Returns a random delay to wait before starting a block production task, so that co-located
+/// proposers do not all hit the beacon nodes at the slot boundary. The delay is at most `max_ms`
+/// and at most a quarter of the time left before `time_to_deadline`, so that it never costs the slot.
+pub fn spawn_jitter(max_ms: u64, time_to_deadline: Option<Duration>) -> Duration {
+    let window_ms = time_to_deadline.map_or(0, |d| d.as_millis() as u64 / 4);
+    match max_ms.min(window_ms) {
+        0 => Duration::ZERO,
+        bound => Duration::from_millis(rand::thread_rng().gen_range(0..=bound)),
     }
+}
 
-    pub fn build(self) -> Result<BlockService<T, E>, String> {
-        Ok(BlockService {
-            inner: Arc::new(Inner {
-                validator_store: self
-                    .validator_store
-                    .ok_or("Cannot build BlockService without validator_store")?,
-                slot_clock: self
-                    .slot_clock
-                    .ok_or("Cannot build BlockService without slot_clock")?,
-                beacon_nodes: self
-                    .beacon_nodes
-                    .ok_or("Cannot build BlockService without beacon_node")?,
-                context: self
-                    .context
-                    .ok_or("Cannot build BlockService without runtime_context")?,
-                graffiti: self.graffiti,
-                graffiti_file: self.graffiti_file,
-                private_tx_proposals: self.private_tx_proposals,
-            }),
-        })
+/// Returns whether a notification for `notification_slot`, received during `current_slot`, is an
+/// early one for the next slot that starts within `prefetch_lead_ms` (zero disables prefetching).
+fn is_prefetch_notification(
+    notification_slot: Slot,
+    current_slot: Slot,
+    time_to_notification_slot: Option<Duration>,
+    prefetch_lead_ms: u64,
+) -> bool {
+    prefetch_lead_ms > 0
+        && notification_slot == current_slot + 1
+        && time_to_notification_slot
+            .map_or(false, |remaining| remaining <= Duration::from_millis(prefetch_lead_ms))
+}
+
+/// Looks `validator_pubkey` up in each of `graffiti_files` in turn, returning the graffiti of the
+/// first one that has an entry for it. If none has, returns the default graffiti of the first file
+/// that has one. Files that cannot be read are skipped.
+fn graffiti_from_files(
+    graffiti_files: &[Mutex<GraffitiFile>],
+    validator_pubkey: &PublicKeyBytes,
+    log: &Logger,
+) -> Option<Graffiti> {
+    let mut default = None;
+    for graffiti_file in graffiti_files {
+        let mut graffiti_file = graffiti_file.lock();
+        match graffiti_file.load_validator_graffiti(validator_pubkey) {
+            Ok(Some(graffiti)) => return Some(graffiti),
+            Ok(None) => default = default.or_else(|| graffiti_file.default_graffiti()),
+            Err(e) => warn!(log, "Failed to read graffiti file"; "error" => ?e),
+        }
     }
+    default
 }
 
-/// Helper to minimise `Arc` usage.
-pub struct Inner<T, E: EthSpec> {
-    validator_store: Arc<ValidatorStore<T, E>>,
-    slot_clock: Arc<T>,
-    beacon_nodes: Arc<BeaconNodeFallback<T, E>>,
-    context: RuntimeContext<E>,
-    graffiti: Option<Graffiti>,
-    graffiti_file: Option<GraffitiFile>,
-    private_tx_proposals: bool,
+/// Returns a random delay to wait before retrying a throttled block proposal.
+fn throttled_retry_delay() -> Duration {
+    let max_ms = THROTTLED_RETRY_MAX_DELAY.as_millis() as u64;
+    Duration::from_millis(rand::thread_rng().gen_range(max_ms / 2..=max_ms))
 }
 
-/// Attempts to produce attestations for any block producer(s) at the start of the epoch.
-pub struct BlockService<T, E: EthSpec> {
-    inner: Arc<Inner<T, E>>,
+/// The order in which graffiti sources are consulted when proposing a block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraffitiResolutionOrder {
+    /// The graffiti file, then the validator definition, then the process-wide default.
+    FilePreferred,
+    /// The validator definition, then the graffiti file, then the process-wide default.
+    ValidatorPreferred,
+    /// Only the process-wide default.
+    DefaultOnly,
 }
 
-impl<T, E: EthSpec> Clone for BlockService<T, E> {
-    fn clone(&self) -> Self {
-        Self {
-            inner: self.inner.clone(),
+impl Default for GraffitiResolutionOrder {
+    fn default() -> Self {
+        GraffitiResolutionOrder::FilePreferred
+    }
+}
+
+impl GraffitiResolutionOrder {
+    /// Picks the graffiti to use from the candidates of each source.
+    pub fn resolve(
+        self,
+        file: Option<Graffiti>,
+        validator: Option<Graffiti>,
+        default: Option<Graffiti>,
+    ) -> Option<Graffiti> {
+        match self {
+            GraffitiResolutionOrder::FilePreferred => file.or(validator).or(default),
+            GraffitiResolutionOrder::ValidatorPreferred => validator.or(file).or(default),
+            GraffitiResolutionOrder::DefaultOnly => default,
         }
     }
 }
 
-impl<T, E: EthSpec> Deref for BlockService<T, E> {
-    type Target = Inner<T, E>;
+/// How the resolved graffiti is varied over time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraffitiRotation {
+    /// Use the resolved graffiti as is.
+    Static,
+    /// Append the epoch of the proposal to the resolved graffiti.
+    AppendEpoch,
+}
 
-    fn deref(&self) -> &Self::Target {
-        self.inner.deref()
+impl Default for GraffitiRotation {
+    fn default() -> Self {
+        GraffitiRotation::Static
     }
 }
 
-/// Notification from the duties service that we should try to produce a block.
-pub struct BlockServiceNotification {
-    pub slot: Slot,
-    pub block_proposers: Vec<PublicKeyBytes>,
+impl GraffitiRotation {
+    /// Applies the rotation to a base graffiti. The base is truncated (on a character boundary)
+    /// so that the result fits in a `Graffiti`.
+    pub fn apply(self, graffiti: Graffiti, epoch: Epoch) -> Graffiti {
+        match self {
+            GraffitiRotation::Static => graffiti,
+            GraffitiRotation::AppendEpoch => {
+                let base = graffiti.as_utf8_lossy();
+                let base = base.trim_end_matches('\0');
+                let suffix = if base.is_empty() {
+                    epoch.as_u64().to_string()
+                } else {
+                    format!(" {}", epoch.as_u64())
+                };
+                let base = truncate_str(base, GRAFFITI_BYTES_LEN.saturating_sub(suffix.len()));
+                GraffitiString::from_str(&format!("{}{}", base, suffix))
+                    .map(Into::into)
+                    .unwrap_or(graffiti)
+            }
+        }
+    }
 }
 
-impl<T: SlotClock + 'static, E: EthSpec> BlockService<T, E> {
-    pub fn start_update_service(
-        self,
-        mut notification_rx: mpsc::Receiver<BlockServiceNotification>,
-    ) -> Result<(), String> {
-        let log = self.context.log().clone();
+/// Resolves the tag stamped into the graffiti of a validator's blinded blocks, e.g. the name of
+/// the relay configuration that built them. `None` leaves the graffiti untouched.
+pub type GraffitiTagResolver = Arc<dyn Fn(&PublicKeyBytes) -> Option<String> + Send + Sync>;
 
-        info!(log, "Block production service started");
+/// Where a graffiti tag goes relative to the base graffiti.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraffitiTagPlacement {
+    Prefix,
+    Suffix,
+}
 
-        let executor = self.inner.context.executor.clone();
+impl Default for GraffitiTagPlacement {
+    fn default() -> Self {
+        GraffitiTagPlacement::Suffix
+    }
+}
 
-        executor.spawn(
-            async move {
-                while let Some(notif) = notification_rx.recv().await {
-                    let service = self.clone();
-                    service.do_update(notif).await.ok();
-                }
-                debug!(log, "Block service shutting down");
-            },
-            "block_service",
+impl GraffitiTagPlacement {
+    /// Stamps `tag` into the graffiti, separated from the base by a space. The tag is kept whole
+    /// (up to the graffiti length) and the base is truncated to make room for it.
+    pub fn apply(self, graffiti: Option<Graffiti>, tag: &str) -> Option<Graffiti> {
+        let tag = truncate_str(tag, GRAFFITI_BYTES_LEN);
+        let base = graffiti.map(|g| g.as_utf8_lossy()).unwrap_or_default();
+        let base = truncate_str(
+            base.trim_end_matches('\0'),
+            GRAFFITI_BYTES_LEN.saturating_sub(tag.len() + 1),
         );
-
-        Ok(())
+        let tagged = match (self, base.is_empty()) {
+            (_, true) => tag.to_string(),
+            (GraffitiTagPlacement::Prefix, false) => format!("{} {}", tag, base),
+            (GraffitiTagPlacement::Suffix, false) => format!("{} {}", base, tag),
+        };
+        GraffitiString::from_str(&tagged)
+            .map(Into::into)
+            .ok()
+            .or(graffiti)
     }
+}
 
-    /// Attempt to produce a block for any block producers in the `ValidatorStore`.
-    async fn do_update(&self, notification: BlockServiceNotification) -> Result<(), ()> {
-        let log = self.context.log();
-        let _timer =
-            metrics::start_timer_vec(&metrics::BLOCK_SERVICE_TIMES, &[metrics::FULL_UPDATE]);
+/// Returns the longest prefix of `s` that fits in `max_len` bytes without splitting a character.
+fn truncate_str(s: &str, max_len: usize) -> &str {
+    let mut end = max_len.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
 
-        let slot = self.slot_clock.now().ok_or_else(move || {
-            crit!(log, "Duties manager failed to read slot clock");
-        })?;
+/// Synthesizes a graffiti from a validator index, so that blocks without configured graffiti are
+/// still attributable. The result is truncated to fit in a `Graffiti`.
+pub fn graffiti_from_id(validator_index: u64) -> Graffiti {
+    let mut graffiti = format!("{}{}", DEFAULT_GRAFFITI_PREFIX, validator_index);
+    graffiti.truncate(GRAFFITI_BYTES_LEN);
+    GraffitiString::from_str(&graffiti)
+        .expect("ASCII graffiti within the length limit")
+        .into()
+}
 
-        if notification.slot != slot {
-            warn!(
-                log,
-                "Skipping block production for expired slot";
-                "current_slot" => slot.as_u64(),
-                "notification_slot" => notification.slot.as_u64(),
-                "info" => "Your machine could be overloaded"
-            );
-            return Ok(());
-        }
+/// Remembers consecutive blinded block failures across slots, so that a persistently failing
+/// builder relay is skipped for a while instead of delaying every proposal.
+pub struct BlindedFallbackState {
+    /// Consecutive blinded failures needed to start a cooldown. `None` never skips blinded blocks.
+    threshold: Option<u32>,
+    /// Number of slots during which blinded blocks are skipped once the threshold is reached.
+    cooldown_slots: u64,
+    consecutive_failures: AtomicU32,
+    /// The first slot at which blinded blocks are tried again.
+    skip_until_slot: AtomicU64,
+}
 
-        if slot == self.context.eth2_config.spec.genesis_slot {
-            debug!(
-                log,
-                "Not producing block at genesis slot";
-                "proposers" => format!("{:?}", notification.block_proposers),
-            );
-            return Ok(());
+impl BlindedFallbackState {
+    pub fn new(threshold: Option<u32>, cooldown_slots: u64) -> Self {
+        Self {
+            threshold,
+            cooldown_slots,
+            consecutive_failures: AtomicU32::new(0),
+            skip_until_slot: AtomicU64::new(0),
         }
+    }
 
-        trace!(
-            log,
-            "Block service update started";
-            "slot" => slot.as_u64()
-        );
+    /// Returns `true` if blinded production should be skipped at `slot`.
+    pub fn in_cooldown(&self, slot: Slot) -> bool {
+        slot.as_u64() < self.skip_until_slot.load(Ordering::Relaxed)
+    }
 
-        let proposers = notification.block_proposers;
+    pub fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
 
-        if proposers.is_empty() {
-            trace!(
-                log,
-                "No local block proposers for this slot";
-                "slot" => slot.as_u64()
-            )
-        } else if proposers.len() > 1 {
-            error!(
-                log,
-                "Multiple block proposers for this slot";
-                "action" => "producing blocks for all proposers",
-                "num_proposers" => proposers.len(),
-                "slot" => slot.as_u64(),
-            )
+    /// Records a failed blinded attempt at `slot`. Returns `true` if this starts a cooldown.
+    pub fn record_failure(&self, slot: Slot) -> bool {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        match self.threshold {
+            Some(threshold) if failures >= threshold => {
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+                self.skip_until_slot
+                    .store(slot.as_u64() + 1 + self.cooldown_slots, Ordering::Relaxed);
+                true
+            }
+            _ => false,
         }
+    }
+}
 
-        let private_tx_proposals = self.private_tx_proposals;
-        let merge_slot = self
-            .context
-            .eth2_config
-            .spec
-            .bellatrix_fork_epoch
-            .unwrap_or_else(Epoch::max_value)
-            .start_slot(E::slots_per_epoch());
-        for validator_pubkey in proposers {
-            let service = self.clone();
-            let log = log.clone();
-            self.inner.context.executor.spawn(
-                async move {
-                    let publish_result = if private_tx_proposals && slot >= merge_slot {
-                        let mut result = service.clone()
-                            .publish_block::<BlindedPayload<E>>(slot, validator_pubkey)
-                            .await;
-                        match result.as_ref() {
-                            Err(BlockError::Recoverable(e)) => {
-                                error!(log, "Error whilst producing a blinded block, attempting to publish full block"; "error" => ?e);
-                                result = service
-                                    .publish_block::<FullPayload<E>>(slot, validator_pubkey)
-                                    .await;
-                            },
-                            Err(BlockError::Irrecoverable(e))  => {
-                                error!(log, "Error whilst producing a blinded block, cannot fallback because block was signed"; "error" => ?e);
-                            },
-                            _ => {},
-                        };
-                        result
-                    } else {
-                        service
-                            .publish_block::<FullPayload<E>>(slot, validator_pubkey)
-                            .await
-                    };
-                    if let Err(e) = publish_result {
-                        match e {
-                            BlockError::RandaoNotLeader => {
-                                error!(log,
-                                    "Error whilst producing block";
-                                    "message" => ?e
-                                );
-                            },
-                            BlockError::SignBlockNotLeader => {
-                                info!(log,
-                                    "Not a leader for proposing this block";
-                                    "message" => ?e
-                                );
-                            },
-                            _ => {
-                                crit!(
-                                    log,
-                                    "Error whilst producing block";
-                                    "message" => ?e
-                                );
-                            }
-                        };
-                    }
-                },
-                "block service",
-            );
-        }
+/// Remembers that no beacon node served blinded blocks, so that full payloads are produced
+/// directly until the nodes are probed again.
+#[derive(Default)]
+pub struct BlindedSupport {
+    /// The first slot at which blinded blocks are tried again.
+    unsupported_until_slot: AtomicU64,
+}
 
-        Ok(())
+impl BlindedSupport {
+    /// Returns `true` if blinded production is known to be unsupported at `slot`.
+    pub fn is_unsupported(&self, slot: Slot) -> bool {
+        slot.as_u64() < self.unsupported_until_slot.load(Ordering::Relaxed)
     }
 
-    /// Produce a block at the given slot for validator_pubkey
-    async fn publish_block<Payload: AbstractExecPayload<E>>(
-        self,
-        slot: Slot,
-        validator_pubkey: PublicKeyBytes,
-    ) -> Result<(), BlockError> {
-        let log = self.context.log();
-        let _timer =
-            metrics::start_timer_vec(&metrics::BLOCK_SERVICE_TIMES, &[metrics::BEACON_BLOCK]);
+    /// Records that blinded blocks were unsupported at `slot`, skipping them for
+    /// `BLINDED_UNSUPPORTED_REPROBE_EPOCHS`. Returns the first slot at which they are tried again,
+    /// or `None` if they were already skipped.
+    pub fn mark_unsupported(&self, slot: Slot, slots_per_epoch: u64) -> Option<Slot> {
+        let reprobe_slot = slot.as_u64() + 1 + BLINDED_UNSUPPORTED_REPROBE_EPOCHS * slots_per_epoch;
+        let previous = self.unsupported_until_slot.fetch_max(reprobe_slot, Ordering::Relaxed);
+        (previous <= slot.as_u64()).then(|| Slot::new(reprobe_slot))
+    }
+}
 
-        let current_slot = self.slot_clock.now().ok_or_else(|| {
-            BlockError::Recoverable("Unable to determine current slot from clock".to_string())
-        })?;
+/// The circuit breaker state of a beacon node's block publishing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CircuitState {
+    /// Blocks are published through the node. Holds the number of consecutive failures.
+    Closed(u32),
+    /// The node is skipped until the given slot.
+    Open(Slot),
+    /// The cooldown is over: the next attempt probes the node, closing the circuit on success and
+    /// opening it again on failure.
+    HalfOpen,
+}
 
-        let randao_reveal = self
-            .validator_store
-            .randao_reveal(validator_pubkey, slot.epoch(E::slots_per_epoch()))
-            .await
-            .map_err(|e| {
-                match e {
-                    VSError::UnableToSign(SigningError::NotLeader) => BlockError::RandaoNotLeader,
-                    _ => BlockError::Recoverable(format!("Unable to produce randao reveal signature: {:?}", e))
-                }
-            })?
-            .into();
+/// Tracks consecutive block publishing failures per beacon node, so that a node that keeps failing
+/// is skipped for a while instead of delaying every publication.
+pub struct PublishCircuitBreaker {
+    /// Consecutive failures needed to open the circuit of a node. `None` never opens it.
+    threshold: Option<u32>,
+    /// Number of slots during which a node is skipped once its circuit opens.
+    cooldown_slots: u64,
+    states: Mutex<HashMap<String, CircuitState>>,
+}
 
-        let graffiti = self
-            .graffiti_file
-            .clone()
-            .and_then(|mut g| match g.load_graffiti(&validator_pubkey) {
-                Ok(g) => g,
-                Err(e) => {
-                    warn!(log, "Failed to read graffiti file"; "error" => ?e);
-                    None
-                }
-            })
-            .or(self.validator_store.graffiti(&validator_pubkey).await)
-            .or(self.graffiti);
+impl PublishCircuitBreaker {
+    pub fn new(threshold: Option<u32>, cooldown_slots: u64) -> Self {
+        Self {
+            threshold,
+            cooldown_slots,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
 
-        let randao_reveal_ref = &randao_reveal;
-        let self_ref = &self;
-        let proposer_index = self.validator_store.validator_index(&validator_pubkey).await;
-        let validator_pubkey_ref = &validator_pubkey;
-        let signed_block = self
-            .beacon_nodes
-            .first_success(RequireSynced::No, OfflineOnFailure::Yes, |beacon_node| async move {
-                let get_timer = metrics::start_timer_vec(
-                    &metrics::BLOCK_SERVICE_TIMES,
-                    &[metrics::BEACON_BLOCK_HTTP_GET],
-                );
-                let block = match Payload::block_type() {
-                    BlockType::Full => {
-                        beacon_node
-                            .get_validator_blocks::<E, Payload>(
-                                slot,
-                                randao_reveal_ref,
-                                graffiti.as_ref(),
-                            )
-                            .await
-                            .map_err(|e| {
-                                BlockError::Recoverable(format!(
-                                    "Error from beacon node when producing block: {:?}",
-                                    e
-                                ))
-                            })?
-                            .data
-                    }
-                    BlockType::Blinded => {
-                        beacon_node
-                            .get_validator_blinded_blocks::<E, Payload>(
-                                slot,
-                                randao_reveal_ref,
-                                graffiti.as_ref(),
-                            )
-                            .await
-                            .map_err(|e| {
-                                BlockError::Recoverable(format!(
-                                    "Error from beacon node when producing block: {:?}",
-                                    e
-                                ))
-                            })?
-                            .data
-                    }
-                };
-                drop(get_timer);
+    /// Returns the state of `node` at `slot`, half-opening its circuit if the cooldown is over.
+    pub fn state(&self, node: &str, slot: Slot) -> CircuitState {
+        let mut states = self.states.lock();
+        let state = states.entry(node.to_string()).or_insert(CircuitState::Closed(0));
+        if matches!(*state, CircuitState::Open(until) if slot >= until) {
+            *state = CircuitState::HalfOpen;
+        }
+        *state
+    }
 
-                if proposer_index != Some(block.proposer_index()) {
-                    return Err(BlockError::Recoverable(
-                        "Proposer index does not match block proposer. Beacon chain re-orged"
-                            .to_string(),
-                    ));
-                }
+    /// Returns `true` if blocks may be published through `node` at `slot`.
+    pub fn allows(&self, node: &str, slot: Slot) -> bool {
+        !matches!(self.state(node, slot), CircuitState::Open(_))
+    }
 
-                let signed_block = self_ref
-                    .validator_store
-                    .sign_block::<Payload>(*validator_pubkey_ref, block, current_slot)
-                    .await
-                    .map_err(|e| {
-                        match e {
-                            VSError::UnableToSign(SigningError::NotLeader) => BlockError::SignBlockNotLeader,
-                            _ => BlockError::Recoverable(format!("Unable to sign block: {:?}", e))
-                        }
-                    })?;
+    /// Returns `true` if blocks may be published through any of `nodes` at `slot`.
+    pub fn allows_any(&self, nodes: &[String], slot: Slot) -> bool {
+        nodes.iter().any(|node| self.allows(node, slot))
+    }
 
-                let _post_timer = metrics::start_timer_vec(
-                    &metrics::BLOCK_SERVICE_TIMES,
-                    &[metrics::BEACON_BLOCK_HTTP_POST],
-                );
+    pub fn record_success(&self, node: &str) {
+        self.states
+            .lock()
+            .insert(node.to_string(), CircuitState::Closed(0));
+    }
 
-                match Payload::block_type() {
-                    BlockType::Full => beacon_node
-                        .post_beacon_blocks(&signed_block)
-                        .await
-                        .map_err(|e| {
+    /// Records a failed publication through `node` at `slot`. Returns `true` if this opens its
+    /// circuit.
+    pub fn record_failure(&self, node: &str, slot: Slot) -> bool {
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+        let mut states = self.states.lock();
+        let state = states.entry(node.to_string()).or_insert(CircuitState::Closed(0));
+        let failures = match *state {
+            CircuitState::Closed(failures) => failures + 1,
+            CircuitState::HalfOpen => threshold,
+            CircuitState::Open(_) => return false,
+        };
+        if failures >= threshold {
+            *state = CircuitState::Open(slot + 1 + self.cooldown_slots);
+            true
+        } else {
+            *state = CircuitState::Closed(failures);
+            false
+        }
+    }
+
+    /// Whether a beacon node that fails to publish a block should be set offline. The breaker keeps
+    /// track of failing nodes itself, and a node skipped because its circuit is open has not
+    /// failed at all, so nodes are only set offline while the breaker is disabled.
+    fn offline_on_failure(&self) -> OfflineOnFailure {
+        match self.threshold {
+            Some(_) => OfflineOnFailure::No,
+            None => OfflineOnFailure::Yes,
+        }
+    }
+
+    /// Runs `publish` through `node` and records its outcome, unless the circuit of `node` is open
+    /// and `bypass` is not set.
+    async fn guard<F>(&self, node: String, slot: Slot, bypass: bool, publish: F) -> Result<String, BlockError>
+    where
+        F: Future<Output = Result<String, BlockError>>,
+    {
+        if !bypass && !self.allows(&node, slot) {
+            return Err(BlockError::Recoverable(format!(
+                "Skipping {}: too many recent block publishing failures",
+                node
+            )));
+        }
+        let result = publish.await;
+        match result {
+            Ok(_) => self.record_success(&node),
+            Err(_) => {
+                if self.record_failure(&node, slot) {
+                    metrics::inc_counter(&metrics::BLOCK_SERVICE_PUBLISH_CIRCUIT_OPENED);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Bounds the number of block productions running at the same time. Productions beyond the limit
+/// wait for a permit; they are still bounded by the slot, since the next slot cancels them.
+#[derive(Default)]
+pub struct ProductionLimiter {
+    /// `None` lets every production run right away.
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl ProductionLimiter {
+    pub fn new(max_concurrent: Option<usize>) -> Self {
+        Self {
+            permits: max_concurrent.map(|n| Arc::new(Semaphore::new(n))),
+        }
+    }
+
+    /// Waits until a production may start. It may run as long as the returned permit is held.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.permits {
+            // The semaphore is never closed.
+            Some(permits) => permits.clone().acquire_owned().await.ok(),
+            None => None,
+        }
+    }
+
+    /// Returns `true` if a production would have to wait for a permit.
+    pub fn is_saturated(&self) -> bool {
+        self.permits
+            .as_ref()
+            .map_or(false, |permits| permits.available_permits() == 0)
+    }
+}
+
+/// The full validator keys this operator may sign blocks with when the committee cannot gather
+/// enough signature shares. The keys are ignored unless the fallback is enabled.
+#[derive(Default)]
+pub struct LocalSigningFallback {
+    enabled: bool,
+    keypairs: HashMap<PublicKeyBytes, Arc<Keypair>>,
+}
+
+impl LocalSigningFallback {
+    pub fn new(enabled: bool, keypairs: Vec<Arc<Keypair>>) -> Self {
+        Self {
+            enabled,
+            keypairs: keypairs
+                .into_iter()
+                .map(|keypair| (PublicKeyBytes::from(&keypair.pk), keypair))
+                .collect(),
+        }
+    }
+
+    /// Returns the key of `validator_pubkey` if the fallback is enabled and we hold that key.
+    pub fn keypair(&self, validator_pubkey: &PublicKeyBytes) -> Option<&Keypair> {
+        if !self.enabled {
+            return None;
+        }
+        self.keypairs.get(validator_pubkey).map(|keypair| keypair.as_ref())
+    }
+}
+
+/// Memoizes randao reveals per validator and epoch, so that retries and several proposals within
+/// an epoch do not run the threshold signing protocol again for an identical signature.
+///
+/// Only successful reveals are cached; errors (including `NotLeader`) are never stored, so a later
+/// call still reaches the committee. This relies on the randao reveal not being leader-gated: every
+/// operator takes part in it (see `SigningMethod::get_signature`), and the resulting signature
+/// only depends on the validator and the epoch. If randao signing ever becomes leader-gated, a
+/// cached reveal could hide a `NotLeader` result from a later call in a different leader context,
+/// and this cache must be revisited.
+#[derive(Default)]
+pub struct RandaoCache {
+    reveals: Mutex<HashMap<(PublicKeyBytes, Epoch), Signature>>,
+}
+
+impl RandaoCache {
+    pub fn get(&self, validator_pubkey: &PublicKeyBytes, epoch: Epoch) -> Option<Signature> {
+        self.reveals.lock().get(&(*validator_pubkey, epoch)).cloned()
+    }
+
+    /// Caches a reveal, evicting those older than the last `RANDAO_CACHE_EPOCHS` epochs.
+    pub fn insert(&self, validator_pubkey: PublicKeyBytes, epoch: Epoch, reveal: Signature) {
+        let mut reveals = self.reveals.lock();
+        reveals.insert((validator_pubkey, epoch), reveal);
+        let latest = reveals.keys().map(|(_, epoch)| *epoch).max().unwrap_or(epoch);
+        let oldest = latest.saturating_sub(RANDAO_CACHE_EPOCHS - 1);
+        reveals.retain(|(_, epoch), _| *epoch >= oldest);
+    }
+}
+
+/// What to do when a randao reveal is about to be signed again for an epoch the validator already
+/// revealed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RandaoReplayPolicy {
+    /// Reuse the reveal produced the first time.
+    Reuse,
+    /// Fail the proposal instead.
+    Refuse,
+}
+
+/// Whether a randao reveal may be signed, according to the `RandaoGuard`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RandaoGuardDecision {
+    /// No reveal was produced for this epoch or a later one yet.
+    Sign,
+    /// The reveal produced earlier in this epoch.
+    Reuse(Signature),
+    /// The reveal was already produced in this epoch, or the validator moved past it.
+    Refuse(String),
+}
+
+/// Records the latest epoch for which each validator produced a randao reveal, so that the
+/// committee is never asked to sign a reveal again for that epoch or an earlier one. Unlike the
+/// `RandaoCache`, which only saves work and evicts old epochs, the guard is a correctness check:
+/// the latest reveal of each validator is kept for as long as the service runs.
+///
+/// Only successful reveals are recorded. A `NotLeader` result records nothing, so a later request
+/// under another leader still reaches the committee. Once any leader produced the reveal though,
+/// later requests for the epoch are reused or refused whoever the leader is, so that leader churn
+/// within an epoch never leads to signing the same reveal twice.
+pub struct RandaoGuard {
+    /// `None` disables the guard.
+    policy: Option<RandaoReplayPolicy>,
+    revealed: Mutex<HashMap<PublicKeyBytes, (Epoch, Signature)>>,
+}
+
+impl RandaoGuard {
+    pub fn new(policy: Option<RandaoReplayPolicy>) -> Self {
+        Self {
+            policy,
+            revealed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Tells whether a randao reveal may be signed for `validator_pubkey` at `epoch`.
+    pub fn check(&self, validator_pubkey: &PublicKeyBytes, epoch: Epoch) -> RandaoGuardDecision {
+        let policy = match self.policy {
+            Some(policy) => policy,
+            None => return RandaoGuardDecision::Sign,
+        };
+        match self.revealed.lock().get(validator_pubkey) {
+            Some((revealed, _)) if *revealed > epoch => RandaoGuardDecision::Refuse(format!(
+                "randao reveal requested for epoch {} after one was produced for epoch {}",
+                epoch, revealed
+            )),
+            Some((revealed, reveal)) if *revealed == epoch => match policy {
+                RandaoReplayPolicy::Reuse => RandaoGuardDecision::Reuse(reveal.clone()),
+                RandaoReplayPolicy::Refuse => RandaoGuardDecision::Refuse(format!(
+                    "randao reveal already produced for epoch {}",
+                    epoch
+                )),
+            },
+            _ => RandaoGuardDecision::Sign,
+        }
+    }
+
+    /// Records a reveal produced for `validator_pubkey` at `epoch`.
+    pub fn record(&self, validator_pubkey: PublicKeyBytes, epoch: Epoch, reveal: &Signature) {
+        if self.policy.is_none() {
+            return;
+        }
+        let mut revealed = self.revealed.lock();
+        match revealed.get(&validator_pubkey) {
+            Some((latest, _)) if *latest >= epoch => {}
+            _ => {
+                revealed.insert(validator_pubkey, (epoch, reveal.clone()));
+            }
+        }
+    }
+}
+
+/// Remembers the latest slot for which block production was started for each validator, so that
+/// a repeated notification (e.g. after the duties service reconnected) does not produce the same
+/// block twice.
+#[derive(Default)]
+pub struct HandledProposals {
+    last_slots: Mutex<HashMap<PublicKeyBytes, Slot>>,
+}
+
+impl HandledProposals {
+    /// Records that production starts for `validator_pubkey` at `slot`. Returns `false`, without
+    /// recording anything, if it was already started for that slot or a later one.
+    pub fn start(&self, validator_pubkey: PublicKeyBytes, slot: Slot) -> bool {
+        let mut last_slots = self.last_slots.lock();
+        match last_slots.get(&validator_pubkey) {
+            Some(last_slot) if *last_slot >= slot => false,
+            _ => {
+                last_slots.insert(validator_pubkey, slot);
+                true
+            }
+        }
+    }
+
+    /// Forgets that production started for `validator_pubkey` at `slot`, so that a later
+    /// notification for that slot starts it again. A prefetched proposal only keeps its mark if it
+    /// succeeds: a failed prefetch must not cost the attempt at the slot start.
+    pub fn release(&self, validator_pubkey: PublicKeyBytes, slot: Slot) {
+        let mut last_slots = self.last_slots.lock();
+        if last_slots.get(&validator_pubkey) == Some(&slot) {
+            last_slots.remove(&validator_pubkey);
+        }
+    }
+}
+
+/// A snapshot of the recent operational state of a `BlockService`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BlockServiceHealth {
+    /// The latest slot for which a block was successfully proposed.
+    pub last_proposal_slot: Option<Slot>,
+    pub recoverable_errors: u64,
+    pub irrecoverable_errors: u64,
+    pub last_error: Option<String>,
+    /// The number of proposals each validator failed in a row, for validators whose latest
+    /// proposal failed. `NotLeader` outcomes are not misses: only the leader operator proposes.
+    pub consecutive_misses: HashMap<PublicKeyBytes, u64>,
+    /// The outcomes of the proposals of each validator since the service started.
+    pub proposal_stats: HashMap<PublicKeyBytes, ProposalStats>,
+}
+
+/// The outcomes of the proposals of a single validator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct ProposalStats {
+    pub attempts: u64,
+    pub successes: u64,
+    /// Attempts in which another operator was the leader. These are neither successes nor
+    /// failures.
+    pub not_leader: u64,
+    pub failures: u64,
+}
+
+impl ProposalStats {
+    fn record(&mut self, result: &Result<(), BlockError>) {
+        self.attempts += 1;
+        match result {
+            Ok(()) => self.successes += 1,
+            Err(BlockError::RandaoNotLeader) | Err(BlockError::SignBlockNotLeader) => {
+                self.not_leader += 1
+            }
+            Err(_) => self.failures += 1,
+        }
+    }
+
+    /// The share of the proposals we led that were published, or `None` if we led none yet.
+    pub fn success_ratio(&self) -> Option<f64> {
+        match self.successes + self.failures {
+            0 => None,
+            led => Some(self.successes as f64 / led as f64),
+        }
+    }
+}
+
+/// The live counterpart of `BlockServiceHealth`. Counters are atomics so that concurrent proposals
+/// do not contend on a lock; only recording an error takes one.
+#[derive(Default)]
+pub struct BlockServiceHealthState {
+    /// The latest successful proposal slot plus one, zero meaning none yet.
+    last_proposal_slot: AtomicU64,
+    recoverable_errors: AtomicU64,
+    irrecoverable_errors: AtomicU64,
+    last_error: RwLock<Option<String>>,
+    consecutive_misses: Mutex<HashMap<PublicKeyBytes, u64>>,
+    proposal_stats: Mutex<HashMap<PublicKeyBytes, ProposalStats>>,
+}
+
+impl BlockServiceHealthState {
+    /// Records the outcome of a proposal by `validator_pubkey` at `slot`. Not being the leader is
+    /// neither an error nor a miss, and leaves the validator's streak of misses as it is.
+    pub fn record(&self, slot: Slot, validator_pubkey: &PublicKeyBytes, result: &Result<(), BlockError>) {
+        self.proposal_stats
+            .lock()
+            .entry(*validator_pubkey)
+            .or_default()
+            .record(result);
+        let counter = match result {
+            Ok(()) => {
+                self.last_proposal_slot
+                    .fetch_max(slot.as_u64() + 1, Ordering::Relaxed);
+                self.consecutive_misses.lock().remove(validator_pubkey);
+                return;
+            }
+            Err(BlockError::RandaoNotLeader) | Err(BlockError::SignBlockNotLeader) => return,
+            Err(BlockError::Recoverable(_))
+            | Err(BlockError::Throttled(_))
+            | Err(BlockError::BlindedUnsupported(_))
+            | Err(BlockError::BeaconNodeNotSynced(_)) => &self.recoverable_errors,
+            Err(BlockError::Irrecoverable(_)) | Err(BlockError::SlashingProtection(_)) => {
+                &self.irrecoverable_errors
+            }
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        *self.consecutive_misses.lock().entry(*validator_pubkey).or_insert(0) += 1;
+        if let Err(e) = result {
+            *self.last_error.write() = Some(format!("{:?}", e));
+        }
+    }
+
+    /// Returns the number of proposals `validator_pubkey` failed in a row.
+    pub fn consecutive_misses(&self, validator_pubkey: &PublicKeyBytes) -> u64 {
+        self.consecutive_misses
+            .lock()
+            .get(validator_pubkey)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Returns the outcomes of the proposals of each validator.
+    pub fn proposal_stats(&self) -> HashMap<PublicKeyBytes, ProposalStats> {
+        self.proposal_stats.lock().clone()
+    }
+
+    pub fn snapshot(&self) -> BlockServiceHealth {
+        BlockServiceHealth {
+            last_proposal_slot: match self.last_proposal_slot.load(Ordering::Relaxed) {
+                0 => None,
+                slot => Some(Slot::new(slot - 1)),
+            },
+            recoverable_errors: self.recoverable_errors.load(Ordering::Relaxed),
+            irrecoverable_errors: self.irrecoverable_errors.load(Ordering::Relaxed),
+            last_error: self.last_error.read().clone(),
+            consecutive_misses: self.consecutive_misses.lock().clone(),
+            proposal_stats: self.proposal_stats(),
+        }
+    }
+}
+
+/// The kind of execution payload a published block carried.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockPayloadType {
+    Full,
+    Blinded,
+}
+
+impl From<BlockType> for BlockPayloadType {
+    fn from(block_type: BlockType) -> Self {
+        match block_type {
+            BlockType::Full => BlockPayloadType::Full,
+            BlockType::Blinded => BlockPayloadType::Blinded,
+        }
+    }
+}
+
+/// Emitted after a block has been successfully published, for consumption by external monitoring.
+#[derive(Debug, Clone)]
+pub struct BlockPublishedEvent {
+    pub slot: Slot,
+    pub proposer_index: u64,
+    pub graffiti: Option<Graffiti>,
+    pub deposits: usize,
+    pub attestations: usize,
+    pub payload_type: BlockPayloadType,
+    /// The (redacted) URL of the beacon node that produced and published the block.
+    pub beacon_node: String,
+}
+
+/// How long each stage of a block proposal took. Waiting for the slot to start before signing a
+/// prefetched block is not counted.
+#[derive(Debug, Clone, Copy)]
+struct BlockTimings {
+    production_time: Duration,
+    signing_time: Duration,
+    publishing_time: Duration,
+}
+
+/// The beacon node operations used to produce and publish blocks. `BlockService` is generic over
+/// it so that tests can drive block production without a beacon node.
+#[async_trait]
+pub trait BlockPublisher<E: EthSpec>: Send + Sync + 'static {
+    /// Requests an unsigned block with a full execution payload.
+    async fn produce_block<Payload: AbstractExecPayload<E>>(
+        &self,
+        slot: Slot,
+        randao_reveal: &SignatureBytes,
+        graffiti: Option<&Graffiti>,
+    ) -> Result<BeaconBlock<E, Payload>, BlockError>;
+
+    /// Requests an unsigned block with a blinded execution payload.
+    async fn produce_blinded_block<Payload: AbstractExecPayload<E>>(
+        &self,
+        slot: Slot,
+        randao_reveal: &SignatureBytes,
+        graffiti: Option<&Graffiti>,
+    ) -> Result<BeaconBlock<E, Payload>, BlockError>;
+
+    /// Requests an unsigned block from every beacon node, with a full or blinded execution payload
+    /// depending on `Payload`. Returns the blocks of the nodes that produced one, failing only if
+    /// none did. A node that fails is not marked offline: a node that cannot build a matching
+    /// block is no reason to stop using it for everything else.
+    async fn produce_blocks_from_all<Payload: AbstractExecPayload<E>>(
+        &self,
+        slot: Slot,
+        randao_reveal: &SignatureBytes,
+        graffiti: Option<&Graffiti>,
+    ) -> Result<Vec<BeaconBlock<E, Payload>>, BlockError>;
+
+    /// Publishes a signed full block, returning a description of the beacon node that took it.
+    /// Beacon nodes whose `breaker` is open are skipped.
+    async fn publish<Payload: AbstractExecPayload<E>>(
+        &self,
+        block: &SignedBeaconBlock<E, Payload>,
+        breaker: &PublishCircuitBreaker,
+    ) -> Result<String, BlockError>;
+
+    /// Publishes a signed blinded block, returning a description of the beacon node that took it.
+    /// Beacon nodes whose `breaker` is open are skipped.
+    async fn publish_blinded<Payload: AbstractExecPayload<E>>(
+        &self,
+        block: &SignedBeaconBlock<E, Payload>,
+        breaker: &PublishCircuitBreaker,
+    ) -> Result<String, BlockError>;
+
+    /// Returns the index of the validator expected to propose at `slot`, according to the head
+    /// of the beacon node.
+    async fn proposer_index(&self, slot: Slot) -> Result<Option<u64>, BlockError>;
+}
+
+#[async_trait]
+impl<T: SlotClock + 'static, E: EthSpec> BlockPublisher<E> for BeaconNodeFallback<T, E> {
+    async fn produce_block<Payload: AbstractExecPayload<E>>(
+        &self,
+        slot: Slot,
+        randao_reveal: &SignatureBytes,
+        graffiti: Option<&Graffiti>,
+    ) -> Result<BeaconBlock<E, Payload>, BlockError> {
+        self.first_success(RequireSynced::No, OfflineOnFailure::Yes, |beacon_node| async move {
+            beacon_node
+                .get_validator_blocks::<E, Payload>(slot, randao_reveal, graffiti)
+                .await
+                .map(|response| response.data)
+                .map_err(BlockError::from_block_production)
+        })
+        .await
+        .map_err(BlockError::from)
+    }
+
+    async fn produce_blinded_block<Payload: AbstractExecPayload<E>>(
+        &self,
+        slot: Slot,
+        randao_reveal: &SignatureBytes,
+        graffiti: Option<&Graffiti>,
+    ) -> Result<BeaconBlock<E, Payload>, BlockError> {
+        self.first_success(RequireSynced::No, OfflineOnFailure::Yes, |beacon_node| async move {
+            beacon_node
+                .get_validator_blinded_blocks::<E, Payload>(slot, randao_reveal, graffiti)
+                .await
+                .map(|response| response.data)
+                .map_err(BlockError::from_blinded_block_production)
+        })
+        .await
+        .map_err(BlockError::from)
+    }
+
+    async fn produce_blocks_from_all<Payload: AbstractExecPayload<E>>(
+        &self,
+        slot: Slot,
+        randao_reveal: &SignatureBytes,
+        graffiti: Option<&Graffiti>,
+    ) -> Result<Vec<BeaconBlock<E, Payload>>, BlockError> {
+        let blocks = Mutex::new(vec![]);
+        let result = self
+            .run_on_all(RequireSynced::No, OfflineOnFailure::No, |beacon_node| {
+                let blocks = &blocks;
+                async move {
+                    let block = match Payload::block_type() {
+                        BlockType::Full => beacon_node
+                            .get_validator_blocks::<E, Payload>(slot, randao_reveal, graffiti)
+                            .await
+                            .map_err(BlockError::from_block_production)?
+                            .data,
+                        BlockType::Blinded => beacon_node
+                            .get_validator_blinded_blocks::<E, Payload>(slot, randao_reveal, graffiti)
+                            .await
+                            .map_err(BlockError::from_blinded_block_production)?
+                            .data,
+                    };
+                    blocks.lock().push(block);
+                    Ok::<_, BlockError>(())
+                }
+            })
+            .await;
+        let blocks = blocks.into_inner();
+        match result {
+            Err(e) if blocks.is_empty() => Err(BlockError::from(e)),
+            _ => Ok(blocks),
+        }
+    }
+
+    async fn publish<Payload: AbstractExecPayload<E>>(
+        &self,
+        block: &SignedBeaconBlock<E, Payload>,
+        breaker: &PublishCircuitBreaker,
+    ) -> Result<String, BlockError> {
+        let slot = block.slot();
+        let bypass = !breaker.allows_any(&self.candidate_names(), slot);
+        self.first_success(RequireSynced::No, breaker.offline_on_failure(), |beacon_node| async move {
+            breaker
+                .guard(beacon_node.to_string(), slot, bypass, async {
+                    beacon_node
+                        .post_beacon_blocks(block)
+                        .await
+                        .map(|()| beacon_node.to_string())
+                        .map_err(|e| {
                             BlockError::Irrecoverable(format!(
                                 "Error from beacon node when publishing block: {:?}",
                                 e
                             ))
-                        })?,
-                    BlockType::Blinded => beacon_node
-                        .post_beacon_blinded_blocks(&signed_block)
+                        })
+                })
+                .await
+        })
+        .await
+        .map_err(BlockError::from)
+    }
+
+    async fn publish_blinded<Payload: AbstractExecPayload<E>>(
+        &self,
+        block: &SignedBeaconBlock<E, Payload>,
+        breaker: &PublishCircuitBreaker,
+    ) -> Result<String, BlockError> {
+        let slot = block.slot();
+        let bypass = !breaker.allows_any(&self.candidate_names(), slot);
+        self.first_success(RequireSynced::No, breaker.offline_on_failure(), |beacon_node| async move {
+            breaker
+                .guard(beacon_node.to_string(), slot, bypass, async {
+                    beacon_node
+                        .post_beacon_blinded_blocks(block)
                         .await
+                        .map(|()| beacon_node.to_string())
                         .map_err(|e| {
                             BlockError::Irrecoverable(format!(
                                 "Error from beacon node when publishing block: {:?}",
                                 e
                             ))
-                        })?,
-                }
+                        })
+                })
+                .await
+        })
+        .await
+        .map_err(BlockError::from)
+    }
 
-                Ok::<_, BlockError>(signed_block)
-            })
-            .await?;
+    async fn proposer_index(&self, slot: Slot) -> Result<Option<u64>, BlockError> {
+        let epoch = slot.epoch(E::slots_per_epoch());
+        self.first_success(RequireSynced::No, OfflineOnFailure::Yes, |beacon_node| async move {
+            beacon_node
+                .get_validator_duties_proposer(epoch)
+                .await
+                .map(|response| {
+                    response
+                        .data
+                        .into_iter()
+                        .find(|duty| duty.slot == slot)
+                        .map(|duty| duty.validator_index)
+                })
+                .map_err(|e| {
+                    BlockError::Recoverable(format!(
+                        "Error from beacon node when fetching proposer duties: {:?}",
+                        e
+                    ))
+                })
+        })
+        .await
+        .map_err(BlockError::from)
+    }
+}
 
-        info!(
+/// Requests a block for `slot` until its proposer index matches `proposer_index`. The beacon node
+/// may be following a short re-org, so a mismatching block is re-requested after a growing delay,
+/// up to `max_retries` times and as long as `time_to_deadline` leaves room for the delay.
+///
+/// A block for another slot is never signed: the beacon node is not to be trusted with it, so it is
+/// rejected without retrying.
+#[allow(clippy::too_many_arguments)]
+async fn produce_matching_block<E, Payload, P>(
+    publisher: &P,
+    slot: Slot,
+    randao_reveal: &SignatureBytes,
+    graffiti: Option<&Graffiti>,
+    proposer_index: Option<u64>,
+    max_retries: u8,
+    time_to_deadline: impl Fn() -> Option<Duration>,
+    log: &Logger,
+) -> Result<BeaconBlock<E, Payload>, BlockError>
+where
+    E: EthSpec,
+    Payload: AbstractExecPayload<E>,
+    P: BlockPublisher<E>,
+{
+    let mut mismatch_retries = 0;
+    loop {
+        let get_timer = metrics::start_timer_vec(
+            &metrics::BLOCK_SERVICE_TIMES,
+            &[metrics::BEACON_BLOCK_HTTP_GET],
+        );
+        let block = match Payload::block_type() {
+            BlockType::Full => {
+                publisher
+                    .produce_block::<Payload>(slot, randao_reveal, graffiti)
+                    .await?
+            }
+            BlockType::Blinded => {
+                publisher
+                    .produce_blinded_block::<Payload>(slot, randao_reveal, graffiti)
+                    .await?
+            }
+        };
+        drop(get_timer);
+
+        if block.slot() != slot {
+            metrics::inc_counter(&metrics::BLOCK_SERVICE_WRONG_SLOT);
+            warn!(
+                log,
+                "Beacon node returned block for wrong slot";
+                "requested" => slot.as_u64(),
+                "received" => block.slot().as_u64(),
+            );
+            return Err(BlockError::Recoverable(
+                "beacon node returned block for wrong slot".to_string(),
+            ));
+        }
+
+        if proposer_index == Some(block.proposer_index()) {
+            return Ok(block);
+        }
+
+        mismatch_retries += 1;
+        let delay = PROPOSER_MISMATCH_RETRY_DELAY * mismatch_retries;
+        let within_deadline = time_to_deadline().map_or(false, |remaining| remaining > delay);
+        if mismatch_retries > u32::from(max_retries) || !within_deadline {
+            return Err(BlockError::Recoverable(
+                "Proposer index does not match block proposer. Beacon chain re-orged".to_string(),
+            ));
+        }
+
+        metrics::inc_counter_vec(
+            &metrics::BLOCK_SERVICE_RETRIES,
+            &[metrics::PROPOSER_MISMATCH_RETRY],
+        );
+        debug!(
             log,
-            "Successfully published block";
-            "deposits" => signed_block.message().body().deposits().len(),
-            "attestations" => signed_block.message().body().attestations().len(),
-            "graffiti" => ?graffiti.map(|g| g.as_utf8_lossy()),
-            "slot" => signed_block.slot().as_u64(),
+            "Proposer index mismatch, re-requesting block";
+            "attempt" => mismatch_retries,
+            "expected" => ?proposer_index,
+            "received" => block.proposer_index(),
+            "slot" => slot.as_u64(),
         );
+        sleep(delay).await;
+    }
+}
 
-        Ok(())
+/// Returns whether two blocks build the same proposal: same slot, proposer and parent, and an
+/// execution payload with the same parent and block hashes. The operations of blocks from different
+/// beacon nodes differ, so their roots seldom match; the key fields rule out a node leading us onto
+/// a fork of its own.
+fn blocks_agree<E: EthSpec, Payload: AbstractExecPayload<E>>(
+    a: &BeaconBlock<E, Payload>,
+    b: &BeaconBlock<E, Payload>,
+) -> bool {
+    a.slot() == b.slot()
+        && a.proposer_index() == b.proposer_index()
+        && a.parent_root() == b.parent_root()
+        && execution_hashes(a) == execution_hashes(b)
+}
+
+/// Returns the parent and block hashes of the execution payload of `block`, or `None` before the
+/// merge.
+fn execution_hashes<E: EthSpec, Payload: AbstractExecPayload<E>>(
+    block: &BeaconBlock<E, Payload>,
+) -> Option<(ExecutionBlockHash, ExecutionBlockHash)> {
+    let payload = block.body().execution_payload().ok()?;
+    Some((payload.parent_hash(), payload.block_hash()))
+}
+
+/// Checks that at least `required` beacon nodes produce a block agreeing with `block` (see
+/// `blocks_agree`) before it is signed. Every node is asked, so this costs a round of requests.
+async fn check_block_agreement<E, Payload, P>(
+    publisher: &P,
+    block: &BeaconBlock<E, Payload>,
+    randao_reveal: &SignatureBytes,
+    graffiti: Option<&Graffiti>,
+    required: u8,
+    log: &Logger,
+) -> Result<(), BlockError>
+where
+    E: EthSpec,
+    Payload: AbstractExecPayload<E>,
+    P: BlockPublisher<E>,
+{
+    let candidates = publisher
+        .produce_blocks_from_all::<Payload>(block.slot(), randao_reveal, graffiti)
+        .await?;
+    let agreeing = candidates
+        .iter()
+        .filter(|candidate| blocks_agree(block, candidate))
+        .count();
+    if agreeing >= usize::from(required) {
+        return Ok(());
     }
+
+    metrics::inc_counter(&metrics::BLOCK_SERVICE_BLOCK_DISAGREEMENTS);
+    warn!(
+        log,
+        "Beacon nodes disagree on the block";
+        "agreeing" => agreeing,
+        "required" => required,
+        "candidates" => candidates.len(),
+        "parent_root" => ?block.parent_root(),
+        "slot" => block.slot().as_u64(),
+    );
+    Err(BlockError::Recoverable(format!(
+        "only {} of the {} required beacon nodes agree on the block",
+        agreeing, required
+    )))
+}
+
+/// Builds a `BlockService`.
+pub struct BlockServiceBuilder<T, E: EthSpec, P = BeaconNodeFallback<T, E>> {
+    validator_store: Option<Arc<ValidatorStore<T, E>>>,
+    slot_clock: Option<Arc<T>>,
+    beacon_nodes: Option<Arc<P>>,
+    context: Option<RuntimeContext<E>>,
+    graffiti: Option<Graffiti>,
+    graffiti_files: Vec<GraffitiFile>,
+    graffiti_resolution_order: GraffitiResolutionOrder,
+    graffiti_rotation: GraffitiRotation,
+    private_tx_proposals: bool,
+    proposer_mismatch_retries: u8,
+    require_block_agreement: u8,
+    blinded_block_deadline_ms: Option<u64>,
+    blinded_failure_threshold: Option<u32>,
+    blinded_cooldown_slots: u64,
+    publish_failure_threshold: Option<u32>,
+    publish_cooldown_slots: u64,
+    events_tx: Option<mpsc::Sender<BlockPublishedEvent>>,
+    dry_run: bool,
+    produce_at_genesis: bool,
+    validator_allow_list: Option<HashSet<PublicKeyBytes>>,
+    max_proposers_per_slot: usize,
+    default_graffiti_from_id: bool,
+    graffiti_tag_resolver: Option<GraffitiTagResolver>,
+    graffiti_tag_placement: GraffitiTagPlacement,
+    spawn_jitter_ms: u64,
+    prefetch_lead_ms: u64,
+    max_concurrent_productions: Option<usize>,
+    allow_local_signing_fallback: bool,
+    local_signing_keypairs: Vec<Arc<Keypair>>,
+    randao_replay_policy: Option<RandaoReplayPolicy>,
 }
 
+impl<T: SlotClock + 'static, E: EthSpec, P: BlockPublisher<E>> BlockServiceBuilder<T, E, P> {
+    pub fn new() -> Self {
+        Self {
+            validator_store: None,
+            slot_clock: None,
+            beacon_nodes: None,
+            context: None,
+            graffiti: None,
+            graffiti_files: Vec::new(),
+            graffiti_resolution_order: GraffitiResolutionOrder::default(),
+            graffiti_rotation: GraffitiRotation::default(),
+            private_tx_proposals: false,
+            proposer_mismatch_retries: 0,
+            require_block_agreement: 1,
+            blinded_block_deadline_ms: None,
+            blinded_failure_threshold: None,
+            blinded_cooldown_slots: 0,
+            publish_failure_threshold: None,
+            publish_cooldown_slots: 0,
+            events_tx: None,
+            dry_run: false,
+            produce_at_genesis: false,
+            validator_allow_list: None,
+            max_proposers_per_slot: DEFAULT_MAX_PROPOSERS_PER_SLOT,
+            default_graffiti_from_id: false,
+            graffiti_tag_resolver: None,
+            graffiti_tag_placement: GraffitiTagPlacement::default(),
+            spawn_jitter_ms: 0,
+            prefetch_lead_ms: 0,
+            max_concurrent_productions: None,
+            allow_local_signing_fallback: false,
+            local_signing_keypairs: Vec::new(),
+            randao_replay_policy: None,
+        }
+    }
+
+    pub fn validator_store(mut self, store: Arc<ValidatorStore<T, E>>) -> Self {
+        self.validator_store = Some(store);
+        self
+    }
+
+    pub fn slot_clock(mut self, slot_clock: T) -> Self {
+        self.slot_clock = Some(Arc::new(slot_clock));
+        self
+    }
+
+    /// Sets the beacon nodes blocks are produced by and published to.
+    pub fn beacon_nodes(mut self, beacon_nodes: Arc<P>) -> Self {
+        self.beacon_nodes = Some(beacon_nodes);
+        self
+    }
+
+    pub fn runtime_context(mut self, context: RuntimeContext<E>) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    pub fn graffiti(mut self, graffiti: Option<Graffiti>) -> Self {
+        self.graffiti = graffiti;
+        self
+    }
+
+    pub fn graffiti_file(self, graffiti_file: Option<GraffitiFile>) -> Self {
+        self.graffiti_files(graffiti_file.into_iter().collect())
+    }
+
+    /// Sets the graffiti files to consult, in order: the first one with an entry for the validator
+    /// wins. Failing that, the default graffiti of the first file that has one is used.
+    pub fn graffiti_files(mut self, graffiti_files: Vec<GraffitiFile>) -> Self {
+        self.graffiti_files = graffiti_files;
+        self
+    }
+
+    pub fn graffiti_resolution_order(mut self, order: GraffitiResolutionOrder) -> Self {
+        self.graffiti_resolution_order = order;
+        self
+    }
+
+    pub fn graffiti_rotation(mut self, rotation: GraffitiRotation) -> Self {
+        self.graffiti_rotation = rotation;
+        self
+    }
+
+    pub fn private_tx_proposals(mut self, private_tx_proposals: bool) -> Self {
+        self.private_tx_proposals = private_tx_proposals;
+        self
+    }
+
+    /// Sets how many times a block is re-requested from the same beacon node when its proposer
+    /// index does not match ours (e.g. during a short re-org), before failing over to the next
+    /// node. Retries are abandoned once the block-production deadline of the slot has passed.
+    pub fn proposer_mismatch_retries(mut self, proposer_mismatch_retries: u8) -> Self {
+        self.proposer_mismatch_retries = proposer_mismatch_retries;
+        self
+    }
+
+    /// Sets how many beacon nodes must produce a matching block (see `blocks_agree`) before it is
+    /// signed. Above 1, every beacon node is asked for a block once ours is produced, trading
+    /// latency for not trusting a single node; too few matches fail the proposal. The default of 1
+    /// trusts the node that produced the block. 0 is rejected by `build`.
+    pub fn require_block_agreement(mut self, require_block_agreement: u8) -> Self {
+        self.require_block_agreement = require_block_agreement;
+        self
+    }
+
+    /// Sets the deadline for blinded block production, in milliseconds *relative to the start of
+    /// the slot*. If the blinded block has not been produced by then it is abandoned and a full
+    /// block is produced instead. A blinded block that is already being signed is never abandoned.
+    /// `None` (the default) places no deadline on the blinded attempt.
+    pub fn blinded_block_deadline_ms(mut self, blinded_block_deadline_ms: Option<u64>) -> Self {
+        self.blinded_block_deadline_ms = blinded_block_deadline_ms;
+        self
+    }
+
+    /// Sets how many consecutive blinded block failures (across slots) make the service skip
+    /// blinded production and go straight to full payloads for `blinded_cooldown_slots` slots,
+    /// after which blinded production is tried again. `None` (the default) never skips it.
+    pub fn blinded_failure_threshold(mut self, blinded_failure_threshold: Option<u32>) -> Self {
+        self.blinded_failure_threshold = blinded_failure_threshold;
+        self
+    }
+
+    pub fn blinded_cooldown_slots(mut self, blinded_cooldown_slots: u64) -> Self {
+        self.blinded_cooldown_slots = blinded_cooldown_slots;
+        self
+    }
+
+    /// Sets how many consecutive block publishing failures make the service skip a beacon node
+    /// for `publish_cooldown_slots` slots, after which it is probed again. If every beacon node
+    /// would be skipped, they are all tried instead. `None` (the default) never skips them.
+    pub fn publish_failure_threshold(mut self, publish_failure_threshold: Option<u32>) -> Self {
+        self.publish_failure_threshold = publish_failure_threshold;
+        self
+    }
+
+    pub fn publish_cooldown_slots(mut self, publish_cooldown_slots: u64) -> Self {
+        self.publish_cooldown_slots = publish_cooldown_slots;
+        self
+    }
+
+    /// Sets a channel on which a `BlockPublishedEvent` is sent after every successful proposal.
+    /// Events are dropped if the channel is full or closed.
+    pub fn events_tx(mut self, events_tx: mpsc::Sender<BlockPublishedEvent>) -> Self {
+        self.events_tx = Some(events_tx);
+        self
+    }
+
+    /// When enabled, blocks are produced and signed as usual but never published to the beacon
+    /// node. Useful to exercise the signing pipeline in staging without risking a broadcast.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// When enabled, blocks are produced at the genesis slot instead of being skipped. Only
+    /// intended for interop and devnet testing with a genesis configured at a nonzero time.
+    pub fn produce_at_genesis(mut self, produce_at_genesis: bool) -> Self {
+        self.produce_at_genesis = produce_at_genesis;
+        self
+    }
+
+    /// Restricts block production to the given validators; proposals of other validators in the
+    /// store are skipped. `None` (the default) produces blocks for every validator.
+    pub fn validator_allow_list(mut self, validator_allow_list: Option<HashSet<PublicKeyBytes>>) -> Self {
+        self.validator_allow_list = validator_allow_list;
+        self
+    }
+
+    /// Caps the number of proposers a single slot produces blocks for (default
+    /// `DEFAULT_MAX_PROPOSERS_PER_SLOT`). The proposers beyond it are skipped with an error, as a
+    /// safety valve against a runaway configuration spawning a production task for each of them.
+    pub fn max_proposers_per_slot(mut self, max_proposers_per_slot: usize) -> Self {
+        self.max_proposers_per_slot = max_proposers_per_slot;
+        self
+    }
+
+    /// When enabled and no graffiti is configured for a proposal, a graffiti of the form
+    /// `SafeStake-<validator index>` is used instead of an empty one.
+    pub fn default_graffiti_from_id(mut self, default_graffiti_from_id: bool) -> Self {
+        self.default_graffiti_from_id = default_graffiti_from_id;
+        self
+    }
+
+    /// Stamps the tag returned by `resolver` into the graffiti of blinded blocks, so that they
+    /// can be matched on-chain with the relay configuration that built them.
+    pub fn graffiti_tag_resolver(mut self, resolver: GraffitiTagResolver) -> Self {
+        self.graffiti_tag_resolver = Some(resolver);
+        self
+    }
+
+    pub fn graffiti_tag_placement(mut self, placement: GraffitiTagPlacement) -> Self {
+        self.graffiti_tag_placement = placement;
+        self
+    }
+
+    /// Delays each block production task by a random amount of up to `spawn_jitter_ms`
+    /// milliseconds, to spread the load on the beacon nodes. The delay is further capped so that
+    /// it stays well within the production window. Zero (the default) starts them right away.
+    pub fn spawn_jitter_ms(mut self, spawn_jitter_ms: u64) -> Self {
+        self.spawn_jitter_ms = spawn_jitter_ms;
+        self
+    }
+
+    /// Accepts block production notifications for the next slot up to `prefetch_lead_ms`
+    /// milliseconds before it starts. The block is then requested right away, but only signed and
+    /// published once the slot has started. Zero (the default) only produces blocks during their
+    /// own slot.
+    pub fn prefetch_lead_ms(mut self, prefetch_lead_ms: u64) -> Self {
+        self.prefetch_lead_ms = prefetch_lead_ms;
+        self
+    }
+
+    /// Limits how many block productions run at the same time, e.g. when many co-located
+    /// validators propose in the same slot. The others wait for their turn until the slot is over.
+    /// `None` (the default) runs them all at once.
+    pub fn max_concurrent_productions(mut self, max_concurrent_productions: Option<usize>) -> Self {
+        self.max_concurrent_productions = max_concurrent_productions;
+        self
+    }
+
+    /// Lets this operator sign a block with the full validator key when the committee fails to
+    /// gather enough signature shares. Off by default, and only takes effect for the validators
+    /// whose key is given to `local_signing_keypairs`.
+    ///
+    /// ## Security
+    ///
+    /// This gives up on the guarantees of the distributed validator, as a last resort to keep
+    /// proposing through a committee outage. Only enable it knowingly:
+    ///
+    /// - The full key must be held on this host. Whoever compromises the host can sign anything
+    ///   for the validator, without any other operator being involved.
+    /// - Slashing protection still applies, but only this operator's database is consulted. Other
+    ///   operators do not know about blocks signed here. If more than one operator can fall back
+    ///   for the same validator, they may sign conflicting blocks and get it slashed: enable the
+    ///   fallback on at most one of them.
+    /// - Only block proposals fall back. Randao reveals, attestations and other duties still
+    ///   need the committee, so the proposal fails anyway if the randao reveal cannot be signed.
+    /// - Each use is logged at `CRIT` level and counted under the `local_signing_fallback` status
+    ///   of `vc_signed_beacon_blocks_total`.
+    pub fn allow_local_signing_fallback(mut self, allow: bool) -> Self {
+        self.allow_local_signing_fallback = allow;
+        self
+    }
+
+    /// Sets the full validator keys used by the local signing fallback, if it is allowed.
+    pub fn local_signing_keypairs(mut self, keypairs: Vec<Arc<Keypair>>) -> Self {
+        self.local_signing_keypairs = keypairs;
+        self
+    }
+
+    /// Guards against signing a randao reveal twice for the same epoch, e.g. when the leader
+    /// changes between two proposals of a validator: a second request is either served the first
+    /// reveal or refused, depending on `policy`. Requests for an epoch older than the latest
+    /// revealed one are always refused. Off by default. See `RandaoGuard`.
+    pub fn randao_replay_guard(mut self, policy: Option<RandaoReplayPolicy>) -> Self {
+        self.randao_replay_policy = policy;
+        self
+    }
+
+    pub fn build(self) -> Result<BlockService<T, E, P>, String> {
+        if self.require_block_agreement == 0 {
+            return Err("Cannot require block agreement from zero beacon nodes".to_string());
+        }
+        if self.allow_local_signing_fallback {
+            if let Some(context) = &self.context {
+                crit!(
+                    context.log(),
+                    "Local signing fallback enabled";
+                    "msg" => "blocks may be signed with a full validator key held by this operator",
+                    "validators" => self.local_signing_keypairs.len(),
+                );
+            }
+        }
+        Ok(BlockService {
+            inner: Arc::new(Inner {
+                validator_store: self
+                    .validator_store
+                    .ok_or("Cannot build BlockService without validator_store")?,
+                slot_clock: self
+                    .slot_clock
+                    .ok_or("Cannot build BlockService without slot_clock")?,
+                beacon_nodes: self
+                    .beacon_nodes
+                    .ok_or("Cannot build BlockService without beacon_node")?,
+                context: self
+                    .context
+                    .ok_or("Cannot build BlockService without runtime_context")?,
+                graffiti: self.graffiti,
+                graffiti_files: self.graffiti_files.into_iter().map(Mutex::new).collect(),
+                graffiti_resolution_order: self.graffiti_resolution_order,
+                graffiti_rotation: self.graffiti_rotation,
+                private_tx_proposals: self.private_tx_proposals,
+                proposer_mismatch_retries: self.proposer_mismatch_retries,
+                require_block_agreement: self.require_block_agreement,
+                blinded_block_deadline_ms: self.blinded_block_deadline_ms,
+                blinded_fallback: BlindedFallbackState::new(
+                    self.blinded_failure_threshold,
+                    self.blinded_cooldown_slots,
+                ),
+                publish_breaker: PublishCircuitBreaker::new(
+                    self.publish_failure_threshold,
+                    self.publish_cooldown_slots,
+                ),
+                events_tx: self.events_tx,
+                dry_run: self.dry_run,
+                produce_at_genesis: self.produce_at_genesis,
+                randao_cache: RandaoCache::default(),
+                randao_guard: RandaoGuard::new(self.randao_replay_policy),
+                handled_proposals: HandledProposals::default(),
+                slot_cancellation: Mutex::new(None),
+                validator_allow_list: self.validator_allow_list,
+                max_proposers_per_slot: self.max_proposers_per_slot,
+                health: Arc::new(BlockServiceHealthState::default()),
+                default_graffiti_from_id: self.default_graffiti_from_id,
+                graffiti_tag_resolver: self.graffiti_tag_resolver,
+                graffiti_tag_placement: self.graffiti_tag_placement,
+                spawn_jitter_ms: self.spawn_jitter_ms,
+                prefetch_lead_ms: self.prefetch_lead_ms,
+                production_limiter: ProductionLimiter::new(self.max_concurrent_productions),
+                local_signing_fallback: LocalSigningFallback::new(
+                    self.allow_local_signing_fallback,
+                    self.local_signing_keypairs,
+                ),
+                paused: AtomicBool::new(false),
+                blinded_support: BlindedSupport::default(),
+            }),
+        })
+    }
+}
+
+/// Helper to minimise `Arc` usage.
+pub struct Inner<T, E: EthSpec, P = BeaconNodeFallback<T, E>> {
+    validator_store: Arc<ValidatorStore<T, E>>,
+    slot_clock: Arc<T>,
+    beacon_nodes: Arc<P>,
+    context: RuntimeContext<E>,
+    graffiti: Option<Graffiti>,
+    /// Shared by all proposals, so that they benefit from their cached contents.
+    graffiti_files: Vec<Mutex<GraffitiFile>>,
+    graffiti_resolution_order: GraffitiResolutionOrder,
+    graffiti_rotation: GraffitiRotation,
+    private_tx_proposals: bool,
+    proposer_mismatch_retries: u8,
+    require_block_agreement: u8,
+    blinded_block_deadline_ms: Option<u64>,
+    blinded_fallback: BlindedFallbackState,
+    publish_breaker: PublishCircuitBreaker,
+    events_tx: Option<mpsc::Sender<BlockPublishedEvent>>,
+    dry_run: bool,
+    produce_at_genesis: bool,
+    randao_cache: RandaoCache,
+    randao_guard: RandaoGuard,
+    handled_proposals: HandledProposals,
+    /// The slot whose block production is in progress, and the token to cancel it.
+    slot_cancellation: Mutex<Option<(Slot, CancellationToken)>>,
+    validator_allow_list: Option<HashSet<PublicKeyBytes>>,
+    max_proposers_per_slot: usize,
+    health: Arc<BlockServiceHealthState>,
+    default_graffiti_from_id: bool,
+    graffiti_tag_resolver: Option<GraffitiTagResolver>,
+    graffiti_tag_placement: GraffitiTagPlacement,
+    spawn_jitter_ms: u64,
+    prefetch_lead_ms: u64,
+    production_limiter: ProductionLimiter,
+    local_signing_fallback: LocalSigningFallback,
+    /// Set while block production is paused, e.g. during a maintenance window.
+    paused: AtomicBool,
+    /// Tracks whether the beacon nodes serve blinded blocks. Only full blocks are produced while
+    /// they are known not to.
+    blinded_support: BlindedSupport,
+}
+
+/// Attempts to produce attestations for any block producer(s) at the start of the epoch.
+pub struct BlockService<T, E: EthSpec, P = BeaconNodeFallback<T, E>> {
+    inner: Arc<Inner<T, E, P>>,
+}
+
+impl<T, E: EthSpec, P> Clone for BlockService<T, E, P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T, E: EthSpec, P> Deref for BlockService<T, E, P> {
+    type Target = Inner<T, E, P>;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.deref()
+    }
+}
+
+/// Notification from the duties service that we should try to produce a block.
+pub struct BlockServiceNotification {
+    pub slot: Slot,
+    pub block_proposers: Vec<PublicKeyBytes>,
+    /// A graffiti to use as is for the blocks of this slot only, instead of resolving it from the
+    /// graffiti files, the validator definitions and the default.
+    pub graffiti_override: Option<Graffiti>,
+}
+
+impl<T: SlotClock + 'static, E: EthSpec, P: BlockPublisher<E>> BlockService<T, E, P> {
+    /// Builds a service from its required components, with every optional setting left at its
+    /// default. Prefer `BlockServiceBuilder` when any setting has to be changed; this goes through
+    /// the same `build` so the same checks apply.
+    pub fn from_parts(
+        validator_store: Arc<ValidatorStore<T, E>>,
+        slot_clock: T,
+        beacon_nodes: Arc<P>,
+        context: RuntimeContext<E>,
+    ) -> Result<Self, String> {
+        BlockServiceBuilder::new()
+            .validator_store(validator_store)
+            .slot_clock(slot_clock)
+            .beacon_nodes(beacon_nodes)
+            .runtime_context(context)
+            .build()
+    }
+
+    /// Returns a snapshot of the recent operational state of the service.
+    pub fn health(&self) -> BlockServiceHealth {
+        self.health.snapshot()
+    }
+
+    /// Returns the outcomes of the proposals of each validator, from which their success ratio
+    /// can be told apart when a single validator is failing among many.
+    pub fn proposal_stats(&self) -> HashMap<PublicKeyBytes, ProposalStats> {
+        self.health.proposal_stats()
+    }
+
+    /// Stops producing blocks until `resume` is called. Notifications received in the meantime
+    /// are skipped, the service itself keeps running.
+    pub fn pause(&self) {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            info!(self.context.log(), "Block production paused");
+        }
+    }
+
+    /// Resumes block production, starting with the next notification.
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            info!(self.context.log(), "Block production resumed");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Returns the graffiti a block proposed by `validator_pubkey` in the current epoch would
+    /// carry, resolved and rotated the same way as when proposing. The tag stamped into blinded
+    /// blocks is not included. Nothing is produced or signed, though the graffiti files may be
+    /// re-read as they would be for a proposal.
+    pub async fn resolved_graffiti(&self, validator_pubkey: &PublicKeyBytes) -> Option<Graffiti> {
+        let graffiti = self.resolve_graffiti(validator_pubkey).await?;
+        match self.slot_clock.now() {
+            Some(slot) => {
+                let epoch = slot.epoch(E::slots_per_epoch());
+                Some(self.graffiti_rotation.apply(graffiti, epoch))
+            }
+            None => Some(graffiti),
+        }
+    }
+
+    /// Returns the validators this node would propose blocks for in `epoch`, should they be
+    /// assigned a slot: the enabled validators it manages, minus those outside the allow-list.
+    /// The proposer duties are not queried. Validators still under doppelganger protection cannot
+    /// sign in the current epoch, so they are only included for later ones.
+    pub async fn proposer_candidates(&self, epoch: Epoch) -> Vec<PublicKeyBytes> {
+        let current_epoch = self
+            .slot_clock
+            .now_or_genesis()
+            .map(|slot| slot.epoch(E::slots_per_epoch()));
+        let pubkeys: Vec<PublicKeyBytes> = match current_epoch {
+            Some(current_epoch) if epoch <= current_epoch => {
+                self.validator_store.voting_pubkeys(DoppelgangerStatus::only_safe).await
+            }
+            _ => self.validator_store.voting_pubkeys(DoppelgangerStatus::ignored).await,
+        };
+        filter_proposers(pubkeys, self.validator_allow_list.as_ref()).0
+    }
+
+    pub fn start_update_service(
+        self,
+        mut notification_rx: mpsc::Receiver<BlockServiceNotification>,
+    ) -> Result<(), String> {
+        let log = self.context.log().clone();
+
+        info!(log, "Block production service started");
+
+        let executor = self.inner.context.executor.clone();
+
+        executor.spawn(
+            async move {
+                while let Some(notif) = notification_rx.recv().await {
+                    let service = self.clone();
+                    service.do_update(notif).await.ok();
+                }
+                debug!(log, "Block service shutting down");
+            },
+            "block_service",
+        );
+
+        Ok(())
+    }
+
+    /// Attempt to produce a block for any block producers in the `ValidatorStore`.
+    async fn do_update(&self, notification: BlockServiceNotification) -> Result<(), ()> {
+        let log = self.context.log();
+        let _timer =
+            metrics::start_timer_vec(&metrics::BLOCK_SERVICE_TIMES, &[metrics::FULL_UPDATE]);
+
+        if self.is_paused() {
+            debug!(
+                log,
+                "Skipping block production while paused";
+                "notification_slot" => notification.slot.as_u64(),
+            );
+            metrics::inc_counter(&metrics::BLOCK_SERVICE_PAUSED_SKIPS);
+            return Ok(());
+        }
+
+        let current_slot = self.slot_clock.now().ok_or_else(move || {
+            crit!(log, "Duties manager failed to read slot clock");
+        })?;
+        let prefetch = is_prefetch_notification(
+            notification.slot,
+            current_slot,
+            self.slot_clock.duration_to_slot(notification.slot),
+            self.prefetch_lead_ms,
+        );
+        let slot = if prefetch { notification.slot } else { current_slot };
+
+        if notification.slot != slot {
+            warn!(
+                log,
+                "Skipping block production for expired slot";
+                "current_slot" => current_slot.as_u64(),
+                "notification_slot" => notification.slot.as_u64(),
+                "info" => "Your machine could be overloaded"
+            );
+            return Ok(());
+        }
+
+        if slot == self.context.eth2_config.spec.genesis_slot && !self.produce_at_genesis {
+            debug!(
+                log,
+                "Not producing block at genesis slot";
+                "proposers" => format!("{:?}", notification.block_proposers),
+            );
+            return Ok(());
+        }
+
+        trace!(
+            log,
+            "Block service update started";
+            "slot" => slot.as_u64(),
+            "prefetch" => prefetch,
+        );
+
+        let (proposers, skipped) =
+            filter_proposers(notification.block_proposers, self.validator_allow_list.as_ref());
+        for validator_pubkey in skipped {
+            trace!(
+                log,
+                "Skipping block proposer not in the allow-list";
+                "validator" => ?validator_pubkey,
+                "slot" => slot.as_u64()
+            );
+        }
+
+        let (proposers, beyond_cap) = cap_proposers(proposers, self.max_proposers_per_slot);
+        if !beyond_cap.is_empty() {
+            error!(
+                log,
+                "Too many block proposers for this slot";
+                "action" => "skipping the proposers beyond the cap",
+                "max_proposers_per_slot" => self.max_proposers_per_slot,
+                "skipped" => ?beyond_cap,
+                "slot" => slot.as_u64(),
+            );
+        }
+
+        let (proposers, duplicates): (Vec<_>, Vec<_>) = proposers
+            .into_iter()
+            .partition(|validator_pubkey| self.handled_proposals.start(*validator_pubkey, slot));
+        for validator_pubkey in duplicates {
+            debug!(
+                log,
+                "Skipping duplicate block production notification";
+                "validator" => ?validator_pubkey,
+                "slot" => slot.as_u64()
+            );
+        }
+
+        if proposers.is_empty() {
+            trace!(
+                log,
+                "No local block proposers for this slot";
+                "slot" => slot.as_u64()
+            )
+        } else if proposers.len() > 1 {
+            error!(
+                log,
+                "Multiple block proposers for this slot";
+                "action" => "producing blocks for all proposers",
+                "num_proposers" => proposers.len(),
+                "slot" => slot.as_u64(),
+            )
+        }
+
+        // A notification for a newer slot cancels whatever is left of the production for the
+        // previous one. Notifications for the same slot (e.g. after a proposer change) share it.
+        let slot_cancellation = {
+            let mut current = self.slot_cancellation.lock();
+            match current.as_ref() {
+                Some((current_slot, token)) if *current_slot == slot => token.clone(),
+                _ => {
+                    let token = CancellationToken::new();
+                    if let Some((_, previous)) = current.replace((slot, token.clone())) {
+                        previous.cancel();
+                    }
+                    token
+                }
+            }
+        };
+
+        let graffiti_override = notification.graffiti_override;
+        let private_tx_proposals = self.private_tx_proposals;
+        let fork_allows_blinded =
+            should_attempt_blinded::<E>(slot, &self.context.eth2_config.spec);
+        for validator_pubkey in proposers {
+            let service = self.clone();
+            let log = log.clone();
+            let cancel = slot_cancellation.clone();
+            let health = self.health.clone();
+            let validator_store = self.validator_store.clone();
+            self.inner.context.executor.spawn(
+                async move {
+                    let production = async {
+                        // Not being the leader is no reason to skip the block: the leader can
+                        // only aggregate the signature shares the other operators sign and store
+                        // in `sign_block`, so they must produce the same block. The check only
+                        // tells the logs which role this operator plays.
+                        if !service.validator_store.is_leader_for(&validator_pubkey, slot).await {
+                            debug!(
+                                log,
+                                "Producing block signature share for the leader";
+                                "validator" => ?validator_pubkey,
+                                "slot" => slot.as_u64(),
+                            );
+                        }
+                        if service.spawn_jitter_ms > 0 {
+                            sleep(spawn_jitter(
+                                service.spawn_jitter_ms,
+                                service.time_to_production_deadline(slot),
+                            ))
+                            .await;
+                        }
+                        if service.production_limiter.is_saturated() {
+                            debug!(
+                                log,
+                                "Waiting for other block productions to finish";
+                                "validator" => ?validator_pubkey,
+                                "slot" => slot.as_u64(),
+                            );
+                        }
+                        let _permit = service.production_limiter.acquire().await;
+                        let try_blinded = private_tx_proposals
+                            && fork_allows_blinded
+                            && !service.blinded_support.is_unsupported(slot)
+                            && !service.blinded_fallback.in_cooldown(slot);
+                        let mut fell_back = false;
+                        let publish_result = match service.validator_index(&validator_pubkey).await {
+                            Err(e) => Err(e),
+                            Ok(proposer_index) if try_blinded => {
+                                let mut result = service.clone()
+                                    .publish_block::<BlindedPayload<E>>(slot, validator_pubkey, proposer_index, graffiti_override)
+                                    .await;
+                                match result.as_ref() {
+                                    Ok(()) => service.blinded_fallback.record_success(),
+                                    Err(BlockError::Recoverable(_))
+                                    | Err(BlockError::Throttled(_))
+                                    | Err(BlockError::Irrecoverable(_)) => {
+                                        if service.blinded_fallback.record_failure(slot) {
+                                            warn!(
+                                                log,
+                                                "Repeated blinded block failures, using full payloads for a while";
+                                                "cooldown_slots" => service.blinded_fallback.cooldown_slots,
+                                                "slot" => slot.as_u64(),
+                                            );
+                                        }
+                                    }
+                                    _ => {}
+                                }
+                                match result.as_ref() {
+                                    Err(BlockError::Recoverable(e)) | Err(BlockError::BeaconNodeNotSynced(e)) => {
+                                        error!(log, "Error whilst producing a blinded block, attempting to publish full block"; "error" => ?e);
+                                        fell_back = true;
+                                        result = service
+                                            .publish_block::<FullPayload<E>>(slot, validator_pubkey, proposer_index, graffiti_override)
+                                            .await;
+                                    },
+                                    Err(BlockError::Throttled(e)) => {
+                                        let delay = throttled_retry_delay();
+                                        warn!(log, "Beacon node throttled blinded block production, attempting to publish full block"; "error" => ?e, "delay_ms" => delay.as_millis() as u64);
+                                        sleep(delay).await;
+                                        fell_back = true;
+                                        result = service
+                                            .publish_block::<FullPayload<E>>(slot, validator_pubkey, proposer_index, graffiti_override)
+                                            .await;
+                                    },
+                                    Err(BlockError::BlindedUnsupported(e)) => {
+                                        if let Some(reprobe_slot) = service.blinded_support.mark_unsupported(slot, E::slots_per_epoch()) {
+                                            warn!(log, "Beacon node does not support blinded blocks, using full payloads for a while"; "error" => ?e, "reprobe_slot" => reprobe_slot.as_u64());
+                                        }
+                                        fell_back = true;
+                                        result = service
+                                            .publish_block::<FullPayload<E>>(slot, validator_pubkey, proposer_index, graffiti_override)
+                                            .await;
+                                    },
+                                    Err(BlockError::Irrecoverable(e))  => {
+                                        error!(log, "Error whilst producing a blinded block, cannot fallback because block was signed"; "error" => ?e);
+                                    },
+                                    _ => {},
+                                };
+                                result
+                            }
+                            Ok(proposer_index) => {
+                                let result = service
+                                    .clone()
+                                    .publish_block::<FullPayload<E>>(slot, validator_pubkey, proposer_index, graffiti_override)
+                                    .await;
+                                match result {
+                                    Err(BlockError::Throttled(e)) => {
+                                        let delay = throttled_retry_delay();
+                                        warn!(log, "Beacon node throttled block production, retrying"; "error" => ?e, "delay_ms" => delay.as_millis() as u64);
+                                        sleep(delay).await;
+                                        service
+                                            .publish_block::<FullPayload<E>>(slot, validator_pubkey, proposer_index, graffiti_override)
+                                            .await
+                                    }
+                                    result => result,
+                                }
+                            }
+                        };
+                        metrics::inc_counter_vec(
+                            &metrics::BLOCK_SERVICE_PAYLOAD_OUTCOME,
+                            &[payload_outcome(&publish_result, try_blinded, fell_back)],
+                        );
+                        let pubkey_hex = validator_pubkey.as_hex_string();
+                        let pubkey_prefix = &pubkey_hex[..PUBKEY_LABEL_LEN];
+                        match &publish_result {
+                            Ok(()) => metrics::inc_counter_vec(
+                                &metrics::BLOCK_SERVICE_IS_LEADER,
+                                &[pubkey_prefix],
+                            ),
+                            Err(BlockError::RandaoNotLeader) | Err(BlockError::SignBlockNotLeader) => {
+                                metrics::inc_counter_vec(
+                                    &metrics::BLOCK_SERVICE_NOT_LEADER,
+                                    &[pubkey_prefix],
+                                )
+                            }
+                            Err(_) => {}
+                        }
+                        health.record(slot, &validator_pubkey, &publish_result);
+                        // Another operator leading the slot is no failure: retrying would not
+                        // change the leader.
+                        let failed = !matches!(
+                            publish_result,
+                            Ok(()) | Err(BlockError::RandaoNotLeader) | Err(BlockError::SignBlockNotLeader)
+                        );
+                        if prefetch && failed {
+                            service.handled_proposals.release(validator_pubkey, slot);
+                        }
+                        metrics::set_gauge_vec(
+                            &metrics::BLOCK_SERVICE_CONSECUTIVE_MISSES,
+                            &[pubkey_prefix],
+                            health.consecutive_misses(&validator_pubkey) as i64,
+                        );
+                        if let Err(e) = publish_result {
+                            match e {
+                                BlockError::RandaoNotLeader => {
+                                    error!(log,
+                                        "Error whilst producing block";
+                                        "message" => ?e
+                                    );
+                                },
+                                BlockError::SignBlockNotLeader => {
+                                    // The leaders the signing layer picked for this slot.
+                                    let leaders = validator_store
+                                        .leader_history(&validator_pubkey)
+                                        .await
+                                        .into_iter()
+                                        .find(|assignment| assignment.slot == slot.as_u64())
+                                        .map(|assignment| assignment.leaders);
+                                    info!(log,
+                                        "Not a leader for proposing this block";
+                                        "message" => ?e,
+                                        "leaders" => ?leaders,
+                                    );
+                                },
+                                BlockError::SlashingProtection(_) => {
+                                    crit!(log,
+                                        "Slashing protection rejected block proposal";
+                                        "message" => ?e,
+                                        "slot" => slot.as_u64(),
+                                        "validator" => ?validator_pubkey,
+                                    );
+                                },
+                                _ => {
+                                    crit!(
+                                        log,
+                                        "Error whilst producing block";
+                                        "message" => ?e
+                                    );
+                                }
+                            };
+                        }
+                    };
+                    tokio::select! {
+                        () = production => {},
+                        () = cancel.cancelled() => {
+                            metrics::inc_counter_vec(
+                                &metrics::BLOCK_SERVICE_PAYLOAD_OUTCOME,
+                                &[metrics::PAYLOAD_FAILED],
+                            );
+                            warn!(
+                                log,
+                                "Cancelled block production for expired slot";
+                                "slot" => slot.as_u64(),
+                                "validator" => ?validator_pubkey,
+                            );
+                            let cancelled = Err(BlockError::Recoverable(
+                                "block production cancelled for expired slot".to_string(),
+                            ));
+                            health.record(slot, &validator_pubkey, &cancelled);
+                            let pubkey_hex = validator_pubkey.as_hex_string();
+                            metrics::set_gauge_vec(
+                                &metrics::BLOCK_SERVICE_CONSECUTIVE_MISSES,
+                                &[&pubkey_hex[..PUBKEY_LABEL_LEN]],
+                                health.consecutive_misses(&validator_pubkey) as i64,
+                            );
+                        }
+                    }
+                },
+                "block service",
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the index of `validator_pubkey`. Without it, no block can be matched against the
+    /// validator: it is most likely not active yet. This is checked once per proposal, before the
+    /// payload type is chosen, so that it never counts as a blinded block failure.
+    async fn validator_index(&self, validator_pubkey: &PublicKeyBytes) -> Result<u64, BlockError> {
+        self.validator_store.validator_index(validator_pubkey).await.ok_or_else(|| {
+            metrics::inc_counter(&metrics::BLOCK_SERVICE_UNKNOWN_VALIDATOR_INDEX);
+            BlockError::Recoverable(
+                "validator index unknown, deposit may not be processed".to_string(),
+            )
+        })
+    }
+
+    /// Produce a block at the given slot for validator_pubkey
+    async fn publish_block<Payload: AbstractExecPayload<E>>(
+        self,
+        slot: Slot,
+        validator_pubkey: PublicKeyBytes,
+        proposer_index: u64,
+        graffiti_override: Option<Graffiti>,
+    ) -> Result<(), BlockError> {
+        let log = self.context.log();
+        let _timer =
+            metrics::start_timer_vec(&metrics::BLOCK_SERVICE_TIMES, &[metrics::BEACON_BLOCK]);
+
+        let epoch = slot.epoch(E::slots_per_epoch());
+        // The guard comes first: under `RandaoReplayPolicy::Refuse`, a cached reveal must not
+        // serve a second request for the epoch.
+        let randao_reveal = match self.randao_guard.check(&validator_pubkey, epoch) {
+            RandaoGuardDecision::Sign => match self.randao_cache.get(&validator_pubkey, epoch) {
+                Some(randao_reveal) => randao_reveal,
+                None => {
+                    let randao_reveal = self
+                        .validator_store
+                        .randao_reveal(validator_pubkey, epoch)
+                        .await
+                        .map_err(|e| {
+                            match e {
+                                VSError::UnableToSign(SigningError::NotLeader) => BlockError::RandaoNotLeader,
+                                _ => BlockError::Recoverable(format!("Unable to produce randao reveal signature: {:?}", e))
+                            }
+                        })?;
+                    self.randao_guard.record(validator_pubkey, epoch, &randao_reveal);
+                    self.randao_cache.insert(validator_pubkey, epoch, randao_reveal.clone());
+                    randao_reveal
+                }
+            },
+            RandaoGuardDecision::Reuse(randao_reveal) => {
+                debug!(log, "Reusing randao reveal"; "epoch" => epoch.as_u64());
+                randao_reveal
+            }
+            RandaoGuardDecision::Refuse(reason) => {
+                warn!(log, "Refusing to sign randao reveal"; "reason" => &reason);
+                return Err(BlockError::Irrecoverable(reason));
+            }
+        }
+        .into();
+
+        let graffiti = self
+            .proposal_graffiti::<Payload>(&validator_pubkey, epoch, graffiti_override)
+            .await;
+
+        // A block that is still in the works at the production deadline is unlikely to get
+        // attested, so give up on it rather than trying the remaining beacon nodes. Only the
+        // production is bounded: a block that is being signed must not be abandoned half-way.
+        let production = async {
+            let (service, randao_reveal, graffiti) = (&self, &randao_reveal, graffiti.as_ref());
+            let produce = move || {
+                produce_matching_block(
+                    service.beacon_nodes.as_ref(),
+                    slot,
+                    randao_reveal,
+                    graffiti,
+                    Some(proposer_index),
+                    service.proposer_mismatch_retries,
+                    move || service.time_to_production_deadline(slot),
+                    log,
+                )
+            };
+            let started = Instant::now();
+            let mut block = produce().await?;
+            let mut production_time = started.elapsed();
+
+            // A prefetched block is held back until its slot starts. The chain may have re-orged
+            // in the meantime, so the proposer is checked again against the latest head and the
+            // block re-requested if it no longer matches.
+            if let Some(wait) = self.time_to_slot_start(slot) {
+                debug!(
+                    log,
+                    "Holding prefetched block until the slot starts";
+                    "wait_ms" => wait.as_millis(),
+                    "slot" => slot.as_u64(),
+                );
+                sleep(wait).await;
+                let head_proposer = self.beacon_nodes.proposer_index(slot).await?;
+                if head_proposer != Some(block.proposer_index()) {
+                    debug!(
+                        log,
+                        "Proposer changed while holding prefetched block";
+                        "expected" => block.proposer_index(),
+                        "head" => ?head_proposer,
+                        "slot" => slot.as_u64(),
+                    );
+                    let started = Instant::now();
+                    block = produce().await?;
+                    production_time += started.elapsed();
+                }
+            }
+            if self.require_block_agreement > 1 {
+                let started = Instant::now();
+                check_block_agreement(
+                    self.beacon_nodes.as_ref(),
+                    &block,
+                    randao_reveal,
+                    graffiti,
+                    self.require_block_agreement,
+                    log,
+                )
+                .await?;
+                production_time += started.elapsed();
+            }
+            Ok::<_, BlockError>((block, production_time))
+        };
+        // A blinded block that misses its own deadline is given up on before it is signed, so
+        // that a full block can still be produced in its place.
+        let blinded_timeout = match Payload::block_type() {
+            BlockType::Blinded => self.blinded_block_timeout(slot),
+            BlockType::Full => None,
+        };
+        let production = async move {
+            match blinded_timeout {
+                Some(blinded_timeout) => timeout(blinded_timeout, production)
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(BlockError::Recoverable("Blinded block deadline exceeded".to_string()))
+                    }),
+                None => production.await,
+            }
+        };
+        let (block, production_time) = match self.time_to_production_deadline(slot) {
+            Some(remaining) => match timeout(remaining, production).await {
+                Ok(result) => result?,
+                Err(_) => {
+                    metrics::inc_counter(&metrics::BLOCK_SERVICE_SLOT_DEADLINE_EXCEEDED);
+                    return Err(BlockError::Recoverable("slot deadline exceeded".to_string()));
+                }
+            },
+            None => production.await?,
+        };
+        metrics::observe_timer_vec(
+            &metrics::BLOCK_SERVICE_TIMES,
+            &[metrics::BEACON_BLOCK_PRODUCE],
+            production_time,
+        );
+
+        let current_slot = self.slot_clock.now().ok_or_else(|| {
+            BlockError::Recoverable("Unable to determine current slot from clock".to_string())
+        })?;
+        let started = Instant::now();
+        let signed_block = self
+            .validator_store
+            .sign_block_with_fallback::<Payload>(
+                validator_pubkey,
+                block,
+                current_slot,
+                self.local_signing_fallback.keypair(&validator_pubkey),
+            )
+            .await
+            .map_err(BlockError::from_sign_block)?;
+        let signing_time = started.elapsed();
+        metrics::observe_timer_vec(
+            &metrics::BLOCK_SERVICE_TIMES,
+            &[metrics::BEACON_BLOCK_SIGN],
+            signing_time,
+        );
+        let timings = BlockTimings {
+            production_time,
+            signing_time,
+            publishing_time: Duration::ZERO,
+        };
+
+        if self.dry_run {
+            info!(
+                log,
+                "Dry run, not publishing block";
+                "block_root" => ?signed_block.canonical_root(),
+                "size_bytes" => signed_block.ssz_bytes_len(),
+                "slot" => signed_block.slot().as_u64(),
+            );
+            return Ok(());
+        }
+
+        let _post_timer = metrics::start_timer_vec(
+            &metrics::BLOCK_SERVICE_TIMES,
+            &[metrics::BEACON_BLOCK_HTTP_POST],
+        );
+        let started = Instant::now();
+        let beacon_node = match Payload::block_type() {
+            BlockType::Full => {
+                self.beacon_nodes.publish(&signed_block, &self.publish_breaker).await?
+            }
+            BlockType::Blinded => {
+                self.beacon_nodes
+                    .publish_blinded(&signed_block, &self.publish_breaker)
+                    .await?
+            }
+        };
+
+        let timings = BlockTimings {
+            publishing_time: started.elapsed(),
+            ..timings
+        };
+
+        let delay_into_slot = self.delay_into_slot(slot);
+        if let Some(delay) = delay_into_slot {
+            metrics::observe_duration(&metrics::BLOCK_PUBLISH_DELAY_INTO_SLOT, delay);
+        }
+        info!(
+            log,
+            "Successfully published block";
+            "beacon_node" => %beacon_node,
+            "deposits" => signed_block.message().body().deposits().len(),
+            "attestations" => signed_block.message().body().attestations().len(),
+            "graffiti" => ?graffiti.map(|g| g.as_utf8_lossy()),
+            "slot" => signed_block.slot().as_u64(),
+            "delay_into_slot_ms" => ?delay_into_slot.map(|delay| delay.as_millis()),
+            "production_ms" => timings.production_time.as_millis(),
+            "signing_ms" => timings.signing_time.as_millis(),
+            "publishing_ms" => timings.publishing_time.as_millis(),
+        );
+
+        if let Some(events_tx) = &self.events_tx {
+            let event = BlockPublishedEvent {
+                slot: signed_block.slot(),
+                proposer_index: signed_block.message().proposer_index(),
+                graffiti,
+                deposits: signed_block.message().body().deposits().len(),
+                attestations: signed_block.message().body().attestations().len(),
+                payload_type: Payload::block_type().into(),
+                beacon_node,
+            };
+            // Event delivery must never hold up block production.
+            if let Err(e) = events_tx.try_send(event) {
+                debug!(log, "Unable to send block published event"; "error" => %e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the graffiti for `validator_pubkey` from the configured sources, in the order given
+    /// by `graffiti_resolution_order`.
+    ///
+    /// If no source has a graffiti and `default_graffiti_from_id` is set, one is synthesized from
+    /// the validator index.
+    async fn resolve_graffiti(&self, validator_pubkey: &PublicKeyBytes) -> Option<Graffiti> {
+        let graffiti = self.resolve_configured_graffiti(validator_pubkey).await;
+        if graffiti.is_some() || !self.default_graffiti_from_id {
+            return graffiti;
+        }
+
+        let validator_index = self.validator_store.validator_index(validator_pubkey).await?;
+        let graffiti = graffiti_from_id(validator_index);
+        info!(
+            self.context.log(),
+            "Using graffiti derived from the validator index";
+            "graffiti" => graffiti.as_utf8_lossy(),
+            "validator_index" => validator_index,
+        );
+        Some(graffiti)
+    }
+
+    async fn resolve_configured_graffiti(&self, validator_pubkey: &PublicKeyBytes) -> Option<Graffiti> {
+        let order = self.graffiti_resolution_order;
+        if order == GraffitiResolutionOrder::DefaultOnly {
+            return self.graffiti;
+        }
+
+        let file_graffiti =
+            graffiti_from_files(&self.graffiti_files, validator_pubkey, self.context.log());
+        let validator_graffiti = self.validator_store.graffiti(validator_pubkey).await;
+
+        order.resolve(file_graffiti, validator_graffiti, self.graffiti)
+    }
+
+    /// Returns the time remaining until the configured blinded block deadline of `slot`, or `None`
+    /// if no deadline is configured. Returns a zero duration if the deadline has already passed.
+    fn blinded_block_timeout(&self, slot: Slot) -> Option<Duration> {
+        let deadline_ms = self.blinded_block_deadline_ms?;
+        let deadline = self
+            .slot_clock
+            .start_of(slot)
+            .map(|start| start + Duration::from_millis(deadline_ms));
+        let remaining = deadline
+            .zip(self.slot_clock.now_duration())
+            .and_then(|(deadline, now)| deadline.checked_sub(now))
+            .unwrap_or_default();
+        Some(remaining)
+    }
+
+    /// Returns the time remaining until the block-production deadline of `slot`, i.e. the point
+    /// at which attesters are expected to vote on the block (one third into the slot).
+    ///
+    /// Returns a zero duration if the deadline has passed, and `None` if the slot clock cannot be
+    /// read.
+    fn time_to_production_deadline(&self, slot: Slot) -> Option<Duration> {
+        let deadline = self.slot_clock.start_of(slot)?
+            + self.slot_clock.unagg_attestation_production_delay();
+        Some(deadline.saturating_sub(self.slot_clock.now_duration()?))
+    }
+
+    /// Returns the graffiti of a block proposed by `validator_pubkey` in `epoch`. An override from
+    /// the notification is used as is; otherwise the graffiti is resolved, rotated and, for blinded
+    /// blocks, tagged.
+    async fn proposal_graffiti<Payload: AbstractExecPayload<E>>(
+        &self,
+        validator_pubkey: &PublicKeyBytes,
+        epoch: Epoch,
+        graffiti_override: Option<Graffiti>,
+    ) -> Option<Graffiti> {
+        if graffiti_override.is_some() {
+            return graffiti_override;
+        }
+        let graffiti = self
+            .resolve_graffiti(validator_pubkey)
+            .await
+            .map(|g| self.graffiti_rotation.apply(g, epoch));
+        match (&self.graffiti_tag_resolver, Payload::block_type()) {
+            (Some(resolver), BlockType::Blinded) => match resolver(validator_pubkey) {
+                Some(tag) => self.graffiti_tag_placement.apply(graffiti, &tag),
+                None => graffiti,
+            },
+            _ => graffiti,
+        }
+    }
+
+    /// Returns how long ago `slot` started, or `None` if it has not yet.
+    fn delay_into_slot(&self, slot: Slot) -> Option<Duration> {
+        let start = self.slot_clock.start_of(slot)?;
+        self.slot_clock.now_duration()?.checked_sub(start)
+    }
+
+    /// Returns the time remaining until `slot` starts, or `None` if it already has.
+    fn time_to_slot_start(&self, slot: Slot) -> Option<Duration> {
+        let start = self.slot_clock.start_of(slot)?;
+        start
+            .checked_sub(self.slot_clock.now_duration()?)
+            .filter(|remaining| !remaining.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validation::account_utils::validator_definitions::ValidatorDefinitions;
+    use crate::validation::account_utils::ZeroizeString;
+    use crate::validation::beacon_node_fallback::CandidateError;
+    use crate::validation::initialized_validators::InitializedValidators;
+    use crate::validation::validator_dir::share_builder::insecure_kdf;
+    use crate::validation::validator_store::local_fallback_keypair;
+    use crate::validation::Config;
+    use environment::{Environment, EnvironmentBuilder};
+    use eth2_keystore::KeystoreBuilder;
+    use slashing_protection::{NotSafe, SlashingDatabase, SLASHING_PROTECTION_FILENAME};
+    use slot_clock::ManualSlotClock;
+    use std::path::Path;
+    use types::{BeaconBlockCapella, Hash256, MainnetEthSpec};
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    const PK: &str = "0x800012708dc03f611751aad7a43a082142832b5c1aceed07ff9b543cf836381861352aa923c70eeb02018b638aa306aa";
+
+    fn graffiti(s: &str) -> Graffiti {
+        GraffitiString::from_str(s).unwrap().into()
+    }
+
+    #[test]
+    fn multiple_graffiti_files() {
+        let pk = PublicKeyBytes::from_str(PK).unwrap();
+        let temp = TempDir::new().unwrap();
+        let first = temp.path().join("first.txt");
+        writeln!(File::create(&first).unwrap(), "default: first-default").unwrap();
+        let second = temp.path().join("second.txt");
+        let mut file = File::create(&second).unwrap();
+        writeln!(file, "default: second-default").unwrap();
+        writeln!(file, "{}: from-second", PK).unwrap();
+        let log = Logger::root(slog::Discard, slog::o!());
+
+        // An entry for the validator in a later file beats the default of an earlier one.
+        let files = vec![
+            Mutex::new(GraffitiFile::new(first.clone())),
+            Mutex::new(GraffitiFile::new(second)),
+        ];
+        assert_eq!(graffiti_from_files(&files, &pk, &log), Some(graffiti("from-second")));
+
+        // Without an entry, the first default wins.
+        let other = PublicKeyBytes::empty();
+        assert_eq!(graffiti_from_files(&files, &other, &log), Some(graffiti("first-default")));
+
+        // Unreadable files are skipped.
+        let files = vec![
+            Mutex::new(GraffitiFile::new(temp.path().join("missing.txt"))),
+            Mutex::new(GraffitiFile::new(first)),
+        ];
+        assert_eq!(graffiti_from_files(&files, &pk, &log), Some(graffiti("first-default")));
+    }
+
+    #[test]
+    fn graffiti_resolution_order() {
+        let pk = PublicKeyBytes::from_str(PK).unwrap();
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("graffiti.txt");
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "{}: from-file", PK).unwrap();
+
+        let file_graffiti = GraffitiFile::new(path).load_graffiti(&pk).unwrap();
+        let validator_graffiti = Some(graffiti("from-validator"));
+        let default_graffiti = Some(graffiti("from-default"));
+
+        let resolve = |order: GraffitiResolutionOrder| {
+            order.resolve(file_graffiti, validator_graffiti, default_graffiti)
+        };
+        assert_eq!(
+            resolve(GraffitiResolutionOrder::FilePreferred),
+            Some(graffiti("from-file"))
+        );
+        assert_eq!(
+            resolve(GraffitiResolutionOrder::ValidatorPreferred),
+            Some(graffiti("from-validator"))
+        );
+        assert_eq!(
+            resolve(GraffitiResolutionOrder::DefaultOnly),
+            Some(graffiti("from-default"))
+        );
+
+        // Missing sources fall through to the next one in line.
+        assert_eq!(
+            GraffitiResolutionOrder::ValidatorPreferred.resolve(file_graffiti, None, default_graffiti),
+            Some(graffiti("from-file"))
+        );
+        assert_eq!(
+            GraffitiResolutionOrder::FilePreferred.resolve(None, None, default_graffiti),
+            Some(graffiti("from-default"))
+        );
+    }
+
+    #[test]
+    fn graffiti_rotation() {
+        let epoch = Epoch::new(1234);
+        assert_eq!(
+            GraffitiRotation::Static.apply(graffiti("safestake"), epoch),
+            graffiti("safestake")
+        );
+        assert_eq!(
+            GraffitiRotation::AppendEpoch.apply(graffiti("safestake"), epoch),
+            graffiti("safestake 1234")
+        );
+        assert_eq!(
+            GraffitiRotation::AppendEpoch.apply(graffiti(""), epoch),
+            graffiti("1234")
+        );
+
+        // The base is truncated to make room for the epoch.
+        let long = "a".repeat(GRAFFITI_BYTES_LEN);
+        let rotated = GraffitiRotation::AppendEpoch.apply(graffiti(&long), epoch);
+        assert_eq!(rotated, graffiti(&format!("{} 1234", "a".repeat(GRAFFITI_BYTES_LEN - 5))));
+
+        // Truncation does not split a multibyte character: the 27-byte cut falls inside the ninth
+        // three-byte character, which is dropped whole.
+        let base = format!("a{}", "€".repeat(10));
+        let rotated = GraffitiRotation::AppendEpoch.apply(graffiti(&base), epoch);
+        assert_eq!(rotated, graffiti(&format!("a{} 1234", "€".repeat(8))));
+        assert!(!rotated.as_utf8_lossy().contains('\u{FFFD}'));
+    }
+
+    #[test]
+    fn blinded_unsupported() {
+        for status in [StatusCode::NOT_FOUND, StatusCode::METHOD_NOT_ALLOWED, StatusCode::NOT_IMPLEMENTED] {
+            assert!(matches!(
+                BlockError::from_blinded_block_production(eth2::Error::StatusCode(status)),
+                BlockError::BlindedUnsupported(_)
+            ));
+        }
+        let unavailable = eth2::Error::StatusCode(StatusCode::SERVICE_UNAVAILABLE);
+        assert!(matches!(
+            BlockError::from_blinded_block_production(unavailable),
+            BlockError::Recoverable(_)
+        ));
+
+        // Blinded blocks are only given up on if no beacon node serves them.
+        let unsupported = || {
+            FallbackError::RequestFailed(BlockError::BlindedUnsupported("404".to_string()))
+        };
+        let errors = Errors(vec![("a".to_string(), unsupported()), ("b".to_string(), unsupported())]);
+        assert!(matches!(BlockError::from(errors), BlockError::BlindedUnsupported(_)));
+        let errors = Errors(vec![
+            ("a".to_string(), unsupported()),
+            ("b".to_string(), FallbackError::RequestFailed(BlockError::Recoverable("500".to_string()))),
+        ]);
+        assert!(matches!(BlockError::from(errors), BlockError::Recoverable(_)));
+    }
+
+    #[test]
+    fn blinded_fork_gate() {
+        let slots_per_epoch = MainnetEthSpec::slots_per_epoch();
+        let mut spec = MainnetEthSpec::default_spec();
+        spec.altair_fork_epoch = Some(Epoch::new(0));
+        spec.bellatrix_fork_epoch = Some(Epoch::new(10));
+        spec.capella_fork_epoch = Some(Epoch::new(20));
+
+        let first_slot = |epoch: u64| Epoch::new(epoch).start_slot(slots_per_epoch);
+        assert!(!should_attempt_blinded::<MainnetEthSpec>(Slot::new(0), &spec));
+        assert!(!should_attempt_blinded::<MainnetEthSpec>(first_slot(10) - 1, &spec));
+        assert!(should_attempt_blinded::<MainnetEthSpec>(first_slot(10), &spec));
+        assert!(should_attempt_blinded::<MainnetEthSpec>(first_slot(20), &spec));
+
+        // Without a merge, blinded payloads are never attempted.
+        spec.bellatrix_fork_epoch = None;
+        spec.capella_fork_epoch = None;
+        assert!(!should_attempt_blinded::<MainnetEthSpec>(first_slot(100), &spec));
+    }
+
+    #[tokio::test]
+    async fn production_limit() {
+        let limiter = Arc::new(ProductionLimiter::new(Some(2)));
+        let running = Arc::new(AtomicU32::new(0));
+        let max_running = Arc::new(AtomicU32::new(0));
+
+        let productions = (0..8).map(|_| {
+            let (limiter, running, max_running) =
+                (limiter.clone(), running.clone(), max_running.clone());
+            tokio::spawn(async move {
+                let _permit = limiter.acquire().await;
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now_running, Ordering::SeqCst);
+                sleep(Duration::from_millis(20)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        });
+        for production in futures::future::join_all(productions).await {
+            production.unwrap();
+        }
+
+        // Every production ran, but never more than two at a time.
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+        assert!(!limiter.is_saturated());
+        assert!(!ProductionLimiter::new(None).is_saturated());
+    }
+
+    #[test]
+    fn spawn_jitter_bounds() {
+        assert_eq!(spawn_jitter(0, Some(Duration::from_secs(4))), Duration::ZERO);
+        assert_eq!(spawn_jitter(100, None), Duration::ZERO);
+        for _ in 0..100 {
+            assert!(spawn_jitter(100, Some(Duration::from_secs(4))) <= Duration::from_millis(100));
+            // A quarter of the time left caps the configured jitter.
+            assert!(spawn_jitter(1_000, Some(Duration::from_millis(200))) <= Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn throttled_block_production() {
+        let throttled = eth2::Error::StatusCode(StatusCode::TOO_MANY_REQUESTS);
+        assert!(matches!(
+            BlockError::from_block_production(throttled),
+            BlockError::Throttled(_)
+        ));
+        let unavailable = eth2::Error::StatusCode(StatusCode::SERVICE_UNAVAILABLE);
+        assert!(matches!(
+            BlockError::from_block_production(unavailable),
+            BlockError::Recoverable(_)
+        ));
+
+        // Throttling survives the fallback, but never hides an irrecoverable error.
+        let throttled = || {
+            FallbackError::RequestFailed(BlockError::Throttled("429".to_string()))
+        };
+        let errors = Errors(vec![
+            ("a".to_string(), throttled()),
+            ("b".to_string(), FallbackError::RequestFailed(BlockError::Recoverable("500".to_string()))),
+        ]);
+        assert!(matches!(BlockError::from(errors), BlockError::Throttled(_)));
+        let errors = Errors(vec![
+            ("a".to_string(), throttled()),
+            ("b".to_string(), FallbackError::RequestFailed(BlockError::Irrecoverable("signed".to_string()))),
+        ]);
+        assert!(matches!(BlockError::from(errors), BlockError::Irrecoverable(_)));
+    }
+
+    #[test]
+    fn not_synced_block_production() {
+        let server_message = |code: u16, message: &str| {
+            eth2::Error::ServerMessage(eth2::types::ErrorMessage {
+                code,
+                message: message.to_string(),
+                stacktraces: vec![],
+            })
+        };
+        let not_synced = server_message(503, "SERVICE_UNAVAILABLE: beacon node is syncing: head slot is 100");
+        assert!(matches!(
+            BlockError::from_block_production(not_synced),
+            BlockError::BeaconNodeNotSynced(_)
+        ));
+        // Any other 503, e.g. from a proxy in front of a node that is down, stays generic.
+        let unavailable = server_message(503, "Service Unavailable");
+        assert!(matches!(
+            BlockError::from_block_production(unavailable),
+            BlockError::Recoverable(_)
+        ));
+
+        // The condition is reported, and counted, once no beacon node is synced.
+        let not_synced = || {
+            FallbackError::RequestFailed(BlockError::BeaconNodeNotSynced("503".to_string()))
+        };
+        let count = || {
+            metrics::BLOCK_SERVICE_BEACON_NODE_NOT_SYNCED
+                .as_ref()
+                .map_or(0, |c| c.get())
+        };
+        let before = count();
+        let errors = Errors(vec![("a".to_string(), not_synced()), ("b".to_string(), not_synced())]);
+        assert!(matches!(BlockError::from(errors), BlockError::BeaconNodeNotSynced(_)));
+        assert!(count() >= before + 2);
+
+        // A node that failed for another reason is the one worth reporting.
+        let errors = Errors(vec![
+            ("a".to_string(), not_synced()),
+            ("b".to_string(), FallbackError::RequestFailed(BlockError::Recoverable("500".to_string()))),
+        ]);
+        match BlockError::from(errors) {
+            BlockError::Recoverable(message) => assert_eq!(message, "500"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn per_node_errors() {
+        let errors = Errors(vec![
+            ("a".to_string(), FallbackError::RequestFailed(BlockError::Recoverable("500".to_string()))),
+            ("b".to_string(), FallbackError::RequestFailed(BlockError::Irrecoverable("signed".to_string()))),
+            ("c".to_string(), FallbackError::Unavailable(CandidateError::Offline)),
+        ]);
+        assert_eq!(count_node_errors(&errors), (2, 1));
+        assert_eq!(
+            describe_node_errors(&errors),
+            "3 beacon node(s) failed: [0] a => RequestFailed(Recoverable(\"500\")); \
+             [1] b => RequestFailed(Irrecoverable(\"signed\")); [2] c => Unavailable(Offline)"
+        );
+        match BlockError::from(errors) {
+            BlockError::Irrecoverable(message) => assert_eq!(message, "signed"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn first_node_error_message() {
+        let recoverable =
+            |message: &str| FallbackError::RequestFailed(BlockError::Recoverable(message.to_string()));
+        let irrecoverable =
+            |message: &str| FallbackError::RequestFailed(BlockError::Irrecoverable(message.to_string()));
+
+        // The first irrecoverable error is kept verbatim, whatever failed before or after it.
+        let errors = Errors(vec![
+            ("a".to_string(), recoverable("500")),
+            ("b".to_string(), FallbackError::Unavailable(CandidateError::Offline)),
+            ("c".to_string(), irrecoverable("invalid signature")),
+            ("d".to_string(), irrecoverable("already signed")),
+        ]);
+        match BlockError::from(errors) {
+            BlockError::Irrecoverable(message) => assert_eq!(message, "invalid signature"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // Without one, the first recoverable error is kept verbatim.
+        let errors = Errors(vec![
+            ("a".to_string(), FallbackError::Unavailable(CandidateError::Offline)),
+            ("b".to_string(), recoverable("timeout")),
+            ("c".to_string(), recoverable("500")),
+        ]);
+        match BlockError::from(errors) {
+            BlockError::Recoverable(message) => assert_eq!(message, "timeout"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // Nodes that were not tried have no message, so all of them are described.
+        let errors = Errors(vec![("a".to_string(), FallbackError::Unavailable(CandidateError::Offline))]);
+        match BlockError::from(errors) {
+            BlockError::Recoverable(message) => {
+                assert_eq!(message, "1 beacon node(s) failed: [0] a => Unavailable(Offline)")
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn publish_circuit_breaker() {
+        let breaker = PublishCircuitBreaker::new(Some(2), 3);
+        assert!(!breaker.record_failure("a", Slot::new(10)));
+        assert_eq!(breaker.state("a", Slot::new(10)), CircuitState::Closed(1));
+        assert!(breaker.record_failure("a", Slot::new(11)));
+
+        // The node is skipped for the next 3 slots, while the others are not.
+        for slot in 12..15 {
+            assert_eq!(breaker.state("a", Slot::new(slot)), CircuitState::Open(Slot::new(15)));
+        }
+        assert!(breaker.allows("b", Slot::new(12)));
+        assert!(breaker.allows_any(&["a".to_string(), "b".to_string()], Slot::new(12)));
+        assert!(!breaker.allows_any(&["a".to_string()], Slot::new(12)));
+
+        // Once half-open, a single failure opens the circuit again.
+        assert_eq!(breaker.state("a", Slot::new(15)), CircuitState::HalfOpen);
+        assert!(breaker.allows("a", Slot::new(15)));
+        assert!(breaker.record_failure("a", Slot::new(15)));
+        assert_eq!(breaker.state("a", Slot::new(16)), CircuitState::Open(Slot::new(19)));
+
+        // And a success closes it.
+        assert_eq!(breaker.state("a", Slot::new(19)), CircuitState::HalfOpen);
+        breaker.record_success("a");
+        assert_eq!(breaker.state("a", Slot::new(19)), CircuitState::Closed(0));
+
+        // Without a threshold, the circuit never opens.
+        let disabled = PublishCircuitBreaker::new(None, 3);
+        for slot in 0..10 {
+            assert!(!disabled.record_failure("a", Slot::new(slot)));
+        }
+        assert!(disabled.allows("a", Slot::new(10)));
+
+        // Nodes skipped by an enabled breaker are not set offline.
+        assert!(breaker.offline_on_failure() == OfflineOnFailure::No);
+        assert!(disabled.offline_on_failure() == OfflineOnFailure::Yes);
+    }
+
+    #[test]
+    fn blinded_support_reprobe() {
+        let support = BlindedSupport::default();
+        assert!(!support.is_unsupported(Slot::new(10)));
+
+        let reprobe_slot = 11 + BLINDED_UNSUPPORTED_REPROBE_EPOCHS * 32;
+        assert_eq!(support.mark_unsupported(Slot::new(10), 32), Some(Slot::new(reprobe_slot)));
+        assert!(support.is_unsupported(Slot::new(11)));
+        assert!(support.is_unsupported(Slot::new(reprobe_slot - 1)));
+        // A concurrent proposal in the same slot does not log again.
+        assert_eq!(support.mark_unsupported(Slot::new(10), 32), None);
+
+        // Blinded blocks are probed again after the cooldown, and skipped anew if still unsupported.
+        assert!(!support.is_unsupported(Slot::new(reprobe_slot)));
+        assert!(support.mark_unsupported(Slot::new(reprobe_slot), 32).is_some());
+        assert!(support.is_unsupported(Slot::new(reprobe_slot + 1)));
+    }
+
+    #[test]
+    fn blinded_fallback_cooldown() {
+        let state = BlindedFallbackState::new(Some(2), 3);
+        assert!(!state.record_failure(Slot::new(10)));
+        assert!(!state.in_cooldown(Slot::new(11)));
+        assert!(state.record_failure(Slot::new(11)));
+
+        // Blinded production is skipped for the next 3 slots, then probed again.
+        for slot in 12..15 {
+            assert!(state.in_cooldown(Slot::new(slot)));
+        }
+        assert!(!state.in_cooldown(Slot::new(15)));
+
+        // A success resets the count of consecutive failures.
+        assert!(!state.record_failure(Slot::new(15)));
+        state.record_success();
+        assert!(!state.record_failure(Slot::new(16)));
+
+        // Without a threshold blinded production is never skipped.
+        let state = BlindedFallbackState::new(None, 3);
+        for slot in 0..10 {
+            assert!(!state.record_failure(Slot::new(slot)));
+        }
+        assert!(!state.in_cooldown(Slot::new(10)));
+    }
+
+    #[test]
+    fn randao_cache_evicts_old_epochs() {
+        let cache = RandaoCache::default();
+        let pubkey = PublicKeyBytes::from_str(PK).unwrap();
+        let other = PublicKeyBytes::empty();
+
+        cache.insert(pubkey, Epoch::new(1), Signature::empty());
+        assert!(cache.get(&pubkey, Epoch::new(1)).is_some());
+        assert!(cache.get(&pubkey, Epoch::new(2)).is_none());
+        assert!(cache.get(&other, Epoch::new(1)).is_none());
+
+        // The previous epoch is kept, older ones are evicted.
+        cache.insert(other, Epoch::new(2), Signature::empty());
+        assert!(cache.get(&pubkey, Epoch::new(1)).is_some());
+        cache.insert(other, Epoch::new(3), Signature::empty());
+        assert!(cache.get(&pubkey, Epoch::new(1)).is_none());
+        assert!(cache.get(&other, Epoch::new(2)).is_some());
+        assert!(cache.get(&other, Epoch::new(3)).is_some());
+    }
+
+    #[test]
+    fn randao_guard() {
+        let pubkey = PublicKeyBytes::from_str(PK).unwrap();
+        let other = PublicKeyBytes::empty();
+
+        // Disabled, the guard lets every reveal be signed.
+        let guard = RandaoGuard::new(None);
+        guard.record(pubkey, Epoch::new(2), &Signature::empty());
+        assert_eq!(guard.check(&pubkey, Epoch::new(2)), RandaoGuardDecision::Sign);
+        assert_eq!(guard.check(&pubkey, Epoch::new(1)), RandaoGuardDecision::Sign);
+
+        // Within an epoch, the first reveal is reused.
+        let guard = RandaoGuard::new(Some(RandaoReplayPolicy::Reuse));
+        assert_eq!(guard.check(&pubkey, Epoch::new(2)), RandaoGuardDecision::Sign);
+        guard.record(pubkey, Epoch::new(2), &Signature::empty());
+        assert_eq!(
+            guard.check(&pubkey, Epoch::new(2)),
+            RandaoGuardDecision::Reuse(Signature::empty())
+        );
+        assert_eq!(guard.check(&other, Epoch::new(2)), RandaoGuardDecision::Sign);
+
+        // A new epoch is signed, after which the previous ones are refused.
+        assert_eq!(guard.check(&pubkey, Epoch::new(3)), RandaoGuardDecision::Sign);
+        guard.record(pubkey, Epoch::new(3), &Signature::empty());
+        assert!(matches!(guard.check(&pubkey, Epoch::new(2)), RandaoGuardDecision::Refuse(_)));
+
+        // Recording an older epoch does not move the guard back.
+        guard.record(pubkey, Epoch::new(1), &Signature::empty());
+        assert!(matches!(guard.check(&pubkey, Epoch::new(1)), RandaoGuardDecision::Refuse(_)));
+        assert!(matches!(guard.check(&pubkey, Epoch::new(3)), RandaoGuardDecision::Reuse(_)));
+
+        // Refusing also covers the epoch already revealed.
+        let guard = RandaoGuard::new(Some(RandaoReplayPolicy::Refuse));
+        guard.record(pubkey, Epoch::new(2), &Signature::empty());
+        assert!(matches!(guard.check(&pubkey, Epoch::new(2)), RandaoGuardDecision::Refuse(_)));
+        assert_eq!(guard.check(&pubkey, Epoch::new(3)), RandaoGuardDecision::Sign);
+    }
+
+    #[test]
+    fn duplicate_notifications() {
+        let handled = HandledProposals::default();
+        let pubkey = PublicKeyBytes::from_str(PK).unwrap();
+        let other = PublicKeyBytes::empty();
+
+        // Two identical notifications only start production once.
+        let notification = vec![pubkey];
+        let started = (0..2)
+            .flat_map(|_| notification.iter())
+            .filter(|pubkey| handled.start(**pubkey, Slot::new(5)))
+            .count();
+        assert_eq!(started, 1);
+
+        // A new proposer for the same slot, or the next slot, are not duplicates.
+        assert!(handled.start(other, Slot::new(5)));
+        assert!(handled.start(pubkey, Slot::new(6)));
+        assert!(!handled.start(pubkey, Slot::new(5)));
+
+        // A released slot can be started again, but releasing an older slot changes nothing.
+        handled.release(pubkey, Slot::new(5));
+        assert!(!handled.start(pubkey, Slot::new(6)));
+        handled.release(pubkey, Slot::new(6));
+        assert!(handled.start(pubkey, Slot::new(6)));
+    }
+
+    #[test]
+    fn filter_allow_listed_proposers() {
+        let allowed = PublicKeyBytes::from_str(PK).unwrap();
+        let other = PublicKeyBytes::empty();
+        let proposers = vec![allowed, other];
+
+        let allow_list: HashSet<_> = vec![allowed].into_iter().collect();
+        let (kept, skipped) = filter_proposers(proposers.clone(), Some(&allow_list));
+        assert_eq!(kept, vec![allowed]);
+        assert_eq!(skipped, vec![other]);
+
+        // Without an allow-list every proposer is kept.
+        let (kept, skipped) = filter_proposers(proposers.clone(), None);
+        assert_eq!(kept, proposers);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn cap_proposers_per_slot() {
+        let proposers: Vec<_> = (0..5)
+            .map(|_| PublicKeyBytes::from(&Keypair::random().pk))
+            .collect();
+
+        // Only the first proposers up to the cap are processed.
+        let (kept, skipped) = cap_proposers(proposers.clone(), 3);
+        assert_eq!(kept, proposers[..3]);
+        assert_eq!(skipped, proposers[3..]);
+
+        // Nothing is skipped below the cap.
+        let (kept, skipped) = cap_proposers(proposers.clone(), DEFAULT_MAX_PROPOSERS_PER_SLOT);
+        assert_eq!(kept, proposers);
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn health_snapshot() {
+        let health = BlockServiceHealthState::default();
+        assert_eq!(health.snapshot(), BlockServiceHealth::default());
+
+        let pk = PublicKeyBytes::from_str(PK).unwrap();
+        health.record(Slot::new(5), &pk, &Ok(()));
+        health.record(Slot::new(3), &pk, &Ok(()));
+        health.record(Slot::new(6), &pk, &Err(BlockError::SignBlockNotLeader));
+        health.record(Slot::new(7), &pk, &Err(BlockError::Throttled("429".to_string())));
+        health.record(Slot::new(8), &pk, &Err(BlockError::Irrecoverable("publish".to_string())));
+
+        let snapshot = health.snapshot();
+        assert_eq!(snapshot.last_proposal_slot, Some(Slot::new(5)));
+        assert_eq!(snapshot.recoverable_errors, 1);
+        assert_eq!(snapshot.irrecoverable_errors, 1);
+        assert_eq!(snapshot.last_error, Some("Irrecoverable(\"publish\")".to_string()));
+    }
+
+    #[test]
+    fn consecutive_misses() {
+        let health = BlockServiceHealthState::default();
+        let pk = PublicKeyBytes::from_str(PK).unwrap();
+
+        health.record(Slot::new(1), &pk, &Err(BlockError::Recoverable("timeout".to_string())));
+        health.record(Slot::new(2), &pk, &Err(BlockError::Irrecoverable("publish".to_string())));
+        assert_eq!(health.consecutive_misses(&pk), 2);
+
+        // Not being the leader neither counts as a miss nor ends the streak.
+        health.record(Slot::new(3), &pk, &Err(BlockError::RandaoNotLeader));
+        health.record(Slot::new(4), &pk, &Err(BlockError::SignBlockNotLeader));
+        assert_eq!(health.consecutive_misses(&pk), 2);
+        assert_eq!(health.snapshot().consecutive_misses.get(&pk), Some(&2));
+
+        // A successful proposal resets the streak.
+        health.record(Slot::new(5), &pk, &Ok(()));
+        assert_eq!(health.consecutive_misses(&pk), 0);
+        assert!(health.snapshot().consecutive_misses.is_empty());
+    }
+
+    #[test]
+    fn proposal_stats() {
+        let health = BlockServiceHealthState::default();
+        let pk = PublicKeyBytes::from_str(PK).unwrap();
+        let other = PublicKeyBytes::empty();
+
+        health.record(Slot::new(1), &pk, &Ok(()));
+        health.record(Slot::new(2), &pk, &Err(BlockError::RandaoNotLeader));
+        health.record(Slot::new(3), &pk, &Err(BlockError::SignBlockNotLeader));
+        health.record(Slot::new(4), &pk, &Err(BlockError::Recoverable("timeout".to_string())));
+        health.record(Slot::new(5), &pk, &Ok(()));
+        health.record(Slot::new(6), &pk, &Ok(()));
+        health.record(Slot::new(7), &other, &Err(BlockError::SignBlockNotLeader));
+
+        // Not being the leader is tallied apart from failures and left out of the ratio.
+        let stats = health.proposal_stats();
+        let expected = ProposalStats { attempts: 6, successes: 3, not_leader: 2, failures: 1 };
+        assert_eq!(stats.get(&pk), Some(&expected));
+        assert_eq!(expected.success_ratio(), Some(0.75));
+        assert_eq!(stats[&other].not_leader, 1);
+        assert_eq!(stats[&other].success_ratio(), None);
+        assert_eq!(health.snapshot().proposal_stats, stats);
+    }
+
+    #[test]
+    fn graffiti_tags() {
+        let base = Some(graffiti("base"));
+        assert_eq!(
+            GraffitiTagPlacement::Suffix.apply(base, "relay-a"),
+            Some(graffiti("base relay-a"))
+        );
+        assert_eq!(
+            GraffitiTagPlacement::Prefix.apply(base, "relay-a"),
+            Some(graffiti("relay-a base"))
+        );
+        assert_eq!(GraffitiTagPlacement::Suffix.apply(None, "relay-a"), Some(graffiti("relay-a")));
+
+        // The base is truncated to keep the tag whole within the 32-byte cap.
+        let long = Some(graffiti("0123456789012345678901234567890"));
+        assert_eq!(
+            GraffitiTagPlacement::Suffix.apply(long, "relay-a"),
+            Some(graffiti("012345678901234567890123 relay-a"))
+        );
+        assert_eq!(
+            GraffitiTagPlacement::Prefix.apply(long, "relay-a"),
+            Some(graffiti("relay-a 012345678901234567890123"))
+        );
+    }
+
+    #[test]
+    fn graffiti_from_validator_index() {
+        assert_eq!(graffiti_from_id(42), graffiti("SafeStake-42"));
+        // The largest index still fits in the graffiti.
+        assert_eq!(
+            graffiti_from_id(u64::MAX),
+            graffiti(&format!("SafeStake-{}", u64::MAX))
+        );
+    }
+
+    #[test]
+    fn slashing_protection_sign_error() {
+        let slashable = VSError::Slashable(NotSafe::UnregisteredValidator(PublicKeyBytes::empty()));
+        assert!(matches!(
+            BlockError::from_sign_block(slashable),
+            BlockError::SlashingProtection(_)
+        ));
+        assert!(matches!(
+            BlockError::from_sign_block(VSError::UnableToSign(SigningError::NotLeader)),
+            BlockError::SignBlockNotLeader
+        ));
+        assert!(matches!(
+            BlockError::from_sign_block(VSError::SameData),
+            BlockError::Recoverable(_)
+        ));
+    }
+
+    /// Hands out empty blocks with the given proposer indices, in order. The blocks are for the
+    /// requested slot unless `block_slot` says otherwise. Asked for a block from every node, each
+    /// node answers with an empty block on the parent root at its position in `node_parent_roots`.
+    struct MockPublisher {
+        proposer_indices: Mutex<Vec<u64>>,
+        block_slot: Option<Slot>,
+        node_parent_roots: Vec<Hash256>,
+    }
+
+    #[async_trait]
+    impl BlockPublisher<MainnetEthSpec> for MockPublisher {
+        async fn produce_block<Payload: AbstractExecPayload<MainnetEthSpec>>(
+            &self,
+            slot: Slot,
+            _randao_reveal: &SignatureBytes,
+            _graffiti: Option<&Graffiti>,
+        ) -> Result<BeaconBlock<MainnetEthSpec, Payload>, BlockError> {
+            let mut block = BeaconBlock::empty(&MainnetEthSpec::default_spec());
+            *block.slot_mut() = self.block_slot.unwrap_or(slot);
+            *block.proposer_index_mut() = self.proposer_indices.lock().remove(0);
+            Ok(block)
+        }
+
+        async fn produce_blinded_block<Payload: AbstractExecPayload<MainnetEthSpec>>(
+            &self,
+            slot: Slot,
+            randao_reveal: &SignatureBytes,
+            graffiti: Option<&Graffiti>,
+        ) -> Result<BeaconBlock<MainnetEthSpec, Payload>, BlockError> {
+            self.produce_block(slot, randao_reveal, graffiti).await
+        }
+
+        async fn produce_blocks_from_all<Payload: AbstractExecPayload<MainnetEthSpec>>(
+            &self,
+            slot: Slot,
+            _randao_reveal: &SignatureBytes,
+            _graffiti: Option<&Graffiti>,
+        ) -> Result<Vec<BeaconBlock<MainnetEthSpec, Payload>>, BlockError> {
+            Ok(self
+                .node_parent_roots
+                .iter()
+                .map(|parent_root| {
+                    let mut block = BeaconBlock::empty(&MainnetEthSpec::default_spec());
+                    *block.slot_mut() = slot;
+                    *block.parent_root_mut() = *parent_root;
+                    block
+                })
+                .collect())
+        }
+
+        async fn publish<Payload: AbstractExecPayload<MainnetEthSpec>>(
+            &self,
+            _block: &SignedBeaconBlock<MainnetEthSpec, Payload>,
+            _breaker: &PublishCircuitBreaker,
+        ) -> Result<String, BlockError> {
+            Ok("mock".to_string())
+        }
+
+        async fn publish_blinded<Payload: AbstractExecPayload<MainnetEthSpec>>(
+            &self,
+            _block: &SignedBeaconBlock<MainnetEthSpec, Payload>,
+            _breaker: &PublishCircuitBreaker,
+        ) -> Result<String, BlockError> {
+            Ok("mock".to_string())
+        }
+
+        async fn proposer_index(&self, _slot: Slot) -> Result<Option<u64>, BlockError> {
+            Ok(self.proposer_indices.lock().first().copied())
+        }
+    }
+
+    /// A runtime, a manual slot clock at genesis and a temporary directory for the validator
+    /// store, to build `BlockService`s against a `MockPublisher`.
+    struct ManualClockEnv {
+        env: Environment<MainnetEthSpec>,
+        context: RuntimeContext<MainnetEthSpec>,
+        slot_clock: ManualSlotClock,
+        dir: TempDir,
+    }
+
+    impl ManualClockEnv {
+        fn new() -> Self {
+            let mut env = EnvironmentBuilder::mainnet()
+                .null_logger()
+                .unwrap()
+                .multi_threaded_tokio_runtime()
+                .unwrap()
+                .build()
+                .unwrap();
+            let context = env.core_context();
+            Self {
+                env,
+                context,
+                slot_clock: ManualSlotClock::new(
+                    Slot::new(0),
+                    Duration::from_secs(0),
+                    Duration::from_secs(12),
+                ),
+                dir: TempDir::new().unwrap(),
+            }
+        }
+
+        fn block_on<F: Future>(&self, future: F) -> F::Output {
+            self.env.runtime().block_on(future)
+        }
+
+        async fn builder(
+            &self,
+        ) -> BlockServiceBuilder<ManualSlotClock, MainnetEthSpec, MockPublisher> {
+            manual_clock_builder(
+                self.context.clone(),
+                self.slot_clock.clone(),
+                self.dir.path(),
+            )
+            .await
+        }
+
+        async fn service(&self) -> BlockService<ManualSlotClock, MainnetEthSpec, MockPublisher> {
+            self.builder().await.build().unwrap()
+        }
+    }
+
+    /// Builds a `BlockService` driven by `slot_clock`, with an empty validator store kept in `dir`
+    /// and a `MockPublisher` in place of the beacon nodes.
+    async fn manual_clock_builder(
+        context: RuntimeContext<MainnetEthSpec>,
+        slot_clock: ManualSlotClock,
+        dir: &Path,
+    ) -> BlockServiceBuilder<ManualSlotClock, MainnetEthSpec, MockPublisher> {
+        let log = context.log().clone();
+        let definitions = ValidatorDefinitions::open_or_create(dir).unwrap();
+        let validators =
+            InitializedValidators::from_definitions(definitions, dir.into(), None, log.clone())
+                .await
+                .unwrap();
+        let slashing_protection =
+            SlashingDatabase::open_or_create(&dir.join(SLASHING_PROTECTION_FILENAME)).unwrap();
+        let validator_store = Arc::new(ValidatorStore::new(
+            validators,
+            slashing_protection,
+            Hash256::zero(),
+            context.eth2_config.spec.clone(),
+            None,
+            slot_clock.clone(),
+            &Config::default(),
+            context.executor.clone(),
+            log,
+        ));
+        BlockServiceBuilder::new()
+            .validator_store(validator_store)
+            .slot_clock(slot_clock)
+            .beacon_nodes(Arc::new(MockPublisher {
+                proposer_indices: Mutex::new(vec![]),
+                block_slot: None,
+                node_parent_roots: vec![],
+            }))
+            .runtime_context(context)
+    }
+
+    #[test]
+    fn skip_expired_and_genesis_slots() {
+        let test = ManualClockEnv::new();
+        let pk = PublicKeyBytes::from_str(PK).unwrap();
+        let notification = |slot: u64| BlockServiceNotification {
+            slot: Slot::new(slot),
+            block_proposers: vec![pk],
+            graffiti_override: None,
+        };
+
+        test.block_on(async {
+            let service = test.service().await;
+
+            // Nothing is produced at genesis. A skipped notification never reaches the proposal
+            // bookkeeping, so the proposal can still be started afterwards.
+            service.do_update(notification(0)).await.unwrap();
+            assert!(service.handled_proposals.start(pk, Slot::new(0)));
+
+            // Nor for a slot that has already passed.
+            test.slot_clock.set_slot(5);
+            service.do_update(notification(4)).await.unwrap();
+            assert!(service.handled_proposals.start(pk, Slot::new(4)));
+
+            // A notification for the current slot goes ahead.
+            service.do_update(notification(5)).await.unwrap();
+            assert!(!service.handled_proposals.start(pk, Slot::new(5)));
+        });
+    }
+
+    #[test]
+    fn resolved_graffiti() {
+        let test = ManualClockEnv::new();
+        test.slot_clock.set_slot(MainnetEthSpec::slots_per_epoch() * 7);
+        let pk = PublicKeyBytes::from_str(PK).unwrap();
+        let other = PublicKeyBytes::empty();
+        let path = test.dir.path().join("graffiti.txt");
+        writeln!(File::create(&path).unwrap(), "{}: from-file", PK).unwrap();
+
+        test.block_on(async {
+            let service = test
+                .builder()
+                .await
+                .graffiti(Some(graffiti("from-default")))
+                .graffiti_file(Some(GraffitiFile::new(path)))
+                .graffiti_rotation(GraffitiRotation::AppendEpoch)
+                .build()
+                .unwrap();
+
+            // The file has an entry for `pk`, the other validator falls back to the default. Both
+            // are rotated with the current epoch.
+            assert_eq!(service.resolved_graffiti(&pk).await, Some(graffiti("from-file 7")));
+            assert_eq!(service.resolved_graffiti(&other).await, Some(graffiti("from-default 7")));
+            // Nothing was proposed along the way.
+            assert!(service.handled_proposals.start(pk, test.slot_clock.now().unwrap()));
+        });
+    }
+
+    #[test]
+    fn graffiti_override() {
+        let test = ManualClockEnv::new();
+        let pk = PublicKeyBytes::from_str(PK).unwrap();
+        let path = test.dir.path().join("graffiti.txt");
+        writeln!(File::create(&path).unwrap(), "{}: from-file", PK).unwrap();
+
+        test.block_on(async {
+            let service = test
+                .builder()
+                .await
+                .graffiti_file(Some(GraffitiFile::new(path)))
+                .graffiti_rotation(GraffitiRotation::AppendEpoch)
+                .build()
+                .unwrap();
+            let epoch = Epoch::new(7);
+
+            assert_eq!(
+                service
+                    .proposal_graffiti::<FullPayload<MainnetEthSpec>>(&pk, epoch, None)
+                    .await,
+                Some(graffiti("from-file 7"))
+            );
+            // The override beats the graffiti file and is not rotated.
+            let milestone = Some(graffiti("milestone"));
+            assert_eq!(
+                service
+                    .proposal_graffiti::<FullPayload<MainnetEthSpec>>(&pk, epoch, milestone)
+                    .await,
+                milestone
+            );
+        });
+    }
+
+    #[test]
+    fn proposer_candidates() {
+        let test = ManualClockEnv::new();
+        test.slot_clock.set_slot(MainnetEthSpec::slots_per_epoch() * 3);
+        let epoch = Epoch::new(3);
+
+        test.block_on(async {
+            let builder = test.builder().await;
+            let validator_store = builder.validator_store.clone().unwrap();
+
+            // Register two enabled validators and a disabled one.
+            let mut pubkeys = vec![];
+            for (i, enable) in [true, true, false].into_iter().enumerate() {
+                let keypair = Keypair::random();
+                let keystore = KeystoreBuilder::new(&keypair, b"password", String::new())
+                    .unwrap()
+                    .kdf(insecure_kdf())
+                    .build()
+                    .unwrap();
+                let path = test.dir.path().join(format!("keystore-{}.json", i));
+                keystore.to_json_writer(File::create(&path).unwrap()).unwrap();
+                let password = ZeroizeString::from("password".to_string());
+                validator_store
+                    .add_validator_keystore(path, password, enable, None, None, None, None)
+                    .await
+                    .unwrap();
+                pubkeys.push(PublicKeyBytes::from(&keypair.pk));
+            }
+
+            // Without doppelganger protection, the enabled validators are candidates whatever the
+            // epoch.
+            let service = builder.build().unwrap();
+            for epoch in [epoch, epoch + 1] {
+                let candidates: HashSet<_> =
+                    service.proposer_candidates(epoch).await.into_iter().collect();
+                assert_eq!(candidates, pubkeys[..2].iter().cloned().collect());
+            }
+
+            // The allow-list narrows them down.
+            let other_dir = TempDir::new().unwrap();
+            let service = manual_clock_builder(
+                test.context.clone(),
+                test.slot_clock.clone(),
+                other_dir.path(),
+            )
+            .await
+            .validator_store(validator_store)
+            .validator_allow_list(Some(HashSet::from([pubkeys[1], pubkeys[2]])))
+            .build()
+            .unwrap();
+            assert_eq!(service.proposer_candidates(epoch).await, vec![pubkeys[1]]);
+        });
+    }
+
+    #[test]
+    fn delay_into_slot() {
+        let test = ManualClockEnv::new();
+        test.slot_clock.set_current_time(Duration::from_millis(5 * 12_000 + 1_500));
+
+        test.block_on(async {
+            let service = test.service().await;
+            assert_eq!(service.delay_into_slot(Slot::new(5)), Some(Duration::from_millis(1_500)));
+            assert_eq!(service.delay_into_slot(Slot::new(4)), Some(Duration::from_millis(13_500)));
+            // The slot has not started yet.
+            assert_eq!(service.delay_into_slot(Slot::new(6)), None);
+        });
+    }
+
+    #[test]
+    fn unknown_validator_index() {
+        let test = ManualClockEnv::new();
+        let pk = PublicKeyBytes::from_str(PK).unwrap();
+
+        test.block_on(async {
+            // The validator store does not know the index of `pk`.
+            let service = test.service().await;
+            let result = service.validator_index(&pk).await;
+            assert!(
+                matches!(result, Err(BlockError::Recoverable(e)) if e.contains("validator index unknown"))
+            );
+        });
+    }
+
+    #[test]
+    fn refuse_cached_randao_replay() {
+        let test = ManualClockEnv::new();
+        test.slot_clock.set_slot(5);
+        let pk = PublicKeyBytes::from_str(PK).unwrap();
+        let epoch = Slot::new(5).epoch(MainnetEthSpec::slots_per_epoch());
+
+        test.block_on(async {
+            let service = test
+                .builder()
+                .await
+                .randao_replay_guard(Some(RandaoReplayPolicy::Refuse))
+                .build()
+                .unwrap();
+            // A reveal was produced for the epoch, and is still cached. The mock publisher has
+            // no block to produce, so getting past the reveal would panic.
+            service.randao_guard.record(pk, epoch, &Signature::empty());
+            service.randao_cache.insert(pk, epoch, Signature::empty());
+            let result = service
+                .clone()
+                .publish_block::<FullPayload<MainnetEthSpec>>(Slot::new(5), pk, 0, None)
+                .await;
+            assert!(matches!(result, Err(BlockError::Irrecoverable(_))));
+        });
+    }
+
+    #[test]
+    fn local_signing_fallback() {
+        let keypair = Arc::new(Keypair::random());
+        let pk = PublicKeyBytes::from(&keypair.pk);
+        let other = PublicKeyBytes::from(&Keypair::random().pk);
+        let insufficient = SigningError::InsufficientSignatures { got: 1, expected: 3 };
+
+        // Holding the key is not enough: the fallback must be enabled.
+        let disabled = LocalSigningFallback::new(false, vec![keypair.clone()]);
+        assert!(disabled.keypair(&pk).is_none());
+        assert!(local_fallback_keypair(&insufficient, &pk, disabled.keypair(&pk)).is_none());
+
+        let enabled = LocalSigningFallback::new(true, vec![keypair.clone()]);
+        assert!(enabled.keypair(&other).is_none());
+        let fallback = local_fallback_keypair(&insufficient, &pk, enabled.keypair(&pk));
+        assert_eq!(fallback.map(|keypair| &keypair.pk), Some(&keypair.pk));
+
+        // Other signing failures do not engage it.
+        for error in [
+            SigningError::CommitteeSignFailed("Timeout".to_string()),
+            SigningError::NotLeader,
+        ] {
+            assert!(local_fallback_keypair(&error, &pk, enabled.keypair(&pk)).is_none());
+        }
+
+        // Nor does a key that is not the validator's.
+        assert!(local_fallback_keypair(&insufficient, &other, Some(keypair.as_ref())).is_none());
+    }
+
+    #[tokio::test]
+    async fn retry_proposer_mismatch() {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let produce = |proposer_indices: Vec<u64>, max_retries: u8, remaining: Option<Duration>| {
+            let publisher = MockPublisher {
+                proposer_indices: Mutex::new(proposer_indices),
+                block_slot: None,
+                node_parent_roots: vec![],
+            };
+            let log = log.clone();
+            async move {
+                produce_matching_block::<MainnetEthSpec, FullPayload<MainnetEthSpec>, _>(
+                    &publisher,
+                    Slot::new(1),
+                    &SignatureBytes::empty(),
+                    None,
+                    Some(7),
+                    max_retries,
+                    || remaining,
+                    &log,
+                )
+                .await
+            }
+        };
+        let enough_time = Some(Duration::from_secs(10));
+
+        // The block is re-requested until the beacon node catches up with our proposer index.
+        let block = produce(vec![1, 1, 7], 2, enough_time).await.unwrap();
+        assert_eq!(block.proposer_index(), 7);
+
+        // Give up once the retries are exhausted, or when the deadline leaves no room to retry.
+        assert!(matches!(
+            produce(vec![1, 1, 7], 1, enough_time).await,
+            Err(BlockError::Recoverable(_))
+        ));
+        assert!(matches!(
+            produce(vec![1, 7], 2, Some(Duration::from_millis(100))).await,
+            Err(BlockError::Recoverable(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reject_wrong_slot() {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let publisher = MockPublisher {
+            proposer_indices: Mutex::new(vec![7, 7]),
+            block_slot: Some(Slot::new(2)),
+            node_parent_roots: vec![],
+        };
+
+        // The block is rejected before signing even though its proposer index matches, and it is
+        // not re-requested.
+        let result = produce_matching_block::<MainnetEthSpec, FullPayload<MainnetEthSpec>, _>(
+            &publisher,
+            Slot::new(1),
+            &SignatureBytes::empty(),
+            None,
+            Some(7),
+            2,
+            || Some(Duration::from_secs(10)),
+            &log,
+        )
+        .await;
+        match result {
+            Err(BlockError::Recoverable(message)) => {
+                assert_eq!(message, "beacon node returned block for wrong slot")
+            }
+            other => panic!("unexpected result: {:?}", other.map(|block| block.slot())),
+        }
+        assert_eq!(publisher.proposer_indices.lock().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn require_block_agreement() {
+        let log = Logger::root(slog::Discard, slog::o!());
+        let parent = Hash256::repeat_byte(1);
+        let fork = Hash256::repeat_byte(2);
+        let mut block: BeaconBlock<MainnetEthSpec, FullPayload<MainnetEthSpec>> =
+            BeaconBlock::empty(&MainnetEthSpec::default_spec());
+        *block.slot_mut() = Slot::new(1);
+        *block.parent_root_mut() = parent;
+
+        let check = |node_parent_roots: Vec<Hash256>, required: u8| {
+            let publisher = MockPublisher {
+                proposer_indices: Mutex::new(vec![]),
+                block_slot: None,
+                node_parent_roots,
+            };
+            let (block, log) = (block.clone(), log.clone());
+            async move {
+                check_block_agreement(
+                    &publisher,
+                    &block,
+                    &SignatureBytes::empty(),
+                    None,
+                    required,
+                    &log,
+                )
+                .await
+            }
+        };
+
+        // Two of the three nodes build on the same parent.
+        assert!(check(vec![parent, fork, parent], 2).await.is_ok());
+
+        // A node on another fork does not count towards the agreement.
+        assert!(matches!(
+            check(vec![parent, fork, parent], 3).await,
+            Err(BlockError::Recoverable(_))
+        ));
+        assert!(matches!(
+            check(vec![fork, fork], 2).await,
+            Err(BlockError::Recoverable(_))
+        ));
+
+        // Operations may differ, the key fields may not.
+        let mut other_body = block.clone();
+        *other_body.body_mut().graffiti_mut() = graffiti("other");
+        assert!(blocks_agree(&block, &other_body));
+        let mut other_proposer = block.clone();
+        *other_proposer.proposer_index_mut() = 1;
+        assert!(!blocks_agree(&block, &other_proposer));
+
+        // Nor may the execution payload.
+        let post_merge: BeaconBlock<MainnetEthSpec, FullPayload<MainnetEthSpec>> =
+            BeaconBlock::Capella(BeaconBlockCapella::empty(&MainnetEthSpec::default_spec()));
+        let mut other_payload = post_merge.clone();
+        if let BeaconBlock::Capella(block) = &mut other_payload {
+            block.body.execution_payload.execution_payload.block_hash =
+                ExecutionBlockHash::from_root(Hash256::repeat_byte(3));
+        }
+        assert!(blocks_agree(&post_merge, &post_merge.clone()));
+        assert!(!blocks_agree(&post_merge, &other_payload));
+    }
+
+    #[test]
+    fn reject_zero_block_agreement() {
+        let test = ManualClockEnv::new();
+        test.block_on(async {
+            assert!(test.builder().await.require_block_agreement(0).build().is_err());
+        });
+    }
+
+    #[test]
+    fn prefetch_notifications() {
+        let lead = Some(Duration::from_millis(300));
+        assert!(is_prefetch_notification(Slot::new(11), Slot::new(10), lead, 500));
+
+        // Too early, not for the next slot, or prefetching disabled.
+        assert!(!is_prefetch_notification(Slot::new(11), Slot::new(10), lead, 200));
+        assert!(!is_prefetch_notification(Slot::new(12), Slot::new(10), lead, 500));
+        assert!(!is_prefetch_notification(Slot::new(10), Slot::new(10), lead, 500));
+        assert!(!is_prefetch_notification(Slot::new(11), Slot::new(10), lead, 0));
+        assert!(!is_prefetch_notification(Slot::new(11), Slot::new(10), None, 500));
+    }
+
+    #[test]
+    fn payload_outcome_labels() {
+        let failed = Err(BlockError::Recoverable("failed".to_string()));
+        assert_eq!(payload_outcome(&Ok(()), true, false), metrics::PAYLOAD_BLINDED_SUCCESS);
+        assert_eq!(payload_outcome(&Ok(()), true, true), metrics::PAYLOAD_FULL_FALLBACK_SUCCESS);
+        assert_eq!(payload_outcome(&Ok(()), false, false), metrics::PAYLOAD_FULL_DIRECT);
+        assert_eq!(payload_outcome(&failed, true, true), metrics::PAYLOAD_FAILED);
+        assert_eq!(payload_outcome(&failed, false, false), metrics::PAYLOAD_FAILED);
+        let not_leader = Err(BlockError::SignBlockNotLeader);
+        assert_eq!(payload_outcome(&not_leader, true, false), metrics::PAYLOAD_NOT_LEADER);
+        let not_leader = Err(BlockError::RandaoNotLeader);
+        assert_eq!(payload_outcome(&not_leader, false, false), metrics::PAYLOAD_NOT_LEADER);
+    }
+}
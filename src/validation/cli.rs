@@ -188,6 +188,15 @@ pub fn cli_app<'a, 'b>() -> App<'a, 'b> {
                 .takes_value(true)
                 .conflicts_with("graffiti")
         )
+        .arg(
+            Arg::with_name("graffiti-file-reload-interval")
+                .long("graffiti-file-reload-interval")
+                .help("The minimum time between two reads of the graffiti file, unless it is \
+                    modified in the meantime. [default: 60]")
+                .value_name("SECONDS")
+                .requires("graffiti-file")
+                .takes_value(true)
+        )
         .arg(
             Arg::with_name("suggested-fee-recipient")
                 .long("suggested-fee-recipient")
@@ -352,4 +361,37 @@ pub fn cli_app<'a, 'b>() -> App<'a, 'b> {
                     timestamp used in the builder api registration")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("block-share-timeout")
+                .long("block-share-timeout")
+                .value_name("MILLISECONDS")
+                .help("How long to wait for the operators' signature shares of a block before \
+                    aggregating the shares received so far. [default: 2000]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("randao-share-timeout")
+                .long("randao-share-timeout")
+                .value_name("MILLISECONDS")
+                .help("How long to wait for the operators' signature shares of a randao reveal \
+                    before aggregating the shares received so far. [default: 2000]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("attestation-share-timeout")
+                .long("attestation-share-timeout")
+                .value_name("MILLISECONDS")
+                .help("How long to wait for the operators' signature shares of an attestation \
+                    before aggregating the shares received so far. [default: 2000]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("block-prefetch-lead")
+                .long("block-prefetch-lead")
+                .value_name("MILLISECONDS")
+                .help("Start requesting a block from the beacon node this long before the slot of \
+                    the proposal. The block is still signed and published once the slot has \
+                    started. [default: 0, disabled]")
+                .takes_value(true),
+        )
 }
@@ -15,8 +15,9 @@ use slog::{info, warn, Logger, error};
 use std::fs;
 use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
+use std::time::Duration;
 use types::{Address, GRAFFITI_BYTES_LEN};
-use crate::node::config::{NodeConfig,API_ADDRESS};
+use crate::node::config::{NodeConfig, SigningTimeouts, API_ADDRESS};
 use crate::node::contract::{DEFAULT_TRANSPORT_URL, SELF_OPERATOR_ID, NETWORK_CONTRACT, REGISTRY_CONTRACT};
 use dvf_directory::{get_default_base_dir};
 
@@ -60,6 +61,9 @@ pub struct Config {
     /// any of the validators managed by this client before starting up.
     pub enable_doppelganger_protection: bool,
     pub private_tx_proposals: bool,
+    /// How many milliseconds before the slot to start requesting a block, or zero to wait for the
+    /// slot to start.
+    pub block_prefetch_lead_ms: u64,
     /// Enable use of the blinded block endpoints during proposals.
     pub builder_proposals: bool,
     /// Overrides the timestamp field in builder api ValidatorRegistrationV1
@@ -109,6 +113,7 @@ impl Default for Config {
             enable_doppelganger_protection: false,
             beacon_nodes_tls_certs: None,
             private_tx_proposals: false,
+            block_prefetch_lead_ms: 0,
             builder_proposals: false,
             builder_registration_timestamp_override: None,
             gas_limit: None,
@@ -270,6 +275,18 @@ impl Config {
         }
         config.dvf_node_config = config.dvf_node_config.set_beacon_nodes(config.beacon_nodes.clone());
 
+        let mut signing_timeouts = SigningTimeouts::default();
+        if let Some(block_ms) = parse_optional::<u64>(cli_args, "block-share-timeout")? {
+            signing_timeouts.block_ms = block_ms;
+        }
+        if let Some(randao_ms) = parse_optional::<u64>(cli_args, "randao-share-timeout")? {
+            signing_timeouts.randao_ms = randao_ms;
+        }
+        if let Some(attestation_ms) = parse_optional::<u64>(cli_args, "attestation-share-timeout")? {
+            signing_timeouts.attestation_ms = attestation_ms;
+        }
+        config.dvf_node_config = config.dvf_node_config.set_signing_timeouts(signing_timeouts);
+
         if cli_args.is_present("delete-lockfiles") {
             warn!(
                 log,
@@ -286,6 +303,9 @@ impl Config {
 
         if let Some(graffiti_file_path) = cli_args.value_of("graffiti-file") {
             let mut graffiti_file = GraffitiFile::new(graffiti_file_path.into());
+            if let Some(interval) = parse_optional::<u64>(cli_args, "graffiti-file-reload-interval")? {
+                graffiti_file = graffiti_file.with_min_reload_interval(Duration::from_secs(interval));
+            }
             graffiti_file
                 .read_graffiti_file()
                 .map_err(|e| format!("Error reading graffiti file: {:?}", e))?;
@@ -426,6 +446,10 @@ impl Config {
             config.private_tx_proposals = true;
         }
 
+        if let Some(lead_ms) = parse_optional::<u64>(cli_args, "block-prefetch-lead")? {
+            config.block_prefetch_lead_ms = lead_ms;
+        }
+
         config.gas_limit = cli_args
             .value_of("gas-limit")
             .map(|gas_limit| {
@@ -24,6 +24,7 @@ use slog::{debug, error, info, warn, Logger};
 use slot_clock::SlotClock;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 use sync::poll_sync_committee_duties;
 use sync::SyncDutiesMap;
 use tokio::{sync::mpsc::Sender, time::sleep};
@@ -126,6 +127,9 @@ pub struct DutiesService<T, E: EthSpec> {
     /// This functionality is a little redundant since most BNs will likely reject duties when they
     /// aren't synced, but we keep it around for an emergency.
     pub require_synced: RequireSynced,
+    /// If non-zero, the block service is also notified of the proposers of the next slot this
+    /// many milliseconds before it starts, so that it can request their blocks early.
+    pub block_prefetch_lead_ms: u64,
     pub context: RuntimeContext<E>,
     pub spec: ChainSpec,
 }
@@ -270,6 +274,47 @@ pub fn start_update_service<T: SlotClock + 'static, E: EthSpec>(
         "duties_service_indices",
     );
 
+    /*
+     * Spawn the task which notifies the block service of the next slot's proposers ahead of time.
+     */
+    if core_duties_service.block_prefetch_lead_ms > 0 {
+        let duties_service = core_duties_service.clone();
+        let mut block_service_tx = block_service_tx.clone();
+        let lead = Duration::from_millis(core_duties_service.block_prefetch_lead_ms);
+        core_duties_service.context.executor.spawn(
+            async move {
+                loop {
+                    let duration = match duties_service.slot_clock.duration_to_next_slot() {
+                        Some(duration) => duration,
+                        None => {
+                            sleep(duties_service.slot_clock.slot_duration()).await;
+                            continue;
+                        }
+                    };
+                    // Too close to the next slot for this round, wait for it to start instead.
+                    if duration <= lead {
+                        sleep(duration).await;
+                        continue;
+                    }
+                    sleep(duration - lead).await;
+
+                    if let Some(current_slot) = duties_service.slot_clock.now() {
+                        let next_slot = current_slot + 1;
+                        notify_block_production_service(
+                            next_slot,
+                            &duties_service.block_proposers(next_slot).await,
+                            &mut block_service_tx,
+                            &duties_service.validator_store,
+                            duties_service.context.log(),
+                        )
+                        .await;
+                    }
+                }
+            },
+            "duties_service_proposer_prefetch",
+        );
+    }
+
     /*
      * Spawn the task which keeps track of local block proposal duties.
      */
@@ -936,6 +981,7 @@ async fn notify_block_production_service<T: SlotClock + 'static, E: EthSpec>(
             .send(BlockServiceNotification {
                 slot: current_slot,
                 block_proposers: non_doppelganger_proposers,
+                graffiti_override: None,
             })
             .await
         {
@@ -5,8 +5,16 @@ use types::{Hash256, Signature, PublicKey};
 use tokio::sync::{RwLock};
 use tokio::sync::mpsc::{Receiver};
 use async_trait::async_trait;
+use std::time::Duration;
 
 
+/// Returns the leader for `nonce`: the operators take turns in the order of their ids.
+pub fn select_leader(operator_ids: &[u64], nonce: u64) -> u64 {
+    let mut ids = operator_ids.to_vec();
+    ids.sort();
+    ids[(nonce % ids.len() as u64) as usize]
+}
+
 /// Operator committee for a validator. 
 /// 
 #[async_trait]
@@ -15,7 +23,9 @@ pub trait TOperatorCommittee : Send {
     fn validator_id(&self) -> u64;
     async fn add_operator(&mut self, operator_id: u64, operator: Arc<RwLock<dyn TOperator>>); 
     async fn consensus(&self, msg: Hash256) -> Result<(), DvfError>;
-    async fn sign(&self, msg: Hash256) -> Result<(Signature, Vec<u64>), DvfError>;
+    /// Aggregates the operators' signature shares of `msg`. If `share_timeout` is given, the
+    /// operators that have not returned a share by then are left out.
+    async fn sign(&self, msg: Hash256, share_timeout: Option<Duration>) -> Result<(Signature, Vec<u64>), DvfError>;
     async fn get_leader(&self, nonce: u64) -> u64;
     fn get_validator_pk(&self) -> String;
     fn threshold(&self) -> usize;
@@ -48,8 +58,8 @@ where
         self.cmt.threshold()
     }
 
-    pub async fn sign(&self, msg: Hash256) -> Result<(Signature, Vec<u64>), DvfError> {
-        self.cmt.sign(msg).await
+    pub async fn sign(&self, msg: Hash256, share_timeout: Option<Duration>) -> Result<(Signature, Vec<u64>), DvfError> {
+        self.cmt.sign(msg, share_timeout).await
     }
 
     pub async fn get_leader(&self, nonce: u64) -> u64 {
@@ -4,10 +4,14 @@ use std::fs::File;
 use std::io::{prelude::*, BufReader};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
 
 use bls::PublicKeyBytes;
 use types::{graffiti::GraffitiString, Graffiti};
 
+/// The default minimum time between two reads of an unmodified graffiti file.
+pub const DEFAULT_MIN_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Debug)]
 #[allow(clippy::enum_variant_names)]
 pub enum Error {
@@ -24,11 +28,23 @@ pub enum Error {
 /// public_key1: graffiti1
 /// public_key2: graffiti2
 /// ...
+///
+/// The parsed contents are cached: the file is read again only once `min_reload_interval` has
+/// elapsed, or earlier if its modification time changes.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GraffitiFile {
     graffiti_path: PathBuf,
     graffitis: HashMap<PublicKeyBytes, Graffiti>,
     default: Option<Graffiti>,
+    #[serde(default = "default_min_reload_interval")]
+    min_reload_interval: Duration,
+    /// When the file was last read, and its modification time at that point.
+    #[serde(skip)]
+    last_read: Option<(Instant, Option<SystemTime>)>,
+}
+
+fn default_min_reload_interval() -> Duration {
+    DEFAULT_MIN_RELOAD_INTERVAL
 }
 
 impl GraffitiFile {
@@ -37,42 +53,90 @@ impl GraffitiFile {
             graffiti_path,
             graffitis: HashMap::new(),
             default: None,
+            min_reload_interval: DEFAULT_MIN_RELOAD_INTERVAL,
+            last_read: None,
         }
     }
 
-    /// Loads the graffiti file and populates the default graffiti and `graffitis` hashmap.
-    /// Returns the graffiti corresponding to the given public key if present, else returns the
-    /// default graffiti.
+    /// Sets the minimum time between two reads of the file while it is not modified.
+    pub fn with_min_reload_interval(mut self, min_reload_interval: Duration) -> Self {
+        self.min_reload_interval = min_reload_interval;
+        self
+    }
+
+    /// Loads the graffiti file, unless the cached contents are still fresh, and returns the
+    /// graffiti corresponding to the given public key if present, else returns the default
+    /// graffiti.
     ///
     /// Returns an error if loading from the graffiti file fails.
     pub fn load_graffiti(
         &mut self,
         public_key: &PublicKeyBytes,
     ) -> Result<Option<Graffiti>, Error> {
-        self.read_graffiti_file()?;
-        Ok(self.graffitis.get(public_key).copied().or(self.default))
+        Ok(self.load_validator_graffiti(public_key)?.or(self.default))
     }
 
-    /// Reads from a graffiti file with the specified format and populates the default value
+    /// Like `load_graffiti`, but without falling back to the default graffiti of the file.
+    pub fn load_validator_graffiti(
+        &mut self,
+        public_key: &PublicKeyBytes,
+    ) -> Result<Option<Graffiti>, Error> {
+        if self.needs_reload() {
+            self.read_graffiti_file()?;
+        }
+        Ok(self.graffitis.get(public_key).copied())
+    }
+
+    /// Returns the default graffiti of the file, as of the last time it was read.
+    pub fn default_graffiti(&self) -> Option<Graffiti> {
+        self.default
+    }
+
+    /// Returns `true` if the file was never read, if the reload interval has elapsed since, or if
+    /// the file's modification time differs from the one it had when it was read.
+    fn needs_reload(&self) -> bool {
+        match self.last_read {
+            Some((read_at, modified)) => {
+                read_at.elapsed() >= self.min_reload_interval || self.modified() != modified
+            }
+            None => true,
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.graffiti_path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Reads from a graffiti file with the specified format and replaces the default value
     /// and the hashmap.
     ///
-    /// Returns an error if the file does not exist, or if the format is invalid.
+    /// Returns an error if the file does not exist, or if the format is invalid. The previously
+    /// read contents are kept in that case.
     pub fn read_graffiti_file(&mut self) -> Result<(), Error> {
+        // Taken before reading, so that a write racing with the read triggers another one.
+        let modified = self.modified();
         let file = File::open(self.graffiti_path.as_path()).map_err(Error::InvalidFile)?;
         let reader = BufReader::new(file);
 
         let lines = reader.lines();
 
+        let mut graffitis = HashMap::new();
+        let mut default = None;
         for line in lines {
             let line = line.map_err(|e| Error::InvalidLine(e.to_string()))?;
             let (pk_opt, graffiti) = read_line(&line)?;
             match pk_opt {
                 Some(pk) => {
-                    self.graffitis.insert(pk, graffiti);
+                    graffitis.insert(pk, graffiti);
                 }
-                None => self.default = Some(graffiti),
+                None => default = Some(graffiti),
             }
         }
+        self.graffitis = graffitis;
+        self.default = default;
+        self.last_read = Some((Instant::now(), modified));
         Ok(())
     }
 }
@@ -104,6 +168,7 @@ fn read_line(line: &str) -> Result<(Option<PublicKeyBytes>, Graffiti), Error> {
 mod tests {
     use super::*;
     use bls::Keypair;
+    use filetime::{set_file_mtime, FileTime};
     use std::io::LineWriter;
     use tempfile::TempDir;
 
@@ -175,5 +240,29 @@ mod tests {
             GraffitiString::from_str(DEFAULT_GRAFFITI).unwrap().into()
         );
     }
+
+    #[test]
+    fn test_reload_interval() {
+        let graffiti_file_path = create_graffiti_file();
+        let mut gf = GraffitiFile::new(graffiti_file_path.clone())
+            .with_min_reload_interval(Duration::from_millis(200));
+        let random_pk = Keypair::random().pk.compress();
+        let graffiti = |s: &str| -> Graffiti { GraffitiString::from_str(s).unwrap().into() };
+        assert_eq!(gf.load_graffiti(&random_pk).unwrap(), Some(graffiti(DEFAULT_GRAFFITI)));
+
+        // Rewrite the file, but keep its modification time so that only the interval matters.
+        let modified = FileTime::from_last_modification_time(
+            &std::fs::metadata(&graffiti_file_path).unwrap(),
+        );
+        std::fs::write(&graffiti_file_path, "default: rewritten\n").unwrap();
+        set_file_mtime(&graffiti_file_path, modified).unwrap();
+
+        // A rapid second load is served from the cache.
+        assert_eq!(gf.load_graffiti(&random_pk).unwrap(), Some(graffiti(DEFAULT_GRAFFITI)));
+
+        // Once the interval has elapsed, the file is read again.
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(gf.load_graffiti(&random_pk).unwrap(), Some(graffiti("rewritten")));
+    }
 }
 
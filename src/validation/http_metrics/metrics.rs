@@ -7,10 +7,21 @@ pub const SUCCESS: &str = "success";
 pub const SLASHABLE: &str = "slashable";
 pub const SAME_DATA: &str = "same_data";
 pub const UNREGISTERED: &str = "unregistered";
+pub const LOCAL_SIGNING_FALLBACK: &str = "local_signing_fallback";
 pub const FULL_UPDATE: &str = "full_update";
 pub const BEACON_BLOCK: &str = "beacon_block";
 pub const BEACON_BLOCK_HTTP_GET: &str = "beacon_block_http_get";
 pub const BEACON_BLOCK_HTTP_POST: &str = "beacon_block_http_post";
+pub const BEACON_BLOCK_PRODUCE: &str = "beacon_block_produce";
+pub const BEACON_BLOCK_SIGN: &str = "beacon_block_sign";
+pub const PROPOSER_MISMATCH_RETRY: &str = "proposer_mismatch_retry";
+pub const PAYLOAD_BLINDED_SUCCESS: &str = "blinded_success";
+pub const PAYLOAD_FULL_FALLBACK_SUCCESS: &str = "full_fallback_success";
+pub const PAYLOAD_FULL_DIRECT: &str = "full_direct";
+pub const PAYLOAD_FAILED: &str = "failed";
+pub const PAYLOAD_NOT_LEADER: &str = "not_leader";
+pub const NODE_ERROR_RECOVERABLE: &str = "recoverable";
+pub const NODE_ERROR_IRRECOVERABLE: &str = "irrecoverable";
 pub const ATTESTATIONS: &str = "attestations";
 pub const ATTESTATIONS_HTTP_GET: &str = "attestations_http_get";
 pub const ATTESTATIONS_HTTP_POST: &str = "attestations_http_post";
@@ -108,6 +119,68 @@ lazy_static::lazy_static! {
         "Duration to perform beacon block service tasks",
         &["task"]
     );
+    pub static ref BLOCK_SERVICE_RETRIES: Result<IntCounterVec> = try_create_int_counter_vec(
+        "vc_beacon_block_service_retries_total",
+        "Total count of beacon block service retries",
+        &["reason"]
+    );
+    pub static ref BLOCK_SERVICE_IS_LEADER: Result<IntCounterVec> = try_create_int_counter_vec(
+        "vc_beacon_block_service_is_leader_total",
+        "Total count of block proposals where this operator was the leader and published the block",
+        &["pubkey"]
+    );
+    pub static ref BLOCK_SERVICE_NOT_LEADER: Result<IntCounterVec> = try_create_int_counter_vec(
+        "vc_beacon_block_service_not_leader_total",
+        "Total count of block proposals skipped because this operator was not the leader",
+        &["pubkey"]
+    );
+    pub static ref BLOCK_SERVICE_CONSECUTIVE_MISSES: Result<IntGaugeVec> = try_create_int_gauge_vec(
+        "vc_beacon_block_service_consecutive_missed_proposals",
+        "Number of proposals a validator failed in a row, not counting those where this operator was not the leader",
+        &["pubkey"]
+    );
+    pub static ref BLOCK_SERVICE_SLOT_DEADLINE_EXCEEDED: Result<IntCounter> = try_create_int_counter(
+        "vc_beacon_block_service_slot_deadline_exceeded_total",
+        "Total count of block proposals abandoned at the block production deadline of their slot",
+    );
+    pub static ref BLOCK_PUBLISH_DELAY_INTO_SLOT: Result<Histogram> = try_create_histogram(
+        "vc_beacon_block_publish_delay_into_slot_seconds",
+        "Time between the start of a slot and the successful publication of our block for it",
+    );
+    pub static ref BLOCK_SERVICE_UNKNOWN_VALIDATOR_INDEX: Result<IntCounter> = try_create_int_counter(
+        "vc_beacon_block_service_unknown_validator_index_total",
+        "Total count of block proposals abandoned because the validator index is not known yet",
+    );
+    pub static ref BLOCK_SERVICE_WRONG_SLOT: Result<IntCounter> = try_create_int_counter(
+        "vc_beacon_block_service_wrong_slot_total",
+        "Total count of blocks rejected because the beacon node returned them for another slot",
+    );
+    pub static ref BLOCK_SERVICE_BEACON_NODE_NOT_SYNCED: Result<IntCounter> = try_create_int_counter(
+        "vc_beacon_block_service_beacon_node_not_synced_total",
+        "Total count of block production requests a beacon node refused because it is not synced",
+    );
+    pub static ref BLOCK_SERVICE_BLOCK_DISAGREEMENTS: Result<IntCounter> = try_create_int_counter(
+        "vc_beacon_block_service_block_disagreements_total",
+        "Total count of blocks not signed because too few beacon nodes produced a matching block",
+    );
+    pub static ref BLOCK_SERVICE_PAUSED_SKIPS: Result<IntCounter> = try_create_int_counter(
+        "vc_beacon_block_service_paused_skips_total",
+        "Total count of block production notifications skipped while the block service is paused",
+    );
+    pub static ref BLOCK_SERVICE_PAYLOAD_OUTCOME: Result<IntCounterVec> = try_create_int_counter_vec(
+        "vc_beacon_block_service_payload_outcome_total",
+        "Total count of block proposal attempts by the payload type that was published, if any",
+        &["outcome"]
+    );
+    pub static ref BLOCK_SERVICE_PUBLISH_CIRCUIT_OPENED: Result<IntCounter> = try_create_int_counter(
+        "vc_beacon_block_service_publish_circuit_opened_total",
+        "Total count of beacon nodes skipped for block publishing after repeated failures",
+    );
+    pub static ref BLOCK_SERVICE_NODE_ERRORS: Result<IntCounterVec> = try_create_int_counter_vec(
+        "vc_beacon_block_service_node_errors_total",
+        "Total count of beacon node errors behind failed block service requests, by whether they were recoverable",
+        &["kind"]
+    );
     pub static ref PROPOSER_COUNT: Result<IntGaugeVec> = try_create_int_gauge_vec(
         "vc_beacon_block_proposer_count",
         "Number of beacon block proposers on this host",
@@ -11,6 +11,7 @@ use tokio::sync::{RwLock};
 use tokio::sync::mpsc::{Receiver};
 use futures::executor::block_on;
 use async_trait::async_trait;
+use std::time::Duration;
 
 
 /// Provides the externally-facing operator committee type.
@@ -65,7 +66,7 @@ impl TOperatorCommittee for FakeOperatorCommittee {
         Ok(())
     }
 
-    async fn sign(&self, msg: Hash256) -> Result<Signature, DvfError> {
+    async fn sign(&self, msg: Hash256, _share_timeout: Option<Duration>) -> Result<Signature, DvfError> {
         println!("<<<<<<<[Duty]: {:02x?}>>>>>>", msg);
         println!("<<<<<<<[Committee Sign]>>>>>>");
         println!("<<<<<<<[Start Consensus]>>>>>>");
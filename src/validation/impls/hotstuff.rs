@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::sync::{Arc};
 use crate::validation::{
-    generic_operator_committee::{TOperatorCommittee},
+    generic_operator_committee::{select_leader, TOperatorCommittee},
     operator::{TOperator},
 };
 use crate::crypto::ThresholdSignature;
@@ -9,13 +9,47 @@ use crate::utils::error::DvfError;
 use bls::{Hash256, Signature, PublicKey};
 use tokio::sync::{RwLock};
 use tokio::sync::mpsc::{Receiver};
-use futures::future::join_all;
-use log::{info};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::future::Future;
+use std::time::Duration;
+use log::{info, warn};
 use tokio::sync::Notify;
 use tokio::task::JoinHandle;
 use async_trait::async_trait;
 
 
+/// Waits for the operators' signature shares, leaving out the operators that failed and, once
+/// `share_timeout` has elapsed, those that have not answered yet.
+async fn collect_shares<F>(
+    signing_futs: impl IntoIterator<Item = F>,
+    share_timeout: Option<Duration>,
+) -> Vec<(u64, PublicKey, Signature)>
+where
+    F: Future<Output = Result<(u64, PublicKey, Signature), DvfError>>,
+{
+    let mut pending: FuturesUnordered<F> = signing_futs.into_iter().collect();
+    let deadline = share_timeout.map(|x| tokio::time::Instant::now() + x);
+    let mut results = Vec::new();
+    loop {
+        let next = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, pending.next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    warn!("Signature share collection timed out with {} operators pending", pending.len());
+                    break;
+                }
+            },
+            None => pending.next().await,
+        };
+        match next {
+            Some(Ok(share)) => results.push(share),
+            Some(Err(_)) => {}
+            None => break,
+        }
+    }
+    results
+}
+
 /// Provides the externally-facing operator committee type.
 pub mod types {
     pub use super::HotstuffOperatorCommittee as OperatorCommittee;
@@ -94,10 +128,8 @@ impl TOperatorCommittee for HotstuffOperatorCommittee {
 
     async fn get_leader(&self, nonce: u64) -> u64 {
         let operators = self.operators.read().await;
-        let select_order = nonce % operators.len() as u64;
-        let mut ids : Vec<u64> = operators.keys().map(|k| *k).collect();
-        ids.sort();
-        ids[select_order as usize]
+        let ids : Vec<u64> = operators.keys().map(|k| *k).collect();
+        select_leader(&ids, nonce)
     }
 
     async fn consensus(&self, msg: Hash256) -> Result<(), DvfError> {
@@ -138,7 +170,7 @@ impl TOperatorCommittee for HotstuffOperatorCommittee {
         Ok(())
     }
 
-    async fn sign(&self, msg: Hash256) -> Result<(Signature, Vec<u64>), DvfError> {
+    async fn sign(&self, msg: Hash256, share_timeout: Option<Duration>) -> Result<(Signature, Vec<u64>), DvfError> {
         // Run consensus protocol 
         self.consensus(msg).await?;
 
@@ -150,11 +182,7 @@ impl TOperatorCommittee for HotstuffOperatorCommittee {
                 .map(|x| (operator_id.clone(), operator.public_key(), x))
             
         });
-        let results = join_all(signing_futs)
-            .await
-            .into_iter()
-            .flatten()
-            .collect::<Vec<(u64, PublicKey, Signature)>>();
+        let results = collect_shares(signing_futs, share_timeout).await;
 
         let ids = results.iter().map(|x| x.0).collect::<Vec<u64>>();
         let pks = results.iter().map(|x| &x.1).collect::<Vec<&PublicKey>>();
@@ -163,7 +191,21 @@ impl TOperatorCommittee for HotstuffOperatorCommittee {
         info!("Received {} signatures", sigs.len());
 
         let threshold_sig = ThresholdSignature::new(self.threshold());
-        let sig = threshold_sig.threshold_aggregate(&sigs[..], &pks[..], &ids[..], msg)?;
+        let sig = threshold_sig.threshold_aggregate(&sigs[..], &pks[..], &ids[..], msg)
+            .map_err(|e| match e {
+                // Add the operators that did not return a share at all.
+                DvfError::InsufficientSignatures { got, expected, mut failed_operators } => {
+                    failed_operators.extend(operators.keys().filter(|id| !ids.contains(id)));
+                    failed_operators.sort();
+                    DvfError::InsufficientSignatures { got, expected, failed_operators }
+                }
+                DvfError::InsufficientValidSignatures { got, expected, mut failed_operators } => {
+                    failed_operators.extend(operators.keys().filter(|id| !ids.contains(id)));
+                    failed_operators.sort();
+                    DvfError::InsufficientValidSignatures { got, expected, failed_operators }
+                }
+                e => e,
+            })?;
 
         Ok((sig, ids))
     }
@@ -174,3 +216,59 @@ impl TOperatorCommittee for HotstuffOperatorCommittee {
 
     
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bls::Keypair;
+    use tokio::time::{sleep, Instant};
+
+    fn share(
+        id: u64,
+        keypair: &Keypair,
+        msg: Hash256,
+        delay: Duration,
+    ) -> impl Future<Output = Result<(u64, PublicKey, Signature), DvfError>> {
+        let share = (id, keypair.pk.clone(), keypair.sk.sign(msg));
+        async move {
+            sleep(delay).await;
+            Ok(share)
+        }
+    }
+
+    #[tokio::test]
+    async fn share_timeout() {
+        let msg = Hash256::repeat_byte(1);
+        let keypairs: Vec<_> = (0..2).map(|_| Keypair::random()).collect();
+
+        // The slow operator is left out once the timeout fires.
+        let start = Instant::now();
+        let shares = collect_shares(
+            vec![
+                share(1, &keypairs[0], msg, Duration::from_millis(0)),
+                share(2, &keypairs[1], msg, Duration::from_secs(60)),
+            ],
+            Some(Duration::from_millis(100)),
+        )
+        .await;
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(shares.iter().map(|x| x.0).collect::<Vec<_>>(), vec![1]);
+
+        // One share is not enough for a threshold of 2, so signing fails fast.
+        let sigs = shares.iter().map(|x| &x.2).collect::<Vec<_>>();
+        let pks = shares.iter().map(|x| &x.1).collect::<Vec<_>>();
+        let result = ThresholdSignature::new(2).threshold_aggregate(&sigs[..], &pks[..], &[1], msg);
+        assert!(matches!(result, Err(DvfError::InsufficientSignatures { got: 1, expected: 2, .. })));
+
+        // Without a timeout, the slow operator is waited for.
+        let shares = collect_shares(
+            vec![
+                share(1, &keypairs[0], msg, Duration::from_millis(0)),
+                share(2, &keypairs[1], msg, Duration::from_millis(50)),
+            ],
+            None,
+        )
+        .await;
+        assert_eq!(shares.len(), 2);
+    }
+}
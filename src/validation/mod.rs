@@ -495,6 +495,7 @@ impl<T: EthSpec> ProductionValidatorClient<T> {
             } else {
                 RequireSynced::No
             },
+            block_prefetch_lead_ms: config.block_prefetch_lead_ms,
             spec: context.eth2_config.spec.clone(),
             context: duties_context,
         });
@@ -513,6 +514,7 @@ impl<T: EthSpec> ProductionValidatorClient<T> {
             .graffiti(config.graffiti)
             .graffiti_file(config.graffiti_file.clone())
             .private_tx_proposals(config.private_tx_proposals)
+            .prefetch_lead_ms(config.block_prefetch_lead_ms)
             .build()?;
 
         let attestation_service = AttestationServiceBuilder::new()
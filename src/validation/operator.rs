@@ -95,6 +95,7 @@ impl TOperator for RemoteOperator {
         
         let n_try: u64 = 3;
         let timeout_mill: u64 = 600;
+        let start = Instant::now();
         let dvf_message = DvfMessage { version: VERSION, validator_id: self.validator_id, message: msg.to_fixed_bytes().to_vec() };
         let serialize_msg = bincode::serialize(&dvf_message).unwrap();
         for i in 0..n_try {
@@ -129,7 +130,10 @@ impl TOperator for RemoteOperator {
             }
         }
         warn!("Failed to receive a signature from operator {}/{} ({:?})", self.operator_id, self.validator_id, self.signature_address());
-        Err(DvfError::Unknown)
+        Err(DvfError::Timeout {
+            context: format!("signature from operator {}/{}", self.operator_id, self.validator_id),
+            elapsed_ms: start.elapsed().as_millis() as u64,
+        })
     }
 
     fn public_key(&self) -> PublicKey {
@@ -9,6 +9,7 @@
 use crate::validation::http_metrics::metrics;
 use eth2_keystore::Keystore;
 use lockfile::Lockfile;
+use mempool::MempoolStats;
 use parking_lot::Mutex;
 use reqwest::Client;
 use std::path::PathBuf;
@@ -18,8 +19,10 @@ use types::*;
 use url::Url;
 use web3signer::{ForkInfo, SigningRequest, SigningResponse};
 use crate::node::dvfcore::DvfSigner;
+use crate::node::leader_history::LeaderAssignment;
 use crate::node::config::{API_ADDRESS, COLLECT_PERFORMANCE_URL};
 use crate::node::utils::{request_to_web_server, DvfPerformanceRequest, SignDigest};
+use crate::utils::error::DvfError;
 pub use web3signer::Web3SignerObject;
 use chrono::prelude::*;
 use crate::validation::eth2_keystore_share::keystore_share::KeystoreShare;
@@ -40,6 +43,11 @@ pub enum Error {
     MergeForkNotSupported,
     GenesisForkVersionRequired,
     CommitteeSignFailed(String),
+    /// Too few operators contributed a signature share for the committee to sign.
+    InsufficientSignatures {
+        got: usize,
+        expected: usize,
+    },
     SignDigestFailed(String),
     NotLeader,
 }
@@ -160,6 +168,36 @@ impl SigningMethod {
     }
 
 
+    /// Returns whether this operator aggregates the signatures of the duties that only the leader
+    /// signs (e.g. blocks) at `slot`, in `signing_epoch`. The other operators still sign and store
+    /// their own share, which the leader collects. Always `true` for non-distributed validators.
+    pub async fn is_leader(&self, slot: Slot, signing_epoch: Epoch) -> bool {
+        match self {
+            SigningMethod::DistributedKeystore { dvf_signer, .. } => {
+                dvf_signer.is_aggregator(slot.as_u64(), signing_epoch.as_u64()).await
+            }
+            SigningMethod::LocalKeystore { .. } | SigningMethod::Web3Signer { .. } => true,
+        }
+    }
+
+    /// Returns the latest leader decisions taken by `is_leader`. Empty for non-distributed
+    /// validators, which have no leader.
+    pub fn leader_history(&self) -> Vec<LeaderAssignment> {
+        match self {
+            SigningMethod::DistributedKeystore { dvf_signer, .. } => dvf_signer.leader_history.snapshot(),
+            SigningMethod::LocalKeystore { .. } | SigningMethod::Web3Signer { .. } => vec![],
+        }
+    }
+
+    /// Returns the backlog of the mempool of a distributed validator, e.g. the batches it is still
+    /// missing. `None` for non-distributed validators, which have no mempool.
+    pub fn mempool_stats(&self) -> Option<Arc<MempoolStats>> {
+        match self {
+            SigningMethod::DistributedKeystore { dvf_signer, .. } => Some(dvf_signer.mempool_stats.clone()),
+            SigningMethod::LocalKeystore { .. } | SigningMethod::Web3Signer { .. } => None,
+        }
+    }
+
     /// Return the signature of `signable_message`, with respect to the `signing_context`.
     pub async fn get_signature_from_root<T: EthSpec, Payload: AbstractExecPayload<T>>(
         &self,
@@ -317,7 +355,13 @@ impl SigningMethod {
                 // it is safe (from this operator's point of view) to sign it locally.
                 dvf_signer.local_sign_and_store(signing_root).await;
 
-                if !only_aggregator || (only_aggregator && dvf_signer.is_aggregator(signing_epoch.as_u64()).await) {
+                // Duties that carry no slot are recorded at the start of their signing epoch.
+                let leader_slot = if slot == Slot::new(0) {
+                    signing_epoch.start_slot(T::slots_per_epoch())
+                } else {
+                    slot
+                };
+                if !only_aggregator || self.is_leader(leader_slot, signing_epoch).await {
                     log::info!("[Dvf {}/{}] Leader trying to achieve duty consensus and aggregate duty signatures",
                         dvf_signer.operator_id, 
                         dvf_signer.operator_committee.validator_id()
@@ -329,7 +373,14 @@ impl SigningMethod {
                     // 2. most duties should complete in a slot
                     let task_timeout = Duration::from_secs(spec.seconds_per_slot * 2);
                     let timeout = sleep(task_timeout);
-                    let work = dvf_signer.threshold_sign(signing_root);
+                    // Time-critical duties stop waiting for slow operators once enough time passed.
+                    let share_timeout = match signable_message {
+                        SignableMessage::BeaconBlock(_) => Some(dvf_signer.signing_timeouts.block()),
+                        SignableMessage::RandaoReveal(_) => Some(dvf_signer.signing_timeouts.randao()),
+                        SignableMessage::AttestationData(_) => Some(dvf_signer.signing_timeouts.attestation()),
+                        _ => None,
+                    };
+                    let work = dvf_signer.threshold_sign(signing_root, share_timeout);
                     let dt : DateTime<Utc> = Utc::now();
                     tokio::select!{
                         result = work => {
@@ -339,6 +390,9 @@ impl SigningMethod {
                                     Self::dvf_report::<T>(slot, duty, dvf_signer.validator_public_key(), dvf_signer.operator_id(), ids, dt, &dvf_signer.node_secret).await?;
                                     Ok(signature)
                                 },
+                                Err(DvfError::InsufficientSignatures { got, expected, .. }) => {
+                                    Err(Error::InsufficientSignatures { got, expected })
+                                }
                                 Err(e) => {
                                     Err(Error::CommitteeSignFailed(format!("{:?}", e)))
                                 }
@@ -1,6 +1,7 @@
 //! Reference: lighthouse/validator_client/validator_store.rs 
 
 use crate::{
+    node::leader_history::LeaderAssignment,
     validation::doppelganger_service::DoppelgangerService,
     validation::http_metrics::metrics,
     validation::initialized_validators::InitializedValidators,
@@ -8,12 +9,15 @@ use crate::{
     validation::Config,
 };
 use crate::validation::account_utils::{validator_definitions::ValidatorDefinition, ZeroizeString};
+use hscrypto::Digest;
+use mempool::MempoolStats;
 use tokio::sync::{Mutex, RwLock};
 use slashing_protection::{
     interchange::Interchange, InterchangeError, NotSafe, Safe, SlashingDatabase,
 };
 use slog::{crit, error, info, warn, Logger};
 use slot_clock::SlotClock;
+use std::collections::HashSet;
 use std::iter::FromIterator;
 use std::marker::PhantomData;
 use std::path::Path;
@@ -87,6 +91,21 @@ impl PartialEq for LocalValidator {
     }
 }
 
+/// Returns the key to sign a block with after the committee failed with `error`, if any. The
+/// local key is only used when too few operators contributed a signature share, and only if it
+/// is the key of `validator_pubkey`: any other failure is left to the usual retries.
+pub fn local_fallback_keypair<'a>(
+    error: &SigningError,
+    validator_pubkey: &PublicKeyBytes,
+    local_fallback: Option<&'a Keypair>,
+) -> Option<&'a Keypair> {
+    match error {
+        SigningError::InsufficientSignatures { .. } => local_fallback
+            .filter(|keypair| PublicKeyBytes::from(&keypair.pk) == *validator_pubkey),
+        _ => None,
+    }
+}
+
 pub struct ValidatorStore<T, E: EthSpec> {
     validators: Arc<RwLock<InitializedValidators<E>>>,
     slashing_protection: SlashingDatabase,
@@ -399,6 +418,47 @@ impl<T: SlotClock + 'static, E: EthSpec> ValidatorStore<T, E> {
             .ok_or(Error::UnknownPubkey(validator_pubkey))
     }
 
+    /// Returns whether this operator is the one that aggregates and publishes the block of
+    /// `validator_pubkey` at `slot`, using the same rule `sign_block` enforces. Unknown validators
+    /// are never led.
+    ///
+    /// This must not be used to skip block production on the other operators: `sign_block` is
+    /// where they sign and store the signature share the leader collects, so an operator that
+    /// skipped the block would leave the leader short of shares. The answer is only fit for
+    /// logging and metrics.
+    pub async fn is_leader_for(&self, validator_pubkey: &PublicKeyBytes, slot: Slot) -> bool {
+        let signing_method = self.validators.read().await.signing_method(validator_pubkey);
+        match signing_method {
+            Some(signing_method) => {
+                signing_method
+                    .is_leader(slot, slot.epoch(E::slots_per_epoch()))
+                    .await
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the latest leader decisions taken for `validator_pubkey`, oldest first.
+    pub async fn leader_history(&self, validator_pubkey: &PublicKeyBytes) -> Vec<LeaderAssignment> {
+        let signing_method = self.validators.read().await.signing_method(validator_pubkey);
+        signing_method.map_or_else(Vec::new, |signing_method| signing_method.leader_history())
+    }
+
+    /// Returns the backlog of the mempool of `validator_pubkey`, if it is a distributed validator.
+    pub async fn mempool_stats(&self, validator_pubkey: &PublicKeyBytes) -> Option<Arc<MempoolStats>> {
+        let signing_method = self.validators.read().await.signing_method(validator_pubkey);
+        signing_method.and_then(|signing_method| signing_method.mempool_stats())
+    }
+
+    /// Returns the batches the mempool of `validator_pubkey` is still syncing from the other
+    /// operators, e.g. to follow the progress of a node catching up. Empty for non-distributed
+    /// validators.
+    pub async fn missing_batches(&self, validator_pubkey: &PublicKeyBytes) -> HashSet<Digest> {
+        self.mempool_stats(validator_pubkey)
+            .await
+            .map_or_else(HashSet::new, |mempool_stats| mempool_stats.missing_batches())
+    }
+
     fn signing_context(&self, domain: Domain, signing_epoch: Epoch) -> SigningContext {
         SigningContext {
             domain,
@@ -465,6 +525,23 @@ impl<T: SlotClock + 'static, E: EthSpec> ValidatorStore<T, E> {
         validator_pubkey: PublicKeyBytes,
         block: BeaconBlock<E, Payload>,
         current_slot: Slot,
+    ) -> Result<SignedBeaconBlock<E, Payload>, Error> {
+        self.sign_block_with_fallback(validator_pubkey, block, current_slot, None)
+            .await
+    }
+
+    /// Like `sign_block`, but if the committee fails to gather enough signature shares, signs the
+    /// block with `local_fallback` instead: the full validator key held by this operator.
+    ///
+    /// This is an emergency measure that trades away the guarantees of the distributed validator.
+    /// See `BlockServiceBuilder::allow_local_signing_fallback` before using it. The block still goes
+    /// through slashing protection first, exactly as for a committee signature.
+    pub async fn sign_block_with_fallback<Payload: AbstractExecPayload<E>>(
+        &self,
+        validator_pubkey: PublicKeyBytes,
+        block: BeaconBlock<E, Payload>,
+        current_slot: Slot,
+        local_fallback: Option<&Keypair>,
     ) -> Result<SignedBeaconBlock<E, Payload>, Error> {
         // Make sure the block slot is not higher than the current slot to avoid potential attacks.
         if block.slot() > current_slot {
@@ -497,14 +574,35 @@ impl<T: SlotClock + 'static, E: EthSpec> ValidatorStore<T, E> {
                 metrics::inc_counter_vec(&metrics::SIGNED_BLOCKS_TOTAL, &[metrics::SUCCESS]);
 
                 let signing_method = self.doppelganger_checked_signing_method(validator_pubkey).await?;
-                let signature = signing_method
+                let result = signing_method
                     .get_signature::<E, Payload>(
                         SignableMessage::BeaconBlock(&block),
                         signing_context,
                         &self.spec,
                         &self.task_executor,
                     )
-                    .await?;
+                    .await;
+                let signature = match result {
+                    Ok(signature) => signature,
+                    Err(e) => match local_fallback_keypair(&e, &validator_pubkey, local_fallback) {
+                        Some(keypair) => {
+                            crit!(
+                                self.log,
+                                "Signing block with the local validator key";
+                                "msg" => "the committee could not gather enough signature shares",
+                                "error" => format!("{:?}", e),
+                                "slot" => block.slot().as_u64(),
+                                "public_key" => format!("{:?}", validator_pubkey),
+                            );
+                            metrics::inc_counter_vec(
+                                &metrics::SIGNED_BLOCKS_TOTAL,
+                                &[metrics::LOCAL_SIGNING_FALLBACK],
+                            );
+                            keypair.sk.sign(block.signing_root(domain_hash))
+                        }
+                        None => return Err(e.into()),
+                    },
+                };
                 Ok(SignedBeaconBlock::from_block(block, signature))
             }
             Ok(Safe::SameData) => {